@@ -0,0 +1,42 @@
+use std::env;
+use std::process::Command;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Capture information about the build, exposed by the `/version` endpoint
+fn main() {
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BUILD_GIT_SHA={git_sha}");
+
+    // Respect SOURCE_DATE_EPOCH for reproducible builds
+    let build_time = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(Utc::now);
+    println!(
+        "cargo:rustc-env=BUILD_TIMESTAMP={}",
+        build_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Migrations are embedded in the binary
+    println!("cargo:rerun-if-changed=migrations");
+}
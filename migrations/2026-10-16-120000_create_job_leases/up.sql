@@ -0,0 +1,8 @@
+-- The most recent tick of each scheduled job that a replica has claimed, so
+-- that a job runs exactly once per tick however many replicas are running
+CREATE TABLE job_leases (
+  job VARCHAR PRIMARY KEY,
+  tick TIMESTAMPTZ NOT NULL,
+  holder VARCHAR NOT NULL,
+  claimed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)
@@ -0,0 +1,26 @@
+-- Endpoints that are sent a signed copy of each change to the catalog
+CREATE TABLE webhooks (
+  id SERIAL PRIMARY KEY,
+  url VARCHAR NOT NULL,
+  secret VARCHAR NOT NULL,
+  -- The event types to send, or all of them if empty
+  events TEXT[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- One event to send to one webhook, and how sending it has gone so far
+CREATE TABLE webhook_deliveries (
+  id BIGSERIAL PRIMARY KEY,
+  webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+  event_type VARCHAR NOT NULL,
+  payload TEXT NOT NULL,
+  -- pending, delivered or failed
+  status VARCHAR NOT NULL DEFAULT 'pending',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_status_code INTEGER,
+  last_error TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  last_attempt_at TIMESTAMPTZ
+);
+
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, id);
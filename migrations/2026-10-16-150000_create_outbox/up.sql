@@ -0,0 +1,11 @@
+-- Book events written in the same transaction as the change they describe,
+-- until they have been relayed to webhooks and Kafka
+CREATE TABLE outbox (
+  id BIGSERIAL PRIMARY KEY,
+  event_type VARCHAR NOT NULL,
+  payload TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  published_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_unpublished ON outbox (id) WHERE published_at IS NULL;
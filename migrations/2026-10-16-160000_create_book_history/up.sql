@@ -0,0 +1,24 @@
+-- Every change made to each book, never updated or deleted, so that a book
+-- can be audited and its state at any time derived
+CREATE TABLE book_history (
+  id BIGSERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL,
+  event_type VARCHAR NOT NULL,
+  payload TEXT NOT NULL,
+  recorded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX book_history_book_id ON book_history (book_id, id);
+
+-- Books that already exist start their history from their current state
+INSERT INTO book_history (book_id, event_type, payload, recorded_at)
+SELECT
+  id,
+  'book_created',
+  json_build_object(
+    'type', 'book_created',
+    'book', json_build_object('id', id, 'name', name, 'author', author, 'updated_at', updated_at)
+  )::text,
+  updated_at
+FROM books
+ORDER BY id;
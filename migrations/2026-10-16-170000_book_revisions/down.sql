@@ -0,0 +1,8 @@
+DROP INDEX book_revisions_book_id_revision;
+CREATE INDEX book_history_book_id ON book_revisions (book_id, id);
+ALTER TABLE book_revisions
+  DROP COLUMN revision,
+  DROP COLUMN actor;
+ALTER INDEX book_revisions_pkey RENAME TO book_history_pkey;
+ALTER SEQUENCE book_revisions_id_seq RENAME TO book_history_id_seq;
+ALTER TABLE book_revisions RENAME TO book_history;
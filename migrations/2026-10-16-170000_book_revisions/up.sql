@@ -0,0 +1,19 @@
+-- Each change is a numbered revision of its book, made by an actor
+ALTER TABLE book_history RENAME TO book_revisions;
+ALTER SEQUENCE book_history_id_seq RENAME TO book_revisions_id_seq;
+ALTER INDEX book_history_pkey RENAME TO book_revisions_pkey;
+ALTER TABLE book_revisions
+  ADD COLUMN revision INTEGER,
+  ADD COLUMN actor VARCHAR;
+
+UPDATE book_revisions
+SET revision = numbered.revision
+FROM (
+  SELECT id, row_number() OVER (PARTITION BY book_id ORDER BY id) AS revision
+  FROM book_revisions
+) AS numbered
+WHERE book_revisions.id = numbered.id;
+
+ALTER TABLE book_revisions ALTER COLUMN revision SET NOT NULL;
+DROP INDEX book_history_book_id;
+CREATE UNIQUE INDEX book_revisions_book_id_revision ON book_revisions (book_id, revision);
@@ -0,0 +1,2 @@
+-- The earlier revision that a revert restored, for revisions made by one
+ALTER TABLE book_revisions ADD COLUMN restores_revision INTEGER
@@ -0,0 +1,19 @@
+-- Background work, claimed by workers with SELECT ... FOR UPDATE SKIP LOCKED
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    -- The job as JSON
+    payload TEXT NOT NULL,
+    -- queued, running, done or dead
+    status VARCHAR NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- When a running job's worker is presumed to have died
+    locked_until TIMESTAMPTZ,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX jobs_runnable ON jobs (run_at) WHERE status IN ('queued', 'running');
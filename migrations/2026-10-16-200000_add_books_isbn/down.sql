@@ -0,0 +1,4 @@
+ALTER TABLE books
+    DROP COLUMN isbn,
+    DROP COLUMN publisher,
+    DROP COLUMN cover_url
@@ -0,0 +1,6 @@
+ALTER TABLE books
+    ADD COLUMN isbn VARCHAR,
+    ADD COLUMN publisher VARCHAR,
+    ADD COLUMN cover_url VARCHAR;
+
+CREATE INDEX books_isbn ON books (isbn);
@@ -0,0 +1,10 @@
+-- What each metadata provider returned for an ISBN, so that it isn't asked
+-- again until the lookup expires
+CREATE TABLE metadata_lookups (
+    provider VARCHAR NOT NULL,
+    isbn VARCHAR NOT NULL,
+    -- The metadata as JSON, or NULL if the provider didn't know the ISBN
+    metadata TEXT,
+    looked_up_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, isbn)
+);
@@ -0,0 +1,2 @@
+DROP INDEX books_normalized_name_author;
+DROP FUNCTION normalize_text(text)
@@ -0,0 +1,9 @@
+-- Lower-cased, with each run of anything but letters and digits replaced by a
+-- single space, so that titles and authors that differ only in case,
+-- punctuation or spacing compare equal
+CREATE FUNCTION normalize_text(text) RETURNS text AS $$
+    SELECT trim(regexp_replace(lower($1), '[^[:alnum:]]+', ' ', 'g'))
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Used to find probable duplicates of a new book
+CREATE INDEX books_normalized_name_author ON books (normalize_text(name), normalize_text(author));
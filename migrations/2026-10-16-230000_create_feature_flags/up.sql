@@ -0,0 +1,7 @@
+-- Feature flags toggled at runtime through the admin API, overriding the
+-- defaults in the config file. Every replica reads them from here.
+CREATE TABLE feature_flags (
+    name VARCHAR PRIMARY KEY,
+    enabled BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
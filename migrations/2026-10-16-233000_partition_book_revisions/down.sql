@@ -0,0 +1,26 @@
+ALTER TABLE book_revisions RENAME TO book_revisions_partitioned;
+ALTER INDEX book_revisions_pkey RENAME TO book_revisions_partitioned_pkey;
+ALTER INDEX book_revisions_book_id_revision RENAME TO book_revisions_partitioned_book_id_revision;
+
+CREATE TABLE book_revisions (
+  id BIGINT PRIMARY KEY DEFAULT nextval('book_revisions_id_seq'),
+  book_id INTEGER NOT NULL,
+  event_type VARCHAR NOT NULL,
+  payload TEXT NOT NULL,
+  recorded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  revision INTEGER NOT NULL,
+  actor VARCHAR,
+  restores_revision INTEGER
+);
+ALTER SEQUENCE book_revisions_id_seq OWNED BY book_revisions.id;
+
+CREATE UNIQUE INDEX book_revisions_book_id_revision ON book_revisions (book_id, revision);
+
+INSERT INTO book_revisions
+  (id, book_id, event_type, payload, recorded_at, revision, actor, restores_revision)
+SELECT id, book_id, event_type, payload, recorded_at, revision, actor, restores_revision
+FROM book_revisions_partitioned;
+
+-- Drops the partitions too
+DROP TABLE book_revisions_partitioned;
+DROP FUNCTION create_monthly_partitions(TEXT, TIMESTAMPTZ, INTEGER);
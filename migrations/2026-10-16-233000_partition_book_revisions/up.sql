@@ -0,0 +1,79 @@
+-- Creates the monthly partitions of a table partitioned by range of a
+-- timestamp, named like `book_revisions_2026_10`, for each month (in UTC)
+-- from the one containing `since` to `months_ahead` months after the
+-- current one. Partitions that already exist are left as they are, as are
+-- months whose rows have already landed in the default partition. Returns
+-- how many were created.
+CREATE FUNCTION create_monthly_partitions(
+  parent TEXT,
+  since TIMESTAMPTZ,
+  months_ahead INTEGER
+) RETURNS INTEGER AS $$
+DECLARE
+  month TIMESTAMP := date_trunc('month', since AT TIME ZONE 'UTC');
+  last_month TIMESTAMP := date_trunc('month', now() AT TIME ZONE 'UTC')
+    + make_interval(months => months_ahead);
+  partition TEXT;
+  created INTEGER := 0;
+BEGIN
+  WHILE month <= last_month LOOP
+    partition := parent || '_' || to_char(month, 'YYYY_MM');
+    IF to_regclass(partition) IS NULL THEN
+      BEGIN
+        EXECUTE format(
+          'CREATE TABLE %I PARTITION OF %I FOR VALUES FROM (%L) TO (%L)',
+          partition,
+          parent,
+          month AT TIME ZONE 'UTC',
+          (month + INTERVAL '1 month') AT TIME ZONE 'UTC'
+        );
+        created := created + 1;
+      EXCEPTION WHEN check_violation THEN
+        RAISE WARNING 'Not creating %, as the default partition has rows for it', partition;
+      END;
+    END IF;
+    month := month + INTERVAL '1 month';
+  END LOOP;
+  RETURN created;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Revisions are never updated or deleted, so the table only grows. It is
+-- partitioned by month, so that each partition's indexes stay small and old
+-- months can be archived or dropped on their own. The partition key has to
+-- be part of every unique index: revision numbers stay unique per book
+-- because they are assigned holding a lock.
+ALTER TABLE book_revisions RENAME TO book_revisions_unpartitioned;
+ALTER INDEX book_revisions_pkey RENAME TO book_revisions_unpartitioned_pkey;
+ALTER INDEX book_revisions_book_id_revision RENAME TO book_revisions_unpartitioned_book_id_revision;
+
+CREATE TABLE book_revisions (
+  id BIGINT NOT NULL DEFAULT nextval('book_revisions_id_seq'),
+  book_id INTEGER NOT NULL,
+  event_type VARCHAR NOT NULL,
+  payload TEXT NOT NULL,
+  recorded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  revision INTEGER NOT NULL,
+  actor VARCHAR,
+  restores_revision INTEGER,
+  PRIMARY KEY (id, recorded_at)
+) PARTITION BY RANGE (recorded_at);
+ALTER SEQUENCE book_revisions_id_seq OWNED BY book_revisions.id;
+
+CREATE INDEX book_revisions_book_id_revision ON book_revisions (book_id, revision);
+-- For any month without a partition, e.g. if the create_partitions job
+-- hasn't run
+CREATE TABLE book_revisions_default PARTITION OF book_revisions DEFAULT;
+
+SELECT create_monthly_partitions(
+  'book_revisions',
+  coalesce((SELECT min(recorded_at) FROM book_revisions_unpartitioned), now()),
+  1
+);
+
+INSERT INTO book_revisions
+  (id, book_id, event_type, payload, recorded_at, revision, actor, restores_revision)
+SELECT id, book_id, event_type, payload, recorded_at, revision, actor, restores_revision
+FROM book_revisions_unpartitioned;
+
+DROP TABLE book_revisions_unpartitioned;
@@ -0,0 +1,11 @@
+-- How many times each book was viewed in each hour, for trending books.
+-- Views are kept after a book is deleted, and pruned once they are older
+-- than the longest trending window.
+CREATE TABLE book_views (
+    book_id INTEGER NOT NULL,
+    hour TIMESTAMPTZ NOT NULL,
+    views INTEGER NOT NULL,
+    PRIMARY KEY (book_id, hour)
+);
+
+CREATE INDEX book_views_hour ON book_views (hour);
@@ -0,0 +1,14 @@
+-- The requests to each route by each client, by their IP address, on each
+-- day, with how many failed and how long they took. Each replica adds to
+-- the counts every few seconds.
+CREATE TABLE api_usage (
+    day DATE NOT NULL,
+    route VARCHAR NOT NULL,
+    client VARCHAR NOT NULL,
+    requests BIGINT NOT NULL,
+    client_errors BIGINT NOT NULL,
+    server_errors BIGINT NOT NULL,
+    total_latency_ms DOUBLE PRECISION NOT NULL,
+    max_latency_ms DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (day, route, client)
+);
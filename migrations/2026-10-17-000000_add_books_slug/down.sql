@@ -0,0 +1,3 @@
+DROP TABLE book_slugs;
+ALTER TABLE books DROP COLUMN slug;
+DROP FUNCTION slugify(text)
@@ -0,0 +1,64 @@
+-- The URL-friendly form of a book's name, as made by `slug::slugify`:
+-- lower-cased, with common accented letters replaced by plain ones, and each
+-- run of anything but letters and digits replaced by a hyphen
+CREATE FUNCTION slugify(name TEXT) RETURNS TEXT AS $$
+    SELECT coalesce(
+        nullif(
+            rtrim(
+                left(
+                    trim(leading '-' from regexp_replace(
+                        replace(replace(replace(
+                            translate(
+                                lower(name),
+                                'àáâãäåçèéêëìíîïñòóôõöøùúûüýÿ',
+                                'aaaaaaceeeeiiiinoooooouuuuyy'
+                            ),
+                            'æ', 'ae'), 'œ', 'oe'), 'ß', 'ss'),
+                        '[^a-z0-9]+', '-', 'g'
+                    )),
+                    80
+                ),
+                '-'
+            ),
+            ''
+        ),
+        'book'
+    )
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Each book's slug, unique among the books, for GET /books/by-slug/{slug}
+ALTER TABLE books ADD COLUMN slug VARCHAR;
+CREATE UNIQUE INDEX books_slug ON books (slug);
+
+-- Slugs a book had before it was renamed, deleted or merged into another,
+-- so that links to them keep working. A slug is never given to another book.
+CREATE TABLE book_slugs (
+    slug VARCHAR PRIMARY KEY,
+    book_id INTEGER NOT NULL
+);
+
+CREATE INDEX book_slugs_book_id ON book_slugs (book_id);
+
+-- Books with the same slug are told apart by a suffix, in order of ID, as
+-- they would have been had they been added since
+DO $$
+DECLARE
+  book RECORD;
+  base TEXT;
+  candidate TEXT;
+  n INTEGER;
+BEGIN
+  FOR book IN SELECT id, name FROM books ORDER BY id LOOP
+    base := slugify(book.name);
+    candidate := base;
+    n := 1;
+    WHILE EXISTS (SELECT 1 FROM books WHERE slug = candidate) LOOP
+      n := n + 1;
+      candidate := base || '-' || n;
+    END LOOP;
+    UPDATE books SET slug = candidate WHERE id = book.id;
+  END LOOP;
+END;
+$$;
+
+ALTER TABLE books ALTER COLUMN slug SET NOT NULL;
@@ -0,0 +1,2 @@
+DROP INDEX books_author_prefix;
+DROP INDEX books_name_prefix
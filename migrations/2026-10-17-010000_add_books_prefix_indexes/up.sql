@@ -0,0 +1,5 @@
+-- For the prefix searches of typeahead suggestions, e.g.
+-- lower(name) LIKE 'gre%', which a plain index can't be used for unless the
+-- database's collation is C
+CREATE INDEX books_name_prefix ON books (lower(name) text_pattern_ops);
+CREATE INDEX books_author_prefix ON books (lower(author) text_pattern_ops);
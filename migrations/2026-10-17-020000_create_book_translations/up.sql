@@ -0,0 +1,11 @@
+-- Each book's name and description in other languages, e.g. for the French
+-- and German storefronts. `language` is a lower-cased language tag like
+-- `fr` or `de-at`.
+CREATE TABLE book_translations (
+    book_id INTEGER NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+    language VARCHAR(35) NOT NULL,
+    name VARCHAR NOT NULL,
+    description TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (book_id, language)
+);
@@ -0,0 +1,2 @@
+DROP INDEX books_search_vector;
+ALTER TABLE books DROP COLUMN search_vector
@@ -0,0 +1,7 @@
+-- The words of each book's name and author, stemmed, for full-text search.
+-- Words in the name weigh more than those in the author when ranking.
+ALTER TABLE books ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') || setweight(to_tsvector('english', author), 'B')
+) STORED;
+
+CREATE INDEX books_search_vector ON books USING GIN (search_vector);
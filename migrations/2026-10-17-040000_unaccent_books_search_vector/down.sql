@@ -0,0 +1,9 @@
+DROP INDEX books_search_vector;
+ALTER TABLE books DROP COLUMN search_vector;
+ALTER TABLE books ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') || setweight(to_tsvector('english', author), 'B')
+) STORED;
+CREATE INDEX books_search_vector ON books USING GIN (search_vector);
+
+DROP FUNCTION unaccent_text(text);
+DROP EXTENSION IF EXISTS unaccent
@@ -0,0 +1,18 @@
+-- With diacritics stripped, e.g. Brontë -> Bronte, so that searches match
+-- with or without them. unaccent() is only STABLE, as its dictionary could be
+-- changed, so naming the dictionary is what lets this be IMMUTABLE, as a
+-- generated column needs.
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+CREATE FUNCTION unaccent_text(text) RETURNS text AS $$
+    SELECT public.unaccent('public.unaccent'::regdictionary, $1)
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE STRICT;
+
+DROP INDEX books_search_vector;
+ALTER TABLE books DROP COLUMN search_vector;
+ALTER TABLE books ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', unaccent_text(name)), 'A')
+        || setweight(to_tsvector('english', unaccent_text(author)), 'B')
+) STORED;
+
+CREATE INDEX books_search_vector ON books USING GIN (search_vector);
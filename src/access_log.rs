@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+use axum::{
+    body::HttpBody,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info, warn};
+
+use crate::client_ip::ClientIp;
+use crate::slow_log::SlowLogThresholds;
+
+/// The tracing target used for access log events.
+///
+/// Access logs can be turned on or off independently of the application logs
+/// using this target in `RUST_LOG`, e.g. `RUST_LOG=info,access_log=off`.
+pub const ACCESS_LOG_TARGET: &str = "access_log";
+
+/// Middleware that emits one structured event per completed request, plus a
+/// warning for any request slower than the configured threshold
+pub async fn access_log(
+    State(thresholds): State<SlowLogThresholds>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().unwrap_or_default().to_string();
+    let user_agent = request
+        .headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    // Set by the `resolve_client_ip` middleware
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .and_then(|ClientIp(ip)| *ip)
+        .map(|ip| ip.to_string());
+
+    let response = next.run(request).await;
+
+    let latency = start.elapsed();
+    let latency_ms = latency.as_secs_f64() * 1000.0;
+    let response_size = response.body().size_hint().exact();
+
+    info!(
+        target: ACCESS_LOG_TARGET,
+        %method,
+        path,
+        status = response.status().as_u16(),
+        latency_ms,
+        response_size,
+        client_ip,
+        "request completed"
+    );
+
+    if latency > thresholds.request {
+        warn!(
+            %method,
+            path,
+            query,
+            status = response.status().as_u16(),
+            latency_ms,
+            threshold_ms = thresholds.request.as_millis() as u64,
+            response_size,
+            client_ip,
+            user_agent,
+            "slow request"
+        );
+    }
+
+    response
+}
@@ -0,0 +1,152 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::config::RuntimeConfig;
+use crate::runtime_config::RuntimeConfigHandle;
+
+/// Routes for operating the service, plus `routes`, all of which require
+/// the admin token
+pub fn admin_router(
+    runtime_config: RuntimeConfigHandle,
+    admin_token: String,
+    routes: Router,
+) -> Router {
+    let router = Router::new()
+        .route("/admin/reload-config", post(reload_config))
+        .with_state(runtime_config)
+        .merge(routes);
+    #[cfg(feature = "admin-ui")]
+    let router = router.merge(crate::admin_ui::admin_ui_router());
+
+    router.route_layer(middleware::from_fn_with_state(
+        admin_token,
+        require_admin_token,
+    ))
+}
+
+async fn reload_config(
+    State(runtime_config): State<RuntimeConfigHandle>,
+) -> Result<Json<RuntimeConfig>, (StatusCode, String)> {
+    runtime_config
+        .reload()
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Middleware rejecting requests without the admin token, given either as
+/// `Authorization: Bearer <token>` or, so that a browser can open the admin
+/// UI, as the password of HTTP Basic auth (with any username)
+pub(crate) async fn require_admin_token(
+    State(admin_token): State<String>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let provided_token = authorization.and_then(|value| {
+        value
+            .strip_prefix("Bearer ")
+            .map(str::to_string)
+            .or_else(|| value.strip_prefix("Basic ").and_then(basic_auth_password))
+    });
+
+    let token_matches = provided_token.is_some_and(|token| tokens_match(&token, &admin_token));
+    if !admin_token.is_empty() && token_matches {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"bookstore admin\"")],
+            "A valid admin token is required",
+        )
+            .into_response()
+    }
+}
+
+/// Compares the SHA-256 digests of the tokens in constant time, so that
+/// neither the time taken nor an early exit on the length gives the admin
+/// token away
+fn tokens_match(provided: &str, expected: &str) -> bool {
+    let provided = Sha256::digest(provided.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    provided.as_slice().ct_eq(expected.as_slice()).into()
+}
+
+fn basic_auth_password(credentials: &str) -> Option<String> {
+    let decoded = BASE64_STANDARD.decode(credentials).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (_username, password) = decoded.split_once(':')?;
+    Some(password.to_string())
+}
+
+/// Middleware returning 503 for every request while maintenance mode is on
+pub async fn maintenance_mode(
+    State(runtime_config): State<RuntimeConfigHandle>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if runtime_config.maintenance_mode() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The service is down for maintenance",
+        )
+            .into_response()
+    } else {
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn the_admin_token_can_be_a_bearer_token_or_basic_auth_password() {
+        let router = Router::new().route("/admin", get(|| async {})).route_layer(
+            middleware::from_fn_with_state("s3cret".to_string(), require_admin_token),
+        );
+        let status = |authorization: Option<&str>| {
+            let mut request = Request::get("/admin");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            let router = router.clone();
+            async move {
+                router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(status(Some("Bearer s3cret")).await, StatusCode::OK);
+        let basic = format!("Basic {}", BASE64_STANDARD.encode("admin:s3cret"));
+        assert_eq!(status(Some(&basic)).await, StatusCode::OK);
+
+        let wrong = format!("Basic {}", BASE64_STANDARD.encode("admin:guess"));
+        assert_eq!(status(Some(&wrong)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer guess")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer s3cre")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(Some("Bearer s3cret!")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+    }
+}
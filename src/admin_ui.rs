@@ -0,0 +1,58 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use rust_embed::Embed;
+
+/// The admin UI's static files, embedded in the binary at build time
+#[derive(Embed)]
+#[folder = "ui/"]
+struct Assets;
+
+/// Routes serving the admin UI, a single page for browsing and editing books
+pub(crate) fn admin_ui_router() -> Router {
+    Router::new()
+        .route("/admin/ui", get(|| asset("index.html")))
+        .route(
+            "/admin/ui/{*path}",
+            get(|Path(path): Path<String>| asset(path)),
+        )
+}
+
+async fn asset(path: impl AsRef<str>) -> Response {
+    match Assets::get(path.as_ref()) {
+        Some(file) => (
+            [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+            file.data,
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "No such file").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_the_embedded_files() {
+        let get = |uri| admin_ui_router().oneshot(Request::get(uri).body(Body::empty()).unwrap());
+
+        let index = get("/admin/ui").await.unwrap();
+        assert_eq!(index.status(), StatusCode::OK);
+        assert_eq!(index.headers()[header::CONTENT_TYPE], "text/html");
+
+        let script = get("/admin/ui/app.js").await.unwrap();
+        assert_eq!(script.status(), StatusCode::OK);
+        assert_eq!(script.headers()[header::CONTENT_TYPE], "text/javascript");
+
+        let missing = get("/admin/ui/nope.js").await.unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,51 +1,907 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::get,
-    Json, Router,
+    extract::{FromRequestParts, OriginalUri, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Json, Router,
 };
+use chrono::{DateTime, Utc};
+use std::convert::Infallible;
 use std::error::Error;
-use tracing::info;
+use std::sync::Arc;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info_span, Span};
 
-use crate::models::{Book, NewBook};
+use crate::access_log::access_log;
+use crate::admin::{admin_router, maintenance_mode};
+use crate::api_version::{api_version, negotiate_version, ApiVersion};
+use crate::body_limit::BodyLimits;
+use crate::cache_control::CacheTtls;
+use crate::client_ip::{resolve_client_ip, ClientIp, TrustedProxies};
+use crate::compression::CompressionConfig;
+use crate::conditional::http_date;
+use crate::config::RuntimeConfig;
+use crate::cors::CorsConfig;
+use crate::deprecation::{deprecated, UNVERSIONED_ALIASES};
+use crate::events::{BookChange, ChangeFeed, RevisionDiff};
+use crate::fallback::with_fallbacks;
+use crate::filter::{Filter, TextField};
+use crate::flags::{flags_router, FeatureFlags};
+use crate::isbn::IsbnCheck;
+use crate::json_error::json_errors;
+use crate::json_stream::json_array;
+use crate::load_shed::{shed_load, ConcurrencyLimit, ConcurrencyLimits};
+use crate::metrics::metrics;
+use crate::models::{Actor, Book, BookTranslation, NewBook, NewTranslation};
+use crate::page::decode_cursor;
+use crate::qr::{render_png, PublicUrl};
 use crate::repo::BookRepo;
+use crate::route_config::{BookRoute, RouteConfig};
+use crate::runtime_config::RuntimeConfigHandle;
+use crate::scheduler::JobMetrics;
+use crate::search::{self, SearchResult};
+use crate::service::{BookService, ServiceError};
+use crate::slow_log::SlowLogThresholds;
+use crate::sort::{Collation, SortError, SortSpec};
+use crate::stats::{Aggregation, BookField, CatalogStats, Metric};
+use crate::suggest::{self, Suggestion};
+use crate::timeout::RequestTimeouts;
+use crate::translations::{normalize_language, preferred_languages, LocalizedBook};
+use crate::usage::ApiUsage;
+use crate::version::version;
+use crate::views::{parse_window, TrendingBook, DEFAULT_LIMIT, MAX_LIMIT};
 
+mod v1;
+
+/// Settings for the middleware wrapped around the routes
+#[derive(Debug, Clone, Default)]
+pub struct MiddlewareConfig {
+    pub slow_log: SlowLogThresholds,
+    pub concurrency: ConcurrencyLimits,
+    pub timeouts: RequestTimeouts,
+    pub compression: CompressionConfig,
+    pub body_limits: BodyLimits,
+    /// Add CORS headers to responses, if set
+    pub cors: Option<CorsConfig>,
+    pub cache_ttls: CacheTtls,
+    /// Overrides of the settings above for individual routes
+    pub routes: RouteConfig,
+    pub trusted_proxies: TrustedProxies,
+}
+
+/// Checks a request to one of the `/books` or `/changes` routes before it is
+/// handled, returning the status and message to reject it with
+pub type AuthHook =
+    Arc<dyn Fn(BookRoute, &Request) -> Result<(), (StatusCode, String)> + Send + Sync>;
+
+/// Changes the response from one of the `/books` or `/changes` routes, e.g.
+/// to add headers, before it is sent
+pub type ResponseHook = Arc<dyn Fn(BookRoute, &mut Response) + Send + Sync>;
+
+/// How many changes `GET /changes`, books in a page of `GET /books`, or
+/// groups `GET /books/aggregate` returns when no `limit` is given, and the
+/// most it allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pagination {
+    pub default_limit: i64,
+    pub max_limit: i64,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination {
+            default_limit: 100,
+            max_limit: 1000,
+        }
+    }
+}
+
+/// What `build_api` serves, and how
 #[derive(Clone)]
-struct AppState<R> {
-    repo: R,
+pub struct ApiOptions {
+    /// Where every route is mounted, e.g. `/api/bookstore`. Empty to serve
+    /// them from the root.
+    pub base_path: String,
+    /// The `/books` and `/changes` routes to serve. The others are treated
+    /// as if they didn't exist.
+    pub endpoints: Vec<BookRoute>,
+    pub pagination: Pagination,
+    /// What the links in books' QR codes start with
+    pub public_url: PublicUrl,
+    /// Checked before any of the `endpoints`' own middleware
+    pub auth: Option<AuthHook>,
+    /// Run on every response from the `endpoints`, including rejections
+    pub decorate_response: Option<ResponseHook>,
+    /// Counts the requests to each of the `endpoints` by each client, for
+    /// `/admin/usage`
+    pub usage: Option<ApiUsage>,
+    /// Enables the `/admin` endpoints, including `admin_routes`, which
+    /// require this bearer token
+    pub admin_token: Option<String>,
+    pub admin_routes: Router,
+    pub runtime_config: RuntimeConfigHandle,
+    /// Toggled through `/admin/flags`, and available to handlers as an
+    /// `Extension`. If not set, flags are read from `runtime_config` and
+    /// toggles only last as long as the process.
+    pub flags: Option<FeatureFlags>,
+    /// The background jobs whose stats `/metrics` reports
+    pub jobs: JobMetrics,
+    pub middleware: MiddlewareConfig,
+}
+
+impl Default for ApiOptions {
+    fn default() -> Self {
+        ApiOptions {
+            base_path: String::new(),
+            endpoints: BookRoute::ALL.to_vec(),
+            pagination: Pagination::default(),
+            public_url: PublicUrl::default(),
+            auth: None,
+            decorate_response: None,
+            usage: None,
+            admin_token: None,
+            admin_routes: Router::new(),
+            runtime_config: RuntimeConfigHandle::new(RuntimeConfig::default(), None),
+            flags: None,
+            jobs: JobMetrics::default(),
+            middleware: MiddlewareConfig::default(),
+        }
+    }
 }
 
-pub fn build_api<E: Error + 'static>(
-    repo: impl BookRepo<E> + Send + Sync + Clone + 'static,
+/// The whole app: the versioned API, its unversioned aliases, the admin
+/// endpoints, `/metrics` and `/version`, with their middleware. It can be
+/// merged with other routes, or served as it is.
+pub fn build_api<E: Error + Send + 'static>(
+    books: BookService<impl BookRepo<E> + Send + Sync + Clone + 'static, E>,
+    options: ApiOptions,
 ) -> Router {
-    Router::new()
-        .route("/books", get(list_books).post(insert_book))
+    let ApiOptions {
+        base_path,
+        endpoints,
+        pagination,
+        public_url,
+        auth,
+        decorate_response,
+        usage,
+        admin_token,
+        admin_routes,
+        runtime_config,
+        flags,
+        jobs,
+        middleware: middleware_config,
+    } = options;
+    let flags = flags.unwrap_or_else(|| FeatureFlags::new(runtime_config.clone()));
+
+    // Merging books is unversioned, alongside the other admin endpoints
+    let admin_routes = admin_routes
         .route(
-            "/books/{id}",
-            get(get_book).put(update_book).delete(delete_book),
+            "/admin/books/{keep}/merge/{duplicate}",
+            post(merge_books).with_state(books.clone()),
+        )
+        .merge(flags_router(flags.clone()));
+    let v1 = v1::routes(
+        books,
+        &middleware_config,
+        v1::RouteOptions {
+            admin_token: admin_token.as_deref(),
+            endpoints: &endpoints,
+            pagination,
+            public_url,
+            auth,
+            decorate_response,
+            usage,
+        },
+    );
+    let MiddlewareConfig {
+        slow_log,
+        concurrency,
+        compression,
+        cors,
+        trusted_proxies,
+        ..
+    } = middleware_config;
+
+    let mut router = Router::new()
+        .nest(
+            ApiVersion::V1.prefix(),
+            v1.clone()
+                .route_layer(middleware::from_fn_with_state(ApiVersion::V1, api_version)),
         )
-        .with_state(AppState { repo })
+        // Unversioned aliases, kept until clients have moved to /v1
+        .merge(
+            v1.route_layer(middleware::from_fn(negotiate_version))
+                .route_layer(middleware::from_fn_with_state(
+                    UNVERSIONED_ALIASES,
+                    deprecated,
+                )),
+        );
+    if let Some(limit) = concurrency.global {
+        // The routes share one limit, because clones of it share their permits
+        router = router.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+    }
+
+    let mut router = router
+        .route_layer(middleware::from_fn_with_state(
+            runtime_config.clone(),
+            maintenance_mode,
+        ))
+        .route("/metrics", get(metrics).with_state(jobs))
+        .route("/version", get(version));
+
+    if let Some(admin_token) = admin_token {
+        router = router.merge(admin_router(runtime_config, admin_token, admin_routes));
+    }
+
+    let base_path = base_path.trim_end_matches('/');
+    if !base_path.is_empty() {
+        router = Router::new().nest(base_path, router);
+    }
+
+    // Set after every route has been added, as the method fallback is only
+    // applied to the routes the router has so far
+    let mut router = with_fallbacks(router);
+
+    // CORS is outside the other /books middleware, so that preflight
+    // requests are answered even in maintenance mode or when overloaded
+    if let Some(cors) = cors {
+        router = router.layer(cors.layer());
+    }
+
+    // The access log is outside compression, so it sees the compressed
+    // response, whose size isn't known until it has been streamed
+    // Outside every route and fallback, so that all their errors have the
+    // same JSON body
+    router
+        .layer(middleware::from_fn(json_errors))
+        .layer(Extension(flags))
+        .layer(compression.layer())
+        .layer(middleware::from_fn_with_state(slow_log, access_log))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(trusted_proxies),
+            resolve_client_ip,
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
 
+/// Build the tracing span for a request, so that every log line emitted while
+/// handling it carries the request ID
+fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+    )
+}
+
+#[derive(Default, serde::Deserialize)]
+struct ListBooksParams {
+    /// Only list the books matching this, e.g. `author=="Kazuo Ishiguro";id>100`
+    filter: Option<String>,
+    /// Sort the books by these fields, e.g. `name,-author`
+    sort: Option<String>,
+    /// Sort text by the rules of this language, e.g. `de`
+    collation: Option<String>,
+    /// Only list the books with this in their name, ignoring case and
+    /// diacritics
+    name: Option<String>,
+    /// Only list the books with this in their author, ignoring case and
+    /// diacritics
+    author: Option<String>,
+    /// Get a page of the books with IDs after this
+    after_id: Option<i32>,
+    /// Get the page of books after an earlier one, from its `next_cursor`
+    cursor: Option<String>,
+    /// Get a page of this many books
+    limit: Option<i64>,
+}
+
+/// Streamed, so that the books needn't all be in memory at once, unless a
+/// page is asked for with `after_id`, `cursor` or `limit`. Pages link to the
+/// next one, under wherever the API is mounted, as for the change feed.
 async fn list_books<E, R>(
-    State(state): State<AppState<R>>,
-) -> Result<Json<Vec<Book>>, (StatusCode, String)>
+    State(books): State<BookService<R, E>>,
+    Extension(pagination): Extension<Pagination>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<ListBooksParams>,
+) -> Result<Response, (StatusCode, String)>
+where
+    E: Error + Send + 'static,
+    R: BookRepo<E> + Clone + Send + Sync + 'static,
+{
+    let filter = params
+        .filter
+        .as_deref()
+        .map(Filter::parse)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let searches = [
+        (TextField::Name, &params.name),
+        (TextField::Author, &params.author),
+    ]
+    .into_iter()
+    .filter_map(|(field, text)| {
+        let text = text
+            .as_deref()
+            .map(str::trim)
+            .filter(|text| !text.is_empty())?;
+        Some(Filter::contains(field, text))
+    });
+    let filter = Filter::all(filter.into_iter().chain(searches).collect());
+
+    let sort = match (params.sort.as_deref(), params.collation.as_deref()) {
+        (None, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "A collation can only be given with a sort".to_string(),
+            ))
+        }
+        (sort, collation) => sort
+            .map(|sort| {
+                let sort = SortSpec::parse(sort)?;
+                match collation {
+                    Some(collation) => Ok(sort.with_collation(Collation::parse(collation)?)),
+                    None => Ok(sort),
+                }
+            })
+            .transpose()
+            .map_err(|e: SortError| (StatusCode::BAD_REQUEST, e.to_string()))?,
+    };
+
+    if params.after_id.is_none() && params.cursor.is_none() && params.limit.is_none() {
+        let results = books
+            .stream_books(filter, sort.unwrap_or_default())
+            .await
+            .map_err(error_response)?;
+        return Ok(json_array(results));
+    }
+    if sort.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Pages are in order of ID, so can't be sorted".to_string(),
+        ));
+    }
+
+    let after_id = match (params.after_id, &params.cursor) {
+        (Some(_), Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Give either after_id or cursor, not both".to_string(),
+            ))
+        }
+        (None, Some(cursor)) => Some(
+            decode_cursor(cursor)
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Invalid cursor: {cursor}")))?,
+        ),
+        (after_id, None) => after_id,
+    };
+    let limit = params.limit.unwrap_or(pagination.default_limit);
+    if !(1..=pagination.max_limit).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {}", pagination.max_limit),
+        ));
+    }
+
+    let page = books
+        .list_books_after(after_id, limit, filter.as_ref())
+        .await
+        .map_err(error_response)?;
+
+    let mut headers = HeaderMap::new();
+    if let Some(next_cursor) = &page.next_cursor {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("cursor", next_cursor);
+        if let Some(limit) = params.limit {
+            query.append_pair("limit", &limit.to_string());
+        }
+        let kept = [
+            ("filter", &params.filter),
+            ("name", &params.name),
+            ("author", &params.author),
+        ];
+        for (name, value) in kept {
+            if let Some(value) = value {
+                query.append_pair(name, value);
+            }
+        }
+        let next = format!("<{}?{}>; rel=\"next\"", uri.path(), query.finish());
+        let next = HeaderValue::try_from(next).expect("a path is a valid header value");
+        headers.insert(header::LINK, next);
+    }
+
+    Ok((headers, Json(page)).into_response())
+}
+
+#[derive(Default, serde::Deserialize)]
+struct GetBookParams {
+    /// Get the book as it was at this time, e.g. `2026-10-13T09:00:00Z`
+    as_of: Option<DateTime<Utc>>,
+    /// Get the book in this language, e.g. `fr`, instead of those in the
+    /// `Accept-Language` header
+    lang: Option<String>,
+}
+
+async fn get_book<E, R>(
+    State(books): State<BookService<R, E>>,
+    Path(id): Path<String>,
+    Query(params): Query<GetBookParams>,
+    headers: HeaderMap,
+) -> Result<(HeaderMap, Json<LocalizedBook>), (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+    let preferred = preferred_languages_for(params.lang, &headers)?;
+
+    let book = match params.as_of {
+        Some(at) => books.get_book_as_of(id, at).await,
+        None => {
+            let book = books.get_book(id).await;
+            // Looking at the past isn't viewing the book
+            if book.is_ok() {
+                books.record_view(id);
+            }
+            book
+        }
+    }
+    .map_err(error_response)?;
+    let book = books
+        .localize(book, &preferred)
+        .await
+        .map_err(error_response)?;
+
+    Ok((localized_headers(&book), Json(book)))
+}
+
+/// A slug the book has since lost to a rename or merge redirects to its
+/// current one, under wherever the API is mounted
+async fn get_book_by_slug<E, R>(
+    State(books): State<BookService<R, E>>,
+    OriginalUri(uri): OriginalUri,
+    Path(slug): Path<String>,
+    Query(params): Query<GetBookParams>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let preferred = preferred_languages_for(params.lang, &headers)?;
+    let book = books
+        .get_book_by_slug(&slug)
+        .await
+        .map_err(error_response)?;
+
+    if book.slug != slug {
+        let path = uri.path();
+        // The path ends with the slug, which is URL-friendly, as it was found
+        let prefix = path.strip_suffix(slug.as_str()).unwrap_or(path);
+        // Keep the query, which may ask for a language
+        let query = uri
+            .query()
+            .map(|query| format!("?{query}"))
+            .unwrap_or_default();
+        return Ok(Redirect::permanent(&format!("{prefix}{}{query}", book.slug)).into_response());
+    }
+    books.record_view(book.id);
+    let book = books
+        .localize(book, &preferred)
+        .await
+        .map_err(error_response)?;
+
+    Ok((localized_headers(&book), Json(book)).into_response())
+}
+
+/// The languages to look for a book's translation in: that given by `?lang=`
+/// if any, or else those in the `Accept-Language` header
+fn preferred_languages_for(
+    lang: Option<String>,
+    headers: &HeaderMap,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    match lang {
+        Some(lang) => normalize_language(&lang).map(|lang| vec![lang]).ok_or((
+            StatusCode::BAD_REQUEST,
+            format!("Not a language tag: {lang}"),
+        )),
+        None => Ok(headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(preferred_languages)
+            .unwrap_or_default()),
+    }
+}
+
+/// The response may differ by `Accept-Language`, so caches must key on it,
+/// and is last modified when either the book or its translation was
+fn localized_headers(book: &LocalizedBook) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::LAST_MODIFIED, http_date(book.last_modified()));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-language"));
+    if let Some(language) = book
+        .language
+        .as_deref()
+        .and_then(|language| HeaderValue::from_str(language).ok())
+    {
+        headers.insert(header::CONTENT_LANGUAGE, language);
+    }
+    headers
+}
+
+/// A QR code for a shelf label, linking to the book by its slug, which keeps
+/// working after the book is renamed
+async fn book_qr_code<E, R>(
+    State(books): State<BookService<R, E>>,
+    Extension(public_url): Extension<PublicUrl>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let book = books.get_book(id).await.map_err(error_response)?;
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or(uri.authority().map(|authority| authority.as_str()));
+    let origin = public_url.origin(host).ok_or((
+        StatusCode::BAD_REQUEST,
+        "The request has no Host to link to the book under".to_string(),
+    ))?;
+    // Under wherever the API is mounted, as for slug redirects
+    let path = uri.path();
+    let prefix = path
+        .strip_suffix(&format!("/books/{id}/qr.png"))
+        .unwrap_or_default();
+    let link = format!("{origin}{prefix}/books/by-slug/{}", book.slug);
+
+    let png = render_png(&link).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let last_modified = http_date(book.updated_at);
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+            (header::LAST_MODIFIED, last_modified),
+        ],
+        png,
+    )
+        .into_response())
+}
+
+async fn book_history<E, R>(
+    State(books): State<BookService<R, E>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<BookChange>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let history = books.book_history(id).await.map_err(error_response)?;
+
+    Ok(Json(history))
+}
+
+async fn diff_revisions<E, R>(
+    State(books): State<BookService<R, E>>,
+    Path((id, from, to)): Path<(String, i32, i32)>,
+) -> Result<Json<RevisionDiff>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let diff = books
+        .diff_revisions(id, from, to)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(diff))
+}
+
+#[derive(Default, serde::Deserialize)]
+struct ChangesParams {
+    /// A cursor from an earlier response
+    since: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Also links to the next page, under wherever the API is mounted, so clients
+/// behind a gateway can follow it as it is
+async fn list_changes<E, R>(
+    State(books): State<BookService<R, E>>,
+    Extension(pagination): Extension<Pagination>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<ChangesParams>,
+) -> Result<([(HeaderName, HeaderValue); 1], Json<ChangeFeed>), (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let since = params
+        .since
+        .map(|cursor| {
+            cursor
+                .parse::<i64>()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid cursor: {cursor}")))
+        })
+        .transpose()?;
+    let limit = params.limit.unwrap_or(pagination.default_limit);
+    if !(1..=pagination.max_limit).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {}", pagination.max_limit),
+        ));
+    }
+
+    let feed = books.changes(since, limit).await.map_err(error_response)?;
+
+    let limit_param = params
+        .limit
+        .map_or(String::new(), |limit| format!("&limit={limit}"));
+    let next = format!(
+        "<{}?since={}{limit_param}>; rel=\"next\"",
+        uri.path(),
+        feed.next_cursor
+    );
+    let next = HeaderValue::try_from(next).expect("a path is a valid header value");
+
+    Ok(([(header::LINK, next)], Json(feed)))
+}
+
+#[derive(serde::Deserialize)]
+struct AggregateParams {
+    group_by: BookField,
+    #[serde(default)]
+    metric: Metric,
+    limit: Option<i64>,
+}
+
+async fn aggregate_books<E, R>(
+    State(books): State<BookService<R, E>>,
+    Extension(pagination): Extension<Pagination>,
+    Query(params): Query<AggregateParams>,
+) -> Result<Json<Aggregation>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let limit = params.limit.unwrap_or(pagination.default_limit);
+    if !(1..=pagination.max_limit).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {}", pagination.max_limit),
+        ));
+    }
+
+    let aggregation = books
+        .aggregate_books(params.group_by, params.metric, limit)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(aggregation))
+}
+
+#[derive(serde::Deserialize)]
+struct TrendingParams {
+    /// How far back views count from, e.g. `24h` or `7d`
+    window: Option<String>,
+    limit: Option<i64>,
+}
+
+async fn trending_books<E, R>(
+    State(books): State<BookService<R, E>>,
+    Query(params): Query<TrendingParams>,
+) -> Result<Json<Vec<TrendingBook>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let window = params.window.as_deref().unwrap_or("24h");
+    let window = parse_window(window).ok_or((
+        StatusCode::BAD_REQUEST,
+        "The window must be a number of hours or days, like 24h or 7d, of at most 30d".to_string(),
+    ))?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let trending = books
+        .trending_books(window, limit)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(trending))
+}
+
+async fn catalog_stats<E, R>(
+    State(books): State<BookService<R, E>>,
+) -> Result<Json<CatalogStats>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let stats = books.catalog_stats().await.map_err(error_response)?;
+
+    Ok(Json(stats))
+}
+
+async fn enrich_book<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    Path(id): Path<String>,
+    actor: Actor,
+) -> Result<Json<Book>, (StatusCode, String)>
 where
     E: Error,
-    R: BookRepo<E> + Send + Sync + Clone,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let book = books
+        .enrich_book(id, &actor)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(book))
+}
+
+#[derive(serde::Deserialize)]
+struct SuggestParams {
+    /// What has been typed so far
+    q: String,
+    limit: Option<i64>,
+}
+
+async fn suggest_books<E, R>(
+    State(books): State<BookService<R, E>>,
+    Query(params): Query<SuggestParams>,
+) -> Result<Json<Vec<Suggestion>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let limit = params.limit.unwrap_or(suggest::DEFAULT_LIMIT);
+    if !(1..=suggest::MAX_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {}", suggest::MAX_LIMIT),
+        ));
+    }
+
+    let suggestions = books
+        .suggest(&params.q, limit)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(suggestions))
+}
+
+#[derive(serde::Deserialize)]
+struct SearchParams {
+    /// The words to search for
+    q: String,
+    limit: Option<i64>,
+}
+
+async fn search_books<E, R>(
+    State(books): State<BookService<R, E>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
 {
-    // TODO pagination
-    let results = state.repo.list_books().await.map_err(internal_error)?;
+    let limit = params.limit.unwrap_or(search::DEFAULT_LIMIT);
+    if !(1..=search::MAX_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The limit must be between 1 and {}", search::MAX_LIMIT),
+        ));
+    }
+    if params.q.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The search must have at least one word".to_string(),
+        ));
+    }
+    if params.q.chars().count() > search::MAX_QUERY_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "The search must be at most {} characters",
+                search::MAX_QUERY_LENGTH
+            ),
+        ));
+    }
 
-    info!("Retrieved {} books from the DB", results.len());
+    let results = books
+        .search_books(&params.q, limit)
+        .await
+        .map_err(error_response)?;
 
     Ok(Json(results))
 }
 
-async fn get_book<E, R>(
-    State(state): State<AppState<R>>,
+/// A different book each time, so it is never cached
+async fn random_book<E, R>(
+    State(books): State<BookService<R, E>>,
+) -> Result<([(HeaderName, HeaderValue); 1], Json<Book>), (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let book = books
+        .random_book()
+        .await
+        .map_err(error_response)?
+        .ok_or((StatusCode::NOT_FOUND, "There are no books".to_string()))?;
+
+    Ok((
+        [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+        Json(book),
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct IsbnToolRequest {
+    /// An ISBN-10 or ISBN-13, with or without hyphens, or a scanned EAN-13
+    isbn: String,
+}
+
+/// An invalid ISBN is still a 200, with the problem explained
+async fn check_isbn<E, R>(
+    State(books): State<BookService<R, E>>,
+    Json(request): Json<IsbnToolRequest>,
+) -> Result<Json<IsbnCheck>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let check = books
+        .check_isbn(&request.isbn)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(check))
+}
+
+#[derive(serde::Deserialize)]
+struct RevertParams {
+    /// The revision to restore
+    revision: i32,
+}
+
+async fn revert_book<E, R>(
+    State(mut books): State<BookService<R, E>>,
     Path(id): Path<String>,
+    Query(params): Query<RevertParams>,
+    actor: Actor,
 ) -> Result<Json<Book>, (StatusCode, String)>
 where
     E: Error,
@@ -53,45 +909,82 @@ where
 {
     let id = parse_book_id(id)?;
 
-    let book = state.repo.get_book(id).await.map_err(internal_error)?;
+    let book = books
+        .revert_book(id, params.revision, &actor)
+        .await
+        .map_err(error_response)?;
 
-    match book {
-        Some(book) => {
-            info!("Retrieved book from DB: {:?}", book);
-            Ok(Json(book))
-        }
-        None => {
-            info!("No book found in DB with ID: {}", id);
-            Err((
-                StatusCode::NOT_FOUND,
-                format!("No book found with ID: {}", id),
-            ))
-        }
+    Ok(Json(book))
+}
+
+async fn merge_books<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    Path((keep, duplicate)): Path<(String, String)>,
+    actor: Actor,
+) -> Result<Json<Book>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let keep = parse_book_id(keep)?;
+    let duplicate = parse_book_id(duplicate)?;
+
+    let book = books
+        .merge_books(keep, duplicate, &actor)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(book))
+}
+
+/// Changes made through the API are attributed to the client's IP address
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        Ok(Actor(ip.map(|ip| ip.to_string())))
     }
 }
 
 async fn insert_book<E, R>(
-    State(mut state): State<AppState<R>>,
+    State(mut books): State<BookService<R, E>>,
+    actor: Actor,
     Json(new_book): Json<NewBook>,
 ) -> Result<Json<Book>, (StatusCode, String)>
 where
     E: Error,
     R: BookRepo<E>,
 {
-    let inserted_book = state
-        .repo
-        .insert_book(new_book)
+    let inserted_book = books
+        .insert_book(new_book, &actor)
         .await
-        .map_err(internal_error)?;
-
-    info!("Inserted book into the DB: {:?}", inserted_book);
+        .map_err(error_response)?;
 
     Ok(Json(inserted_book))
 }
 
+async fn insert_books<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    actor: Actor,
+    Json(new_books): Json<Vec<NewBook>>,
+) -> Result<Json<Vec<Book>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let inserted_books = books
+        .insert_books(new_books, &actor)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(inserted_books))
+}
+
 async fn update_book<E, R>(
-    State(mut state): State<AppState<R>>,
+    State(mut books): State<BookService<R, E>>,
     Path(id): Path<String>,
+    actor: Actor,
     Json(new_book): Json<NewBook>,
 ) -> Result<Json<Book>, (StatusCode, String)>
 where
@@ -100,65 +993,129 @@ where
 {
     let id = parse_book_id(id)?;
 
-    let updated_book = state
-        .repo
-        .update_book(id, new_book)
+    let updated_book = books
+        .update_book(id, new_book, &actor)
         .await
-        .map_err(internal_error)?;
+        .map_err(error_response)?;
 
-    match updated_book {
-        Some(book) => {
-            info!("Updated book in DB: {:?}", book);
-            Ok(Json(book))
-        }
-        None => {
-            info!("Tried to update non-existent book with ID: {}", id);
-            Err((
-                StatusCode::NOT_FOUND,
-                format!("No book found with ID: {}", id),
-            ))
-        }
-    }
+    Ok(Json(updated_book))
 }
 
-async fn delete_book<E, R>(State(state): State<AppState<R>>, Path(id): Path<String>) -> Response
+async fn delete_book<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    Path(id): Path<String>,
+    actor: Actor,
+) -> Result<StatusCode, (StatusCode, String)>
 where
     E: Error,
     R: BookRepo<E>,
 {
-    let deleted_or_error = try_to_delete_book(state.repo, id.clone()).await;
+    let id = parse_book_id(id)?;
 
-    match deleted_or_error {
-        Ok(true) => {
-            info!("Deleted book from DB with ID: {}", id);
-            StatusCode::NO_CONTENT.into_response()
-        }
-        Ok(false) => {
-            info!("Tried to delete non-existent book with ID: {}", id);
-            (
-                StatusCode::NOT_FOUND,
-                format!("No book found with ID: {}", id),
-            )
-                .into_response()
-        }
-        Err(error_response) => error_response.into_response(),
-    }
+    books
+        .delete_book(id, &actor)
+        .await
+        .map_err(error_response)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_translations<E, R>(
+    State(books): State<BookService<R, E>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<BookTranslation>>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let translations = books.list_translations(id).await.map_err(error_response)?;
+
+    Ok(Json(translations))
+}
+
+async fn put_translation<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    Path((id, language)): Path<(String, String)>,
+    Json(translation): Json<NewTranslation>,
+) -> Result<Json<BookTranslation>, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
+    let id = parse_book_id(id)?;
+
+    let translation = books
+        .put_translation(id, &language, translation)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Json(translation))
 }
 
-async fn try_to_delete_book<E: Error>(
-    mut repo: impl BookRepo<E>,
-    id: String,
-) -> Result<bool, (StatusCode, String)> {
+async fn delete_translation<E, R>(
+    State(mut books): State<BookService<R, E>>,
+    Path((id, language)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)>
+where
+    E: Error,
+    R: BookRepo<E>,
+{
     let id = parse_book_id(id)?;
-    repo.delete_book(id).await.map_err(internal_error)
+
+    books
+        .delete_translation(id, &language)
+        .await
+        .map_err(error_response)?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
-/// Build a 500 response for an error
-fn internal_error<E>(err: E) -> (StatusCode, String)
+/// Map a failed catalog operation to a 4xx, or a 5xx if the repo or a
+/// metadata provider failed
+fn error_response<E>(err: ServiceError<E>) -> (StatusCode, String)
 where
     E: Error,
 {
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    match err {
+        ServiceError::NotFound(_)
+        | ServiceError::SlugNotFound(_)
+        | ServiceError::RevisionNotFound { .. }
+        | ServiceError::TranslationNotFound { .. } => (StatusCode::NOT_FOUND, err.to_string()),
+        ServiceError::Invalid(_)
+        | ServiceError::NoIsbn(_)
+        | ServiceError::RevisionIsDeletion { .. }
+        | ServiceError::Rejected(_) => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+        ServiceError::Duplicate(_) | ServiceError::DuplicateInBatch { .. } => {
+            (StatusCode::CONFLICT, err.to_string())
+        }
+        ServiceError::Enrichment(_) => (StatusCode::BAD_GATEWAY, err.to_string()),
+        ServiceError::Repo(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Middleware running the `AuthHook` for the route
+async fn authorize(
+    State((auth, route)): State<(AuthHook, BookRoute)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match auth(route, &request) {
+        Ok(()) => next.run(request).await,
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Middleware running the `ResponseHook` for the route
+async fn decorate(
+    State((decorate_response, route)): State<(ResponseHook, BookRoute)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    decorate_response(route, &mut response);
+    response
 }
 
 fn parse_book_id(id: String) -> Result<i32, (StatusCode, String)> {
@@ -172,7 +1129,19 @@ mod tests {
     use std::fmt::Display;
     use std::sync::{Arc, Mutex};
 
+    use axum::body::Body;
+    use axum::http::Uri;
+    use chrono::Utc;
+    use futures::channel::mpsc;
+    use futures::SinkExt;
+    use tower::ServiceExt;
+
     use super::*;
+    use crate::events::{BookChange, BookCreated, BookDeleted, BookEvent, EventBus};
+    use crate::page::BookPage;
+    use crate::route_config::RouteSettings;
+    use crate::service::DuplicatePolicy;
+    use crate::stats::{BookField, Group, Metric};
 
     #[derive(Debug)]
     struct MockError {}
@@ -191,13 +1160,58 @@ mod tests {
         raise_errors: bool,
     }
 
-    impl BookRepo<MockError> for MockBookRepo {
-        async fn list_books(&self) -> Result<Vec<Book>, MockError> {
-            if self.raise_errors {
-                Err(MockError {})
-            } else {
-                let db = self.db.lock().unwrap();
-                Ok(db.values().cloned().collect())
+    impl BookRepo<MockError> for MockBookRepo {
+        async fn list_books(&self) -> Result<Vec<Book>, MockError> {
+            if self.raise_errors {
+                Err(MockError {})
+            } else {
+                let db = self.db.lock().unwrap();
+                Ok(db.values().cloned().collect())
+            }
+        }
+
+        async fn filter_books(&self, _filter: &Filter) -> Result<Vec<Book>, MockError> {
+            todo!()
+        }
+
+        async fn list_books_after(
+            &self,
+            after_id: i32,
+            limit: i64,
+            filter: Option<&Filter>,
+        ) -> Result<Vec<Book>, MockError> {
+            let mut books = self.list_books().await?;
+            books.retain(|book| {
+                book.id > after_id && filter.is_none_or(|filter| filter.matches(book))
+            });
+            books.sort_by_key(|book| book.id);
+            books.truncate(limit as usize);
+            Ok(books)
+        }
+
+        async fn stream_books(
+            &self,
+            filter: Option<&Filter>,
+            sort: &SortSpec,
+            mut sender: mpsc::Sender<Result<Book, MockError>>,
+        ) {
+            let books = self.list_books().await.map(|books| {
+                let mut books = books
+                    .into_iter()
+                    .filter(|book| filter.is_none_or(|filter| filter.matches(book)))
+                    .collect::<Vec<_>>();
+                books.sort_by(|a, b| sort.compare(a, b));
+                books
+            });
+            match books {
+                Ok(books) => {
+                    for book in books {
+                        let _ = sender.send(Ok(book)).await;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                }
             }
         }
 
@@ -210,7 +1224,15 @@ mod tests {
             }
         }
 
-        async fn insert_book(&mut self, new_book: NewBook) -> Result<Book, MockError> {
+        async fn get_book_by_slug(&self, _slug: &str) -> Result<Option<Book>, MockError> {
+            todo!()
+        }
+
+        async fn insert_book(
+            &mut self,
+            new_book: NewBook,
+            _actor: &Actor,
+        ) -> Result<Book, MockError> {
             if self.raise_errors {
                 Err(MockError {})
             } else {
@@ -220,21 +1242,188 @@ mod tests {
                     id: fresh_id,
                     name: new_book.name,
                     author: new_book.author,
+                    updated_at: Utc::now(),
+                    isbn: new_book.isbn,
+                    publisher: new_book.publisher,
+                    cover_url: new_book.cover_url,
+                    slug: String::new(),
                 };
                 db.insert(fresh_id, book.clone());
                 Ok(book)
             }
         }
 
+        async fn insert_books(
+            &mut self,
+            _new_books: Vec<NewBook>,
+            _actor: &Actor,
+        ) -> Result<Vec<Book>, MockError> {
+            todo!()
+        }
+
         async fn update_book(
             &mut self,
             _id: i32,
             _new_book: NewBook,
+            _actor: &Actor,
         ) -> Result<Option<Book>, MockError> {
             todo!()
         }
 
-        async fn delete_book(&mut self, _id: i32) -> Result<bool, MockError> {
+        async fn delete_book(&mut self, id: i32, _actor: &Actor) -> Result<bool, MockError> {
+            if self.raise_errors {
+                Err(MockError {})
+            } else {
+                Ok(self.db.lock().unwrap().remove(&id).is_some())
+            }
+        }
+
+        async fn list_translations(&self, _id: i32) -> Result<Vec<BookTranslation>, MockError> {
+            Ok(Vec::new())
+        }
+
+        async fn put_translation(
+            &mut self,
+            _id: i32,
+            _language: &str,
+            _translation: &NewTranslation,
+        ) -> Result<Option<BookTranslation>, MockError> {
+            todo!()
+        }
+
+        async fn delete_translation(
+            &mut self,
+            _id: i32,
+            _language: &str,
+        ) -> Result<bool, MockError> {
+            todo!()
+        }
+
+        async fn restore_book(
+            &mut self,
+            _id: i32,
+            _book: NewBook,
+            _restores_revision: i32,
+            _actor: &Actor,
+        ) -> Result<BookEvent, MockError> {
+            todo!()
+        }
+
+        async fn find_duplicates(&self, book: &NewBook) -> Result<Vec<Book>, MockError> {
+            let db = self.db.lock().unwrap();
+            Ok(db
+                .values()
+                .filter(|existing| {
+                    existing.name.eq_ignore_ascii_case(&book.name)
+                        && existing.author.eq_ignore_ascii_case(&book.author)
+                })
+                .cloned()
+                .collect())
+        }
+
+        async fn find_duplicates_of_any(&self, books: &[NewBook]) -> Result<Vec<Book>, MockError> {
+            let db = self.db.lock().unwrap();
+            Ok(db
+                .values()
+                .filter(|existing| {
+                    books.iter().any(|book| {
+                        existing.name.eq_ignore_ascii_case(&book.name)
+                            && existing.author.eq_ignore_ascii_case(&book.author)
+                    })
+                })
+                .cloned()
+                .collect())
+        }
+
+        async fn suggest(&self, _prefix: &str, _limit: i64) -> Result<Vec<Suggestion>, MockError> {
+            todo!()
+        }
+
+        async fn random_book(&self, _pick: f64) -> Result<Option<Book>, MockError> {
+            todo!()
+        }
+
+        async fn find_books_by_isbn(&self, isbns: &[String]) -> Result<Vec<Book>, MockError> {
+            let db = self.db.lock().unwrap();
+            let mut books: Vec<Book> = db
+                .values()
+                .filter(|book| book.isbn.as_ref().is_some_and(|isbn| isbns.contains(isbn)))
+                .cloned()
+                .collect();
+            books.sort_by_key(|book| book.id);
+            Ok(books)
+        }
+
+        async fn merge_books(
+            &mut self,
+            _keep: i32,
+            _duplicate: i32,
+            _merged: Option<NewBook>,
+            _actor: &Actor,
+        ) -> Result<Option<Vec<BookEvent>>, MockError> {
+            todo!()
+        }
+
+        async fn book_history(&self, _id: i32) -> Result<Vec<BookChange>, MockError> {
+            todo!()
+        }
+
+        async fn changes(
+            &self,
+            _after: i64,
+            _limit: i64,
+        ) -> Result<Vec<(i64, BookChange)>, MockError> {
+            Ok(vec![])
+        }
+
+        async fn search_books(
+            &self,
+            query: &str,
+            limit: i64,
+        ) -> Result<Vec<SearchResult>, MockError> {
+            let words = search::words(query);
+            let db = self.db.lock().unwrap();
+            let mut results: Vec<SearchResult> = db
+                .values()
+                .filter_map(|book| {
+                    let rank = search::rank(&words, book)?;
+                    Some(SearchResult {
+                        book: book.clone(),
+                        rank,
+                    })
+                })
+                .collect();
+            results.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(a.book.id.cmp(&b.book.id)));
+            results.truncate(limit as usize);
+            Ok(results)
+        }
+
+        async fn count_books(&self) -> Result<i64, MockError> {
+            Ok(self.db.lock().unwrap().len() as i64)
+        }
+
+        async fn aggregate_books(
+            &self,
+            _group_by: BookField,
+            _metric: Metric,
+            _limit: i64,
+        ) -> Result<Vec<Group>, MockError> {
+            todo!()
+        }
+
+        async fn record_views(
+            &mut self,
+            _views: &[(i32, i32)],
+            _hour: DateTime<Utc>,
+        ) -> Result<(), MockError> {
+            todo!()
+        }
+
+        async fn trending_books(
+            &self,
+            _since: DateTime<Utc>,
+            _limit: i64,
+        ) -> Result<Vec<TrendingBook>, MockError> {
             todo!()
         }
     }
@@ -253,6 +1442,11 @@ mod tests {
                 id: 10,
                 name: "TAOCP".to_string(),
                 author: "Donald Knuth".to_string(),
+                updated_at: Utc::now(),
+                isbn: None,
+                publisher: None,
+                cover_url: None,
+                slug: "taocp".to_string(),
             },
         );
         db.insert(
@@ -261,21 +1455,38 @@ mod tests {
                 id: 20,
                 name: "Manual of Ethics".to_string(),
                 author: "John Mackenzie".to_string(),
+                updated_at: Utc::now(),
+                isbn: None,
+                publisher: None,
+                cover_url: None,
+                slug: "manual-of-ethics".to_string(),
             },
         );
         Arc::new(Mutex::new(db))
     }
 
     #[tokio::test]
+    #[allow(clippy::unnecessary_sort_by)]
     async fn list_books_returns_list_of_books_in_an_unspecified_order() {
         let db = build_db();
         let repo = MockBookRepo {
             db: db.clone(),
             raise_errors: false,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
 
-        let Json(mut result) = list_books(state).await.unwrap();
+        let response = list_books(
+            state,
+            Extension(Pagination::default()),
+            OriginalUri(Uri::from_static("/v1/books")),
+            Query(ListBooksParams::default()),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut result: Vec<Book> = serde_json::from_slice(&body).unwrap();
         result.sort_by(|a, b| a.id.cmp(&b.id));
 
         let mut db_values = db.lock().unwrap().values().cloned().collect::<Vec<Book>>();
@@ -290,11 +1501,16 @@ mod tests {
             db: build_db(),
             raise_errors: true,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
 
-        let (status_code, _) = list_books(state)
-            .await
-            .expect_err("Expected a 500 response");
+        let (status_code, _) = list_books(
+            state,
+            Extension(Pagination::default()),
+            OriginalUri(Uri::from_static("/v1/books")),
+            Query(ListBooksParams::default()),
+        )
+        .await
+        .expect_err("Expected a 500 response");
 
         assert_eq!(status_code, 500);
     }
@@ -305,14 +1521,21 @@ mod tests {
             db: build_db(),
             raise_errors: false,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
         let path = Path("10".to_string());
 
-        let Json(result) = get_book(state, path).await.unwrap();
+        let (_, Json(result)) = get_book(
+            state,
+            path,
+            Query(GetBookParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
 
-        assert_eq!(result.id, 10);
-        assert_eq!(result.name, "TAOCP");
-        assert_eq!(result.author, "Donald Knuth");
+        assert_eq!(result.book.id, 10);
+        assert_eq!(result.book.name, "TAOCP");
+        assert_eq!(result.book.author, "Donald Knuth");
     }
 
     #[tokio::test]
@@ -321,12 +1544,17 @@ mod tests {
             db: build_db(),
             raise_errors: false,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
         let path = Path("99".to_string());
 
-        let (status_code, _) = get_book(state, path)
-            .await
-            .expect_err("Expected a 404 response");
+        let (status_code, _) = get_book(
+            state,
+            path,
+            Query(GetBookParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .expect_err("Expected a 404 response");
 
         assert_eq!(status_code, 404);
     }
@@ -337,12 +1565,17 @@ mod tests {
             db: build_db(),
             raise_errors: true,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
         let path = Path("99".to_string());
 
-        let (status_code, _) = get_book(state, path)
-            .await
-            .expect_err("Expected a 500 response");
+        let (status_code, _) = get_book(
+            state,
+            path,
+            Query(GetBookParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .expect_err("Expected a 500 response");
 
         assert_eq!(status_code, 500);
     }
@@ -354,14 +1587,17 @@ mod tests {
             db: db.clone(),
             raise_errors: false,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
         let new_book = NewBook {
             name: "Paradise Lost".to_string(),
             author: "John Milton".to_string(),
+            ..NewBook::default()
         };
         let new_book_json = Json(new_book.clone());
 
-        let Json(inserted_book) = insert_book(state, new_book_json).await.unwrap();
+        let Json(inserted_book) = insert_book(state, Actor::default(), new_book_json)
+            .await
+            .unwrap();
 
         assert_eq!(inserted_book.name, new_book.name);
         assert_eq!(inserted_book.author, new_book.author);
@@ -371,20 +1607,95 @@ mod tests {
         assert_eq!(updated_db.get(&inserted_book.id), Some(&inserted_book));
     }
 
+    #[tokio::test]
+    async fn insert_books_adds_none_of_the_books_if_any_is_invalid() {
+        let db = build_db();
+        let repo = MockBookRepo {
+            db: db.clone(),
+            raise_errors: false,
+        };
+        let books_before = db.lock().unwrap().len();
+        let new_books = vec![
+            NewBook {
+                name: "Paradise Lost".to_string(),
+                author: "John Milton".to_string(),
+                ..NewBook::default()
+            },
+            NewBook {
+                name: "Paradise Regained".to_string(),
+                ..NewBook::default()
+            },
+        ];
+
+        let (status_code, message) = insert_books(
+            State(BookService::new(repo)),
+            Actor::default(),
+            Json(new_books),
+        )
+        .await
+        .expect_err("Expected a 422 response");
+
+        assert_eq!(status_code, 422);
+        assert!(message.contains("books[1]"), "{message}");
+        assert_eq!(db.lock().unwrap().len(), books_before);
+    }
+
+    #[tokio::test]
+    async fn writes_publish_events() {
+        let repo = MockBookRepo {
+            db: build_db(),
+            raise_errors: false,
+        };
+        let events = EventBus::default();
+        let mut subscriber = events.subscribe();
+        let books = BookService::new(repo).with_events(events);
+        let new_book = NewBook {
+            name: "Paradise Lost".to_string(),
+            author: "John Milton".to_string(),
+            ..NewBook::default()
+        };
+
+        let Json(inserted_book) =
+            insert_book(State(books.clone()), Actor::default(), Json(new_book))
+                .await
+                .unwrap();
+        delete_book(
+            State(books),
+            Path(inserted_book.id.to_string()),
+            Actor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            BookEvent::BookCreated(BookCreated {
+                book: inserted_book.clone()
+            })
+        );
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            BookEvent::BookDeleted(BookDeleted {
+                id: inserted_book.id
+            })
+        );
+    }
+
     #[tokio::test]
     async fn insert_book_returns_a_500_response_if_repo_raises_an_error() {
         let repo = MockBookRepo {
             db: build_db(),
             raise_errors: true,
         };
-        let state = State(AppState { repo });
+        let state = State(BookService::new(repo));
         let new_book = NewBook {
             name: "Paradise Lost".to_string(),
             author: "John Milton".to_string(),
+            ..NewBook::default()
         };
         let new_book_json = Json(new_book.clone());
 
-        let (status_code, _) = insert_book(state, new_book_json)
+        let (status_code, _) = insert_book(state, Actor::default(), new_book_json)
             .await
             .expect_err("Expected a 500 response");
 
@@ -392,4 +1703,574 @@ mod tests {
     }
 
     // TODO skipped the tests for updating and deleting
+
+    #[tokio::test]
+    async fn route_settings_override_the_global_middleware_settings() {
+        let mut routes = RouteConfig::default();
+        routes.set(
+            BookRoute::InsertBook,
+            RouteSettings {
+                max_json_body_size: Some(64),
+                require_admin_token: true,
+                ..RouteSettings::default()
+            },
+        );
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions {
+                admin_token: Some("s3cret".to_string()),
+                middleware: MiddlewareConfig {
+                    routes,
+                    ..MiddlewareConfig::default()
+                },
+                ..ApiOptions::default()
+            },
+        );
+        let insert = |token: &str, name: &str| {
+            let body = serde_json::json!({"name": name, "author": "Anon"}).to_string();
+            Request::post("/v1/books")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(insert("s3cret", "Emma"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(insert("guess", "Emma"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let long_name = "a".repeat(100);
+        let response = router
+            .clone()
+            .oneshot(insert("s3cret", &long_name))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Other routes keep the global settings
+        let response = router
+            .oneshot(Request::get("/v1/books").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_rejected_only_if_the_policy_is_to_block_them() {
+        let repo = MockBookRepo {
+            db: build_db(),
+            raise_errors: false,
+        };
+        let duplicate = || NewBook {
+            name: "taocp".to_string(),
+            author: "DONALD KNUTH".to_string(),
+            ..NewBook::default()
+        };
+
+        let blocking = BookService::new(repo.clone()).with_duplicate_policy(DuplicatePolicy::Block);
+        let (status, message) = insert_book(State(blocking), Actor::default(), Json(duplicate()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "Book looks like a duplicate of: 10");
+
+        let warning = BookService::new(repo.clone());
+        let Json(inserted_book) = insert_book(State(warning), Actor::default(), Json(duplicate()))
+            .await
+            .unwrap();
+        assert_eq!(inserted_book.name, "taocp");
+    }
+
+    #[tokio::test]
+    async fn duplicates_within_a_bulk_insert_are_rejected_if_the_policy_is_to_block_them() {
+        let repo = MockBookRepo {
+            db: build_db(),
+            raise_errors: false,
+        };
+        let new_books = vec![
+            NewBook {
+                name: "Paradise Lost".to_string(),
+                author: "John Milton".to_string(),
+                ..NewBook::default()
+            },
+            NewBook {
+                name: "Areopagitica".to_string(),
+                author: "John Milton".to_string(),
+                isbn: Some("0140433937".to_string()),
+                ..NewBook::default()
+            },
+            NewBook {
+                name: "paradise lost!".to_string(),
+                author: "JOHN MILTON".to_string(),
+                ..NewBook::default()
+            },
+        ];
+
+        let blocking = BookService::new(repo.clone()).with_duplicate_policy(DuplicatePolicy::Block);
+        let (status, message) = insert_books(State(blocking), Actor::default(), Json(new_books))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "books[2] looks like a duplicate of books[0]");
+
+        let same_isbn = vec![
+            NewBook {
+                isbn: Some("0-14-043393-7".to_string()),
+                ..NewBook::default()
+            },
+            NewBook {
+                isbn: Some("0140433937".to_string()),
+                ..NewBook::default()
+            },
+        ];
+        let blocking = BookService::new(repo.clone()).with_duplicate_policy(DuplicatePolicy::Block);
+        let (status, message) = insert_books(State(blocking), Actor::default(), Json(same_isbn))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "books[1] looks like a duplicate of books[0]");
+
+        // Books already in the repo are looked for in one query
+        let existing = vec![
+            NewBook {
+                name: "Paradise Lost".to_string(),
+                author: "John Milton".to_string(),
+                ..NewBook::default()
+            },
+            NewBook {
+                name: "manual of ethics".to_string(),
+                author: "john mackenzie".to_string(),
+                ..NewBook::default()
+            },
+        ];
+        let blocking = BookService::new(repo).with_duplicate_policy(DuplicatePolicy::Block);
+        let (status, message) = insert_books(State(blocking), Actor::default(), Json(existing))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(message, "Book looks like a duplicate of: 20");
+    }
+
+    #[tokio::test]
+    async fn reverting_a_book_always_needs_the_admin_token() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions {
+                admin_token: Some("s3cret".to_string()),
+                ..ApiOptions::default()
+            },
+        );
+
+        let response = router
+            .oneshot(
+                Request::post("/v1/books/1/revert?revision=1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn search_books_finds_books_by_the_words_in_the_query() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions::default(),
+        );
+        let search = |query: &str| {
+            let request = Request::get(format!("/v1/books/search?{query}"))
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let response = search("q=ethics%20MANUAL&limit=5").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let results: Vec<SearchResult> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results.iter().map(|r| r.book.id).collect::<Vec<_>>(), [20]);
+        assert_eq!(results[0].rank, 1.0);
+
+        let too_long = format!("q={}", "a".repeat(search::MAX_QUERY_LENGTH + 1));
+        for query in [
+            "q=",
+            "q=%20%20",
+            "",
+            "q=knuth&limit=0",
+            "q=knuth&limit=101",
+            &too_long,
+        ] {
+            let response = search(query).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn list_changes_rejects_invalid_cursors_and_limits() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions::default(),
+        );
+
+        for query in ["since=abc", "limit=0", "limit=1001"] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get(format!("/v1/changes?{query}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn list_books_pages_through_the_books_in_order_of_id() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions::default(),
+        );
+        let get = |uri: String| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let link = response.headers().get(header::LINK).cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (link, serde_json::from_slice::<BookPage>(&body).unwrap())
+            }
+        };
+
+        let (link, first) = get("/v1/books?limit=1".to_string()).await;
+        assert_eq!(first.books.iter().map(|b| b.id).collect::<Vec<_>>(), [10]);
+        let cursor = first.next_cursor.unwrap();
+        assert_eq!(
+            link.unwrap(),
+            format!("</v1/books?cursor={cursor}&limit=1>; rel=\"next\"").as_str()
+        );
+        let (link, second) = get(format!("/v1/books?cursor={cursor}&limit=1")).await;
+        assert_eq!(second.books.iter().map(|b| b.id).collect::<Vec<_>>(), [20]);
+        assert_eq!((link, second.next_cursor), (None, None));
+
+        let (_, after) = get("/v1/books?after_id=10".to_string()).await;
+        assert_eq!(after.books, second.books);
+
+        for query in [
+            "after_id=1&cursor=abc",
+            "cursor=abc",
+            "limit=0",
+            "limit=1001",
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get(format!("/v1/books?{query}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn list_books_finds_books_by_name_and_author() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions::default(),
+        );
+        let ids = |query: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(
+                        Request::get(format!("/v1/books?{query}"))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let mut ids: Vec<i32> = serde_json::from_slice::<Vec<Book>>(&body)
+                    .unwrap()
+                    .iter()
+                    .map(|book| book.id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        assert_eq!(ids("name=taoc").await, [10]);
+        assert_eq!(ids("author=JOHN").await, [20]);
+        assert_eq!(ids("name=of&author=mackenzie").await, [20]);
+        assert_eq!(ids("name=of&author=knuth").await, [] as [i32; 0]);
+        assert_eq!(ids("name=&filter=id%3E10").await, [20]);
+        // Wildcards are only for `?filter=`
+        assert_eq!(ids("name=*").await, [] as [i32; 0]);
+        assert_eq!(ids("name=manual*ethics").await, [] as [i32; 0]);
+    }
+
+    #[tokio::test]
+    async fn list_books_sorts_by_the_fields_given() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions::default(),
+        );
+        let get = |query: &'static str| {
+            let router = router.clone();
+            async move {
+                router
+                    .oneshot(
+                        Request::get(format!("/v1/books?{query}"))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let ids = |query| async move {
+            let body = axum::body::to_bytes(get(query).await.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<Vec<Book>>(&body)
+                .unwrap()
+                .iter()
+                .map(|book| book.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("sort=-id").await, [20, 10]);
+        assert_eq!(ids("sort=author").await, [10, 20]);
+        assert_eq!(ids("sort=-author,name").await, [20, 10]);
+        assert_eq!(ids("sort=-name&collation=sv").await, [10, 20]);
+
+        for query in [
+            "sort=publication_year",
+            "sort=name,name",
+            "sort=",
+            "sort=name&limit=1",
+            "collation=de",
+            "sort=name&collation=klingon",
+        ] {
+            assert_eq!(
+                get(query).await.status(),
+                StatusCode::BAD_REQUEST,
+                "{query}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn options_choose_the_base_path_endpoints_and_authorization() {
+        let auth: AuthHook =
+            Arc::new(
+                |route, request| match (route, request.headers().contains_key("x-reader")) {
+                    (BookRoute::GetBook, true) => Ok(()),
+                    _ => Err((StatusCode::FORBIDDEN, "Readers only".to_string())),
+                },
+            );
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions {
+                base_path: "/api/bookstore/".to_string(),
+                endpoints: vec![BookRoute::GetBook, BookRoute::ListBooks],
+                auth: Some(auth),
+                ..ApiOptions::default()
+            },
+        );
+        let status = |request: Request| {
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        let get = |uri| Request::get(uri).header("x-reader", "yes");
+        let response = get("/api/bookstore/v1/books/10")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(response).await, StatusCode::OK);
+        let response = get("/v1/books/10").body(Body::empty()).unwrap();
+        assert_eq!(status(response).await, StatusCode::NOT_FOUND);
+        let response = Request::get("/api/bookstore/v1/books/10")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(response).await, StatusCode::FORBIDDEN);
+        let response = get("/api/bookstore/v1/books").body(Body::empty()).unwrap();
+        assert_eq!(status(response).await, StatusCode::FORBIDDEN);
+        // Disabled endpoints
+        let response = Request::delete("/api/bookstore/v1/books/10")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(response).await, StatusCode::METHOD_NOT_ALLOWED);
+        let response = get("/api/bookstore/v1/changes")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(response).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn responses_are_decorated_by_the_hook() {
+        let decorate_response: ResponseHook = Arc::new(|route, response| {
+            let route = HeaderValue::from_str(&format!("{route:?}")).unwrap();
+            response.headers_mut().insert("x-route", route);
+        });
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions {
+                decorate_response: Some(decorate_response),
+                ..ApiOptions::default()
+            },
+        );
+
+        for (uri, status) in [
+            ("/v1/books/10", StatusCode::OK),
+            ("/v1/books/99", StatusCode::NOT_FOUND),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()["x-route"], "GetBook");
+        }
+        let response = router
+            .oneshot(Request::get("/version").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("x-route"));
+    }
+
+    #[tokio::test]
+    async fn the_next_page_of_changes_is_linked_to_under_the_base_path() {
+        let router = build_api(
+            BookService::new(MockBookRepo {
+                db: build_db(),
+                raise_errors: false,
+            }),
+            ApiOptions {
+                base_path: "/api/bookstore".to_string(),
+                ..ApiOptions::default()
+            },
+        );
+
+        let response = router
+            .oneshot(
+                Request::get("/api/bookstore/v1/changes?since=42&limit=5")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LINK],
+            "</api/bookstore/v1/changes?since=42&limit=5>; rel=\"next\""
+        );
+    }
+
+    #[tokio::test]
+    async fn qr_codes_link_to_the_book_by_its_slug() {
+        let options = |public_url| ApiOptions {
+            base_path: "/api/bookstore".to_string(),
+            public_url: PublicUrl(public_url),
+            ..ApiOptions::default()
+        };
+        let repo = MockBookRepo {
+            db: build_db(),
+            raise_errors: false,
+        };
+        let public_url = url::Url::parse("https://books.example.com").unwrap();
+
+        for (public_url, uri, link) in [
+            (
+                Some(public_url),
+                "/api/bookstore/v1/books/10/qr.png",
+                "https://books.example.com/api/bookstore/v1/books/by-slug/taocp",
+            ),
+            (
+                None,
+                "/api/bookstore/books/10/qr.png",
+                "http://localhost:3000/api/bookstore/books/by-slug/taocp",
+            ),
+        ] {
+            let router = build_api(BookService::new(repo.clone()), options(public_url));
+            let response = router
+                .oneshot(
+                    Request::get(uri)
+                        .header(header::HOST, "localhost:3000")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+            let png = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(png, render_png(link).unwrap());
+        }
+
+        let router = build_api(BookService::new(repo), options(None));
+        let response = router
+            .oneshot(
+                Request::get("/api/bookstore/v1/books/99/qr.png")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
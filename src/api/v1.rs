@@ -0,0 +1,395 @@
+use std::error::Error;
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put, MethodRouter},
+    Extension, Router,
+};
+
+use super::{
+    aggregate_books, authorize, book_history, book_qr_code, catalog_stats, check_isbn, decorate,
+    delete_book, delete_translation, diff_revisions, enrich_book, get_book, get_book_by_slug,
+    insert_book, insert_books, list_books, list_changes, list_translations, put_translation,
+    random_book, revert_book, search_books, suggest_books, trending_books, update_book, AuthHook,
+    MiddlewareConfig, Pagination, ResponseHook,
+};
+use crate::admin::require_admin_token;
+use crate::body_limit::explain_body_limit;
+use crate::cache_control::{cache_control, cache_control_header};
+use crate::conditional::if_modified_since;
+use crate::content_type::require_json;
+use crate::load_shed::{shed_load, ConcurrencyLimit};
+use crate::qr::PublicUrl;
+use crate::repo::BookRepo;
+use crate::route_config::{BookRoute, RouteSettings};
+use crate::service::BookService;
+use crate::timeout::request_timeout;
+use crate::usage::{track_usage, ApiUsage};
+
+/// The largest JSON body `POST /books/bulk` accepts unless its own limit is
+/// configured, as it is many books rather than one
+const BULK_JSON_BODY_SIZE: usize = 1024 * 1024;
+
+/// What the routes need besides the middleware settings
+pub(super) struct RouteOptions<'a> {
+    pub admin_token: Option<&'a str>,
+    /// The routes to serve
+    pub endpoints: &'a [BookRoute],
+    pub pagination: Pagination,
+    pub public_url: PublicUrl,
+    pub auth: Option<AuthHook>,
+    pub decorate_response: Option<ResponseHook>,
+    pub usage: Option<ApiUsage>,
+}
+
+/// The `/books` and `/changes` routes of version 1 of the API, with their per-route
+/// middleware. Concurrency limits are shared between clones of the returned
+/// router.
+pub(super) fn routes<E, R>(
+    books: BookService<R, E>,
+    config: &MiddlewareConfig,
+    options: RouteOptions,
+) -> Router
+where
+    E: Error + Send + 'static,
+    R: BookRepo<E> + Send + Sync + Clone + 'static,
+{
+    let MiddlewareConfig {
+        concurrency,
+        timeouts,
+        body_limits,
+        cache_ttls,
+        routes,
+        ..
+    } = config;
+    let RouteOptions {
+        admin_token,
+        endpoints,
+        pagination,
+        public_url,
+        auth,
+        decorate_response,
+        usage,
+    } = options;
+
+    let timeout = |duration| middleware::from_fn_with_state(duration, request_timeout);
+    let cacheable = |ttl| middleware::from_fn_with_state(cache_control_header(ttl), cache_control);
+
+    // The middleware every route gets, outside any specific to the route. A
+    // route that isn't enabled matches no methods.
+    let common = |method_router: MethodRouter<_>, route, settings: RouteSettings| {
+        if !endpoints.contains(&route) {
+            return MethodRouter::new();
+        }
+        let json_limit = settings.max_json_body_size.unwrap_or(body_limits.json);
+        let mut method_router = method_router
+            .layer(DefaultBodyLimit::max(json_limit))
+            .route_layer(middleware::from_fn_with_state(
+                json_limit,
+                explain_body_limit,
+            ));
+        if let Some(limit) = settings.max_concurrent_requests {
+            method_router = method_router.route_layer(middleware::from_fn_with_state(
+                ConcurrencyLimit::new(limit),
+                shed_load,
+            ));
+        }
+        if settings.require_admin_token {
+            // With no admin token configured, the empty token is never accepted
+            method_router = method_router.route_layer(middleware::from_fn_with_state(
+                admin_token.unwrap_or_default().to_string(),
+                require_admin_token,
+            ));
+        }
+        if let Some(auth) = &auth {
+            method_router = method_router.route_layer(middleware::from_fn_with_state(
+                (auth.clone(), route),
+                authorize,
+            ));
+        }
+        // Outside the route's own middleware, so that rejections count too
+        if let Some(usage) = &usage {
+            method_router = method_router.route_layer(middleware::from_fn_with_state(
+                (usage.clone(), route),
+                track_usage,
+            ));
+        }
+        // Outermost, so that it sees every response
+        if let Some(decorate_response) = &decorate_response {
+            method_router = method_router.route_layer(middleware::from_fn_with_state(
+                (decorate_response.clone(), route),
+                decorate,
+            ));
+        }
+        method_router
+    };
+
+    let settings = routes.get(BookRoute::ListBooks);
+    let mut list_route = get(list_books)
+        .route_layer(Extension(pagination))
+        .route_layer(timeout(settings.timeout.unwrap_or(timeouts.list)));
+    // Responses that need the admin token must not be cached by shared caches
+    if let (Some(ttl), false) = (cache_ttls.list, settings.require_admin_token) {
+        list_route = list_route.route_layer(cacheable(ttl));
+    }
+    let list_route = common(list_route, BookRoute::ListBooks, settings);
+
+    let settings = routes.get(BookRoute::GetBook);
+    let mut get_route = get(get_book)
+        .route_layer(timeout(settings.timeout.unwrap_or(timeouts.default)))
+        .route_layer(middleware::from_fn(if_modified_since));
+    if let (Some(ttl), false) = (cache_ttls.book, settings.require_admin_token) {
+        get_route = get_route.route_layer(cacheable(ttl));
+    }
+    let get_route = common(get_route, BookRoute::GetBook, settings);
+
+    let settings = routes.get(BookRoute::GetBookBySlug);
+    let mut slug_route = get(get_book_by_slug)
+        .route_layer(timeout(settings.timeout.unwrap_or(timeouts.default)))
+        .route_layer(middleware::from_fn(if_modified_since));
+    if let (Some(ttl), false) = (cache_ttls.book, settings.require_admin_token) {
+        slug_route = slug_route.route_layer(cacheable(ttl));
+    }
+    let mut slug_routes = common(slug_route, BookRoute::GetBookBySlug, settings);
+
+    let with_timeout = |method_router: MethodRouter<_>, route| {
+        let settings = routes.get(route);
+        common(
+            method_router.route_layer(timeout(settings.timeout.unwrap_or(timeouts.default))),
+            route,
+            settings,
+        )
+    };
+    let json_body = || middleware::from_fn(require_json);
+    let mut books_routes = list_route.merge(with_timeout(
+        post(insert_book).route_layer(json_body()),
+        BookRoute::InsertBook,
+    ));
+    let settings = routes.get(BookRoute::BulkInsertBooks);
+    let settings = RouteSettings {
+        max_json_body_size: settings.max_json_body_size.or(Some(BULK_JSON_BODY_SIZE)),
+        ..settings
+    };
+    let mut bulk_routes = common(
+        post(insert_books)
+            .route_layer(json_body())
+            .route_layer(timeout(settings.timeout.unwrap_or(timeouts.default))),
+        BookRoute::BulkInsertBooks,
+        settings,
+    );
+    let mut book_routes = get_route
+        .merge(with_timeout(
+            put(update_book).route_layer(json_body()),
+            BookRoute::UpdateBook,
+        ))
+        .merge(with_timeout(delete(delete_book), BookRoute::DeleteBook));
+    let mut translations_routes = with_timeout(get(list_translations), BookRoute::ListTranslations);
+    let mut translation_routes = with_timeout(
+        put(put_translation).route_layer(json_body()),
+        BookRoute::PutTranslation,
+    )
+    .merge(with_timeout(
+        delete(delete_translation),
+        BookRoute::DeleteTranslation,
+    ));
+    let mut history_routes = with_timeout(get(book_history), BookRoute::BookHistory);
+    let mut diff_routes = with_timeout(get(diff_revisions), BookRoute::DiffRevisions);
+    let mut changes_routes = with_timeout(
+        get(list_changes).route_layer(Extension(pagination)),
+        BookRoute::ListChanges,
+    );
+    let mut enrich_routes = with_timeout(post(enrich_book), BookRoute::EnrichBook);
+    let mut stats_routes = with_timeout(get(catalog_stats), BookRoute::CatalogStats);
+    let mut aggregate_routes = with_timeout(
+        get(aggregate_books).route_layer(Extension(pagination)),
+        BookRoute::AggregateBooks,
+    );
+    let mut trending_routes = with_timeout(get(trending_books), BookRoute::TrendingBooks);
+    // Asked for on each key pressed in a search box, so cached like the list
+    let settings = routes.get(BookRoute::SuggestBooks);
+    let mut suggest_route =
+        get(suggest_books).route_layer(timeout(settings.timeout.unwrap_or(timeouts.default)));
+    if let (Some(ttl), false) = (cache_ttls.list, settings.require_admin_token) {
+        suggest_route = suggest_route.route_layer(cacheable(ttl));
+    }
+    let mut suggest_routes = common(suggest_route, BookRoute::SuggestBooks, settings);
+    let mut search_routes = with_timeout(get(search_books), BookRoute::SearchBooks);
+    let mut random_routes = with_timeout(get(random_book), BookRoute::RandomBook);
+    let mut isbn_routes = with_timeout(
+        post(check_isbn).route_layer(json_body()),
+        BookRoute::CheckIsbn,
+    );
+    let mut qr_routes = with_timeout(
+        get(book_qr_code).route_layer(Extension(public_url)),
+        BookRoute::BookQrCode,
+    );
+    // Reverting can undo anyone's changes, so always needs the admin token
+    let settings = RouteSettings {
+        require_admin_token: true,
+        ..routes.get(BookRoute::RevertBook)
+    };
+    let mut revert_routes = common(
+        post(revert_book).route_layer(timeout(settings.timeout.unwrap_or(timeouts.default))),
+        BookRoute::RevertBook,
+        settings,
+    );
+
+    if let Some(limit) = concurrency.per_route {
+        books_routes = books_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        bulk_routes = bulk_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        book_routes = book_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        slug_routes = slug_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        translations_routes = translations_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        translation_routes = translation_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        history_routes = history_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        enrich_routes = enrich_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        changes_routes = changes_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        diff_routes = diff_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        revert_routes = revert_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        stats_routes = stats_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        aggregate_routes = aggregate_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        trending_routes = trending_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        qr_routes = qr_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        suggest_routes = suggest_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        search_routes = search_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        random_routes = random_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+        isbn_routes = isbn_routes.route_layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::new(limit),
+            shed_load,
+        ));
+    }
+
+    // A path is only routed if one of its routes is enabled, so that the
+    // others are a 404 rather than a 405
+    let paths = [
+        (
+            "/books",
+            books_routes,
+            &[BookRoute::ListBooks, BookRoute::InsertBook][..],
+        ),
+        (
+            "/books/{id}",
+            book_routes,
+            &[
+                BookRoute::GetBook,
+                BookRoute::UpdateBook,
+                BookRoute::DeleteBook,
+            ],
+        ),
+        ("/books/bulk", bulk_routes, &[BookRoute::BulkInsertBooks]),
+        (
+            "/books/by-slug/{slug}",
+            slug_routes,
+            &[BookRoute::GetBookBySlug],
+        ),
+        (
+            "/books/aggregate",
+            aggregate_routes,
+            &[BookRoute::AggregateBooks],
+        ),
+        (
+            "/books/trending",
+            trending_routes,
+            &[BookRoute::TrendingBooks],
+        ),
+        ("/books/random", random_routes, &[BookRoute::RandomBook]),
+        ("/books/suggest", suggest_routes, &[BookRoute::SuggestBooks]),
+        ("/books/search", search_routes, &[BookRoute::SearchBooks]),
+        (
+            "/books/{id}/translations",
+            translations_routes,
+            &[BookRoute::ListTranslations],
+        ),
+        (
+            "/books/{id}/translations/{language}",
+            translation_routes,
+            &[BookRoute::PutTranslation, BookRoute::DeleteTranslation],
+        ),
+        (
+            "/books/{id}/history",
+            history_routes,
+            &[BookRoute::BookHistory],
+        ),
+        (
+            "/books/{id}/history/{from}/diff/{to}",
+            diff_routes,
+            &[BookRoute::DiffRevisions],
+        ),
+        (
+            "/books/{id}/revert",
+            revert_routes,
+            &[BookRoute::RevertBook],
+        ),
+        ("/books/{id}/qr.png", qr_routes, &[BookRoute::BookQrCode]),
+        (
+            "/books/{id}/enrich",
+            enrich_routes,
+            &[BookRoute::EnrichBook],
+        ),
+        ("/changes", changes_routes, &[BookRoute::ListChanges]),
+        ("/stats", stats_routes, &[BookRoute::CatalogStats]),
+        ("/tools/isbn", isbn_routes, &[BookRoute::CheckIsbn]),
+    ];
+    let mut router = Router::new();
+    for (path, method_router, path_routes) in paths {
+        if path_routes.iter().any(|route| endpoints.contains(route)) {
+            router = router.route(path, method_router);
+        }
+    }
+    router.with_state(books)
+}
@@ -0,0 +1,161 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Header that clients can send on unversioned paths to ask for a version,
+/// and that every versioned response carries
+pub(crate) const API_VERSION: HeaderName = HeaderName::from_static("api-version");
+
+/// A version of the `/books` API. Each version is mounted under its own path
+/// prefix, e.g. `/v1/books`, and may change the request and response formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// The version served by the unversioned aliases, e.g. `/books`
+    pub const UNVERSIONED: ApiVersion = ApiVersion::V1;
+
+    pub const ALL: [ApiVersion; 1] = [ApiVersion::V1];
+
+    /// The path prefix the version is mounted under
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/v1",
+        }
+    }
+
+    fn header_value(self) -> HeaderValue {
+        match self {
+            ApiVersion::V1 => HeaderValue::from_static("1"),
+        }
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiVersion::V1 => write!(f, "v1"),
+        }
+    }
+}
+
+/// Accepts `1` or `v1`
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = s.trim().trim_start_matches(['v', 'V']);
+        ApiVersion::ALL
+            .into_iter()
+            .find(|version| version.header_value() == number)
+            .ok_or_else(|| format!("unknown API version: {s}"))
+    }
+}
+
+/// Handlers can take an `ApiVersion` to find out which version of the API the
+/// request was made against
+impl<S: Send + Sync> FromRequestParts<S> for ApiVersion {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<ApiVersion>()
+            .copied()
+            .unwrap_or(ApiVersion::UNVERSIONED))
+    }
+}
+
+/// Middleware for the routes of one version, which tags requests with the
+/// version and echoes it in the `Api-Version` response header
+pub(crate) async fn api_version(
+    State(version): State<ApiVersion>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    request.extensions_mut().insert(version);
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(API_VERSION, version.header_value());
+    response
+}
+
+/// Middleware for the unversioned aliases. A client can ask for a version
+/// with the `Api-Version` request header; as the aliases only serve
+/// [`ApiVersion::UNVERSIONED`], asking for any other version is answered with
+/// a 406 that points at the versioned path instead.
+pub(crate) async fn negotiate_version(request: Request, next: Next) -> Response {
+    let requested = match request.headers().get(API_VERSION) {
+        Some(value) => value
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(ApiVersion::from_str),
+        None => Ok(ApiVersion::UNVERSIONED),
+    };
+
+    match requested {
+        Ok(version) if version == ApiVersion::UNVERSIONED => {
+            api_version(State(version), request, next).await
+        }
+        Ok(version) => (
+            StatusCode::NOT_ACCEPTABLE,
+            format!(
+                "Unversioned paths serve API {}, request {}{} instead",
+                ApiVersion::UNVERSIONED,
+                version.prefix(),
+                request.uri().path()
+            ),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::NOT_ACCEPTABLE, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unversioned_requests_are_tagged_with_the_negotiated_version() {
+        let router = Router::new()
+            .route(
+                "/books",
+                get(|version: ApiVersion| async move { version.to_string() }),
+            )
+            .route_layer(middleware::from_fn(negotiate_version));
+
+        let request = |version: Option<&str>| {
+            let mut request = Request::get("/books");
+            if let Some(version) = version {
+                request = request.header(API_VERSION, version);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        for version in [None, Some("1"), Some("v1")] {
+            let response = router.clone().oneshot(request(version)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[API_VERSION], "1");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, "v1");
+        }
+
+        let response = router.oneshot(request(Some("7"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+}
@@ -0,0 +1,788 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, BufRead, BufReader, Write};
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, SimpleAsyncConnection};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
+use object_store::path::Path;
+use object_store::{ObjectStore, WriteMultipart};
+use url::Url;
+
+use crate::commands::CommandError;
+use crate::database::{create_db_pool, DBPool};
+use crate::models::{Book, BookTranslation};
+use crate::schema::{book_slugs, book_translations, books};
+use crate::slug::{first_free, slugify};
+use crate::Config;
+
+/// Bumped whenever the layout of a backup changes, so a restore can tell
+/// which layout it is reading
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+const BACKUP_FILE_PREFIX: &str = "bookstore-";
+const BACKUP_FILE_SUFFIX: &str = ".jsonl.gz";
+/// How many rows read from the DB can be waiting to be compressed and
+/// uploaded, before reading waits for the upload
+const BUFFERED_ROWS: usize = 1000;
+
+/// Where to write backups, and how many to keep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupConfig {
+    /// e.g. `s3://my-bucket/backups` or `file:///var/backups/bookstore`
+    pub url: Url,
+    /// How many of the most recent backups to keep. Older ones are deleted
+    /// after each successful backup.
+    pub retention: u32,
+    /// When the server should take backups by itself. If not set, backups
+    /// are only taken by running the `backup` command.
+    pub schedule: Option<Schedule>,
+}
+
+/// The first line of every backup
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BackupHeader {
+    pub format_version: u32,
+    pub app_version: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Every line after the header: one row of one table
+#[derive(serde::Serialize)]
+struct BackupRecord<'a, T> {
+    table: &'a str,
+    row: T,
+}
+
+/// A `BackupRecord` as read back in, before the row is checked against its table
+#[derive(serde::Deserialize)]
+struct RawBackupRecord {
+    table: String,
+    row: serde_json::Value,
+}
+
+/// A row of one of the tables kept in a backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BackupRow {
+    Book(Book),
+    Translation(BookTranslation),
+}
+
+/// The rows of a backup, as read back in
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BackupRows {
+    books: Vec<Book>,
+    translations: Vec<BookTranslation>,
+}
+
+/// How many rows of each table a backup holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RowCounts {
+    books: usize,
+    translations: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupSummary {
+    /// The URL of the new backup
+    pub location: String,
+    pub books: usize,
+    pub translations: usize,
+    /// Old backups that were deleted to stay within the retention limit
+    pub deleted: Vec<String>,
+}
+
+/// Where `restore` should write the rows from a backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreTarget {
+    /// Replace the contents of the live tables. Refuses to overwrite
+    /// existing rows unless `replace` is set.
+    Live { replace: bool },
+    /// Recreate the tables in a separate schema, so the restored data can be
+    /// checked before swapping it in
+    Schema(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreSummary {
+    /// The URL of the backup that was restored
+    pub location: String,
+    pub header: BackupHeader,
+    pub books: usize,
+    pub translations: usize,
+}
+
+/// Build an object store client for the backup URL. S3 credentials and
+/// region are read from the usual `AWS_*` environment variables.
+pub fn open_store(url: &Url) -> Result<(Box<dyn ObjectStore>, Path), object_store::Error> {
+    let aws_options = std::env::vars()
+        .filter(|(key, _)| key.starts_with("AWS_"))
+        .map(|(key, value)| (key.to_ascii_lowercase(), value));
+    object_store::parse_url_opts(url, aws_options)
+}
+
+/// Stream a gzip-compressed snapshot of the catalog to the configured backup
+/// location, then delete backups beyond the retention limit
+pub async fn backup(config: &Config) -> Result<BackupSummary, CommandError> {
+    let backup_config = config
+        .backup
+        .as_ref()
+        .ok_or("backup_url is not configured")?;
+    let pool = create_db_pool(config.database_url.clone(), config.db_pool).await?;
+
+    run_backup(&pool, backup_config).await
+}
+
+/// The guts of `backup`, shared with the scheduled backup job
+pub(crate) async fn run_backup(
+    pool: &DBPool,
+    backup_config: &BackupConfig,
+) -> Result<BackupSummary, CommandError> {
+    let (store, prefix) = open_store(&backup_config.url)?;
+    let mut conn = pool.get().await?;
+
+    // The rows are uploaded as they are read, rather than read in full first
+    let (sender, rows) = mpsc::channel(BUFFERED_ROWS);
+    let created_at = Utc::now();
+    let (_, written) = futures::join!(
+        read_tables(&mut conn, sender),
+        write_backup(store.as_ref(), &prefix, created_at, rows),
+    );
+    let (location, counts) = written?;
+    let deleted = prune_backups(store.as_ref(), &prefix, backup_config.retention).await?;
+
+    let to_url = |path: &Path| {
+        let mut url = backup_config.url.clone();
+        url.set_path(path.as_ref());
+        url.to_string()
+    };
+
+    Ok(BackupSummary {
+        location: to_url(&location),
+        books: counts.books,
+        translations: counts.translations,
+        deleted: deleted.iter().map(to_url).collect(),
+    })
+}
+
+/// Validate a backup and load it into the DB. `location` is the URL of a
+/// backup; if it is not given, the newest backup under `backup_url` is used.
+///
+/// The whole backup is checked before anything is written, and the rows are
+/// written in a single transaction, so a bad backup never leaves the DB half
+/// restored.
+pub async fn restore(
+    config: &Config,
+    location: Option<&str>,
+    target: RestoreTarget,
+) -> Result<RestoreSummary, CommandError> {
+    let location = match location {
+        Some(location) => Url::parse(location)?,
+        None => {
+            let backup_config = config
+                .backup
+                .as_ref()
+                .ok_or("no backup given, and backup_url is not configured")?;
+            let (store, prefix) = open_store(&backup_config.url)?;
+            let newest = list_backups(store.as_ref(), &prefix)
+                .await?
+                .pop()
+                .ok_or_else(|| format!("no backups found under {}", backup_config.url))?;
+            let mut url = backup_config.url.clone();
+            url.set_path(newest.as_ref());
+            url
+        }
+    };
+
+    let (store, path) = open_store(&location)?;
+    let compressed = store.get(&path).await?.bytes().await?;
+    let (header, rows) = read_backup(&compressed[..])?;
+
+    let database_url = config.database_url.clone();
+    let books = rows.books.len();
+    let translations = rows.translations.len();
+    tokio::task::spawn_blocking(move || {
+        let mut conn = PgConnection::establish(&database_url)?;
+        load_rows(&mut conn, &rows, &target)
+    })
+    .await??;
+
+    Ok(RestoreSummary {
+        location: location.to_string(),
+        header,
+        books,
+        translations,
+    })
+}
+
+/// Parse and validate a backup, returning its header and every row
+fn read_backup(compressed: &[u8]) -> Result<(BackupHeader, BackupRows), CommandError> {
+    let mut lines = BufReader::new(GzDecoder::new(compressed)).lines();
+
+    let header_line = lines.next().ok_or("backup is empty")??;
+    let header: BackupHeader = serde_json::from_str(&header_line)
+        .map_err(|e| format!("backup does not start with a valid header: {e}"))?;
+    if header.format_version != BACKUP_FORMAT_VERSION {
+        return Err(format!(
+            "backup format version {} is not supported (expected {BACKUP_FORMAT_VERSION})",
+            header.format_version
+        )
+        .into());
+    }
+
+    let mut rows = BackupRows::default();
+    let mut book_ids = BTreeSet::new();
+    for (index, line) in lines.enumerate() {
+        let line_number = index + 2;
+        let record: RawBackupRecord = serde_json::from_str(&line?)
+            .map_err(|e| format!("line {line_number} is not a valid record: {e}"))?;
+        match record.table.as_str() {
+            "books" => {
+                let book: Book = serde_json::from_value(record.row)
+                    .map_err(|e| format!("line {line_number} is not a valid book: {e}"))?;
+                book_ids.insert(book.id);
+                rows.books.push(book);
+            }
+            // Written after all the books, so the book must already be read
+            "book_translations" => {
+                let translation: BookTranslation = serde_json::from_value(record.row)
+                    .map_err(|e| format!("line {line_number} is not a valid translation: {e}"))?;
+                if !book_ids.contains(&translation.book_id) {
+                    return Err(format!(
+                        "line {line_number} is a translation of book {}, which is not in the backup",
+                        translation.book_id
+                    )
+                    .into());
+                }
+                rows.translations.push(translation);
+            }
+            // Only in full exports, which can be restored like a backup. The
+            // history and views aren't restored; nothing else deletes them.
+            "book_revisions" | "book_views" => {}
+            other => return Err(format!("line {line_number} has unknown table {other:?}").into()),
+        }
+    }
+
+    Ok((header, rows))
+}
+
+/// Write the restored rows in one transaction, keeping the books' original IDs
+fn load_rows(
+    conn: &mut PgConnection,
+    rows: &BackupRows,
+    target: &RestoreTarget,
+) -> Result<(), CommandError> {
+    use diesel::RunQueryDsl;
+
+    conn.transaction(|conn| {
+        match target {
+            RestoreTarget::Live { replace } => {
+                let existing: i64 = books::table.count().get_result(conn)?;
+                if existing > 0 && !replace {
+                    return Err(format!(
+                        "the books table already has {existing} rows; pass --replace to overwrite them"
+                    )
+                    .into());
+                }
+                diesel::delete(book_translations::table).execute(conn)?;
+                diesel::delete(books::table).execute(conn)?;
+                // They lead to the books being replaced
+                diesel::delete(book_slugs::table).execute(conn)?;
+            }
+            RestoreTarget::Schema(schema) => {
+                if !is_identifier(schema) {
+                    return Err(format!("{schema:?} is not a valid schema name").into());
+                }
+                for statement in [
+                    format!("CREATE SCHEMA IF NOT EXISTS {schema}"),
+                    format!("DROP TABLE IF EXISTS {schema}.books"),
+                    format!("CREATE TABLE {schema}.books (LIKE public.books INCLUDING ALL)"),
+                    format!("DROP TABLE IF EXISTS {schema}.book_translations"),
+                    format!(
+                        "CREATE TABLE {schema}.book_translations \
+                         (LIKE public.book_translations INCLUDING ALL)"
+                    ),
+                    format!("SET LOCAL search_path TO {schema}"),
+                ] {
+                    diesel::sql_query(statement).execute(conn)?;
+                }
+            }
+        }
+
+        let slugs = restored_slugs(&rows.books);
+        // Stay well under Postgres' limit of 65535 bind parameters per statement
+        for (chunk, slugs) in rows.books.chunks(1000).zip(slugs.chunks(1000)) {
+            let rows: Vec<_> = chunk
+                .iter()
+                .zip(slugs)
+                .map(|(book, slug)| {
+                    (
+                        books::id.eq(book.id),
+                        books::name.eq(&book.name),
+                        books::author.eq(&book.author),
+                        books::updated_at.eq(book.updated_at),
+                        books::isbn.eq(&book.isbn),
+                        books::publisher.eq(&book.publisher),
+                        books::cover_url.eq(&book.cover_url),
+                        books::slug.eq(slug),
+                    )
+                })
+                .collect();
+            diesel::insert_into(books::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for chunk in rows.translations.chunks(1000) {
+            let rows: Vec<_> = chunk
+                .iter()
+                .map(|translation| {
+                    (
+                        book_translations::book_id.eq(translation.book_id),
+                        book_translations::language.eq(&translation.language),
+                        book_translations::name.eq(&translation.name),
+                        book_translations::description.eq(&translation.description),
+                        book_translations::updated_at.eq(translation.updated_at),
+                    )
+                })
+                .collect();
+            diesel::insert_into(book_translations::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+
+        if matches!(target, RestoreTarget::Live { .. }) {
+            // Rows were inserted with explicit IDs, so move the sequence past them
+            diesel::sql_query(
+                "SELECT setval(pg_get_serial_sequence('books', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM books",
+            )
+            .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The slugs of the restored books. Books backed up before they had slugs
+/// are given them as the migration that added slugs did.
+fn restored_slugs(restored: &[Book]) -> Vec<String> {
+    let mut taken: HashSet<String> = restored
+        .iter()
+        .filter(|book| !book.slug.is_empty())
+        .map(|book| book.slug.clone())
+        .collect();
+    restored
+        .iter()
+        .map(|book| {
+            if !book.slug.is_empty() {
+                return book.slug.clone();
+            }
+            let slug = first_free(&slugify(&book.name), |slug| taken.contains(slug));
+            taken.insert(slug.clone());
+            slug
+        })
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// The name of a backup taken at `created_at`. Names sort in the order the
+/// backups were taken.
+pub(crate) fn backup_file_name(created_at: DateTime<Utc>) -> String {
+    format!(
+        "{BACKUP_FILE_PREFIX}{}{BACKUP_FILE_SUFFIX}",
+        created_at.format("%Y%m%dT%H%M%S%.3fZ")
+    )
+}
+
+fn backup_path(prefix: &Path, created_at: DateTime<Utc>) -> Path {
+    prefix.child(backup_file_name(created_at))
+}
+
+/// Compresses a backup as it is written: the header, then one record per
+/// row. The compressed bytes can be taken as they are produced, so that the
+/// whole backup is never held in memory.
+pub(crate) struct BackupEncoder {
+    encoder: GzEncoder<Vec<u8>>,
+}
+
+impl BackupEncoder {
+    /// Start a backup taken at `created_at`, with its header
+    pub fn new(created_at: DateTime<Utc>) -> io::Result<Self> {
+        let mut encoder = BackupEncoder {
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+        };
+        let header = BackupHeader {
+            format_version: BACKUP_FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at,
+        };
+        encoder.write_line(&header)?;
+        Ok(encoder)
+    }
+
+    pub fn write_row(&mut self, table: &str, row: impl serde::Serialize) -> io::Result<()> {
+        self.write_line(&BackupRecord { table, row })
+    }
+
+    /// The bytes compressed since they were last taken
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(self.encoder.get_mut())
+    }
+
+    /// The last of the compressed bytes
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        self.encoder.finish()
+    }
+
+    fn write_line(&mut self, line: &impl serde::Serialize) -> io::Result<()> {
+        serde_json::to_writer(&mut self.encoder, line)?;
+        self.encoder.write_all(b"\n")
+    }
+}
+
+/// Send every row to back up to `rows`: the books, then their translations.
+/// The tables are read in one snapshot, so they are consistent with each
+/// other however long the upload takes. A failure is sent as the last row,
+/// so that the backup is abandoned.
+async fn read_tables(
+    conn: &mut AsyncPgConnection,
+    mut rows: mpsc::Sender<Result<BackupRow, CommandError>>,
+) {
+    use diesel_async::RunQueryDsl;
+
+    let result = async {
+        conn.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .await?;
+
+        let mut books = books::table
+            .select(Book::as_select())
+            .order(books::id.asc())
+            .load_stream::<Book>(conn)
+            .await?;
+        while let Some(book) = books.try_next().await? {
+            rows.send(Ok(BackupRow::Book(book))).await?;
+        }
+        drop(books);
+
+        let mut translations = book_translations::table
+            .select(BookTranslation::as_select())
+            .order((
+                book_translations::book_id.asc(),
+                book_translations::language.asc(),
+            ))
+            .load_stream::<BookTranslation>(conn)
+            .await?;
+        while let Some(translation) = translations.try_next().await? {
+            rows.send(Ok(BackupRow::Translation(translation))).await?;
+        }
+        Ok::<_, CommandError>(())
+    }
+    .await;
+
+    // Read only, so there is nothing to commit
+    let _ = conn.batch_execute("ROLLBACK").await;
+    if let Err(e) = result {
+        // Fails only if the upload has already failed
+        let _ = rows.send(Err(e)).await;
+    }
+}
+
+/// Write the header and then each row as a line of JSON, compressing as we
+/// go and uploading in parts, so the whole catalog is never held in memory
+async fn write_backup(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    created_at: DateTime<Utc>,
+    rows: impl Stream<Item = Result<BackupRow, CommandError>>,
+) -> Result<(Path, RowCounts), CommandError> {
+    let path = backup_path(prefix, created_at);
+    let mut upload = WriteMultipart::new(store.put_multipart(&path).await?);
+
+    let result = async {
+        let mut encoder = BackupEncoder::new(created_at)?;
+
+        let mut counts = RowCounts::default();
+        let mut rows = std::pin::pin!(rows);
+        while let Some(row) = rows.next().await {
+            match row? {
+                BackupRow::Book(book) => {
+                    encoder.write_row("books", book)?;
+                    counts.books += 1;
+                }
+                BackupRow::Translation(translation) => {
+                    encoder.write_row("book_translations", translation)?;
+                    counts.translations += 1;
+                }
+            }
+            upload.write(&encoder.take());
+        }
+
+        upload.write(&encoder.finish()?);
+        Ok::<_, CommandError>(counts)
+    }
+    .await;
+
+    match result {
+        Ok(counts) => {
+            upload.finish().await?;
+            Ok((path, counts))
+        }
+        Err(e) => {
+            // Don't leave a truncated backup (or orphaned parts) behind
+            let _ = upload.abort().await;
+            Err(e)
+        }
+    }
+}
+
+/// Delete all but the `retention` most recent backups under `prefix`,
+/// returning the paths that were deleted
+async fn prune_backups(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    retention: u32,
+) -> Result<Vec<Path>, CommandError> {
+    let mut backups = list_backups(store, prefix).await?;
+
+    let excess = backups.len().saturating_sub(retention as usize);
+    backups.truncate(excess);
+
+    for path in &backups {
+        store.delete(path).await?;
+    }
+
+    Ok(backups)
+}
+
+/// Every backup under `prefix`, oldest first
+pub async fn list_backups(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+) -> Result<Vec<Path>, object_store::Error> {
+    let mut backups: Vec<Path> = store
+        .list(Some(prefix))
+        .map_ok(|meta| meta.location)
+        .try_filter(|path| {
+            let is_backup = path.filename().is_some_and(|name| {
+                name.starts_with(BACKUP_FILE_PREFIX) && name.ends_with(BACKUP_FILE_SUFFIX)
+            });
+            std::future::ready(is_backup)
+        })
+        .try_collect()
+        .await?;
+
+    backups.sort();
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    fn book(id: i32, name: &str) -> Result<BackupRow, CommandError> {
+        Ok(BackupRow::Book(Book {
+            id,
+            name: name.to_string(),
+            author: "Anon".to_string(),
+            updated_at: Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap(),
+            isbn: None,
+            publisher: None,
+            cover_url: None,
+            slug: name.to_lowercase(),
+        }))
+    }
+
+    fn translation(book_id: i32, language: &str) -> Result<BackupRow, CommandError> {
+        Ok(BackupRow::Translation(BookTranslation {
+            book_id,
+            language: language.to_string(),
+            name: format!("Book {book_id} in {language}"),
+            description: None,
+            updated_at: Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn backup_is_a_gzipped_header_followed_by_one_line_per_row() {
+        let store = InMemory::new();
+        let prefix = Path::from("backups");
+        let created_at = Utc.with_ymd_and_hms(2025, 3, 1, 2, 0, 0).unwrap();
+        let rows = futures::stream::iter([book(1, "Emma"), book(2, "Persuasion")]);
+
+        let (path, counts) = write_backup(&store, &prefix, created_at, rows)
+            .await
+            .unwrap();
+
+        assert_eq!(counts.books, 2);
+        assert_eq!(
+            path.as_ref(),
+            "backups/bookstore-20250301T020000.000Z.jsonl.gz"
+        );
+
+        let bytes = store.get(&path).await.unwrap().bytes().await.unwrap();
+        let lines: Vec<String> = BufReader::new(GzDecoder::new(&bytes[..]))
+            .lines()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let header: BackupHeader = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(header.format_version, BACKUP_FORMAT_VERSION);
+        assert_eq!(header.created_at, created_at);
+        assert_eq!(
+            lines[2],
+            r#"{"table":"books","row":{"id":2,"name":"Persuasion","author":"Anon","updated_at":"2025-03-01T12:00:00Z","slug":"persuasion"}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_backup_leaves_nothing_behind() {
+        let store = InMemory::new();
+        let prefix = Path::from("backups");
+        let rows = futures::stream::iter([book(1, "Emma"), Err("connection lost".into())]);
+
+        let result = write_backup(&store, &prefix, Utc::now(), rows).await;
+
+        assert!(result.is_err());
+        assert!(list_backups(&store, &prefix).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_the_most_recent_backups_are_kept() {
+        let store = InMemory::new();
+        let prefix = Path::from("backups");
+        store
+            .put(&prefix.child("unrelated.txt"), "keep me".into())
+            .await
+            .unwrap();
+        for day in 1..=4 {
+            let created_at = Utc.with_ymd_and_hms(2025, 3, day, 2, 0, 0).unwrap();
+            let rows = futures::stream::iter([book(1, "Emma")]);
+            write_backup(&store, &prefix, created_at, rows)
+                .await
+                .unwrap();
+        }
+
+        let deleted = prune_backups(&store, &prefix, 2).await.unwrap();
+
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted[1].as_ref().contains("20250302"));
+        let remaining = list_backups(&store, &prefix).await.unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining[0].as_ref().contains("20250303"));
+        assert!(store.head(&prefix.child("unrelated.txt")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn backup_can_be_read_back() {
+        let store = InMemory::new();
+        let prefix = Path::from("backups");
+        let written = || {
+            [
+                book(1, "Emma"),
+                book(7, "Persuasion"),
+                translation(7, "de"),
+                translation(7, "fr"),
+            ]
+        };
+        let rows = futures::stream::iter(written());
+        let (path, counts) = write_backup(&store, &prefix, Utc::now(), rows)
+            .await
+            .unwrap();
+        assert_eq!(
+            counts,
+            RowCounts {
+                books: 2,
+                translations: 2
+            }
+        );
+
+        let bytes = store.get(&path).await.unwrap().bytes().await.unwrap();
+        let (header, rows) = read_backup(&bytes).unwrap();
+
+        assert_eq!(header.app_version, env!("CARGO_PKG_VERSION"));
+        let read: Vec<_> = rows
+            .books
+            .into_iter()
+            .map(BackupRow::Book)
+            .chain(rows.translations.into_iter().map(BackupRow::Translation))
+            .collect();
+        assert_eq!(read, written().map(Result::unwrap));
+    }
+
+    #[tokio::test]
+    async fn backup_with_a_translation_of_a_missing_book_is_rejected() {
+        let store = InMemory::new();
+        let prefix = Path::from("backups");
+        let rows = futures::stream::iter([book(1, "Emma"), translation(2, "de")]);
+        let (path, _) = write_backup(&store, &prefix, Utc::now(), rows)
+            .await
+            .unwrap();
+
+        let bytes = store.get(&path).await.unwrap().bytes().await.unwrap();
+        let error = read_backup(&bytes).unwrap_err();
+
+        assert!(
+            error.to_string().contains("translation of book 2"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn full_export_can_be_read_back_as_a_backup() {
+        let mut encoder = BackupEncoder::new(Utc::now()).unwrap();
+        let Ok(BackupRow::Book(emma)) = book(1, "Emma") else {
+            unreachable!()
+        };
+        encoder.write_row("books", &emma).unwrap();
+        encoder
+            .write_row(
+                "book_revisions",
+                serde_json::json!({"id": 1, "book_id": 1, "revision": 1}),
+            )
+            .unwrap();
+        encoder
+            .write_row(
+                "book_views",
+                serde_json::json!({"book_id": 1, "hour": "2025-03-01T12:00:00Z", "views": 3}),
+            )
+            .unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let (_, rows) = read_backup(&compressed).unwrap();
+
+        assert_eq!(rows.books, vec![emma]);
+        assert!(rows.translations.is_empty());
+    }
+
+    #[test]
+    fn backup_with_an_unknown_format_version_is_rejected() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        writeln!(
+            encoder,
+            r#"{{"format_version":99,"app_version":"9.0.0","created_at":"2025-03-01T02:00:00Z"}}"#
+        )
+        .unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let error = read_backup(&compressed).unwrap_err();
+
+        assert!(error.to_string().contains("format version 99"), "{error}");
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_bookstore_api::client::BookstoreClient;
+use rust_bookstore_api::{Book, NewBook};
+
+#[derive(Parser)]
+#[command(version, about = "Manage the books of a running bookstore server")]
+struct Cli {
+    /// Where the server is, including any base path
+    #[arg(long, env = "BOOKSTORE_URL", default_value = "http://localhost:3000")]
+    url: String,
+    /// Bearer token to send, for servers that require the admin token
+    #[arg(long, env = "BOOKSTORE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[arg(long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Aligned columns, for people
+    Table,
+    /// The API's JSON, for scripts
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the books
+    List,
+    /// Add a book
+    Add {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        author: String,
+        #[arg(long)]
+        isbn: Option<String>,
+        #[arg(long)]
+        publisher: Option<String>,
+    },
+    /// Delete a book
+    Delete { id: i32 },
+    /// Add every book in a CSV file, with a header row naming the columns:
+    /// `name`, `author` and optionally `isbn`, `publisher` and `cover_url`
+    Import {
+        /// The CSV file, or `-` for stdin
+        file: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let mut client = BookstoreClient::new(cli.url);
+    if let Some(token) = cli.token {
+        client = client.with_admin_token(token);
+    }
+
+    match cli.command {
+        Command::List => match client.list_books().await {
+            Ok(books) => print_books(&books, cli.output),
+            Err(error) => exit_with_error("Failed to list books", error),
+        },
+        Command::Add {
+            name,
+            author,
+            isbn,
+            publisher,
+        } => {
+            let book = NewBook {
+                name,
+                author,
+                isbn,
+                publisher,
+                cover_url: None,
+            };
+            match client.insert_book(&book).await {
+                Ok(book) => print_books(&[book], cli.output),
+                Err(error) => exit_with_error("Failed to add the book", error),
+            }
+        }
+        Command::Delete { id } => match client.delete_book(id).await {
+            Ok(()) => eprintln!("Deleted book {id}"),
+            Err(error) => exit_with_error("Failed to delete the book", error),
+        },
+        Command::Import { file } => {
+            let input: Box<dyn Read> = if file.as_os_str() == "-" {
+                Box::new(io::stdin().lock())
+            } else {
+                match File::open(&file) {
+                    Ok(file) => Box::new(file),
+                    Err(error) => {
+                        exit_with_error(&format!("Failed to open {}", file.display()), error)
+                    }
+                }
+            };
+            let new_books = read_books(input)
+                .unwrap_or_else(|error| exit_with_error("Failed to read the CSV", error));
+
+            // Carry on past failures, so one bad row doesn't hold up the rest
+            let mut added = Vec::new();
+            let mut failed = 0;
+            for (row, book) in new_books.iter().enumerate() {
+                match client.insert_book(book).await {
+                    Ok(book) => added.push(book),
+                    Err(error) => {
+                        // Counting the header as row 1, as spreadsheets do
+                        eprintln!("Row {}: {error}", row + 2);
+                        failed += 1;
+                    }
+                }
+            }
+            print_books(&added, cli.output);
+            eprintln!("Added {} books, {failed} failed", added.len());
+            if failed > 0 {
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// The books in a CSV file with a header row
+fn read_books(input: impl Read) -> Result<Vec<NewBook>, csv::Error> {
+    csv::Reader::from_reader(input).into_deserialize().collect()
+}
+
+fn print_books(books: &[Book], output: Output) {
+    match output {
+        Output::Table => print!("{}", table(books)),
+        Output::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(books).expect("Books can always be serialized")
+        ),
+    }
+}
+
+/// The books' main fields, in columns padded to line up
+fn table(books: &[Book]) -> String {
+    let header = ["ID", "NAME", "AUTHOR", "ISBN"].map(str::to_string);
+    let rows: Vec<[String; 4]> = std::iter::once(header)
+        .chain(books.iter().map(|book| {
+            [
+                book.id.to_string(),
+                book.name.clone(),
+                book.author.clone(),
+                book.isbn.clone().unwrap_or_default(),
+            ]
+        }))
+        .collect();
+    let widths: Vec<usize> = (0..4)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut table = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+fn exit_with_error(context: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{context}: {error}");
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn books_are_read_from_csv_and_shown_in_columns() {
+        let csv = "name,author,isbn\n\
+                   Dune,Frank Herbert,9780441013593\n\
+                   \"Parable of the Sower\",Octavia E. Butler,\n";
+        let new_books = read_books(csv.as_bytes()).unwrap();
+        assert_eq!(new_books.len(), 2);
+        assert_eq!(new_books[0].isbn.as_deref(), Some("9780441013593"));
+        assert_eq!(new_books[1].name, "Parable of the Sower");
+        assert_eq!(new_books[1].isbn, None);
+
+        let books: Vec<Book> = new_books
+            .into_iter()
+            .zip(1..)
+            .map(|(book, id)| Book {
+                id,
+                name: book.name,
+                author: book.author,
+                updated_at: Utc::now(),
+                isbn: book.isbn,
+                publisher: book.publisher,
+                cover_url: book.cover_url,
+                slug: String::new(),
+            })
+            .collect();
+        assert_eq!(
+            table(&books),
+            "ID  NAME                  AUTHOR             ISBN\n\
+             1   Dune                  Frank Herbert      9780441013593\n\
+             2   Parable of the Sower  Octavia E. Butler\n"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// The largest request bodies accepted by the `/books` routes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    /// Maximum size in bytes of a JSON request body
+    pub json: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        BodyLimits { json: 64 * 1024 }
+    }
+}
+
+/// Middleware that rejects a body over `limit` bytes with a 413 explaining
+/// the limit, either up front from its `Content-Length` or, for a body
+/// without one, once the extractor has read past the limit.
+///
+/// The extractor itself is limited with `DefaultBodyLimit`.
+pub(crate) async fn explain_body_limit(
+    State(limit): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return payload_too_large(limit);
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return payload_too_large(limit);
+    }
+    response
+}
+
+fn payload_too_large(limit: usize) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(json!({
+            "error": format!("The request body must not be larger than {limit} bytes"),
+            "limit_bytes": limit,
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::DefaultBodyLimit, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn router() -> Router {
+        Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .layer(DefaultBodyLimit::max(10))
+            .route_layer(middleware::from_fn_with_state(10, explain_body_limit))
+    }
+
+    async fn limit_in_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["limit_bytes"].clone()
+    }
+
+    #[tokio::test]
+    async fn oversize_bodies_are_rejected_with_the_limit() {
+        let request = Request::post("/echo")
+            .body(Body::from("x".repeat(11)))
+            .unwrap();
+
+        let response = router().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(limit_in_body(response).await, 10);
+    }
+
+    #[tokio::test]
+    async fn oversize_bodies_without_a_content_length_are_rejected_with_the_limit() {
+        let chunks = futures::stream::iter(["xxxxxx", "xxxxxx"].map(Ok::<_, std::io::Error>));
+        let request = Request::post("/echo")
+            .body(Body::from_stream(chunks))
+            .unwrap();
+
+        let response = router().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(limit_in_body(response).await, 10);
+    }
+
+    #[tokio::test]
+    async fn bodies_within_the_limit_are_accepted() {
+        let request = Request::post("/echo").body(Body::from("short")).unwrap();
+
+        let response = router().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
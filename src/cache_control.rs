@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+/// How long CDNs and browsers may cache successful reads. Responses from a
+/// route without a TTL are sent without a `Cache-Control` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheTtls {
+    /// For `GET /books`
+    pub list: Option<Duration>,
+    /// For `GET /books/{id}`
+    pub book: Option<Duration>,
+}
+
+/// The `Cache-Control` header for a route with the given TTL
+pub(crate) fn cache_control_header(ttl: Duration) -> HeaderValue {
+    HeaderValue::from_str(&format!("public, max-age={}", ttl.as_secs()))
+        .expect("a number is a valid header value")
+}
+
+/// Middleware that marks successful (and `304 Not Modified`) responses as
+/// cacheable. Errors, such as a 404 or a 503 during maintenance, are never
+/// cached.
+pub(crate) async fn cache_control(
+    State(cache_control): State<HeaderValue>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+        let headers = response.headers_mut();
+        headers
+            .entry(header::CACHE_CONTROL)
+            .or_insert(cache_control);
+        // Whether the body is compressed depends on the request, so caches
+        // must key on it even when this particular response isn't compressed
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn only_successful_responses_are_cacheable() {
+        let router = Router::new()
+            .route("/found", get(|| async { "a book" }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .route_layer(middleware::from_fn_with_state(
+                cache_control_header(Duration::from_secs(60)),
+                cache_control,
+            ));
+
+        let request = |path| Request::get(path).body(Body::empty()).unwrap();
+
+        let found = router.clone().oneshot(request("/found")).await.unwrap();
+        assert_eq!(found.headers()[header::CACHE_CONTROL], "public, max-age=60");
+        assert_eq!(found.headers()[header::VARY], "accept-encoding");
+
+        let missing = router.oneshot(request("/missing")).await.unwrap();
+        assert!(missing.headers().get(header::CACHE_CONTROL).is_none());
+    }
+}
@@ -0,0 +1,486 @@
+//! A typed client for the API, for other Rust services to use, sharing the
+//! server's models. Enabled by the `client` feature.
+
+use std::error::Error;
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::events::{BookChange, ChangeFeed};
+use crate::isbn::IsbnCheck;
+use crate::models::{Book, BookTranslation, NewBook, NewTranslation};
+use crate::page::BookPage;
+use crate::search::SearchResult;
+use crate::stats::{Aggregation, BookField, CatalogStats, Metric};
+use crate::suggest::Suggestion;
+use crate::translations::LocalizedBook;
+use crate::views::TrendingBook;
+
+/// Why a request to the API failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// 404: there is no such book, or revision
+    NotFound(String),
+    /// 422: the request can't be carried out, e.g. a book without a name
+    Invalid(String),
+    /// 409: the new book looks like a duplicate of one in the catalog
+    Conflict(String),
+    /// Any other error response
+    Status { status: StatusCode, message: String },
+    /// The request couldn't be sent, or the response couldn't be read
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotFound(message)
+            | ClientError::Invalid(message)
+            | ClientError::Conflict(message) => write!(f, "{message}"),
+            ClientError::Status { status, message } => write!(f, "{status}: {message}"),
+            ClientError::Request(e) => write!(f, "Request to the bookstore API failed: {e}"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Request(e)
+    }
+}
+
+/// Calls version 1 of the API. Cloning it is cheap, and clones share their
+/// connections.
+#[derive(Debug, Clone)]
+pub struct BookstoreClient {
+    http: reqwest::Client,
+    /// e.g. `http://localhost:3000`, including any base path
+    base_url: String,
+    admin_token: Option<String>,
+}
+
+impl BookstoreClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        BookstoreClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            admin_token: None,
+        }
+    }
+
+    /// Send requests with this client, e.g. one with timeouts set
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Send this bearer token with every request, as needed to revert and
+    /// merge books, and by routes configured to require it
+    pub fn with_admin_token(mut self, admin_token: impl Into<String>) -> Self {
+        self.admin_token = Some(admin_token.into());
+        self
+    }
+
+    pub async fn list_books(&self) -> Result<Vec<Book>, ClientError> {
+        self.send(self.http.get(self.url("/v1/books"))).await
+    }
+
+    /// The books matching an RSQL filter, e.g. `author=="Kazuo Ishiguro"`
+    pub async fn filter_books(&self, filter: &str) -> Result<Vec<Book>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url("/v1/books"))
+                .query(&[("filter", filter)]),
+        )
+        .await
+    }
+
+    /// The books sorted by the fields, e.g. `name,-author`
+    pub async fn list_books_sorted(&self, sort: &str) -> Result<Vec<Book>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url("/v1/books"))
+                .query(&[("sort", sort)]),
+        )
+        .await
+    }
+
+    /// The books sorted by the fields, with text sorted by the rules of the
+    /// language, e.g. `de`
+    pub async fn list_books_collated(
+        &self,
+        sort: &str,
+        collation: &str,
+    ) -> Result<Vec<Book>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url("/v1/books"))
+                .query(&[("sort", sort), ("collation", collation)]),
+        )
+        .await
+    }
+
+    /// The books with the text in their name and author, ignoring case
+    pub async fn find_books(
+        &self,
+        name: Option<&str>,
+        author: Option<&str>,
+    ) -> Result<Vec<Book>, ClientError> {
+        let mut request = self.http.get(self.url("/v1/books"));
+        if let Some(name) = name {
+            request = request.query(&[("name", name)]);
+        }
+        if let Some(author) = author {
+            request = request.query(&[("author", author)]);
+        }
+        self.send(request).await
+    }
+
+    /// A page of the books, in order of ID, after the `next_cursor` of an
+    /// earlier page if given
+    pub async fn list_books_page(
+        &self,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<BookPage, ClientError> {
+        let mut request = self
+            .http
+            .get(self.url("/v1/books"))
+            .query(&[("limit", limit)]);
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        self.send(request).await
+    }
+
+    pub async fn get_book(&self, id: i32) -> Result<Book, ClientError> {
+        self.send(self.http.get(self.url(&format!("/v1/books/{id}"))))
+            .await
+    }
+
+    /// The book with the slug, following the redirect if it is one the book
+    /// had before it was renamed or merged
+    pub async fn get_book_by_slug(&self, slug: &str) -> Result<Book, ClientError> {
+        self.send(
+            self.http
+                .get(self.url(&format!("/v1/books/by-slug/{slug}"))),
+        )
+        .await
+    }
+
+    /// The book as it was at a time in the past
+    pub async fn get_book_as_of(&self, id: i32, at: DateTime<Utc>) -> Result<Book, ClientError> {
+        let as_of = at.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        self.send(
+            self.http
+                .get(self.url(&format!("/v1/books/{id}")))
+                .query(&[("as_of", as_of)]),
+        )
+        .await
+    }
+
+    pub async fn insert_book(&self, book: &NewBook) -> Result<Book, ClientError> {
+        self.send(self.http.post(self.url("/v1/books")).json(book))
+            .await
+    }
+
+    /// Add the books all at once, or none of them, returning them with their
+    /// IDs in the order given
+    pub async fn insert_books(&self, books: &[NewBook]) -> Result<Vec<Book>, ClientError> {
+        self.send(self.http.post(self.url("/v1/books/bulk")).json(books))
+            .await
+    }
+
+    pub async fn update_book(&self, id: i32, book: &NewBook) -> Result<Book, ClientError> {
+        self.send(
+            self.http
+                .put(self.url(&format!("/v1/books/{id}")))
+                .json(book),
+        )
+        .await
+    }
+
+    pub async fn delete_book(&self, id: i32) -> Result<(), ClientError> {
+        self.execute(self.http.delete(self.url(&format!("/v1/books/{id}"))))
+            .await?;
+        Ok(())
+    }
+
+    /// The book in the language, e.g. `fr`, if it has a translation into it,
+    /// or else as it is
+    pub async fn get_book_in(&self, id: i32, language: &str) -> Result<LocalizedBook, ClientError> {
+        self.send(
+            self.http
+                .get(self.url(&format!("/v1/books/{id}")))
+                .query(&[("lang", language)]),
+        )
+        .await
+    }
+
+    pub async fn list_translations(&self, id: i32) -> Result<Vec<BookTranslation>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url(&format!("/v1/books/{id}/translations"))),
+        )
+        .await
+    }
+
+    /// Add or replace the book's translation into the language
+    pub async fn put_translation(
+        &self,
+        id: i32,
+        language: &str,
+        translation: &NewTranslation,
+    ) -> Result<BookTranslation, ClientError> {
+        self.send(
+            self.http
+                .put(self.url(&format!("/v1/books/{id}/translations/{language}")))
+                .json(translation),
+        )
+        .await
+    }
+
+    pub async fn delete_translation(&self, id: i32, language: &str) -> Result<(), ClientError> {
+        self.execute(
+            self.http
+                .delete(self.url(&format!("/v1/books/{id}/translations/{language}"))),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Every revision of the book, oldest first
+    pub async fn book_history(&self, id: i32) -> Result<Vec<BookChange>, ClientError> {
+        self.send(self.http.get(self.url(&format!("/v1/books/{id}/history"))))
+            .await
+    }
+
+    /// A page of the change feed, after the `next_cursor` of an earlier page
+    /// if given
+    pub async fn changes(
+        &self,
+        since: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<ChangeFeed, ClientError> {
+        let mut request = self.http.get(self.url("/v1/changes"));
+        if let Some(since) = since {
+            request = request.query(&[("since", since)]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        self.send(request).await
+    }
+
+    /// Counts of the books, by author and publisher, up to 30 seconds old
+    pub async fn catalog_stats(&self) -> Result<CatalogStats, ClientError> {
+        self.send(self.http.get(self.url("/v1/stats"))).await
+    }
+
+    /// The books grouped by a field, with a metric for each of the `limit`
+    /// largest groups
+    pub async fn aggregate_books(
+        &self,
+        group_by: BookField,
+        metric: Metric,
+        limit: Option<i64>,
+    ) -> Result<Aggregation, ClientError> {
+        let mut request = self
+            .http
+            .get(self.url("/v1/books/aggregate"))
+            .query(&[("group_by", group_by)])
+            .query(&[("metric", metric)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        self.send(request).await
+    }
+
+    /// The books viewed most in the `window`, e.g. `24h` or `7d`, most first
+    pub async fn trending_books(
+        &self,
+        window: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<TrendingBook>, ClientError> {
+        let mut request = self.http.get(self.url("/v1/books/trending"));
+        if let Some(window) = window {
+            request = request.query(&[("window", window)]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        self.send(request).await
+    }
+
+    /// Names and authors starting with what was typed, for a search box
+    pub async fn suggest_books(&self, q: &str) -> Result<Vec<Suggestion>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url("/v1/books/suggest"))
+                .query(&[("q", q)]),
+        )
+        .await
+    }
+
+    /// The books with every word searched for in their name or author, best
+    /// matches first
+    pub async fn search_books(&self, q: &str) -> Result<Vec<SearchResult>, ClientError> {
+        self.send(
+            self.http
+                .get(self.url("/v1/books/search"))
+                .query(&[("q", q)]),
+        )
+        .await
+    }
+
+    /// A book picked at random
+    pub async fn random_book(&self) -> Result<Book, ClientError> {
+        self.send(self.http.get(self.url("/v1/books/random"))).await
+    }
+
+    /// Check an ISBN, get it in both forms, and find the books with it
+    pub async fn check_isbn(&self, isbn: &str) -> Result<IsbnCheck, ClientError> {
+        self.send(
+            self.http
+                .post(self.url("/v1/tools/isbn"))
+                .json(&serde_json::json!({ "isbn": isbn })),
+        )
+        .await
+    }
+
+    /// Fill in the book's missing fields from its ISBN
+    pub async fn enrich_book(&self, id: i32) -> Result<Book, ClientError> {
+        self.send(self.http.post(self.url(&format!("/v1/books/{id}/enrich"))))
+            .await
+    }
+
+    /// Put the book back as it was at an earlier revision. Needs the admin
+    /// token.
+    pub async fn revert_book(&self, id: i32, revision: i32) -> Result<Book, ClientError> {
+        self.send(
+            self.http
+                .post(self.url(&format!("/v1/books/{id}/revert")))
+                .query(&[("revision", revision)]),
+        )
+        .await
+    }
+
+    /// Merge the book `duplicate` into the book `keep`, deleting it. Needs
+    /// the admin token.
+    pub async fn merge_books(&self, keep: i32, duplicate: i32) -> Result<Book, ClientError> {
+        self.send(
+            self.http
+                .post(self.url(&format!("/admin/books/{keep}/merge/{duplicate}"))),
+        )
+        .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        Ok(self.execute(request).await?.json().await?)
+    }
+
+    /// Send the request, turning error responses into `ClientError`s
+    async fn execute(&self, mut request: RequestBuilder) -> Result<Response, ClientError> {
+        if let Some(admin_token) = &self.admin_token {
+            request = request.bearer_auth(admin_token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        // Error bodies are JSON, like `{"error":"..."}`
+        let body = response.text().await?;
+        let message = serde_json::from_str::<ErrorBody>(&body)
+            .map(|body| body.error)
+            .unwrap_or(body);
+        Err(match status {
+            StatusCode::NOT_FOUND => ClientError::NotFound(message),
+            StatusCode::UNPROCESSABLE_ENTITY => ClientError::Invalid(message),
+            StatusCode::CONFLICT => ClientError::Conflict(message),
+            status => ClientError::Status { status, message },
+        })
+    }
+}
+
+/// The body of an error response
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiOptions;
+    use crate::memory::InMemoryBookRepo;
+    use crate::service::{BookService, DuplicatePolicy};
+    use crate::test_support::{spawn_test_app, spawn_test_app_with};
+
+    #[tokio::test]
+    async fn error_responses_are_mapped_to_client_errors() {
+        let app = spawn_test_app().await;
+        let client = BookstoreClient::new(&app.base_url);
+        let book = NewBook {
+            name: "Piranesi".to_string(),
+            author: "Susanna Clarke".to_string(),
+            ..NewBook::default()
+        };
+
+        let inserted = client.insert_book(&book).await.unwrap();
+        assert_eq!(client.get_book(inserted.id).await.unwrap(), inserted);
+        let history = client.book_history(inserted.id).await.unwrap();
+        assert_eq!(history[0].event.event_type(), "book_created");
+        let feed = client.changes(None, Some(10)).await.unwrap();
+        assert_eq!(feed.changes.len(), 1);
+
+        assert!(matches!(
+            client.get_book(99).await,
+            Err(ClientError::NotFound(_))
+        ));
+        let nameless = NewBook {
+            name: String::new(),
+            ..book.clone()
+        };
+        assert!(matches!(
+            client.insert_book(&nameless).await,
+            Err(ClientError::Invalid(_))
+        ));
+        let blocking = spawn_test_app_with(
+            BookService::new(InMemoryBookRepo::new()).with_duplicate_policy(DuplicatePolicy::Block),
+            ApiOptions::default(),
+        )
+        .await;
+        let blocking_client = BookstoreClient::new(&blocking.base_url);
+        blocking_client.insert_book(&book).await.unwrap();
+        assert!(matches!(
+            blocking_client.insert_book(&book).await,
+            Err(ClientError::Conflict(_))
+        ));
+
+        assert!(matches!(
+            client.revert_book(inserted.id, 1).await,
+            Err(ClientError::Status {
+                status: StatusCode::UNAUTHORIZED,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+/// The proxies, such as load balancers, whose `Forwarded` and
+/// `X-Forwarded-For` headers are believed. Headers from anyone else are
+/// ignored, as clients can put whatever they like in them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    /// Work out the client's address from the peer that connected and the
+    /// forwarding headers. Each trusted proxy appends the address it received
+    /// the request from, so the client is the last address in the chain that
+    /// isn't a trusted proxy.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+
+        let chain = forwarded_for(headers);
+        let mut client = peer;
+        for ip in chain.into_iter().rev() {
+            client = ip;
+            if !self.contains(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The addresses in the `Forwarded` header, or failing that in
+/// `X-Forwarded-For`, from the first hop to the last. Obfuscated or unknown
+/// addresses end the chain, as nothing before them can be trusted.
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded: Vec<&str> = headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .collect();
+
+    let addresses: Vec<&str> = if forwarded.is_empty() {
+        headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect()
+    } else {
+        forwarded
+    };
+
+    let parsed: Vec<Option<IpAddr>> = addresses.into_iter().map(parse_node).collect();
+    // Only keep the addresses after the last one that couldn't be parsed
+    let start = parsed
+        .iter()
+        .rposition(Option::is_none)
+        .map_or(0, |i| i + 1);
+    parsed[start..].iter().flatten().copied().collect()
+}
+
+/// Parse a node as written in either header: `192.0.2.1`, `192.0.2.1:4711`,
+/// `"[2001:db8::1]:4711"` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            node.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+/// The IP address of the client that made the request, taking trusted
+/// proxies into account. `None` for connections over a Unix socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<ClientIp>()
+            .copied()
+            .unwrap_or_else(|| {
+                ClientIp(
+                    parts
+                        .extensions
+                        .get::<ConnectInfo<SocketAddr>>()
+                        .map(|ConnectInfo(addr)| addr.ip()),
+                )
+            }))
+    }
+}
+
+/// Middleware that works out each request's `ClientIp`, for the access log
+/// and anything else that extracts it
+pub(crate) async fn resolve_client_ip(
+    State(trusted_proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client_ip = peer.map(|peer| trusted_proxies.client_ip(peer, request.headers()));
+    request.extensions_mut().insert(ClientIp(client_ip));
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn forwarding_headers_are_only_believed_from_trusted_proxies() {
+        let proxies = TrustedProxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.9, 10.1.1.1")]);
+
+        // From a trusted proxy, skipping the proxies in the chain
+        assert_eq!(
+            proxies.client_ip(ip("10.2.2.2"), &forwarded),
+            ip("203.0.113.9")
+        );
+        // From anyone else, the headers could be spoofed
+        assert_eq!(
+            proxies.client_ip(ip("198.51.100.7"), &forwarded),
+            ip("198.51.100.7")
+        );
+        // A client can't hide behind addresses it prepends itself
+        let spoofed = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.9")]);
+        assert_eq!(
+            proxies.client_ip(ip("10.2.2.2"), &spoofed),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn the_forwarded_header_takes_precedence() {
+        let proxies = TrustedProxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let both = headers(&[
+            (
+                "forwarded",
+                "for=\"[2001:db8::1]:4711\";proto=https, for=10.1.1.1",
+            ),
+            ("x-forwarded-for", "203.0.113.9"),
+        ]);
+        assert_eq!(proxies.client_ip(ip("10.2.2.2"), &both), ip("2001:db8::1"));
+
+        // Nothing before an obfuscated node can be trusted
+        let obfuscated = headers(&[("forwarded", "for=203.0.113.9, for=_hidden, for=10.1.1.1")]);
+        assert_eq!(
+            proxies.client_ip(ip("10.2.2.2"), &obfuscated),
+            ip("10.1.1.1")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::io::Write;
+
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
+use diesel::sql_types::BigInt;
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::database::{create_db_pool, export_books, DatabaseBookRepo};
+use crate::models::{Actor, NewBook};
+use crate::repo::BookRepo;
+use crate::Config;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
+
+/// Arbitrary key for the Postgres advisory lock held while applying
+/// migrations, so that concurrent deploys don't race each other
+const MIGRATION_LOCK_KEY: i64 = 0x626f6f6b73;
+
+pub type CommandError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateMode {
+    /// Apply any pending migrations
+    Apply,
+    /// Only report which migrations would be applied
+    DryRun,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub name: String,
+    pub applied: bool,
+}
+
+/// Run (or with `MigrateMode::DryRun`, just list) any pending DB migrations,
+/// returning the names of the migrations concerned
+pub async fn migrate(config: &Config, mode: MigrateMode) -> Result<Vec<String>, CommandError> {
+    let database_url = config.database_url.clone();
+
+    // Diesel's migration harness is synchronous
+    tokio::task::spawn_blocking(move || {
+        let mut conn = PgConnection::establish(&database_url)?;
+
+        if mode == MigrateMode::DryRun {
+            let pending = conn.pending_migrations(MIGRATIONS)?;
+            return Ok(pending.iter().map(|m| m.name().to_string()).collect());
+        }
+
+        diesel::sql_query("SELECT pg_advisory_lock($1)")
+            .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+            .execute(&mut conn)?;
+
+        // Check for pending migrations after acquiring the lock, in case
+        // another process has just applied them
+        let result = conn.pending_migrations(MIGRATIONS).and_then(|pending| {
+            let mut applied = Vec::new();
+            for migration in pending {
+                conn.run_migration(&migration)?;
+                applied.push(migration.name().to_string());
+            }
+            Ok(applied)
+        });
+
+        diesel::sql_query("SELECT pg_advisory_unlock($1)")
+            .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+            .execute(&mut conn)?;
+
+        result
+    })
+    .await?
+}
+
+/// List every migration known to this build, and whether it has been applied
+pub async fn migration_status(config: &Config) -> Result<Vec<MigrationStatus>, CommandError> {
+    let database_url = config.database_url.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = PgConnection::establish(&database_url)?;
+        let applied = conn.applied_migrations()?;
+        let migrations = MigrationSource::<Pg>::migrations(&MIGRATIONS)?;
+
+        Ok(migrations
+            .iter()
+            .map(|m| MigrationStatus {
+                name: m.name().to_string(),
+                applied: applied.contains(&m.name().version()),
+            })
+            .collect())
+    })
+    .await?
+}
+
+/// Insert some sample books, handy for local development and demos
+pub async fn seed(config: &Config) -> Result<usize, CommandError> {
+    let pool = create_db_pool(config.database_url.clone(), config.db_pool).await?;
+    let mut repo = DatabaseBookRepo::new(pool, config.slow_log.query);
+
+    let samples = [
+        ("Great Expectations", "Charles Dickens"),
+        ("Never Let Me Go", "Kazuo Ishiguro"),
+        ("The Left Hand of Darkness", "Ursula K. Le Guin"),
+        ("Middlemarch", "George Eliot"),
+        ("One Hundred Years of Solitude", "Gabriel García Márquez"),
+    ];
+
+    for (name, author) in samples {
+        let new_book = NewBook {
+            name: name.to_string(),
+            author: author.to_string(),
+            ..NewBook::default()
+        };
+        repo.insert_book(new_book, &Actor(Some("seed".to_string())))
+            .await?;
+    }
+
+    Ok(samples.len())
+}
+
+/// Write every book in the DB to `out` as a JSON array, returning how many
+/// books were exported
+pub async fn export(config: &Config, out: impl Write) -> Result<usize, CommandError> {
+    let pool = create_db_pool(config.database_url.clone(), config.db_pool).await?;
+
+    let books = export_books(&pool).await?;
+    serde_json::to_writer_pretty(out, &books)?;
+
+    Ok(books.len())
+}
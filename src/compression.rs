@@ -0,0 +1,63 @@
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Which responses are compressed, for clients that send `Accept-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Responses smaller than this many bytes aren't worth compressing
+    pub min_size: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig { min_size: 1024 }
+    }
+}
+
+impl CompressionConfig {
+    /// gzip or brotli, whichever the client prefers. Images, streams and
+    /// files that are already gzipped are never compressed.
+    pub(crate) fn layer(&self) -> CompressionLayer<impl Predicate> {
+        CompressionLayer::new().compress_when(
+            SizeAbove::new(self.min_size)
+                .and(NotForContentType::GRPC)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::SSE)
+                .and(NotForContentType::const_new("application/gzip")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn only_responses_over_the_minimum_size_are_compressed() {
+        let router = Router::new()
+            .route("/small", get(|| async { "x".repeat(10) }))
+            .route("/large", get(|| async { "x".repeat(2000) }))
+            .layer(CompressionConfig::default().layer());
+
+        let request = |path| {
+            Request::get(path)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let small = router.clone().oneshot(request("/small")).await.unwrap();
+        assert!(small.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let large = router.oneshot(request("/large")).await.unwrap();
+        assert_eq!(large.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+}
@@ -0,0 +1,102 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+
+/// Format a timestamp as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub(crate) fn http_date(time: DateTime<Utc>) -> HeaderValue {
+    HeaderValue::from_str(&time.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .expect("a formatted date is a valid header value")
+}
+
+fn parse_http_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    let value = value.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Middleware that answers a GET with `304 Not Modified` and no body when the
+/// response's `Last-Modified` is no later than the request's
+/// `If-Modified-Since`, so polling clients don't download unchanged books
+pub(crate) async fn if_modified_since(request: Request, next: Next) -> Response {
+    let if_modified_since = match *request.method() {
+        Method::GET | Method::HEAD => request
+            .headers()
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(parse_http_date),
+        _ => None,
+    };
+
+    let response = next.run(request).await;
+
+    let Some(if_modified_since) = if_modified_since else {
+        return response;
+    };
+    let last_modified = response
+        .headers()
+        .get(header::LAST_MODIFIED)
+        .and_then(parse_http_date);
+    if response.status() != StatusCode::OK
+        || last_modified.is_none_or(|last_modified| last_modified > if_modified_since)
+    {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.status = StatusCode::NOT_MODIFIED;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+    Response::from_parts(parts, Body::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+    use chrono::TimeZone;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unchanged_resources_are_not_sent_again() {
+        let updated_at = Utc.with_ymd_and_hms(2025, 3, 1, 12, 30, 15).unwrap();
+        let router =
+            Router::new()
+                .route(
+                    "/book",
+                    get(move || async move {
+                        ([(header::LAST_MODIFIED, http_date(updated_at))], "a book")
+                    }),
+                )
+                .route_layer(middleware::from_fn(if_modified_since));
+
+        let request = |since: &str| {
+            Request::get("/book")
+                .header(header::IF_MODIFIED_SINCE, since)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let unchanged = router
+            .clone()
+            .oneshot(request("Sat, 01 Mar 2025 12:30:15 GMT"))
+            .await
+            .unwrap();
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            unchanged.headers()[header::LAST_MODIFIED],
+            "Sat, 01 Mar 2025 12:30:15 GMT"
+        );
+
+        let changed = router
+            .oneshot(request("Sat, 01 Mar 2025 12:30:14 GMT"))
+            .await
+            .unwrap();
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+}
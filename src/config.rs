@@ -0,0 +1,998 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::backup::BackupConfig;
+use crate::body_limit::BodyLimits;
+use crate::cache_control::CacheTtls;
+use crate::client_ip::TrustedProxies;
+use crate::compression::CompressionConfig;
+use crate::cors::CorsConfig;
+use crate::database::PoolConfig;
+use crate::enrichment::{EnrichmentConfig, ProviderKind};
+use crate::events::EventBus;
+use crate::kafka::{KafkaConfig, DEFAULT_TOPIC};
+use crate::load_shed::ConcurrencyLimits;
+use crate::route_config::{BookRoute, RouteConfig, RouteSettings};
+use crate::service::DuplicatePolicy;
+use crate::slow_log::SlowLogThresholds;
+use crate::timeout::RequestTimeouts;
+use crate::tls::TlsConfig;
+use crate::{ListenerConfig, ServerOptions};
+use axum::http::{HeaderName, HeaderValue, Method};
+use cron::Schedule;
+use ipnet::IpNet;
+use object_store::ObjectStoreScheme;
+use tracing_subscriber::EnvFilter;
+use url::Url;
+
+/// The config file read when `CONFIG_FILE` is not set. It is fine for it not to exist.
+const DEFAULT_CONFIG_FILE: &str = "bookstore.toml";
+
+/// Every supported setting. Each one can be set in the config file using the
+/// key as written here, or overridden with an environment variable named after
+/// the upper-cased key, e.g. `database_url` -> `DATABASE_URL`.
+const KEYS: &[&str] = &[
+    "storage",
+    "database_url",
+    "db_pool_max_size",
+    "db_pool_min_idle",
+    "db_pool_warm_up",
+    "bind_address",
+    "unix_socket_path",
+    "tls_cert_path",
+    "tls_key_path",
+    "base_path",
+    "public_url",
+    "slow_request_threshold_ms",
+    "slow_query_threshold_ms",
+    "max_concurrent_requests",
+    "max_concurrent_requests_per_route",
+    "request_timeout_ms",
+    "list_request_timeout_ms",
+    "compression_min_size",
+    "max_json_body_size",
+    "cors_allowed_origins",
+    "cors_allowed_methods",
+    "cors_allowed_headers",
+    "cors_allow_credentials",
+    "cors_max_age_secs",
+    "cache_max_age_list_secs",
+    "cache_max_age_book_secs",
+    "trusted_proxies",
+    "log_format",
+    "log_filter",
+    "maintenance_mode",
+    "admin_token",
+    "backup_url",
+    "backup_retention",
+    "backup_schedule",
+    "kafka_brokers",
+    "kafka_topic",
+    "metadata_providers",
+    "google_books_api_key",
+    "duplicate_books",
+];
+
+/// The settings that can be overridden for each route in `BookRoute::ALL`,
+/// e.g. `routes.insert_book.timeout_ms` (`ROUTES_INSERT_BOOK_TIMEOUT_MS`)
+const ROUTE_KEYS: &[&str] = &[
+    "timeout_ms",
+    "max_json_body_size",
+    "max_concurrent_requests",
+    "require_admin_token",
+];
+
+/// The DB used when `database_url` is not set
+pub(crate) const DEFAULT_DATABASE_URL: &str = "postgres://localhost/bookstore";
+
+/// How many backups to keep when `backup_retention` is not set
+const DEFAULT_BACKUP_RETENTION: u32 = 7;
+
+/// Where the server keeps books
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Storage {
+    /// In the Postgres DB at `database_url`
+    #[default]
+    Database,
+    /// In memory, without a DB, so nothing is kept when the server stops
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// The subset of settings that can be changed without restarting, by sending
+/// SIGHUP or calling `POST /admin/reload-config`
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize)]
+pub struct RuntimeConfig {
+    /// Which logs to emit, using `RUST_LOG` syntax. If not set, the
+    /// `RUST_LOG` environment variable is used.
+    pub log_filter: Option<String>,
+    /// Reject all API requests with a 503
+    pub maintenance_mode: bool,
+    /// Feature flags, set in the `[flags]` section of the config file. Flags
+    /// toggled through the admin API take precedence.
+    pub flags: BTreeMap<String, bool>,
+}
+
+/// The application configuration, built from (in increasing order of
+/// precedence) defaults, the config file and environment variables
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub storage: Storage,
+    /// Not used to serve books with `Storage::Memory`, but still by commands
+    /// such as `migrate` and `seed`
+    pub database_url: String,
+    pub db_pool: PoolConfig,
+    pub listener: ListenerConfig,
+    pub tls: Option<TlsConfig>,
+    /// Where the API is mounted, e.g. `/api/bookstore`. Empty to serve it
+    /// from the root.
+    pub base_path: String,
+    /// Where the API is reached from outside, for the links in books' QR codes
+    pub public_url: Option<Url>,
+    pub slow_log: SlowLogThresholds,
+    pub concurrency: ConcurrencyLimits,
+    pub timeouts: RequestTimeouts,
+    pub compression: CompressionConfig,
+    pub body_limits: BodyLimits,
+    /// CORS is disabled unless `cors_allowed_origins` is set
+    pub cors: Option<CorsConfig>,
+    pub cache_ttls: CacheTtls,
+    /// Set in the `[routes.<name>]` sections of the config file
+    pub routes: RouteConfig,
+    pub trusted_proxies: TrustedProxies,
+    pub log_format: LogFormat,
+    /// Bearer token required by the `/admin` endpoints, which are disabled if this is not set
+    pub admin_token: Option<String>,
+    /// Where the `backup` command writes to. Backups are disabled if this is not set.
+    pub backup: Option<BackupConfig>,
+    /// Where book events are published. Disabled if `kafka_brokers` is not set.
+    pub kafka: Option<KafkaConfig>,
+    pub enrichment: EnrichmentConfig,
+    pub duplicate_policy: DuplicatePolicy,
+    pub runtime: RuntimeConfig,
+}
+
+/// Every problem found while loading the config, so they can all be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.problems {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Load the config file named by `CONFIG_FILE` (default `bookstore.toml`),
+    /// apply any environment variable overrides and validate the result
+    pub fn load() -> Result<Config, ConfigError> {
+        let (path, required) = match env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        let file_contents = match fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(_) if !required => None,
+            Err(e) => {
+                return Err(ConfigError {
+                    problems: vec![format!("could not read config file {path}: {e}")],
+                })
+            }
+        };
+
+        Config::from_sources(file_contents.as_deref(), |name| env::var(name).ok())
+    }
+
+    fn from_sources(
+        file_contents: Option<&str>,
+        env_var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+        let settings = Settings::load(file_contents, env_var, &mut problems);
+
+        let storage = match settings.get("storage") {
+            None | Some("database") => Storage::Database,
+            Some("memory") => Storage::Memory,
+            Some(other) => {
+                problems.push(format!(
+                    "storage must be \"database\" or \"memory\", got {other:?}"
+                ));
+                Storage::Database
+            }
+        };
+
+        let database_url = settings
+            .get("database_url")
+            .unwrap_or(DEFAULT_DATABASE_URL)
+            .to_string();
+        if !database_url.starts_with("postgres://") && !database_url.starts_with("postgresql://") {
+            problems.push(format!(
+                "database_url must be a postgres:// connection string, got {database_url:?}"
+            ));
+        }
+
+        let pool_defaults = PoolConfig::default();
+        let db_pool = PoolConfig {
+            max_size: settings
+                .number("db_pool_max_size", &mut problems)
+                .unwrap_or(pool_defaults.max_size),
+            min_idle: settings.number("db_pool_min_idle", &mut problems),
+            warm_up: settings
+                .flag("db_pool_warm_up", &mut problems)
+                .unwrap_or(pool_defaults.warm_up),
+        };
+        if db_pool.max_size == 0 {
+            problems.push("db_pool_max_size must be at least 1".to_string());
+        }
+        if db_pool
+            .min_idle
+            .is_some_and(|min_idle| min_idle > db_pool.max_size)
+        {
+            problems.push("db_pool_min_idle must not be larger than db_pool_max_size".to_string());
+        }
+
+        let listener = if settings.systemd_socket_activation {
+            ListenerConfig::Systemd
+        } else if let Some(path) = settings.get("unix_socket_path") {
+            ListenerConfig::Unix(PathBuf::from(path))
+        } else {
+            match settings.get("bind_address") {
+                Some(addr) => match addr.parse::<SocketAddr>() {
+                    Ok(addr) => ListenerConfig::Tcp(addr),
+                    Err(_) => {
+                        problems.push(format!(
+                            "bind_address must be an address like 127.0.0.1:3000, got {addr:?}"
+                        ));
+                        ListenerConfig::default()
+                    }
+                },
+                None => ListenerConfig::default(),
+            }
+        };
+
+        let tls = match (settings.get("tls_cert_path"), settings.get("tls_key_path")) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path: PathBuf::from(cert_path),
+                key_path: PathBuf::from(key_path),
+            }),
+            (None, None) => None,
+            _ => {
+                problems.push("tls_cert_path and tls_key_path must be set together".to_string());
+                None
+            }
+        };
+        if let Some(tls) = &tls {
+            for path in [&tls.cert_path, &tls.key_path] {
+                if !path.is_file() {
+                    problems.push(format!("TLS file {} does not exist", path.display()));
+                }
+            }
+        }
+        if tls.is_some() && matches!(listener, ListenerConfig::Unix(_)) {
+            problems.push("TLS is not supported together with unix_socket_path".to_string());
+        }
+
+        // Gateways routing on a path prefix pass it on, so the routes are
+        // served under it
+        let base_path = settings
+            .get("base_path")
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+        if !base_path.is_empty()
+            && (!base_path.starts_with('/')
+                || base_path.contains(|c: char| "?#{}".contains(c) || !c.is_ascii_graphic()))
+        {
+            problems.push(format!(
+                "base_path must be a path like /api/bookstore, got {base_path:?}"
+            ));
+        }
+
+        // Only the origin: the path the API is mounted at comes from the request
+        let public_url = settings.get("public_url").and_then(|url| {
+            match Url::parse(url) {
+                Ok(parsed)
+                    if matches!(parsed.scheme(), "http" | "https")
+                        && parsed.has_host()
+                        && parsed.path() == "/"
+                        && parsed.query().is_none() =>
+                {
+                    Some(parsed)
+                }
+                _ => {
+                    problems.push(format!(
+                        "public_url must be an http(s) URL with no path, like https://books.example.com, got {url:?}"
+                    ));
+                    None
+                }
+            }
+        });
+
+        let defaults = SlowLogThresholds::default();
+        let slow_log = SlowLogThresholds {
+            request: settings
+                .millis("slow_request_threshold_ms", &mut problems)
+                .unwrap_or(defaults.request),
+            query: settings
+                .millis("slow_query_threshold_ms", &mut problems)
+                .unwrap_or(defaults.query),
+        };
+
+        let concurrency = ConcurrencyLimits {
+            global: settings
+                .number("max_concurrent_requests", &mut problems)
+                .map(|limit| limit as usize),
+            per_route: settings
+                .number("max_concurrent_requests_per_route", &mut problems)
+                .map(|limit| limit as usize),
+        };
+        if concurrency.global == Some(0) || concurrency.per_route == Some(0) {
+            problems.push("concurrent request limits must be at least 1".to_string());
+        }
+
+        let timeout_defaults = RequestTimeouts::default();
+        let timeouts = RequestTimeouts {
+            default: settings
+                .millis("request_timeout_ms", &mut problems)
+                .unwrap_or(timeout_defaults.default),
+            list: settings
+                .millis("list_request_timeout_ms", &mut problems)
+                .unwrap_or(timeout_defaults.list),
+        };
+        if timeouts.default.is_zero() || timeouts.list.is_zero() {
+            problems.push("request timeouts must be greater than 0".to_string());
+        }
+
+        let mut compression = CompressionConfig::default();
+        if let Some(min_size) = settings.number("compression_min_size", &mut problems) {
+            match u16::try_from(min_size) {
+                Ok(min_size) => compression.min_size = min_size,
+                Err(_) => problems.push("compression_min_size must be at most 65535".to_string()),
+            }
+        }
+
+        let body_limits = BodyLimits {
+            json: settings
+                .number("max_json_body_size", &mut problems)
+                .map_or(BodyLimits::default().json, |size| size as usize),
+        };
+
+        let cors = settings
+            .get("cors_allowed_origins")
+            .map(|origins| cors_config(origins, &settings, &mut problems));
+
+        let cache_ttls = CacheTtls {
+            list: settings
+                .number("cache_max_age_list_secs", &mut problems)
+                .map(|secs| Duration::from_secs(secs.into())),
+            book: settings
+                .number("cache_max_age_book_secs", &mut problems)
+                .map(|secs| Duration::from_secs(secs.into())),
+        };
+
+        // Bare addresses are taken to be single hosts
+        let trusted_proxies = TrustedProxies(
+            settings
+                .list("trusted_proxies", &mut problems, |proxy| {
+                    proxy
+                        .parse::<IpNet>()
+                        .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                        .ok()
+                })
+                .unwrap_or_default(),
+        );
+
+        let log_format = match settings.get("log_format") {
+            None | Some("text") => LogFormat::Text,
+            Some("json") => LogFormat::Json,
+            Some(other) => {
+                problems.push(format!(
+                    "log_format must be \"text\" or \"json\", got {other:?}"
+                ));
+                LogFormat::Text
+            }
+        };
+
+        let log_filter = settings.get("log_filter").map(str::to_string);
+        if let Some(Err(e)) = log_filter.as_deref().map(EnvFilter::try_new) {
+            problems.push(format!("log_filter is not a valid filter: {e}"));
+        }
+
+        let maintenance_mode = settings
+            .flag("maintenance_mode", &mut problems)
+            .unwrap_or(false);
+
+        let mut flags = BTreeMap::new();
+        for key in settings.keys_in(FLAGS_SECTION) {
+            let name = &key[FLAGS_SECTION.len()..];
+            if !valid_flag_name(name) {
+                problems.push(format!(
+                    "{key} must be named with lower-case letters, digits and underscores"
+                ));
+            } else if let Some(enabled) = settings.flag(key, &mut problems) {
+                flags.insert(name.to_string(), enabled);
+            }
+        }
+
+        let admin_token = settings.get("admin_token").map(str::to_string);
+        if admin_token.as_deref() == Some("") {
+            problems.push("admin_token must not be empty".to_string());
+        }
+
+        let mut routes = RouteConfig::default();
+        for route in BookRoute::ALL {
+            let key = |setting| format!("routes.{}.{setting}", route.name());
+            let route_settings = RouteSettings {
+                timeout: settings.millis(&key("timeout_ms"), &mut problems),
+                max_json_body_size: settings
+                    .number(&key("max_json_body_size"), &mut problems)
+                    .map(|size| size as usize),
+                max_concurrent_requests: settings
+                    .number(&key("max_concurrent_requests"), &mut problems)
+                    .map(|limit| limit as usize),
+                require_admin_token: settings
+                    .flag(&key("require_admin_token"), &mut problems)
+                    .unwrap_or(false),
+            };
+            if route_settings
+                .timeout
+                .is_some_and(|timeout| timeout.is_zero())
+            {
+                problems.push(format!("{} must be greater than 0", key("timeout_ms")));
+            }
+            if route_settings.max_concurrent_requests == Some(0) {
+                problems.push(format!(
+                    "{} must be at least 1",
+                    key("max_concurrent_requests")
+                ));
+            }
+            routes.set(route, route_settings);
+        }
+        if routes.requires_admin_token() && admin_token.is_none() {
+            problems
+                .push("require_admin_token is set on a route but admin_token is not".to_string());
+        }
+
+        let backup_retention = settings
+            .number("backup_retention", &mut problems)
+            .unwrap_or(DEFAULT_BACKUP_RETENTION);
+        if backup_retention == 0 {
+            problems.push("backup_retention must be at least 1".to_string());
+        }
+        let backup_schedule = settings.get("backup_schedule").and_then(|schedule| {
+            match schedule.parse::<Schedule>() {
+                Ok(schedule) => Some(schedule),
+                Err(e) => {
+                    problems.push(format!(
+                        "backup_schedule must be a cron expression like \"0 0 2 * * *\": {e}"
+                    ));
+                    None
+                }
+            }
+        });
+        if backup_schedule.is_some() && settings.get("backup_url").is_none() {
+            problems.push("backup_schedule requires backup_url to be set".to_string());
+        }
+        let backup = settings.get("backup_url").and_then(|url| {
+            match Url::parse(url).map(|url| (ObjectStoreScheme::parse(&url).is_ok(), url)) {
+                Ok((true, url)) => Some(BackupConfig {
+                    url,
+                    retention: backup_retention,
+                    schedule: backup_schedule,
+                }),
+                _ => {
+                    problems.push(format!(
+                        "backup_url must be a URL like s3://bucket/path or file:///path, got {url:?}"
+                    ));
+                    None
+                }
+            }
+        });
+
+        let kafka = settings.get("kafka_brokers").map(|brokers| KafkaConfig {
+            brokers: brokers.to_string(),
+            topic: settings
+                .get("kafka_topic")
+                .unwrap_or(DEFAULT_TOPIC)
+                .to_string(),
+        });
+        if kafka.is_some() && !cfg!(feature = "kafka") {
+            problems.push(
+                "kafka_brokers is set, but this build doesn't include the kafka feature"
+                    .to_string(),
+            );
+        }
+        if kafka.is_none() && settings.get("kafka_topic").is_some() {
+            problems.push("kafka_topic requires kafka_brokers to be set".to_string());
+        }
+
+        let enrichment = EnrichmentConfig {
+            providers: settings
+                .list("metadata_providers", &mut problems, ProviderKind::from_name)
+                .unwrap_or_else(|| EnrichmentConfig::default().providers),
+            google_books_api_key: settings.get("google_books_api_key").map(str::to_string),
+        };
+
+        let duplicate_policy = match settings.get("duplicate_books") {
+            None | Some("warn") => DuplicatePolicy::Warn,
+            Some("allow") => DuplicatePolicy::Allow,
+            Some("block") => DuplicatePolicy::Block,
+            Some(other) => {
+                problems.push(format!(
+                    "duplicate_books must be \"allow\", \"warn\" or \"block\", got {other:?}"
+                ));
+                DuplicatePolicy::Warn
+            }
+        };
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        Ok(Config {
+            storage,
+            database_url,
+            db_pool,
+            listener,
+            tls,
+            base_path,
+            public_url,
+            slow_log,
+            concurrency,
+            timeouts,
+            compression,
+            body_limits,
+            cors,
+            cache_ttls,
+            routes,
+            trusted_proxies,
+            log_format,
+            admin_token,
+            backup,
+            kafka,
+            enrichment,
+            duplicate_policy,
+            runtime: RuntimeConfig {
+                log_filter,
+                maintenance_mode,
+                flags,
+            },
+        })
+    }
+
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            listener: self.listener.clone(),
+            db_pool: self.db_pool,
+            slow_log: self.slow_log,
+            concurrency: self.concurrency,
+            timeouts: self.timeouts,
+            compression: self.compression,
+            body_limits: self.body_limits,
+            cors: self.cors.clone(),
+            cache_ttls: self.cache_ttls,
+            routes: self.routes.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            tls: self.tls.clone(),
+            base_path: self.base_path.clone(),
+            public_url: self.public_url.clone(),
+            admin_token: self.admin_token.clone(),
+            backup: self.backup.clone(),
+            kafka: self.kafka.clone(),
+            enrichment: self.enrichment.clone(),
+            duplicate_policy: self.duplicate_policy,
+            runtime: self.runtime.clone(),
+            events: EventBus::default(),
+            log_filter_handle: None,
+        }
+    }
+}
+
+/// Build the CORS config once `cors_allowed_origins` is known to be set.
+/// Each list setting is comma-separated.
+fn cors_config(origins: &str, settings: &Settings, problems: &mut Vec<String>) -> CorsConfig {
+    let allowed_origins = if origins.trim() == "*" {
+        None
+    } else {
+        Some(
+            settings
+                .list("cors_allowed_origins", problems, |origin| {
+                    HeaderValue::from_str(origin).ok()
+                })
+                .unwrap_or_default(),
+        )
+    };
+
+    let mut cors = CorsConfig::new(allowed_origins);
+    if let Some(methods) = settings.list("cors_allowed_methods", problems, |method| {
+        Method::from_bytes(method.to_ascii_uppercase().as_bytes()).ok()
+    }) {
+        cors.allowed_methods = methods;
+    }
+    if let Some(headers) = settings.list("cors_allowed_headers", problems, |name| {
+        HeaderName::from_str(name).ok()
+    }) {
+        cors.allowed_headers = headers;
+    }
+    cors.allow_credentials = settings
+        .flag("cors_allow_credentials", problems)
+        .unwrap_or(false);
+    cors.max_age = settings
+        .number("cors_max_age_secs", problems)
+        .map(|secs| Duration::from_secs(secs.into()));
+
+    if cors.allow_credentials && cors.allowed_origins.is_none() {
+        problems.push(
+            "cors_allow_credentials can't be used when cors_allowed_origins is \"*\"".to_string(),
+        );
+    }
+
+    cors
+}
+
+/// The raw string value of each setting, after merging the file and the environment
+struct Settings {
+    values: Vec<(String, String)>,
+    systemd_socket_activation: bool,
+}
+
+impl Settings {
+    fn load(
+        file_contents: Option<&str>,
+        env_var: impl Fn(&str) -> Option<String>,
+        problems: &mut Vec<String>,
+    ) -> Settings {
+        let mut values = Vec::new();
+
+        if let Some(contents) = file_contents {
+            match contents.parse::<toml::Table>() {
+                Ok(table) => flatten("", table, &mut values, problems),
+                Err(e) => problems.push(format!("config file is not valid TOML: {e}")),
+            }
+        }
+
+        for key in known_keys() {
+            if let Some(value) = env_var(&key.to_uppercase().replace('.', "_")) {
+                values.retain(|(k, _)| *k != key);
+                values.push((key, value));
+            }
+        }
+
+        Settings {
+            values,
+            systemd_socket_activation: env_var("LISTEN_FDS").is_some(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parse each item of a comma-separated list, reporting any that `parse` rejects
+    fn list<T>(
+        &self,
+        key: &str,
+        problems: &mut Vec<String>,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        let value = self.get(key)?;
+        let mut items = Vec::new();
+        for item in value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match parse(item) {
+                Some(parsed) => items.push(parsed),
+                None => problems.push(format!("{key} has an invalid entry: {item:?}")),
+            }
+        }
+        Some(items)
+    }
+
+    /// The keys set in a section, e.g. `flags.`
+    fn keys_in<'a>(&'a self, section: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .filter(move |key| key.starts_with(section))
+    }
+
+    fn flag(&self, key: &str, problems: &mut Vec<String>) -> Option<bool> {
+        match self.get(key)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            other => {
+                problems.push(format!("{key} must be true or false, got {other:?}"));
+                None
+            }
+        }
+    }
+
+    fn number(&self, key: &str, problems: &mut Vec<String>) -> Option<u32> {
+        let value = self.get(key)?;
+        match value.parse::<u32>() {
+            Ok(number) => Some(number),
+            Err(_) => {
+                problems.push(format!("{key} must be a whole number, got {value:?}"));
+                None
+            }
+        }
+    }
+
+    fn millis(&self, key: &str, problems: &mut Vec<String>) -> Option<Duration> {
+        let value = self.get(key)?;
+        match value.parse::<u64>() {
+            Ok(millis) => Some(Duration::from_millis(millis)),
+            Err(_) => {
+                problems.push(format!(
+                    "{key} must be a number of milliseconds, got {value:?}"
+                ));
+                None
+            }
+        }
+    }
+}
+
+/// The section of the config file where feature flags are set. Any key can
+/// be set in it, but only in the file, not by environment variables.
+const FLAGS_SECTION: &str = "flags.";
+
+/// Flag names are also used in URLs, so are kept simple
+pub(crate) fn valid_flag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Every key that can be set, including the per-route ones
+fn known_keys() -> Vec<String> {
+    let route_keys = BookRoute::ALL.into_iter().flat_map(|route| {
+        ROUTE_KEYS
+            .iter()
+            .map(move |setting| format!("routes.{}.{setting}", route.name()))
+    });
+    KEYS.iter()
+        .map(|key| key.to_string())
+        .chain(route_keys)
+        .collect()
+}
+
+/// Collect the settings in a TOML table, naming those in nested tables with
+/// dotted keys, e.g. `routes.insert_book.timeout_ms`
+fn flatten(
+    prefix: &str,
+    table: toml::Table,
+    values: &mut Vec<(String, String)>,
+    problems: &mut Vec<String>,
+) {
+    let known_keys = known_keys();
+    for (key, value) in table {
+        let key = format!("{prefix}{key}");
+        let value = match value {
+            toml::Value::Table(table) => {
+                let prefix = format!("{key}.");
+                if prefix == FLAGS_SECTION || known_keys.iter().any(|k| k.starts_with(&prefix)) {
+                    flatten(&prefix, table, values, problems);
+                } else {
+                    problems.push(format!("unknown section in config file: {key}"));
+                }
+                continue;
+            }
+            toml::Value::String(s) => s,
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => {
+                problems.push(format!("{key} must be a string, an integer or a boolean"));
+                continue;
+            }
+        };
+        if known_keys.contains(&key) || key.starts_with(FLAGS_SECTION) {
+            values.push((key, value));
+        } else {
+            problems.push(format!("unknown setting in config file: {key}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn load(file: Option<&str>, env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_sources(file, |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn defaults_are_used_when_nothing_is_configured() {
+        let config = load(None, &[]).unwrap();
+
+        assert_eq!(config.storage, Storage::Database);
+        assert_eq!(config.database_url, "postgres://localhost/bookstore");
+        assert!(matches!(config.listener, ListenerConfig::Tcp(addr) if addr.port() == 3000));
+        assert!(config.tls.is_none());
+        assert_eq!(config.slow_log.request, Duration::from_millis(500));
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert_eq!(config.runtime, RuntimeConfig::default());
+    }
+
+    #[test]
+    fn runtime_settings_can_be_set_in_the_config_file() {
+        let file = r#"
+            log_filter = "info,access_log=off"
+            maintenance_mode = true
+
+            [flags]
+            new_search = true
+            reviews = false
+        "#;
+
+        let config = load(Some(file), &[]).unwrap();
+
+        assert_eq!(
+            config.runtime.log_filter.as_deref(),
+            Some("info,access_log=off")
+        );
+        assert!(config.runtime.maintenance_mode);
+        assert_eq!(
+            config.runtime.flags,
+            BTreeMap::from([
+                ("new_search".to_string(), true),
+                ("reviews".to_string(), false)
+            ])
+        );
+
+        let file = r#"
+            [flags]
+            New-Search = true
+            reviews = "soon"
+        "#;
+        assert_eq!(load(Some(file), &[]).unwrap_err().problems.len(), 2);
+    }
+
+    #[test]
+    fn environment_variables_override_the_config_file() {
+        let file = r#"
+            database_url = "postgres://file/bookstore"
+            bind_address = "0.0.0.0:8080"
+            slow_query_threshold_ms = 50
+        "#;
+        let env = [("DATABASE_URL", "postgres://env/bookstore")];
+
+        let config = load(Some(file), &env).unwrap();
+
+        assert_eq!(config.database_url, "postgres://env/bookstore");
+        assert!(matches!(config.listener, ListenerConfig::Tcp(addr) if addr.port() == 8080));
+        assert_eq!(config.slow_log.query, Duration::from_millis(50));
+
+        let config = load(None, &[("BASE_PATH", "/api/bookstore/")]).unwrap();
+        assert_eq!(config.base_path, "/api/bookstore");
+
+        let config = load(Some(r#"storage = "database""#), &[("STORAGE", "memory")]).unwrap();
+        assert_eq!(config.storage, Storage::Memory);
+
+        let config = load(None, &[("DB_POOL_WARM_UP", "true")]).unwrap();
+        assert!(config.db_pool.warm_up);
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let file = r#"
+            bind_address = "not an address"
+            colour = "blue"
+        "#;
+        let env = [
+            ("DATABASE_URL", "mysql://localhost/bookstore"),
+            ("DB_POOL_MAX_SIZE", "4"),
+            ("DB_POOL_MIN_IDLE", "8"),
+            ("TLS_CERT_PATH", "/etc/cert.pem"),
+            ("SLOW_REQUEST_THRESHOLD_MS", "soon"),
+            ("LOG_FORMAT", "xml"),
+            ("MAINTENANCE_MODE", "maybe"),
+            ("BACKUP_URL", "ftp://example.com/backups"),
+            ("BACKUP_SCHEDULE", "every night"),
+            ("BASE_PATH", "api/bookstore"),
+            ("PUBLIC_URL", "https://books.example.com/api"),
+            ("STORAGE", "sqlite"),
+        ];
+
+        let error = load(Some(file), &env).unwrap_err();
+
+        assert_eq!(error.problems.len(), 13, "{error}");
+    }
+
+    #[test]
+    fn cors_lists_are_comma_separated() {
+        let env = [
+            (
+                "CORS_ALLOWED_ORIGINS",
+                "https://a.example.com, https://b.example.com",
+            ),
+            ("CORS_ALLOWED_METHODS", "get,post"),
+            ("CORS_ALLOW_CREDENTIALS", "true"),
+        ];
+
+        let cors = load(None, &env).unwrap().cors.unwrap();
+
+        assert_eq!(cors.allowed_origins.unwrap().len(), 2);
+        assert_eq!(cors.allowed_methods, vec![Method::GET, Method::POST]);
+        assert!(cors.allow_credentials);
+
+        let env = [
+            ("CORS_ALLOWED_ORIGINS", "*"),
+            ("CORS_ALLOW_CREDENTIALS", "true"),
+        ];
+        assert_eq!(load(None, &env).unwrap_err().problems.len(), 1);
+    }
+
+    #[test]
+    fn routes_can_be_configured_individually() {
+        let file = r#"
+            admin_token = "s3cret"
+
+            [routes.insert_book]
+            timeout_ms = 2000
+            max_json_body_size = 1024
+            require_admin_token = true
+        "#;
+        let env = [("ROUTES_LIST_BOOKS_MAX_CONCURRENT_REQUESTS", "2")];
+
+        let routes = load(Some(file), &env).unwrap().routes;
+
+        assert_eq!(
+            routes.get(BookRoute::InsertBook),
+            RouteSettings {
+                timeout: Some(Duration::from_secs(2)),
+                max_json_body_size: Some(1024),
+                max_concurrent_requests: None,
+                require_admin_token: true,
+            }
+        );
+        assert_eq!(
+            routes.get(BookRoute::ListBooks).max_concurrent_requests,
+            Some(2)
+        );
+        assert_eq!(routes.get(BookRoute::GetBook), RouteSettings::default());
+
+        let file = r#"
+            [routes.insert_book]
+            require_admin_token = true
+            colour = "blue"
+
+            [routes.search]
+            timeout_ms = 10
+        "#;
+        assert_eq!(load(Some(file), &[]).unwrap_err().problems.len(), 3);
+    }
+}
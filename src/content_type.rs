@@ -0,0 +1,79 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// Middleware that rejects a request whose body isn't declared as JSON with
+/// a 415, before anything tries to parse it. Parameters such as
+/// `; charset=utf-8` are allowed.
+pub(crate) async fn require_json(request: Request, next: Next) -> Response {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let is_json = content_type.is_some_and(|content_type| {
+        let mime_type = content_type.split(';').next().unwrap_or_default();
+        mime_type.trim().eq_ignore_ascii_case("application/json")
+    });
+
+    if is_json {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(json!({
+                "error": "The request body must be JSON, with Content-Type: application/json",
+                "content_type": content_type,
+            })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn only_json_bodies_are_accepted() {
+        let router = Router::new()
+            .route("/books", post(|| async {}))
+            .route_layer(middleware::from_fn(require_json));
+        let status = |content_type: Option<&str>| {
+            let mut request = Request::post("/books");
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            let router = router.clone();
+            async move {
+                router
+                    .oneshot(request.body(Body::from("{}")).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(status(Some("application/json")).await, StatusCode::OK);
+        assert_eq!(
+            status(Some("Application/JSON; charset=utf-8")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(Some("text/plain")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            status(Some("application/jsonp")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(status(None).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Lets browser frontends on other origins call the API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins allowed to make requests, e.g. `https://books.example.com`.
+    /// `None` allows any origin.
+    pub allowed_origins: Option<Vec<HeaderValue>>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    /// Allow requests with cookies or HTTP authentication. This can't be
+    /// combined with allowing any origin.
+    pub allow_credentials: bool,
+    /// How long browsers may cache the result of a preflight request
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    /// A config allowing the given origins, with the default methods and headers
+    pub fn new(allowed_origins: Option<Vec<HeaderValue>>) -> Self {
+        CorsConfig {
+            allowed_origins,
+            allowed_methods: vec![Method::GET, Method::POST, Method::PUT, Method::DELETE],
+            allowed_headers: vec![
+                axum::http::header::CONTENT_TYPE,
+                axum::http::header::AUTHORIZATION,
+            ],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+
+    pub(crate) fn layer(&self) -> CorsLayer {
+        let allow_origin = match &self.allowed_origins {
+            Some(origins) => AllowOrigin::list(origins.iter().cloned()),
+            None => AllowOrigin::any(),
+        };
+
+        let layer = CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(self.allowed_methods.clone())
+            .allow_headers(self.allowed_headers.clone())
+            .allow_credentials(self.allow_credentials)
+            // So browser clients can report the request ID when something goes wrong
+            .expose_headers([HeaderName::from_static("x-request-id")]);
+
+        match self.max_age {
+            Some(max_age) => layer.max_age(max_age),
+            None => layer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn preflight_requests_from_allowed_origins_are_answered() {
+        let mut config = CorsConfig::new(Some(vec![HeaderValue::from_static(
+            "https://books.example.com",
+        )]));
+        config.max_age = Some(Duration::from_secs(600));
+        let router = Router::new()
+            .route("/books", get(|| async {}))
+            .layer(config.layer());
+
+        let preflight = |origin| {
+            Request::options("/books")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let allowed = router
+            .clone()
+            .oneshot(preflight("https://books.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(
+            allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://books.example.com"
+        );
+        assert_eq!(allowed.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let other = router
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(other
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+}
@@ -1,24 +1,123 @@
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
+use std::time::{Duration, Instant};
 
-use crate::models::{Book, NewBook};
-use crate::repo::BookRepo;
-use crate::schema::books;
+use crate::enrichment::needs_enrichment;
+use crate::events::{BookChange, BookCreated, BookDeleted, BookEvent, BookUpdated};
+use crate::filter::{Comparison, Filter, FilterField, FilterValues, TextField};
+use crate::job_queue::{enqueue, Job};
+use crate::models::{
+    Actor, Book, BookTranslation, NewBook, NewTranslation, NewWebhook, Webhook, WebhookDelivery,
+};
+use crate::repo::{id_at, BookRepo};
+use crate::schema::{
+    book_revisions, book_slugs, book_translations, book_views, books, feature_flags, job_leases,
+    outbox, webhook_deliveries, webhooks,
+};
+use crate::search::SearchResult;
+use crate::slug::{first_free, has_base, slugify};
+use crate::sort::SortSpec;
+use crate::stats::{BookField, Group, Metric};
+use crate::suggest::{Suggestion, SuggestionKind};
+use crate::views::TrendingBook;
 use bb8::Pool;
-use diesel::{OptionalExtension, QueryDsl, SelectableHelper};
+use chrono::{DateTime, Utc};
+use diesel::expression::BoxableExpression;
+use diesel::pg::Pg;
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_types::{Array, BigInt, Bool, Nullable, Text};
+use diesel::upsert::excluded;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods,
+    OptionalExtension, PgArrayExpressionMethods, PgTextExpressionMethods, QueryDsl,
+    SelectableHelper, TextExpressionMethods,
+};
 use diesel_async::{
-    pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection, RunQueryDsl,
+    pooled_connection::{AsyncDieselConnectionManager, PoolError},
+    scoped_futures::ScopedFutureExt,
+    AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use tracing::warn;
 
 pub type DBPool = bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>;
 
-pub async fn create_db_pool(connection_string: String) -> DBPool {
+/// Sizing for the DB connection pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    /// Connections to keep open even when idle. These are established when
+    /// the pool is created, so a bad connection string fails fast at startup.
+    pub min_idle: Option<u32>,
+    /// Before the server starts serving, run the most common queries on each
+    /// of the `min_idle` connections (or one, if not set), so that they are
+    /// already prepared for the first requests
+    pub warm_up: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: None,
+            warm_up: false,
+        }
+    }
+}
+
+pub async fn create_db_pool(
+    connection_string: String,
+    pool_config: PoolConfig,
+) -> Result<DBPool, PoolError> {
     let config =
         AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(connection_string);
     Pool::builder()
+        .max_size(pool_config.max_size)
+        .min_idle(pool_config.min_idle)
         .build(config)
         .await
-        .expect("Failed to create DB connection pool")
+}
+
+/// Prepare the statements for `get_book` and `list_books` on `connections`
+/// connections, which are all checked out at once so that each is a
+/// different one. They go back to the pool afterwards.
+pub(crate) async fn warm_up_pool(pool: &DBPool, connections: u32) -> Result<(), DatabaseError> {
+    let mut conns = Vec::new();
+    for _ in 0..connections {
+        conns.push(pool.get().await?);
+    }
+    for conn in &mut conns {
+        // The limit and ID are bound, so these are the same statements as
+        // the real queries, which find nothing
+        books::table
+            .find(0)
+            .select(Book::as_select())
+            .first(&mut **conn)
+            .await
+            .optional()?;
+        books::table
+            .select(Book::as_select())
+            .limit(0)
+            .load(&mut **conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Load every book, ordered by ID. Unlike `list_books`, this is not limited
+/// to one page of results.
+pub async fn export_books(pool: &DBPool) -> Result<Vec<Book>, DatabaseError> {
+    let mut conn = pool.get().await?;
+
+    let books = books::table
+        .select(Book::as_select())
+        .order(books::id.asc())
+        .load(&mut conn)
+        .await?;
+
+    Ok(books)
 }
 
 #[derive(Debug)]
@@ -43,7 +142,10 @@ impl fmt::Display for DatabaseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DatabaseError::PoolError(e) => {
-                write!(f, "problem getting a connection from the connection pool: {e}")
+                write!(
+                    f,
+                    "problem getting a connection from the connection pool: {e}"
+                )
             }
             DatabaseError::ResultError(e) => {
                 write!(f, "problem executing a statement against the DB: {e}")
@@ -64,16 +166,170 @@ impl Error for DatabaseError {
 #[derive(Clone)]
 pub struct DatabaseBookRepo {
     pool: DBPool,
+    slow_query_threshold: Duration,
 }
 
 impl DatabaseBookRepo {
-    pub fn new(pool: DBPool) -> Self {
-        DatabaseBookRepo { pool }
+    pub fn new(pool: DBPool, slow_query_threshold: Duration) -> Self {
+        DatabaseBookRepo {
+            pool,
+            slow_query_threshold,
+        }
+    }
+
+    /// Log a warning if a query (including the time spent waiting for a
+    /// connection) took longer than the slow query threshold
+    fn warn_if_slow(&self, started: Instant, query: fmt::Arguments) {
+        let elapsed = started.elapsed();
+        if elapsed > self.slow_query_threshold {
+            warn!(
+                query = %query,
+                elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+                threshold_ms = self.slow_query_threshold.as_millis() as u64,
+                "slow DB query"
+            );
+        }
     }
 }
 
+/// A filter compiled to SQL, with `NULL` where it compares a missing value
+type Predicate = Box<dyn BoxableExpression<books::table, Pg, SqlType = Nullable<Bool>>>;
+
+/// The predicate comparing a column of IDs or times with the values given
+macro_rules! ordered_predicate {
+    ($column:expr, $comparison:expr, $values:expr) => {{
+        let values = $values.clone();
+        let value = values[0];
+        let predicate: Predicate = match $comparison {
+            Comparison::Eq => Box::new($column.eq(value).nullable()),
+            Comparison::Ne => Box::new($column.ne(value).nullable()),
+            Comparison::Lt => Box::new($column.lt(value).nullable()),
+            Comparison::Le => Box::new($column.le(value).nullable()),
+            Comparison::Gt => Box::new($column.gt(value).nullable()),
+            Comparison::Ge => Box::new($column.ge(value).nullable()),
+            Comparison::In => Box::new($column.eq_any(values).nullable()),
+            Comparison::Out => Box::new($column.ne_all(values).nullable()),
+        };
+        predicate
+    }};
+}
+
+/// The predicate comparing a text column with the values given. Equality
+/// ignores case and diacritics and treats `*` as a wildcard, and a missing
+/// value is never equal to anything, as `Filter::matches` has it.
+macro_rules! text_predicate {
+    ($column:expr, $comparison:expr, $values:expr) => {{
+        let column = $column.nullable();
+        let values: &Vec<String> = $values;
+        let value = values[0].clone();
+        let like = |value: &String| -> Predicate {
+            Box::new(unaccent_text(column).ilike(unaccent_text(like_pattern(value))))
+        };
+        let predicate: Predicate = match $comparison {
+            Comparison::Eq => like(&value),
+            Comparison::In => any(values.iter().map(like)),
+            Comparison::Ne | Comparison::Out => {
+                let unlike = values.iter().map(|value| -> Predicate {
+                    Box::new(unaccent_text(column).not_ilike(unaccent_text(like_pattern(value))))
+                });
+                Box::new(all(unlike).or(column.is_null()))
+            }
+            Comparison::Lt => Box::new(column.lt(value)),
+            Comparison::Le => Box::new(column.le(value)),
+            Comparison::Gt => Box::new(column.gt(value)),
+            Comparison::Ge => Box::new(column.ge(value)),
+        };
+        predicate
+    }};
+}
+
+/// Sort a boxed query by the column next, after any columns it is already
+/// sorted by
+macro_rules! then_order_by {
+    ($query:expr, $column:expr, $descending:expr) => {
+        if $descending {
+            $query.then_order_by($column.desc())
+        } else {
+            $query.then_order_by($column.asc())
+        }
+    };
+}
+
+/// The filter as a `WHERE` clause. Only its values are sent, as binds.
+fn predicate(filter: &Filter) -> Predicate {
+    match filter {
+        Filter::And(filters) => all(filters.iter().map(predicate)),
+        Filter::Or(filters) => any(filters.iter().map(predicate)),
+        Filter::Compare {
+            field,
+            comparison,
+            values,
+        } => match (field, values) {
+            (FilterField::Id, FilterValues::Ids(ids)) => {
+                ordered_predicate!(books::id, comparison, ids)
+            }
+            (FilterField::UpdatedAt, FilterValues::Times(times)) => {
+                ordered_predicate!(books::updated_at, comparison, times)
+            }
+            (FilterField::Name, FilterValues::Texts(texts)) => {
+                text_predicate!(books::name, comparison, texts)
+            }
+            (FilterField::Author, FilterValues::Texts(texts)) => {
+                text_predicate!(books::author, comparison, texts)
+            }
+            (FilterField::Slug, FilterValues::Texts(texts)) => {
+                text_predicate!(books::slug, comparison, texts)
+            }
+            (FilterField::Isbn, FilterValues::Texts(texts)) => {
+                text_predicate!(books::isbn, comparison, texts)
+            }
+            (FilterField::Publisher, FilterValues::Texts(texts)) => {
+                text_predicate!(books::publisher, comparison, texts)
+            }
+            // The parser gives each field values of its type
+            _ => Box::new(diesel::dsl::sql::<Bool>("false").nullable()),
+        },
+        Filter::Contains { field, text } => {
+            let pattern = unaccent_text(format!("%{}%", escape_like(text)));
+            match field {
+                TextField::Name => Box::new(unaccent_text(books::name.nullable()).ilike(pattern)),
+                TextField::Author => {
+                    Box::new(unaccent_text(books::author.nullable()).ilike(pattern))
+                }
+            }
+        }
+    }
+}
+
+fn all(predicates: impl Iterator<Item = Predicate>) -> Predicate {
+    predicates
+        .reduce(|all, predicate| Box::new(all.and(predicate)))
+        .unwrap_or_else(|| Box::new(diesel::dsl::sql::<Bool>("true").nullable()))
+}
+
+fn any(predicates: impl Iterator<Item = Predicate>) -> Predicate {
+    predicates
+        .reduce(|any, predicate| Box::new(any.or(predicate)))
+        .unwrap_or_else(|| Box::new(diesel::dsl::sql::<Bool>("false").nullable()))
+}
+
+/// An `ILIKE` pattern where `*` matches any characters, and `%`, `_` and
+/// `\` match themselves
+fn like_pattern(value: &str) -> String {
+    escape_like(value).replace('*', "%")
+}
+
+/// The text as an `ILIKE` pattern matching just itself
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 impl BookRepo<DatabaseError> for DatabaseBookRepo {
     async fn list_books(&self) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
         let mut conn = self.pool.get().await?;
 
         let books = books::table
@@ -82,10 +338,122 @@ impl BookRepo<DatabaseError> for DatabaseBookRepo {
             .load(&mut conn)
             .await?;
 
+        self.warn_if_slow(started, format_args!("list_books"));
+        Ok(books)
+    }
+
+    async fn filter_books(&self, filter: &Filter) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let books = books::table
+            .filter(predicate(filter))
+            .select(Book::as_select())
+            .order(books::id.asc())
+            .limit(100)
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("filter_books(filter={filter:?})"));
+        Ok(books)
+    }
+
+    async fn list_books_after(
+        &self,
+        after_id: i32,
+        limit: i64,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let mut query = books::table
+            .filter(books::id.gt(after_id))
+            .select(Book::as_select())
+            .order(books::id.asc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(filter) = filter {
+            query = query.filter(predicate(filter));
+        }
+        let books = query.load(&mut conn).await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("list_books_after(after_id={after_id}, limit={limit}, filter={filter:?})"),
+        );
         Ok(books)
     }
 
+    async fn stream_books(
+        &self,
+        filter: Option<&Filter>,
+        sort: &SortSpec,
+        mut sender: mpsc::Sender<Result<Book, DatabaseError>>,
+    ) {
+        let result = async {
+            let started = Instant::now();
+            let mut conn = self.pool.get().await?;
+
+            let mut query = books::table
+                .select(Book::as_select())
+                .limit(100)
+                .into_boxed();
+            if let Some(filter) = filter {
+                query = query.filter(predicate(filter));
+            }
+            // Only a collation from `sort::COLLATIONS` is spliced into the SQL
+            let collated = |column: &str| match sort.collation() {
+                Some(collation) => format!("{column} COLLATE \"{}\"", collation.pg_name()),
+                None => column.to_string(),
+            };
+            for key in sort.keys() {
+                query = match key.field {
+                    FilterField::Id => then_order_by!(query, books::id, key.descending),
+                    FilterField::Name => {
+                        let name = diesel::dsl::sql::<Text>(&collated("name"));
+                        then_order_by!(query, name, key.descending)
+                    }
+                    FilterField::Author => {
+                        let author = diesel::dsl::sql::<Text>(&collated("author"));
+                        then_order_by!(query, author, key.descending)
+                    }
+                    FilterField::Isbn => then_order_by!(query, books::isbn, key.descending),
+                    FilterField::Publisher => {
+                        let publisher = diesel::dsl::sql::<Nullable<Text>>(&collated("publisher"));
+                        then_order_by!(query, publisher, key.descending)
+                    }
+                    FilterField::Slug => then_order_by!(query, books::slug, key.descending),
+                    FilterField::UpdatedAt => {
+                        then_order_by!(query, books::updated_at, key.descending)
+                    }
+                };
+            }
+            if !sort.has_id() {
+                query = query.then_order_by(books::id.asc());
+            }
+            let mut rows = query.load_stream::<Book>(&mut conn).await?;
+
+            // Not counting the time spent waiting for the receiver
+            self.warn_if_slow(
+                started,
+                format_args!("stream_books(filter={filter:?}, sort={sort:?})"),
+            );
+            while let Some(book) = rows.next().await {
+                if sender.send(Ok(book?)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = sender.send(Err(e)).await;
+        }
+    }
+
     async fn get_book(&self, id: i32) -> Result<Option<Book>, DatabaseError> {
+        let started = Instant::now();
         let mut conn = self.pool.get().await?;
 
         let maybe_book = books::table
@@ -95,46 +463,1348 @@ impl BookRepo<DatabaseError> for DatabaseBookRepo {
             .await
             .optional()?;
 
+        self.warn_if_slow(started, format_args!("get_book(id={id})"));
         Ok(maybe_book)
     }
 
-    async fn insert_book(&mut self, new_book: NewBook) -> Result<Book, DatabaseError> {
+    async fn get_book_by_slug(&self, slug: &str) -> Result<Option<Book>, DatabaseError> {
+        let started = Instant::now();
         let mut conn = self.pool.get().await?;
 
-        let inserted_book = diesel::insert_into(books::table)
-            .values(new_book)
-            .returning(Book::as_returning())
-            .get_result(&mut conn)
+        // A slug is either a book's current one or an old one, never both
+        let old_slug_of = book_slugs::table
+            .filter(book_slugs::slug.eq(slug))
+            .select(book_slugs::book_id);
+        let maybe_book = books::table
+            .filter(books::slug.eq(slug).or(books::id.eq_any(old_slug_of)))
+            .select(Book::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        self.warn_if_slow(started, format_args!("get_book_by_slug(slug={slug})"));
+        Ok(maybe_book)
+    }
+
+    async fn insert_book(
+        &mut self,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<Book, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let inserted_book = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let slug = assign_slug(conn, None, None, &new_book.name).await?;
+                    let book = diesel::insert_into(books::table)
+                        .values((new_book, books::slug.eq(slug)))
+                        .returning(Book::as_returning())
+                        .get_result(conn)
+                        .await?;
+                    if needs_enrichment(&book) {
+                        enqueue(conn, &[Job::EnrichBook { book_id: book.id }]).await?;
+                    }
+                    record_event(
+                        conn,
+                        &BookEvent::BookCreated(BookCreated { book: book.clone() }),
+                        actor,
+                        None,
+                    )
+                    .await?;
+                    Ok(book)
+                }
+                .scope_boxed()
+            })
             .await?;
 
+        self.warn_if_slow(started, format_args!("insert_book"));
         Ok(inserted_book)
     }
 
+    async fn insert_books(
+        &mut self,
+        new_books: Vec<NewBook>,
+        actor: &Actor,
+    ) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+        let count = new_books.len();
+        if new_books.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let inserted_books = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let names: Vec<&str> =
+                        new_books.iter().map(|book| book.name.as_str()).collect();
+                    let slugs = assign_new_slugs(conn, &names).await?;
+                    let rows: Vec<_> = new_books
+                        .into_iter()
+                        .zip(slugs)
+                        .map(|(new_book, slug)| (new_book, books::slug.eq(slug)))
+                        .collect();
+                    // RETURNING gives the rows in the order of VALUES
+                    let books = diesel::insert_into(books::table)
+                        .values(rows)
+                        .returning(Book::as_returning())
+                        .get_results(conn)
+                        .await?;
+                    let jobs: Vec<_> = books
+                        .iter()
+                        .filter(|book| needs_enrichment(book))
+                        .map(|book| Job::EnrichBook { book_id: book.id })
+                        .collect();
+                    enqueue(conn, &jobs).await?;
+                    record_creations(conn, &books, actor).await?;
+                    Ok(books)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.warn_if_slow(started, format_args!("insert_books(count={count})"));
+        Ok(inserted_books)
+    }
+
     async fn update_book(
         &mut self,
         id: i32,
         new_book: NewBook,
+        actor: &Actor,
     ) -> Result<Option<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let updated_book = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let Some(current) = current_slug(conn, id).await? else {
+                        return Ok(None);
+                    };
+                    let slug = assign_slug(conn, Some(id), Some(current), &new_book.name).await?;
+                    let book = diesel::update(books::table.find(id))
+                        .set((
+                            new_book,
+                            books::slug.eq(slug),
+                            books::updated_at.eq(diesel::dsl::now),
+                        ))
+                        .returning(Book::as_returning())
+                        .get_result(conn)
+                        .await?;
+                    record_event(
+                        conn,
+                        &BookEvent::BookUpdated(BookUpdated { book: book.clone() }),
+                        actor,
+                        None,
+                    )
+                    .await?;
+                    Ok(Some(book))
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.warn_if_slow(started, format_args!("update_book(id={id})"));
+        Ok(updated_book)
+    }
+
+    async fn delete_book(&mut self, id: i32, actor: &Actor) -> Result<bool, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let deleted = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let slug = diesel::delete(books::table.find(id))
+                        .returning(books::slug)
+                        .get_result(conn)
+                        .await
+                        .optional()?;
+                    let deleted = slug.is_some();
+                    if let Some(slug) = slug {
+                        retire_slugs(conn, id, slug, id).await?;
+                        record_event(
+                            conn,
+                            &BookEvent::BookDeleted(BookDeleted { id }),
+                            actor,
+                            None,
+                        )
+                        .await?;
+                    }
+                    Ok(deleted)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.warn_if_slow(started, format_args!("delete_book(id={id})"));
+        Ok(deleted)
+    }
+
+    async fn list_translations(&self, id: i32) -> Result<Vec<BookTranslation>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let translations = book_translations::table
+            .filter(book_translations::book_id.eq(id))
+            .order(book_translations::language)
+            .select(BookTranslation::as_select())
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("list_translations(id={id})"));
+        Ok(translations)
+    }
+
+    async fn put_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+        translation: &NewTranslation,
+    ) -> Result<Option<BookTranslation>, DatabaseError> {
+        let started = Instant::now();
         let mut conn = self.pool.get().await?;
 
-        let updated_book = diesel::update(books::table.find(id))
-            .set(new_book)
-            .returning(Book::as_returning())
+        let saved = diesel::insert_into(book_translations::table)
+            .values((
+                book_translations::book_id.eq(id),
+                book_translations::language.eq(language),
+                book_translations::name.eq(&translation.name),
+                book_translations::description.eq(&translation.description),
+            ))
+            .on_conflict((book_translations::book_id, book_translations::language))
+            .do_update()
+            .set((
+                book_translations::name.eq(excluded(book_translations::name)),
+                book_translations::description.eq(excluded(book_translations::description)),
+                book_translations::updated_at.eq(diesel::dsl::now),
+            ))
+            .returning(BookTranslation::as_returning())
             .get_result(&mut conn)
+            .await;
+        // The book doesn't exist, or was deleted before this could be saved
+        let saved = match saved {
+            Err(diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                _,
+            )) => None,
+            saved => Some(saved?),
+        };
+
+        self.warn_if_slow(
+            started,
+            format_args!("put_translation(id={id}, language={language})"),
+        );
+        Ok(saved)
+    }
+
+    async fn delete_translation(&mut self, id: i32, language: &str) -> Result<bool, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let deleted = diesel::delete(book_translations::table.find((id, language)))
+            .execute(&mut conn)
+            .await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("delete_translation(id={id}, language={language})"),
+        );
+        Ok(deleted > 0)
+    }
+
+    async fn restore_book(
+        &mut self,
+        id: i32,
+        book: NewBook,
+        restores_revision: i32,
+        actor: &Actor,
+    ) -> Result<BookEvent, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let event = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let current = current_slug(conn, id).await?;
+                    let existed = current.is_some();
+                    let slug = assign_slug(conn, Some(id), current, &book.name).await?;
+                    let event = if existed {
+                        let book = diesel::update(books::table.find(id))
+                            .set((
+                                &book,
+                                books::slug.eq(slug),
+                                books::updated_at.eq(diesel::dsl::now),
+                            ))
+                            .returning(Book::as_returning())
+                            .get_result(conn)
+                            .await?;
+                        BookEvent::BookUpdated(BookUpdated { book })
+                    } else {
+                        // Deleted since, so bring it back under the same ID
+                        let book = diesel::insert_into(books::table)
+                            .values((books::id.eq(id), &book, books::slug.eq(slug)))
+                            .returning(Book::as_returning())
+                            .get_result(conn)
+                            .await?;
+                        BookEvent::BookCreated(BookCreated { book })
+                    };
+                    record_event(conn, &event, actor, Some(restores_revision)).await?;
+                    Ok(event)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("restore_book(id={id}, revision={restores_revision})"),
+        );
+        Ok(event)
+    }
+
+    async fn find_duplicates(&self, book: &NewBook) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let mut query = books::table
+            .filter(books::isbn.eq(book.isbn.clone()))
+            .into_boxed();
+        // A book waiting for its name or author to be looked up could only
+        // be a duplicate by ISBN
+        if !book.name.is_empty() && !book.author.is_empty() {
+            query = query.or_filter(
+                normalize_text(books::name)
+                    .eq(normalize_text(book.name.clone()))
+                    .and(normalize_text(books::author).eq(normalize_text(book.author.clone()))),
+            );
+        }
+        let duplicates = query
+            .order(books::id.asc())
+            .limit(MAX_DUPLICATES)
+            .select(Book::as_select())
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("find_duplicates"));
+        Ok(duplicates)
+    }
+
+    async fn find_duplicates_of_any(&self, books: &[NewBook]) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let isbns: Vec<_> = books.iter().filter_map(|book| book.isbn.clone()).collect();
+        // Any of the names with any of the authors, which finds every pair
+        // in one query, along with a few that aren't duplicates
+        let named = books
+            .iter()
+            .filter(|book| !book.name.is_empty() && !book.author.is_empty());
+        let names: Vec<_> = named.clone().map(|book| book.name.clone()).collect();
+        let authors: Vec<_> = named.map(|book| book.author.clone()).collect();
+        let candidates = books::table
+            .filter(
+                books::isbn.eq_any(isbns).or(normalize_text(books::name)
+                    .eq_any(normalize_all(names))
+                    .and(normalize_text(books::author).eq_any(normalize_all(authors)))),
+            )
+            .order(books::id.asc())
+            .select(Book::as_select())
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("find_duplicates_of_any({} books)", books.len()),
+        );
+        Ok(candidates)
+    }
+
+    async fn find_books_by_isbn(&self, isbns: &[String]) -> Result<Vec<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let books = books::table
+            .filter(books::isbn.eq_any(isbns))
+            .order(books::id.asc())
+            .select(Book::as_select())
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("find_books_by_isbn"));
+        Ok(books)
+    }
+
+    async fn merge_books(
+        &mut self,
+        keep: i32,
+        duplicate: i32,
+        merged: Option<NewBook>,
+        actor: &Actor,
+    ) -> Result<Option<Vec<BookEvent>>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let events = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    // Locked in ID order, so that concurrent merges can't deadlock
+                    let locked: Vec<i32> = books::table
+                        .filter(books::id.eq_any([keep, duplicate]))
+                        .order(books::id.asc())
+                        .select(books::id)
+                        .for_update()
+                        .load(conn)
+                        .await?;
+                    if locked.len() != 2 {
+                        return Ok(None);
+                    }
+
+                    let mut events = Vec::new();
+                    if let Some(merged) = merged {
+                        let current = current_slug(conn, keep).await?;
+                        let slug = assign_slug(conn, Some(keep), current, &merged.name).await?;
+                        let book = diesel::update(books::table.find(keep))
+                            .set((
+                                merged,
+                                books::slug.eq(slug),
+                                books::updated_at.eq(diesel::dsl::now),
+                            ))
+                            .returning(Book::as_returning())
+                            .get_result(conn)
+                            .await?;
+                        events.push(BookEvent::BookUpdated(BookUpdated { book }));
+                    }
+                    // Links to the duplicate lead to the book it was merged into
+                    let slug = diesel::delete(books::table.find(duplicate))
+                        .returning(books::slug)
+                        .get_result(conn)
+                        .await?;
+                    retire_slugs(conn, duplicate, slug, keep).await?;
+                    events.push(BookEvent::BookDeleted(BookDeleted { id: duplicate }));
+                    for event in &events {
+                        record_event(conn, event, actor, None).await?;
+                    }
+                    Ok(Some(events))
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("merge_books(keep={keep}, duplicate={duplicate})"),
+        );
+        Ok(events)
+    }
+
+    async fn book_history(&self, id: i32) -> Result<Vec<BookChange>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let rows: Vec<RevisionRow> = book_revisions::table
+            .filter(book_revisions::book_id.eq(id))
+            .order(book_revisions::revision.asc())
+            .select(REVISION_COLUMNS)
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("book_history(id={id})"));
+        rows.into_iter().map(book_change).collect()
+    }
+
+    async fn changes(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, BookChange)>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let rows: Vec<(i64, RevisionRow)> = book_revisions::table
+            .filter(book_revisions::id.gt(after))
+            .order(book_revisions::id.asc())
+            .limit(limit)
+            .select((book_revisions::id, REVISION_COLUMNS))
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("changes(after={after})"));
+        rows.into_iter()
+            .map(|(position, row)| Ok((position, book_change(row)?)))
+            .collect()
+    }
+
+    async fn random_book(&self, pick: f64) -> Result<Option<Book>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        // Both ends of the range come from the primary key index, as does the
+        // book, so no query reads more than a row of the table
+        let (first, last) = books::table
+            .select((diesel::dsl::min(books::id), diesel::dsl::max(books::id)))
+            .first::<(Option<i32>, Option<i32>)>(&mut conn)
+            .await?;
+        let book = match first.zip(last) {
+            Some((first, last)) => books::table
+                .filter(books::id.ge(id_at(first, last, pick)))
+                .order(books::id.asc())
+                .select(Book::as_select())
+                .first(&mut conn)
+                .await
+                .optional()?,
+            None => None,
+        };
+
+        self.warn_if_slow(started, format_args!("random_book"));
+        Ok(book)
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        // Escaped, so that a % or _ typed is matched literally. Each side is
+        // found through its prefix index, and ranked as by `suggest::rank`.
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let rows: Vec<SuggestionRow> = diesel::sql_query(
+            "SELECT text, name_matched, books FROM (
+                 SELECT name AS text, true AS name_matched, count(*) AS books
+                 FROM books
+                 WHERE lower(name) LIKE $1
+                 GROUP BY name
+                 UNION ALL
+                 SELECT author, false, count(*)
+                 FROM books
+                 WHERE lower(author) LIKE $1
+                 GROUP BY author
+             ) AS suggestions
+             ORDER BY lower(text) = $2 DESC, books DESC, length(text), text
+             LIMIT $3",
+        )
+        .bind::<Text, _>(pattern)
+        .bind::<Text, _>(prefix)
+        .bind::<BigInt, _>(limit)
+        .load(&mut conn)
+        .await?;
+
+        self.warn_if_slow(started, format_args!("suggest(prefix={prefix:?})"));
+        Ok(rows
+            .into_iter()
+            .map(|row| Suggestion {
+                text: row.text,
+                kind: if row.name_matched {
+                    SuggestionKind::Title
+                } else {
+                    SuggestionKind::Author
+                },
+                books: row.books,
+            })
+            .collect())
+    }
+
+    async fn search_books(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        // Found through the GIN index on `search_vector`, which weighs the
+        // name above the author, as `search::rank` does. Its words have no
+        // diacritics, so neither can the query's.
+        let rows: Vec<SearchRow> = diesel::sql_query(
+            "SELECT id, name, author, updated_at, isbn, publisher, cover_url, slug,
+                    ts_rank(search_vector, query) AS rank
+             FROM books, plainto_tsquery('english', unaccent_text($1)) AS query
+             WHERE search_vector @@ query
+             ORDER BY rank DESC, id
+             LIMIT $2",
+        )
+        .bind::<Text, _>(query)
+        .bind::<BigInt, _>(limit)
+        .load(&mut conn)
+        .await?;
+
+        self.warn_if_slow(started, format_args!("search_books(query={query:?})"));
+        Ok(rows
+            .into_iter()
+            .map(|row| SearchResult {
+                book: row.book,
+                rank: row.rank,
+            })
+            .collect())
+    }
+
+    async fn count_books(&self) -> Result<i64, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let count = books::table.count().get_result(&mut conn).await?;
+
+        self.warn_if_slow(started, format_args!("count_books"));
+        Ok(count)
+    }
+
+    async fn aggregate_books(
+        &self,
+        group_by: BookField,
+        metric: Metric,
+        limit: i64,
+    ) -> Result<Vec<Group>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        // Only whitelisted SQL is spliced into the query
+        let column = match group_by {
+            BookField::Author => "author",
+            BookField::Publisher => "publisher",
+        };
+        let result = match metric {
+            Metric::Count => "count(*)",
+            Metric::WithIsbn => "count(isbn)",
+            Metric::DistinctAuthors => "count(DISTINCT author)",
+            Metric::DistinctPublishers => "count(DISTINCT publisher)",
+        };
+        let groups = diesel::sql_query(format!(
+            "SELECT {column} AS value, {result} AS result
+             FROM books
+             GROUP BY {column}
+             ORDER BY result DESC, value
+             LIMIT $1"
+        ))
+        .bind::<BigInt, _>(limit)
+        .load(&mut conn)
+        .await?;
+
+        self.warn_if_slow(
+            started,
+            format_args!("aggregate_books(group_by={group_by:?}, metric={metric:?})"),
+        );
+        Ok(groups)
+    }
+
+    async fn record_views(
+        &mut self,
+        views: &[(i32, i32)],
+        hour: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let rows: Vec<_> = views
+            .iter()
+            .map(|(id, count)| {
+                (
+                    book_views::book_id.eq(id),
+                    book_views::hour.eq(hour),
+                    book_views::views.eq(count),
+                )
+            })
+            .collect();
+        diesel::insert_into(book_views::table)
+            .values(rows)
+            .on_conflict((book_views::book_id, book_views::hour))
+            .do_update()
+            .set(book_views::views.eq(book_views::views + excluded(book_views::views)))
+            .execute(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("record_views(books={})", views.len()));
+        Ok(())
+    }
+
+    async fn trending_books(
+        &self,
+        since: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<TrendingBook>, DatabaseError> {
+        let started = Instant::now();
+        let mut conn = self.pool.get().await?;
+
+        let views = diesel::dsl::sum(book_views::views);
+        let rows: Vec<(Book, Option<i64>)> = books::table
+            .inner_join(book_views::table.on(book_views::book_id.eq(books::id)))
+            .filter(book_views::hour.ge(since))
+            .group_by(books::id)
+            .select((Book::as_select(), views))
+            .order((views.desc(), books::id.asc()))
+            .limit(limit)
+            .load(&mut conn)
+            .await?;
+
+        self.warn_if_slow(started, format_args!("trending_books(since={since})"));
+        Ok(rows
+            .into_iter()
+            .map(|(book, views)| TrendingBook {
+                book,
+                views: views.unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+/// A row of the `suggest` query
+#[derive(diesel::QueryableByName)]
+struct SuggestionRow {
+    #[diesel(sql_type = Text)]
+    text: String,
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    name_matched: bool,
+    #[diesel(sql_type = BigInt)]
+    books: i64,
+}
+
+/// A row of the `search_books` query
+#[derive(diesel::QueryableByName)]
+struct SearchRow {
+    #[diesel(embed)]
+    book: Book,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    rank: f32,
+}
+
+/// The most probable duplicates of a book that are looked for
+const MAX_DUPLICATES: i64 = 10;
+
+diesel::define_sql_function! {
+    /// Defined by a migration: lower-cases the text and replaces each run of
+    /// anything but letters and digits with a single space
+    fn normalize_text(text: diesel::sql_types::Text) -> diesel::sql_types::Text;
+}
+
+diesel::define_sql_function! {
+    /// Defined by a migration: the text without diacritics, e.g. `Brontë` ->
+    /// `Bronte`
+    fn unaccent_text<T: diesel::sql_types::SingleValue>(text: T) -> T;
+}
+
+/// The texts as the DB's `normalize_text` would normalize them
+fn normalize_all(texts: Vec<String>) -> Vec<String> {
+    texts
+        .iter()
+        .map(|text| crate::models::normalize_text(text))
+        .collect()
+}
+
+/// The columns of `book_revisions` that make up a `BookChange`
+const REVISION_COLUMNS: (
+    book_revisions::revision,
+    book_revisions::recorded_at,
+    book_revisions::actor,
+    book_revisions::restores_revision,
+    book_revisions::payload,
+) = (
+    book_revisions::revision,
+    book_revisions::recorded_at,
+    book_revisions::actor,
+    book_revisions::restores_revision,
+    book_revisions::payload,
+);
+
+type RevisionRow = (i32, DateTime<Utc>, Option<String>, Option<i32>, String);
+
+fn book_change(
+    (revision, recorded_at, actor, restores_revision, payload): RevisionRow,
+) -> Result<BookChange, DatabaseError> {
+    let event = serde_json::from_str(&payload)
+        .map_err(|e| diesel::result::Error::DeserializationError(Box::new(e)))?;
+    Ok(BookChange {
+        revision,
+        recorded_at,
+        actor,
+        restores_revision,
+        event,
+    })
+}
+
+/// Identifies the advisory lock that serializes recording revisions
+const REVISIONS_LOCK: i64 = 0x7265766973696f6e;
+
+/// Wait for, and hold until the end of the transaction, the lock that
+/// serializes recording revisions. Holding it again is harmless.
+async fn lock_revisions(conn: &mut AsyncPgConnection) -> Result<(), DatabaseError> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(REVISIONS_LOCK)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The book's slug, locking its row, or `None` if there is no such book
+async fn current_slug(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+) -> Result<Option<String>, DatabaseError> {
+    let slug = books::table
+        .find(id)
+        .select(books::slug)
+        .for_update()
+        .first(conn)
+        .await
+        .optional()?;
+    Ok(slug)
+}
+
+/// The slug to write for the book `id` with the name, in the transaction
+/// writing it: its `current` slug if the name still gives it, or else the
+/// first one no other book has or had, keeping the current one to lead to
+/// the book. `id` is `None` for a book yet to be inserted.
+async fn assign_slug(
+    conn: &mut AsyncPgConnection,
+    id: Option<i32>,
+    current: Option<String>,
+    name: &str,
+) -> Result<String, DatabaseError> {
+    let base = slugify(name);
+    if let Some(current) = &current {
+        if has_base(current, &base) {
+            return Ok(current.clone());
+        }
+    }
+    // Under the lock the change's revision is recorded with, so that two
+    // books can't be given the same slug at once
+    lock_revisions(conn).await?;
+
+    let suffixed = format!("{base}-%");
+    let mut current_slugs = books::table
+        .filter(books::slug.eq(&base).or(books::slug.like(&suffixed)))
+        .select(books::slug)
+        .into_boxed();
+    let mut old_slugs = book_slugs::table
+        .filter(
+            book_slugs::slug
+                .eq(&base)
+                .or(book_slugs::slug.like(&suffixed)),
+        )
+        .select(book_slugs::slug)
+        .into_boxed();
+    if let Some(id) = id {
+        current_slugs = current_slugs.filter(books::id.ne(id));
+        old_slugs = old_slugs.filter(book_slugs::book_id.ne(id));
+    }
+    let mut taken: HashSet<String> = current_slugs.load(conn).await?.into_iter().collect();
+    taken.extend(old_slugs.load::<String>(conn).await?);
+    let slug = first_free(&base, |slug| taken.contains(slug));
+
+    if let Some(id) = id {
+        // The book may be taking back one of its old slugs
+        diesel::delete(book_slugs::table.find(&slug))
+            .execute(conn)
+            .await?;
+        if let Some(current) = current {
+            diesel::insert_into(book_slugs::table)
+                .values((book_slugs::slug.eq(current), book_slugs::book_id.eq(id)))
+                .execute(conn)
+                .await?;
+        }
+    }
+    Ok(slug)
+}
+
+/// Slugs for new books with the names, in order, as `assign_slug` would give
+/// them one at a time, but looked up all at once
+async fn assign_new_slugs(
+    conn: &mut AsyncPgConnection,
+    names: &[&str],
+) -> Result<Vec<String>, DatabaseError> {
+    let bases: Vec<String> = names.iter().map(|name| slugify(name)).collect();
+    let suffixed: Vec<String> = bases.iter().map(|base| format!("{base}-%")).collect();
+    lock_revisions(conn).await?;
+
+    // Slugs are only ever letters, digits and hyphens, so have no wildcards
+    let like_any = || {
+        diesel::dsl::sql::<Bool>("slug LIKE ANY(")
+            .bind::<Array<Text>, _>(suffixed.clone())
+            .sql(")")
+    };
+    let mut taken: HashSet<String> = books::table
+        .filter(books::slug.eq_any(&bases).or(like_any()))
+        .select(books::slug)
+        .load(conn)
+        .await?
+        .into_iter()
+        .collect();
+    taken.extend(
+        book_slugs::table
+            .filter(book_slugs::slug.eq_any(&bases).or(like_any()))
+            .select(book_slugs::slug)
+            .load::<String>(conn)
+            .await?,
+    );
+    Ok(bases
+        .iter()
+        .map(|base| {
+            let slug = first_free(base, |slug| taken.contains(slug));
+            taken.insert(slug.clone());
+            slug
+        })
+        .collect())
+}
+
+/// Keep the slugs of the book `id`, which has been deleted, so that they are
+/// never given to another book, and lead to the book `successor`
+async fn retire_slugs(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    slug: String,
+    successor: i32,
+) -> Result<(), DatabaseError> {
+    diesel::update(book_slugs::table.filter(book_slugs::book_id.eq(id)))
+        .set(book_slugs::book_id.eq(successor))
+        .execute(conn)
+        .await?;
+    diesel::insert_into(book_slugs::table)
+        .values((book_slugs::slug.eq(slug), book_slugs::book_id.eq(successor)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Record an event as the book's next revision and in the outbox, in the
+/// transaction making the change it describes, so that it is kept and
+/// relayed if and only if the change is committed. `restores_revision` is
+/// the revision a revert put the book back to.
+async fn record_event(
+    conn: &mut AsyncPgConnection,
+    event: &BookEvent,
+    actor: &Actor,
+    restores_revision: Option<i32>,
+) -> Result<(), DatabaseError> {
+    let payload = serde_json::to_string(event).expect("Book events can always be serialized");
+    // Revisions are committed in the order of their IDs, so that the change
+    // feed, which pages through them by ID, never skips one committed late
+    lock_revisions(conn).await?;
+    // Concurrent changes to the book are serialized by the lock on its row
+    let revision = book_revisions::table
+        .filter(book_revisions::book_id.eq(event.book_id()))
+        .select(diesel::dsl::max(book_revisions::revision))
+        .get_result::<Option<i32>>(conn)
+        .await?
+        .unwrap_or(0)
+        + 1;
+    diesel::insert_into(book_revisions::table)
+        .values((
+            book_revisions::book_id.eq(event.book_id()),
+            book_revisions::revision.eq(revision),
+            book_revisions::event_type.eq(event.event_type()),
+            book_revisions::payload.eq(&payload),
+            book_revisions::actor.eq(&actor.0),
+            book_revisions::restores_revision.eq(restores_revision),
+        ))
+        .execute(conn)
+        .await?;
+    diesel::insert_into(outbox::table)
+        .values((
+            outbox::event_type.eq(event.event_type()),
+            outbox::payload.eq(payload),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Record the creation of each of the books, which are new, as its first
+/// revision and in the outbox, as `record_event` does for one
+async fn record_creations(
+    conn: &mut AsyncPgConnection,
+    books: &[Book],
+    actor: &Actor,
+) -> Result<(), DatabaseError> {
+    let events: Vec<_> = books
+        .iter()
+        .map(|book| {
+            let event = BookEvent::BookCreated(BookCreated { book: book.clone() });
+            let payload =
+                serde_json::to_string(&event).expect("Book events can always be serialized");
+            (event, payload)
+        })
+        .collect();
+    lock_revisions(conn).await?;
+    let revisions: Vec<_> = events
+        .iter()
+        .map(|(event, payload)| {
+            (
+                book_revisions::book_id.eq(event.book_id()),
+                book_revisions::revision.eq(1),
+                book_revisions::event_type.eq(event.event_type()),
+                book_revisions::payload.eq(payload),
+                book_revisions::actor.eq(&actor.0),
+            )
+        })
+        .collect();
+    diesel::insert_into(book_revisions::table)
+        .values(revisions)
+        .execute(conn)
+        .await?;
+    let outbox_rows: Vec<_> = events
+        .iter()
+        .map(|(event, payload)| {
+            (
+                outbox::event_type.eq(event.event_type()),
+                outbox::payload.eq(payload),
+            )
+        })
+        .collect();
+    diesel::insert_into(outbox::table)
+        .values(outbox_rows)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// How many months after the current one `book_revisions` has partitions
+/// for, so that each month's partition exists before the month starts
+pub(crate) const PARTITION_MONTHS_AHEAD: i32 = 2;
+/// When partitions are created: daily
+pub(crate) const PARTITION_SCHEDULE: &str = "0 15 3 * * *";
+
+diesel::define_sql_function! {
+    /// Defined by a migration: creates the monthly partitions of a table
+    /// partitioned by a timestamp, from the month of `since` until
+    /// `months_ahead` months from now, returning how many were created
+    fn create_monthly_partitions(
+        parent: diesel::sql_types::Text,
+        since: diesel::sql_types::Timestamptz,
+        months_ahead: diesel::sql_types::Integer,
+    ) -> diesel::sql_types::Integer;
+}
+
+/// Create the partitions of `book_revisions` for the current month and the
+/// `months_ahead` after it, returning how many didn't exist yet. Revisions
+/// for a month without a partition go to the default partition, and its
+/// partition is then never created.
+pub(crate) async fn create_revision_partitions(
+    pool: &DBPool,
+    months_ahead: i32,
+) -> Result<i32, DatabaseError> {
+    let mut conn = pool.get().await?;
+
+    let created = diesel::select(create_monthly_partitions(
+        "book_revisions",
+        Utc::now(),
+        months_ahead,
+    ))
+    .get_result(&mut conn)
+    .await?;
+
+    Ok(created)
+}
+
+/// Claims ticks of scheduled jobs in the `job_leases` table, so that when
+/// several replicas run the same schedule, only one of them runs each tick
+#[derive(Clone)]
+pub struct JobLeases {
+    pool: DBPool,
+    /// Identifies this replica in the table, to help with debugging
+    holder: String,
+}
+
+impl JobLeases {
+    pub fn new(pool: DBPool, holder: String) -> Self {
+        JobLeases { pool, holder }
+    }
+
+    /// Returns true if this replica should run `job` for `tick`, or false if
+    /// another replica has already claimed that tick (or a later one)
+    pub async fn claim(&self, job: &str, tick: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        use diesel::query_dsl::methods::FilterDsl;
+
+        let mut conn = self.pool.get().await?;
+
+        let upsert = diesel::insert_into(job_leases::table)
+            .values((
+                job_leases::job.eq(job),
+                job_leases::tick.eq(tick),
+                job_leases::holder.eq(&self.holder),
+            ))
+            .on_conflict(job_leases::job)
+            .do_update()
+            .set((
+                job_leases::tick.eq(excluded(job_leases::tick)),
+                job_leases::holder.eq(excluded(job_leases::holder)),
+                job_leases::claimed_at.eq(diesel::dsl::now),
+            ));
+
+        // Only take over the lease for a newer tick than the one last claimed
+        let claimed = FilterDsl::filter(upsert, job_leases::tick.lt(excluded(job_leases::tick)))
+            .execute(&mut conn)
+            .await
+            .map(|affected_rows| affected_rows == 1)?;
+
+        Ok(claimed)
+    }
+}
+
+/// Feature flags toggled through the admin API, shared by every replica
+#[derive(Clone)]
+pub struct FlagStore {
+    pool: DBPool,
+}
+
+impl FlagStore {
+    pub fn new(pool: DBPool) -> Self {
+        FlagStore { pool }
+    }
+
+    pub async fn load(&self) -> Result<Vec<(String, bool)>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let flags = feature_flags::table
+            .select((feature_flags::name, feature_flags::enabled))
+            .load(&mut conn)
+            .await?;
+
+        Ok(flags)
+    }
+
+    pub async fn set(&self, name: &str, enabled: bool) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        diesel::insert_into(feature_flags::table)
+            .values((
+                feature_flags::name.eq(name),
+                feature_flags::enabled.eq(enabled),
+            ))
+            .on_conflict(feature_flags::name)
+            .do_update()
+            .set((
+                feature_flags::enabled.eq(excluded(feature_flags::enabled)),
+                feature_flags::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove the flag's override, returning whether there was one
+    pub async fn clear(&self, name: &str) -> Result<bool, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let deleted = diesel::delete(feature_flags::table.find(name))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(deleted == 1)
+    }
+}
+
+/// The registered webhooks and the log of deliveries to them
+#[derive(Clone)]
+pub struct WebhookStore {
+    pool: DBPool,
+}
+
+impl WebhookStore {
+    pub fn new(pool: DBPool) -> Self {
+        WebhookStore { pool }
+    }
+
+    pub async fn register(&self, new_webhook: NewWebhook) -> Result<Webhook, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let webhook = diesel::insert_into(webhooks::table)
+            .values(new_webhook)
+            .returning(Webhook::as_returning())
+            .get_result(&mut conn)
+            .await?;
+
+        Ok(webhook)
+    }
+
+    pub async fn list(&self) -> Result<Vec<Webhook>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let webhooks = webhooks::table
+            .select(Webhook::as_select())
+            .order(webhooks::id.asc())
+            .load(&mut conn)
+            .await?;
+
+        Ok(webhooks)
+    }
+
+    pub async fn get(&self, id: i32) -> Result<Option<Webhook>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let webhook = webhooks::table
+            .find(id)
+            .select(Webhook::as_select())
+            .first(&mut conn)
             .await
             .optional()?;
 
-        Ok(updated_book)
+        Ok(webhook)
     }
 
-    async fn delete_book(&mut self, id: i32) -> Result<bool, DatabaseError> {
+    /// Deletes the webhook along with its deliveries
+    pub async fn delete(&self, id: i32) -> Result<bool, DatabaseError> {
         let mut conn = self.pool.get().await?;
 
-        let deleted = diesel::delete(books::table.find(id))
+        let deleted = diesel::delete(webhooks::table.find(id))
             .execute(&mut conn)
             .await
             .map(|affected_rows| affected_rows == 1)?;
 
         Ok(deleted)
     }
+
+    /// Log a pending delivery of the payload to every webhook subscribed to
+    /// the event type, and queue a job to send each
+    pub async fn create_deliveries(
+        &self,
+        event_type: &str,
+        payload: &str,
+    ) -> Result<Vec<WebhookDelivery>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let subscribed: Vec<i32> = webhooks::table
+            .filter(
+                webhooks::events
+                    .eq(Vec::<String>::new())
+                    .or(webhooks::events.contains(vec![event_type])),
+            )
+            .select(webhooks::id)
+            .load(&mut conn)
+            .await?;
+        if subscribed.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<_> = subscribed
+            .into_iter()
+            .map(|webhook_id| {
+                (
+                    webhook_deliveries::webhook_id.eq(webhook_id),
+                    webhook_deliveries::event_type.eq(event_type),
+                    webhook_deliveries::payload.eq(payload),
+                )
+            })
+            .collect();
+        let deliveries = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let deliveries: Vec<WebhookDelivery> =
+                        diesel::insert_into(webhook_deliveries::table)
+                            .values(rows)
+                            .returning(WebhookDelivery::as_returning())
+                            .get_results(conn)
+                            .await?;
+                    let jobs: Vec<Job> = deliveries
+                        .iter()
+                        .map(|delivery| Job::DeliverWebhook {
+                            delivery_id: delivery.id,
+                        })
+                        .collect();
+                    enqueue(conn, &jobs).await?;
+                    Ok(deliveries)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        Ok(deliveries)
+    }
+
+    pub async fn get_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let delivery = webhook_deliveries::table
+            .find(id)
+            .select(WebhookDelivery::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        Ok(delivery)
+    }
+
+    /// The most recent deliveries to a webhook, newest first
+    pub async fn deliveries(
+        &self,
+        webhook_id: i32,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let deliveries = webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .select(WebhookDelivery::as_select())
+            .order(webhook_deliveries::id.desc())
+            .limit(limit)
+            .load(&mut conn)
+            .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Record the outcome of an attempt to send a delivery, and its status
+    /// afterwards
+    pub async fn record_attempt(
+        &self,
+        id: i64,
+        status: &str,
+        status_code: Option<i32>,
+        error: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(status),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::last_status_code.eq(status_code),
+                webhook_deliveries::last_error.eq(error),
+                webhook_deliveries::last_attempt_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark a delivery to a webhook as pending again, and queue a job to
+    /// send it with a fresh set of attempts
+    pub async fn reset_delivery(
+        &self,
+        webhook_id: i32,
+        id: i64,
+    ) -> Result<Option<WebhookDelivery>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let delivery = conn
+            .transaction::<_, DatabaseError, _>(|conn| {
+                async move {
+                    let delivery = diesel::update(
+                        webhook_deliveries::table
+                            .find(id)
+                            .filter(webhook_deliveries::webhook_id.eq(webhook_id)),
+                    )
+                    .set(webhook_deliveries::status.eq("pending"))
+                    .returning(WebhookDelivery::as_returning())
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                    if delivery.is_some() {
+                        enqueue(conn, &[Job::DeliverWebhook { delivery_id: id }]).await?;
+                    }
+                    Ok(delivery)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        Ok(delivery)
+    }
 }
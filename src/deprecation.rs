@@ -0,0 +1,183 @@
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{NaiveDate, NaiveTime};
+use tracing::debug;
+
+use crate::conditional::http_date;
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// The unversioned aliases of the `/v1` routes, e.g. `/books`
+pub(crate) const UNVERSIONED_ALIASES: Deprecation = Deprecation {
+    since: date(2026, 10, 16),
+    sunset: Some(date(2027, 4, 16)),
+    successor_prefix: Some("/v1"),
+    query_param: None,
+};
+
+/// A deprecated route or query parameter. It is still served, but responses
+/// carry `Deprecation`, `Sunset` and `Link: rel="successor-version"` headers
+/// so clients get a machine-readable warning to move off it.
+///
+/// Every deprecation is declared as a constant in this module, so there is
+/// one place to see what is on its way out and when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Deprecation {
+    /// When it was deprecated
+    pub since: NaiveDate,
+    /// When it will be removed, if that has been decided
+    pub sunset: Option<NaiveDate>,
+    /// Prefixed to the request path, after wherever the API is mounted, to
+    /// link to the replacement, if any
+    pub successor_prefix: Option<&'static str>,
+    /// If set, only requests using this query parameter are deprecated, not
+    /// the whole route
+    pub query_param: Option<&'static str>,
+}
+
+const fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    match NaiveDate::from_ymd_opt(year, month, day) {
+        Some(date) => date,
+        None => panic!("invalid date"),
+    }
+}
+
+impl Deprecation {
+    fn applies_to(&self, request: &Request) -> bool {
+        let Some(param) = self.query_param else {
+            return true;
+        };
+        request.uri().query().is_some_and(|query| {
+            query
+                .split('&')
+                .any(|pair| pair.split('=').next() == Some(param))
+        })
+    }
+}
+
+/// Middleware that adds the deprecation headers to the responses of a
+/// deprecated route
+pub(crate) async fn deprecated(
+    State(deprecation): State<Deprecation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !deprecation.applies_to(&request) {
+        return next.run(request).await;
+    }
+
+    debug!(path = request.uri().path(), "Deprecated API used");
+    let successor = deprecation.successor_prefix.map(|prefix| {
+        let path_and_query = request
+            .uri()
+            .path_and_query()
+            .map_or("", |path_and_query| path_and_query.as_str());
+        format!(
+            "<{}{prefix}{path_and_query}>; rel=\"successor-version\"",
+            mount_point(&request)
+        )
+    });
+
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    let since = deprecation.since.and_time(NaiveTime::MIN).and_utc();
+    let since = HeaderValue::try_from(format!("@{}", since.timestamp()))
+        .expect("a timestamp is a valid header value");
+    headers.insert(DEPRECATION, since);
+    if let Some(sunset) = deprecation.sunset {
+        headers.insert(SUNSET, http_date(sunset.and_time(NaiveTime::MIN).and_utc()));
+    }
+    if let Some(link) = successor.and_then(|link| HeaderValue::try_from(link).ok()) {
+        headers.append(header::LINK, link);
+    }
+    response
+}
+
+/// Where the router handling the request is nested, e.g. `/api/bookstore`,
+/// which the request's path no longer includes
+fn mount_point(request: &Request) -> &str {
+    request
+        .extensions()
+        .get::<OriginalUri>()
+        .and_then(|original| original.path().strip_suffix(request.uri().path()))
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn router(deprecation: Deprecation) -> Router {
+        Router::new()
+            .route("/books/{id}", get(|| async { "a book" }))
+            .route_layer(middleware::from_fn_with_state(deprecation, deprecated))
+    }
+
+    #[tokio::test]
+    async fn deprecated_routes_point_at_their_successor() {
+        let response = router(UNVERSIONED_ALIASES)
+            .oneshot(Request::get("/books/1?x=y").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers[DEPRECATION], "@1792108800");
+        assert_eq!(headers[SUNSET], "Fri, 16 Apr 2027 00:00:00 GMT");
+        assert_eq!(
+            headers[header::LINK],
+            "</v1/books/1?x=y>; rel=\"successor-version\""
+        );
+    }
+
+    #[tokio::test]
+    async fn successors_are_linked_to_under_the_mount_point() {
+        let response = Router::new()
+            .nest("/api/bookstore", router(UNVERSIONED_ALIASES))
+            .oneshot(
+                Request::get("/api/bookstore/books/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers()[header::LINK],
+            "</api/bookstore/v1/books/1>; rel=\"successor-version\""
+        );
+    }
+
+    #[tokio::test]
+    async fn deprecated_query_params_only_warn_when_used() {
+        let deprecation = Deprecation {
+            since: date(2026, 1, 1),
+            sunset: None,
+            successor_prefix: None,
+            query_param: Some("old"),
+        };
+        let request = |uri| Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = router(deprecation)
+            .oneshot(request("/books/1?new=1"))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(DEPRECATION));
+
+        let response = router(deprecation)
+            .oneshot(request("/books/1?new=1&old=2"))
+            .await
+            .unwrap();
+        assert!(response.headers().contains_key(DEPRECATION));
+        assert!(!response.headers().contains_key(SUNSET));
+        assert!(!response.headers().contains_key(header::LINK));
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use diesel::upsert::excluded;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::database::{DBPool, DatabaseError};
+use crate::models::{Book, NewBook};
+use crate::schema::metadata_lookups;
+
+const OPEN_LIBRARY_URL: &str = "https://openlibrary.org";
+const GOOGLE_BOOKS_URL: &str = "https://www.googleapis.com";
+/// How long a provider has to respond to a lookup
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest a lookup waits for its provider's rate limit, before giving
+/// up on that provider and trying the next
+const MAX_THROTTLE_WAIT: Duration = Duration::from_secs(5);
+/// How long a lookup's result is reused for, including finding nothing
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// The most lookups cached at once in memory
+const CACHE_CAPACITY: usize = 10_000;
+
+/// What a metadata provider knows about a book
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub cover_url: Option<String>,
+}
+
+impl BookMetadata {
+    /// The book with its missing fields filled in from the metadata, or
+    /// `None` if the metadata has none of them
+    pub fn fill_in(&self, book: &Book) -> Option<NewBook> {
+        let mut filled = NewBook::from(book);
+        if filled.name.is_empty() {
+            filled.name = self.title.clone().unwrap_or_default();
+        }
+        if filled.author.is_empty() {
+            filled.author = self.authors.join(", ");
+        }
+        if filled.publisher.is_none() {
+            filled.publisher = self.publisher.clone();
+        }
+        if filled.cover_url.is_none() {
+            filled.cover_url = self.cover_url.clone();
+        }
+
+        let unchanged = filled.name == book.name
+            && filled.author == book.author
+            && filled.publisher == book.publisher
+            && filled.cover_url == book.cover_url;
+        (!unchanged).then_some(filled)
+    }
+}
+
+/// Whether a book has an ISBN to look up, and fields that could be filled in
+pub(crate) fn needs_enrichment(book: &Book) -> bool {
+    book.isbn.is_some()
+        && (book.name.is_empty()
+            || book.author.is_empty()
+            || book.publisher.is_none()
+            || book.cover_url.is_none())
+}
+
+/// Why a lookup failed
+#[derive(Debug)]
+pub enum EnrichmentError {
+    Request(reqwest::Error),
+    /// The provider's rate limit would have held the lookup up for too long
+    RateLimited {
+        provider: &'static str,
+    },
+}
+
+impl fmt::Display for EnrichmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnrichmentError::Request(e) => write!(f, "Failed to look up book metadata: {e}"),
+            EnrichmentError::RateLimited { provider } => {
+                write!(f, "Too many book metadata lookups from {provider}")
+            }
+        }
+    }
+}
+
+impl Error for EnrichmentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EnrichmentError::Request(e) => Some(e),
+            EnrichmentError::RateLimited { .. } => None,
+        }
+    }
+}
+
+pub type LookupFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<BookMetadata>, EnrichmentError>> + Send + 'a>>;
+
+/// A source of book metadata, looked up by ISBN
+pub trait MetadataProvider: Send + Sync {
+    /// Identifies the provider in logs and the lookup cache
+    fn name(&self) -> &'static str;
+
+    /// The least time to leave between lookups, to stay within the
+    /// provider's rate limit
+    fn min_interval(&self) -> Duration;
+
+    /// What the provider knows about the book with the ISBN, or `None` if it
+    /// doesn't know it
+    fn lookup<'a>(&'a self, isbn: &'a str) -> LookupFuture<'a>;
+}
+
+/// A metadata provider that can be chosen in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    OpenLibrary,
+    GoogleBooks,
+}
+
+impl ProviderKind {
+    /// The provider's name in the config, e.g. `open_library`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "open_library" => Some(ProviderKind::OpenLibrary),
+            "google_books" => Some(ProviderKind::GoogleBooks),
+            _ => None,
+        }
+    }
+}
+
+/// Which metadata providers books are enriched from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnrichmentConfig {
+    /// Tried in order, until one knows the ISBN
+    pub providers: Vec<ProviderKind>,
+    /// Without an API key, Google Books lookups share Google's anonymous quota
+    pub google_books_api_key: Option<String>,
+}
+
+impl Default for EnrichmentConfig {
+    /// OpenLibrary, falling back to Google Books
+    fn default() -> Self {
+        EnrichmentConfig {
+            providers: vec![ProviderKind::OpenLibrary, ProviderKind::GoogleBooks],
+            google_books_api_key: None,
+        }
+    }
+}
+
+/// Looks books up with each of its providers in turn, keeping to each
+/// provider's rate limit and caching what they return. Clones share the
+/// cache and rate limits.
+#[derive(Clone)]
+pub struct Enricher {
+    providers: Arc<[Throttled]>,
+    cache: LookupCache,
+}
+
+impl Default for Enricher {
+    fn default() -> Self {
+        Enricher::new(&EnrichmentConfig::default())
+    }
+}
+
+impl Enricher {
+    pub fn new(config: &EnrichmentConfig) -> Self {
+        Enricher::from_providers(
+            config
+                .providers
+                .iter()
+                .map(|kind| -> Box<dyn MetadataProvider> {
+                    match kind {
+                        ProviderKind::OpenLibrary => {
+                            Box::new(OpenLibrary::new(OPEN_LIBRARY_URL.to_string()))
+                        }
+                        ProviderKind::GoogleBooks => Box::new(GoogleBooks::new(
+                            GOOGLE_BOOKS_URL.to_string(),
+                            config.google_books_api_key.clone(),
+                        )),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Use the providers given, in order, rather than configured ones
+    pub fn from_providers(providers: Vec<Box<dyn MetadataProvider>>) -> Self {
+        Enricher {
+            providers: providers.into_iter().map(Throttled::new).collect(),
+            cache: LookupCache::Memory(Arc::default()),
+        }
+    }
+
+    /// Cache lookups in the `metadata_lookups` table instead of in memory,
+    /// so that they are shared between replicas and kept across restarts
+    pub(crate) fn with_cache(mut self, pool: DBPool) -> Self {
+        self.cache = LookupCache::Database(pool);
+        self
+    }
+
+    /// What the first provider that knows the ISBN knows about it. Providers
+    /// that fail are skipped, but if none of the others know the ISBN either,
+    /// the last failure is returned so that the lookup can be tried again.
+    pub async fn lookup(&self, isbn: &str) -> Result<Option<BookMetadata>, EnrichmentError> {
+        let mut failure = None;
+        for provider in self.providers.iter() {
+            let name = provider.inner.name();
+            let metadata = match self.cache.get(name, isbn).await {
+                Some(cached) => cached,
+                None => match provider.lookup(isbn).await {
+                    Ok(metadata) => {
+                        debug!(
+                            provider = name,
+                            isbn,
+                            found = metadata.is_some(),
+                            "Looked up book metadata"
+                        );
+                        self.cache.put(name, isbn, &metadata).await;
+                        metadata
+                    }
+                    Err(e) => {
+                        warn!(provider = name, isbn, "{e}");
+                        failure = Some(e);
+                        continue;
+                    }
+                },
+            };
+            if metadata.is_some() {
+                return Ok(metadata);
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A provider, and the earliest its next lookup may be made
+struct Throttled {
+    inner: Box<dyn MetadataProvider>,
+    next_slot: Mutex<Instant>,
+}
+
+impl Throttled {
+    fn new(inner: Box<dyn MetadataProvider>) -> Self {
+        Throttled {
+            inner,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn lookup(&self, isbn: &str) -> Result<Option<BookMetadata>, EnrichmentError> {
+        let wait = self.reserve().ok_or(EnrichmentError::RateLimited {
+            provider: self.inner.name(),
+        })?;
+        tokio::time::sleep(wait).await;
+        self.inner.lookup(isbn).await
+    }
+
+    /// Take the next free slot for a lookup, returning how long to wait for
+    /// it, or `None` if that would be longer than `MAX_THROTTLE_WAIT`
+    fn reserve(&self) -> Option<Duration> {
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let now = Instant::now();
+        let slot = (*next_slot).max(now);
+        let wait = slot - now;
+        if wait > MAX_THROTTLE_WAIT {
+            return None;
+        }
+        *next_slot = slot + self.inner.min_interval();
+        Some(wait)
+    }
+}
+
+/// The result of each provider's lookup of each ISBN, and when it was made
+type LookupMap = HashMap<(&'static str, String), (Instant, Option<BookMetadata>)>;
+
+/// Where lookups are cached. A cache that can't be read or written is
+/// treated as empty, so that it never stops a lookup.
+#[derive(Clone)]
+enum LookupCache {
+    Memory(Arc<Mutex<LookupMap>>),
+    Database(DBPool),
+}
+
+impl LookupCache {
+    /// The provider's unexpired result for the ISBN, if it has one
+    async fn get(&self, provider: &'static str, isbn: &str) -> Option<Option<BookMetadata>> {
+        match self {
+            LookupCache::Memory(cache) => {
+                let cache = cache.lock().unwrap();
+                let (looked_up_at, metadata) = cache.get(&(provider, isbn.to_string()))?;
+                (looked_up_at.elapsed() < CACHE_TTL).then(|| metadata.clone())
+            }
+            LookupCache::Database(pool) => match cached_lookup(pool, provider, isbn).await {
+                Ok(cached) => cached,
+                Err(e) => {
+                    warn!(
+                        provider,
+                        isbn, "Failed to read the metadata lookup cache: {e}"
+                    );
+                    None
+                }
+            },
+        }
+    }
+
+    async fn put(&self, provider: &'static str, isbn: &str, metadata: &Option<BookMetadata>) {
+        match self {
+            LookupCache::Memory(cache) => {
+                let mut cache = cache.lock().unwrap();
+                if cache.len() >= CACHE_CAPACITY {
+                    cache.retain(|_, (looked_up_at, _)| looked_up_at.elapsed() < CACHE_TTL);
+                    if cache.len() >= CACHE_CAPACITY {
+                        cache.clear();
+                    }
+                }
+                cache.insert(
+                    (provider, isbn.to_string()),
+                    (Instant::now(), metadata.clone()),
+                );
+            }
+            LookupCache::Database(pool) => {
+                if let Err(e) = cache_lookup(pool, provider, isbn, metadata).await {
+                    warn!(
+                        provider,
+                        isbn, "Failed to write the metadata lookup cache: {e}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn cached_lookup(
+    pool: &DBPool,
+    provider: &str,
+    isbn: &str,
+) -> Result<Option<Option<BookMetadata>>, DatabaseError> {
+    let mut conn = pool.get().await?;
+    let expired_before =
+        chrono::Utc::now() - chrono::Duration::from_std(CACHE_TTL).expect("CACHE_TTL is in range");
+    let cached: Option<Option<String>> = metadata_lookups::table
+        .filter(metadata_lookups::provider.eq(provider))
+        .filter(metadata_lookups::isbn.eq(isbn))
+        .filter(metadata_lookups::looked_up_at.gt(expired_before))
+        .select(metadata_lookups::metadata)
+        .first(&mut conn)
+        .await
+        .optional()?;
+    // A row that no longer parses is treated as a miss, and replaced
+    Ok(cached.and_then(|metadata| match metadata {
+        Some(json) => serde_json::from_str(&json).ok().map(Some),
+        None => Some(None),
+    }))
+}
+
+async fn cache_lookup(
+    pool: &DBPool,
+    provider: &str,
+    isbn: &str,
+    metadata: &Option<BookMetadata>,
+) -> Result<(), DatabaseError> {
+    let mut conn = pool.get().await?;
+    let metadata = metadata.as_ref().map(|metadata| {
+        serde_json::to_string(metadata).expect("Book metadata can always be serialized")
+    });
+    diesel::insert_into(metadata_lookups::table)
+        .values((
+            metadata_lookups::provider.eq(provider),
+            metadata_lookups::isbn.eq(isbn),
+            metadata_lookups::metadata.eq(metadata),
+        ))
+        .on_conflict((metadata_lookups::provider, metadata_lookups::isbn))
+        .do_update()
+        .set((
+            metadata_lookups::metadata.eq(excluded(metadata_lookups::metadata)),
+            metadata_lookups::looked_up_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Fetch a provider's JSON response
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value, EnrichmentError> {
+    request
+        .timeout(LOOKUP_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(EnrichmentError::Request)?
+        .json()
+        .await
+        .map_err(EnrichmentError::Request)
+}
+
+/// The OpenLibrary Books API
+pub(crate) struct OpenLibrary {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl OpenLibrary {
+    pub fn new(base_url: String) -> Self {
+        OpenLibrary {
+            client: reqwest::Client::new(),
+            base_url,
+        }
+    }
+}
+
+impl MetadataProvider for OpenLibrary {
+    fn name(&self) -> &'static str {
+        "open_library"
+    }
+
+    /// OpenLibrary asks for no more than one request a second
+    fn min_interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn lookup<'a>(&'a self, isbn: &'a str) -> LookupFuture<'a> {
+        Box::pin(async move {
+            let bibkey = format!("ISBN:{isbn}");
+            let response = get_json(
+                self.client
+                    .get(format!("{}/api/books", self.base_url))
+                    .query(&[
+                        ("bibkeys", bibkey.as_str()),
+                        ("format", "json"),
+                        ("jscmd", "data"),
+                    ]),
+            )
+            .await?;
+            Ok(parse_open_library(&response[&bibkey]))
+        })
+    }
+}
+
+/// The metadata in an OpenLibrary `jscmd=data` record, which is `null` for a
+/// book it doesn't know
+fn parse_open_library(record: &Value) -> Option<BookMetadata> {
+    let record = record.as_object()?;
+    let names = |key: &str| -> Vec<String> {
+        record
+            .get(key)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry["name"].as_str().map(str::to_string))
+            .collect()
+    };
+    Some(BookMetadata {
+        title: record["title"].as_str().map(str::to_string),
+        authors: names("authors"),
+        publisher: names("publishers").into_iter().next(),
+        cover_url: ["large", "medium", "small"]
+            .iter()
+            .find_map(|size| record["cover"][size].as_str())
+            .map(str::to_string),
+    })
+}
+
+/// The Google Books API
+pub(crate) struct GoogleBooks {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl GoogleBooks {
+    pub fn new(base_url: String, api_key: Option<String>) -> Self {
+        GoogleBooks {
+            client: reqwest::Client::new(),
+            base_url,
+            api_key,
+        }
+    }
+}
+
+impl MetadataProvider for GoogleBooks {
+    fn name(&self) -> &'static str {
+        "google_books"
+    }
+
+    /// Google Books allows bursts, but has a daily quota to spread out
+    fn min_interval(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+
+    fn lookup<'a>(&'a self, isbn: &'a str) -> LookupFuture<'a> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .get(format!("{}/books/v1/volumes", self.base_url))
+                .query(&[("q", format!("isbn:{isbn}"))]);
+            if let Some(api_key) = &self.api_key {
+                request = request.query(&[("key", api_key)]);
+            }
+            let response = get_json(request).await?;
+            Ok(parse_google_books(&response["items"][0]["volumeInfo"]))
+        })
+    }
+}
+
+/// The metadata in a Google Books volume's `volumeInfo`, which is missing
+/// when no volume has the ISBN
+fn parse_google_books(volume: &Value) -> Option<BookMetadata> {
+    let volume = volume.as_object()?;
+    Some(BookMetadata {
+        title: volume["title"].as_str().map(str::to_string),
+        authors: volume
+            .get("authors")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|author| author.as_str().map(str::to_string))
+            .collect(),
+        publisher: volume
+            .get("publisher")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        // Image links are given as http://, but are also served over HTTPS
+        cover_url: ["thumbnail", "smallThumbnail"]
+            .iter()
+            .find_map(|size| volume.get("imageLinks")?.get(size)?.as_str())
+            .map(|url| url.replacen("http://", "https://", 1)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn missing_fields_are_filled_in_from_provider_records() {
+        let record = json!({
+            "title": "Pride and Prejudice",
+            "authors": [{"name": "Jane Austen", "url": "https://openlibrary.org/authors/OL21594A"}],
+            "publishers": [{"name": "Penguin Classics"}, {"name": "Penguin"}],
+            "cover": {"small": "https://covers.openlibrary.org/b/id/1-S.jpg", "large": "https://covers.openlibrary.org/b/id/1-L.jpg"},
+        });
+        let metadata = parse_open_library(&record).unwrap();
+        assert_eq!(
+            metadata,
+            BookMetadata {
+                title: Some("Pride and Prejudice".to_string()),
+                authors: vec!["Jane Austen".to_string()],
+                publisher: Some("Penguin Classics".to_string()),
+                cover_url: Some("https://covers.openlibrary.org/b/id/1-L.jpg".to_string()),
+            }
+        );
+        assert_eq!(parse_open_library(&Value::Null), None);
+
+        let volume = json!({
+            "title": "Pride and Prejudice",
+            "authors": ["Jane Austen"],
+            "imageLinks": {"smallThumbnail": "http://books.google.com/books/content?id=1&zoom=5"},
+        });
+        assert_eq!(
+            parse_google_books(&volume),
+            Some(BookMetadata {
+                title: Some("Pride and Prejudice".to_string()),
+                authors: vec!["Jane Austen".to_string()],
+                publisher: None,
+                cover_url: Some("https://books.google.com/books/content?id=1&zoom=5".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_google_books(&json!({"totalItems": 0})["items"][0]["volumeInfo"]),
+            None
+        );
+
+        let mut book = Book {
+            id: 1,
+            name: String::new(),
+            author: "J. Austen".to_string(),
+            updated_at: Utc::now(),
+            isbn: Some("9780141439518".to_string()),
+            publisher: None,
+            cover_url: None,
+            slug: String::new(),
+        };
+        assert!(needs_enrichment(&book));
+        let filled = metadata.fill_in(&book).unwrap();
+        assert_eq!(filled.name, "Pride and Prejudice");
+        // Fields that are already set are left alone
+        assert_eq!(filled.author, "J. Austen");
+        assert_eq!(filled.publisher.as_deref(), Some("Penguin Classics"));
+
+        book.name = filled.name;
+        book.publisher = filled.publisher;
+        book.cover_url = filled.cover_url;
+        assert!(!needs_enrichment(&book));
+        assert_eq!(metadata.fill_in(&book), None);
+    }
+
+    /// Knows only the ISBNs it is given, or fails every lookup
+    #[derive(Default)]
+    struct FakeProvider {
+        name: &'static str,
+        known: Vec<&'static str>,
+        fails: bool,
+        min_interval: Duration,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl MetadataProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn min_interval(&self) -> Duration {
+            self.min_interval
+        }
+
+        fn lookup<'a>(&'a self, isbn: &'a str) -> LookupFuture<'a> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let result = if self.fails {
+                Err(EnrichmentError::RateLimited {
+                    provider: self.name,
+                })
+            } else {
+                Ok(self.known.contains(&isbn).then(|| BookMetadata {
+                    title: Some(format!("{} title", self.name)),
+                    ..BookMetadata::default()
+                }))
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    #[tokio::test]
+    async fn providers_are_tried_in_order_and_their_lookups_cached() {
+        let provider = |name, known, fails| {
+            let lookups = Arc::new(AtomicUsize::new(0));
+            let provider = FakeProvider {
+                name,
+                known,
+                fails,
+                lookups: lookups.clone(),
+                ..FakeProvider::default()
+            };
+            (Box::new(provider) as Box<dyn MetadataProvider>, lookups)
+        };
+        let (first, first_lookups) = provider("first", vec!["1"], false);
+        let (second, second_lookups) = provider("second", vec!["1", "2"], false);
+        let enricher = Enricher::from_providers(vec![first, second]);
+
+        let title = |metadata: Option<BookMetadata>| metadata.unwrap().title.unwrap();
+        assert_eq!(title(enricher.lookup("1").await.unwrap()), "first title");
+        assert_eq!(title(enricher.lookup("2").await.unwrap()), "second title");
+        assert_eq!(enricher.lookup("3").await.unwrap(), None);
+        // The first provider's answers, including not knowing "2", are reused
+        assert_eq!(title(enricher.lookup("2").await.unwrap()), "second title");
+        let count = |lookups: &AtomicUsize| lookups.load(Ordering::SeqCst);
+        assert_eq!(count(&first_lookups), 3);
+        assert_eq!(count(&second_lookups), 2);
+
+        // A failure is only returned if no other provider knows the ISBN
+        let (failing, _) = provider("failing", vec![], true);
+        let (second, _) = provider("second", vec!["2"], false);
+        let enricher = Enricher::from_providers(vec![failing, second]);
+        assert_eq!(title(enricher.lookup("2").await.unwrap()), "second title");
+        assert!(matches!(
+            enricher.lookup("3").await,
+            Err(EnrichmentError::RateLimited {
+                provider: "failing"
+            })
+        ));
+    }
+
+    #[test]
+    fn lookups_wait_for_their_providers_rate_limit() {
+        let throttled = Throttled::new(Box::new(FakeProvider {
+            name: "fake",
+            min_interval: Duration::from_secs(1),
+            ..FakeProvider::default()
+        }));
+
+        assert_eq!(throttled.reserve(), Some(Duration::ZERO));
+        // One lookup a second, so the fifth waits about four seconds
+        for expected_wait in 1..=5 {
+            let wait = throttled.reserve().unwrap();
+            assert!(wait <= Duration::from_secs(expected_wait));
+            assert!(wait > Duration::from_secs(expected_wait - 1));
+        }
+        // Waiting six seconds is too long
+        assert_eq!(throttled.reserve(), None);
+    }
+}
@@ -0,0 +1,281 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::models::Book;
+
+/// How many events a subscriber can fall behind by before it misses some
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// A change to the catalog, published after it has been made
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BookEvent {
+    BookCreated(BookCreated),
+    BookUpdated(BookUpdated),
+    BookDeleted(BookDeleted),
+}
+
+impl BookEvent {
+    /// The `type` of each kind of event, as serialized
+    pub const TYPES: [&'static str; 3] = ["book_created", "book_updated", "book_deleted"];
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            BookEvent::BookCreated(_) => "book_created",
+            BookEvent::BookUpdated(_) => "book_updated",
+            BookEvent::BookDeleted(_) => "book_deleted",
+        }
+    }
+
+    /// The ID of the book that changed
+    pub fn book_id(&self) -> i32 {
+        match self {
+            BookEvent::BookCreated(created) => created.book.id,
+            BookEvent::BookUpdated(updated) => updated.book.id,
+            BookEvent::BookDeleted(deleted) => deleted.id,
+        }
+    }
+
+    /// The book as it was left by the change, unless it was deleted
+    pub fn book(&self) -> Option<&Book> {
+        match self {
+            BookEvent::BookCreated(created) => Some(&created.book),
+            BookEvent::BookUpdated(updated) => Some(&updated.book),
+            BookEvent::BookDeleted(_) => None,
+        }
+    }
+}
+
+/// A revision of a book: an event from its history, as recorded when the
+/// change was made
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookChange {
+    /// Numbers the book's revisions from 1
+    pub revision: i32,
+    pub recorded_at: DateTime<Utc>,
+    /// Who made the change, if known
+    pub actor: Option<String>,
+    /// The earlier revision this one reverted the book to, if it was a revert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restores_revision: Option<i32>,
+    #[serde(flatten)]
+    pub event: BookEvent,
+}
+
+/// A page of the change feed: revisions of every book, in the order they
+/// were recorded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeFeed {
+    pub changes: Vec<FeedEntry>,
+    /// Where to carry on from to get the changes after these, which is
+    /// `since` again if there were none
+    pub next_cursor: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedEntry {
+    /// Where to carry on from to get the changes after this one
+    pub cursor: String,
+    #[serde(flatten)]
+    pub change: BookChange,
+}
+
+/// The state of a book after the changes in its history up to and including
+/// `at`, or `None` if it didn't exist then. `history` must be oldest first.
+pub fn book_as_of(history: &[BookChange], at: DateTime<Utc>) -> Option<Book> {
+    history
+        .iter()
+        .take_while(|change| change.recorded_at <= at)
+        .fold(None, |_, change| change.event.book().cloned())
+}
+
+/// The fields that differ between two revisions of a book
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RevisionDiff {
+    pub from: i32,
+    pub to: i32,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A field whose value differs between two revisions. Fields are `None` at
+/// a revision that deleted the book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// Compare the book as left by two changes, field by field. `updated_at` is
+/// left out, as it differs between any two revisions.
+pub fn diff(from: &BookChange, to: &BookChange) -> RevisionDiff {
+    let fields = |change: &BookChange| {
+        let book = change.event.book();
+        [
+            ("name", book.map(|book| book.name.clone())),
+            ("author", book.map(|book| book.author.clone())),
+            ("isbn", book.and_then(|book| book.isbn.clone())),
+            ("publisher", book.and_then(|book| book.publisher.clone())),
+            ("cover_url", book.and_then(|book| book.cover_url.clone())),
+        ]
+    };
+    let changes = fields(from)
+        .into_iter()
+        .zip(fields(to))
+        .filter(|((_, from), (_, to))| from != to)
+        .map(|((field, from), (_, to))| FieldChange { field, from, to })
+        .collect();
+    RevisionDiff {
+        from: from.revision,
+        to: to.revision,
+        changes,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookCreated {
+    pub book: Book,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookUpdated {
+    pub book: Book,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookDeleted {
+    pub id: i32,
+}
+
+/// Broadcasts `BookEvent`s to every subscriber in the process, so features
+/// can react to changes without the write paths knowing about them.
+///
+/// Delivery is best-effort: events published while there are no subscribers
+/// are dropped, and a subscriber that falls too far behind skips the oldest
+/// events (`recv` reports how many as `RecvError::Lagged`). Clones share
+/// the same subscribers.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<BookEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus { sender }
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn publish(&self, event: BookEvent) {
+        // Only fails if nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn every_subscriber_receives_each_event() {
+        let bus = EventBus::default();
+        bus.publish(BookEvent::BookDeleted(BookDeleted { id: 1 }));
+
+        let mut first = bus.subscribe();
+        let mut second = bus.clone().subscribe();
+        bus.publish(BookEvent::BookDeleted(BookDeleted { id: 2 }));
+
+        for subscriber in [&mut first, &mut second] {
+            assert_eq!(
+                subscriber.recv().await.unwrap(),
+                BookEvent::BookDeleted(BookDeleted { id: 2 })
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&BookEvent::BookDeleted(BookDeleted { id: 2 })).unwrap(),
+            r#"{"type":"book_deleted","id":2}"#
+        );
+    }
+
+    #[test]
+    fn a_book_can_be_seen_as_it_was_at_any_time() {
+        let day = |d| Utc.with_ymd_and_hms(2026, 10, d, 12, 0, 0).unwrap();
+        let book = |name: &str, d| Book {
+            id: 42,
+            name: name.to_string(),
+            author: "Ursula K. Le Guin".to_string(),
+            updated_at: day(d),
+            isbn: None,
+            publisher: None,
+            cover_url: None,
+            slug: String::new(),
+        };
+        let change = |revision, d, event| BookChange {
+            revision,
+            recorded_at: day(d),
+            actor: None,
+            restores_revision: None,
+            event,
+        };
+        let history = [
+            change(
+                1,
+                5,
+                BookEvent::BookCreated(BookCreated {
+                    book: book("Earthsea", 5),
+                }),
+            ),
+            change(
+                2,
+                8,
+                BookEvent::BookUpdated(BookUpdated {
+                    book: book("A Wizard of Earthsea", 8),
+                }),
+            ),
+            change(3, 12, BookEvent::BookDeleted(BookDeleted { id: 42 })),
+        ];
+
+        assert_eq!(book_as_of(&history, day(4)), None);
+        assert_eq!(book_as_of(&history, day(5)), Some(book("Earthsea", 5)));
+        assert_eq!(
+            book_as_of(&history, day(10)),
+            Some(book("A Wizard of Earthsea", 8))
+        );
+        assert_eq!(book_as_of(&history, day(12)), None);
+
+        let changed = |field, from: Option<&str>, to: Option<&str>| FieldChange {
+            field,
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+        };
+        assert_eq!(
+            diff(&history[0], &history[1]).changes,
+            vec![changed(
+                "name",
+                Some("Earthsea"),
+                Some("A Wizard of Earthsea")
+            )]
+        );
+        assert_eq!(diff(&history[1], &history[1]).changes, vec![]);
+        assert_eq!(
+            diff(&history[1], &history[2]).changes,
+            vec![
+                changed("name", Some("A Wizard of Earthsea"), None),
+                changed("author", Some("Ursula K. Le Guin"), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl, SimpleAsyncConnection};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use tracing::{error, info};
+
+use crate::backup::{backup_file_name, BackupEncoder};
+use crate::commands::CommandError;
+use crate::database::DBPool;
+use crate::models::{Book, BookTranslation};
+use crate::schema::{book_revisions, book_translations, book_views, books};
+
+/// How many rows are compressed between sending the bytes so far
+const CHUNK_ROWS: usize = 500;
+/// How many chunks can be waiting to be sent to a slow client, before
+/// reading from the DB waits for it
+const BUFFERED_CHUNKS: usize = 4;
+
+/// A row of `book_revisions`, as it is in the table
+#[derive(Debug, diesel::Queryable, diesel::Selectable, serde::Serialize)]
+#[diesel(table_name = book_revisions)]
+struct RevisionRow {
+    id: i64,
+    book_id: i32,
+    revision: i32,
+    event_type: String,
+    payload: String,
+    recorded_at: DateTime<Utc>,
+    actor: Option<String>,
+    restores_revision: Option<i32>,
+}
+
+/// A row of `book_views`
+#[derive(Debug, diesel::Queryable, diesel::Selectable, serde::Serialize)]
+#[diesel(table_name = book_views)]
+struct ViewsRow {
+    book_id: i32,
+    hour: DateTime<Utc>,
+    views: i32,
+}
+
+pub(crate) fn export_router(pool: DBPool) -> Router {
+    Router::new()
+        .route("/admin/export/full", get(export_full))
+        .with_state(pool)
+}
+
+/// Stream every book, translation, revision and view as a gzipped JSONL file, in the
+/// layout of a backup, straight from the DB without holding the tables in
+/// memory. The rows are all read from one snapshot, so they are consistent
+/// with each other, however long the download takes.
+async fn export_full(State(pool): State<DBPool>) -> Result<Response, (StatusCode, String)> {
+    let mut conn = pool
+        .get_owned()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    conn.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let created_at = Utc::now();
+    let (mut chunks, body) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::spawn(async move {
+        let result = write_export(&mut conn, created_at, &mut chunks).await;
+        // Read only, so there is nothing to commit
+        let _ = conn.batch_execute("ROLLBACK").await;
+        match result {
+            Ok(rows) => info!(rows, "Exported the catalog"),
+            Err(e) => {
+                error!("Failed to export the catalog: {e}");
+                // Ends the response with an error, rather than a truncated
+                // file that looks complete
+                let _ = chunks.send(Err(e)).await;
+            }
+        }
+    });
+
+    let disposition = format!("attachment; filename=\"{}\"", backup_file_name(created_at));
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/gzip"),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::try_from(disposition).expect("The file name is a valid header"),
+            ),
+            // Each download is a new snapshot, so a part of one can't be
+            // resumed from another
+            (header::ACCEPT_RANGES, HeaderValue::from_static("none")),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+type Chunks = mpsc::Sender<Result<Vec<u8>, CommandError>>;
+
+/// Write each table to `chunks`, returning how many rows there were
+async fn write_export(
+    conn: &mut AsyncPgConnection,
+    created_at: DateTime<Utc>,
+    chunks: &mut Chunks,
+) -> Result<usize, CommandError> {
+    let mut encoder = BackupEncoder::new(created_at)?;
+
+    let books = books::table
+        .select(Book::as_select())
+        .order(books::id.asc())
+        .load_stream::<Book>(conn)
+        .await?;
+    let mut rows = write_rows(&mut encoder, "books", books, chunks).await?;
+
+    let translations = book_translations::table
+        .select(BookTranslation::as_select())
+        .order((
+            book_translations::book_id.asc(),
+            book_translations::language.asc(),
+        ))
+        .load_stream::<BookTranslation>(conn)
+        .await?;
+    rows += write_rows(&mut encoder, "book_translations", translations, chunks).await?;
+
+    let revisions = book_revisions::table
+        .select(RevisionRow::as_select())
+        .order(book_revisions::id.asc())
+        .load_stream::<RevisionRow>(conn)
+        .await?;
+    rows += write_rows(&mut encoder, "book_revisions", revisions, chunks).await?;
+
+    let views = book_views::table
+        .select(ViewsRow::as_select())
+        .order((book_views::book_id.asc(), book_views::hour.asc()))
+        .load_stream::<ViewsRow>(conn)
+        .await?;
+    rows += write_rows(&mut encoder, "book_views", views, chunks).await?;
+
+    chunks.send(Ok(encoder.finish()?)).await?;
+    Ok(rows)
+}
+
+/// Write the rows of a table, sending the compressed bytes every
+/// `CHUNK_ROWS` rows. Fails if the client has gone away.
+async fn write_rows<T: serde::Serialize>(
+    encoder: &mut BackupEncoder,
+    table: &str,
+    rows: impl Stream<Item = QueryResult<T>>,
+    chunks: &mut Chunks,
+) -> Result<usize, CommandError> {
+    let mut count = 0;
+    let mut rows = std::pin::pin!(rows);
+    while let Some(row) = rows.next().await {
+        encoder.write_row(table, row?)?;
+        count += 1;
+        if count % CHUNK_ROWS == 0 {
+            chunks.send(Ok(encoder.take())).await?;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,198 @@
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Request},
+    http::{Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use tower::ServiceExt;
+
+use crate::json_error::json_error;
+
+/// Marks a request sent only to find out whether its path has a route, and
+/// the response to it
+#[derive(Debug, Clone, Copy)]
+struct RouteProbe;
+
+/// Add the fallbacks for requests matching no route, or none of the methods
+/// of their route. Called once the router has all its routes.
+pub(crate) fn with_fallbacks(router: Router) -> Router {
+    // Only run for requests that match a route, so it answers a probe if
+    // and only if the path has one, before any of the route's handlers or
+    // middleware
+    let router = router.route_layer(middleware::from_fn(answer_probe));
+    let routes = router.clone();
+    router
+        .fallback(move |uri| not_found(routes.clone(), uri))
+        .method_not_allowed_fallback(method_not_allowed)
+}
+
+async fn answer_probe(request: Request, next: Next) -> Response {
+    if request.extensions().get::<RouteProbe>().is_some() {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        response.extensions_mut().insert(RouteProbe);
+        return response;
+    }
+    next.run(request).await
+}
+
+/// Fallback for requests that match no route, answered with the API's JSON
+/// error body rather than an empty 404.
+///
+/// A path that would match once normalized, e.g. `/books/` or `/books//1`,
+/// is instead redirected to the normalized path. The redirect is a 308, so
+/// clients repeat the request with the same method and body.
+async fn not_found(routes: Router, OriginalUri(uri): OriginalUri) -> Response {
+    let path = normalize_path(uri.path());
+    if path != uri.path() && has_route(routes, &path).await {
+        let location = match uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+        return Redirect::permanent(&location).into_response();
+    }
+
+    no_route(&uri)
+}
+
+/// The JSON 404 for a path with no route
+pub(crate) fn no_route(uri: &Uri) -> Response {
+    json_error(
+        StatusCode::NOT_FOUND,
+        format!("No route for {}", uri.path()),
+    )
+}
+
+/// Fallback for requests to a known path with a method it doesn't support.
+/// The router adds the `Allow` header listing the methods it does support.
+///
+/// That makes it the place to answer `OPTIONS` requests, with a 204 whose
+/// `Allow` header is derived from the route.
+async fn method_not_allowed(method: Method, OriginalUri(uri): OriginalUri) -> Response {
+    if method == Method::OPTIONS {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    json_error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        format!("{method} is not supported on {}", uri.path()),
+    )
+}
+
+/// Whether a request for the path would match a route of the router
+async fn has_route(routes: Router, path: &str) -> bool {
+    let Ok(mut probe) = Request::get(path).body(Body::empty()) else {
+        return false;
+    };
+    probe.extensions_mut().insert(RouteProbe);
+    match routes.oneshot(probe).await {
+        Ok(response) => response.extensions().get::<RouteProbe>().is_some(),
+        Err(infallible) => match infallible {},
+    }
+}
+
+/// Collapse repeated slashes and drop any trailing slash
+fn normalize_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::header,
+        routing::{get, post, put},
+        Router,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    async fn json_body(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_methods_get_json_errors() {
+        let router = with_fallbacks(Router::new().nest(
+            "/v1",
+            Router::new().route("/books/{id}", get(|| async {}).merge(put(|| async {}))),
+        ));
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/nope").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            json_body(response).await,
+            json!({"error": "No route for /nope"})
+        );
+
+        let response = router
+            .clone()
+            .oneshot(Request::options("/v1/books/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD,PUT");
+
+        let response = router
+            .oneshot(Request::post("/v1/books/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD,PUT");
+        assert_eq!(
+            json_body(response).await,
+            json!({"error": "POST is not supported on /v1/books/1"})
+        );
+    }
+
+    #[tokio::test]
+    async fn unnormalized_paths_are_redirected_only_if_they_have_a_route() {
+        let routes_called = Arc::new(AtomicUsize::new(0));
+        let counter = routes_called.clone();
+        let router = with_fallbacks(Router::new().route("/books", get(|| async {})).route(
+            "/books/{id}/enrich",
+            post(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        ));
+
+        for (path, location) in [
+            ("/books/", "/books"),
+            ("//books?page=2", "/books?page=2"),
+            ("/books//1/enrich/", "/books/1/enrich"),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::post(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(response.headers()[header::LOCATION], location);
+        }
+        // Finding the route doesn't call it
+        assert_eq!(routes_called.load(Ordering::SeqCst), 0);
+
+        for path in ["/nope/", "/books/1/", "/"] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+            assert!(response.headers().get(header::LOCATION).is_none());
+        }
+    }
+}
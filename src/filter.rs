@@ -0,0 +1,636 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::models::{fold_text, Book};
+
+/// The longest filter accepted, in characters
+const MAX_LENGTH: usize = 2000;
+/// The most comparisons a filter can make
+const MAX_COMPARISONS: usize = 32;
+/// How deeply parentheses can be nested
+const MAX_DEPTH: usize = 8;
+
+/// A field of a book that can be filtered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    Id,
+    Name,
+    Author,
+    Isbn,
+    Publisher,
+    Slug,
+    UpdatedAt,
+}
+
+impl FilterField {
+    pub const ALL: [FilterField; 7] = [
+        FilterField::Id,
+        FilterField::Name,
+        FilterField::Author,
+        FilterField::Isbn,
+        FilterField::Publisher,
+        FilterField::Slug,
+        FilterField::UpdatedAt,
+    ];
+
+    /// The name used for the field in filters, as in the book's JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterField::Id => "id",
+            FilterField::Name => "name",
+            FilterField::Author => "author",
+            FilterField::Isbn => "isbn",
+            FilterField::Publisher => "publisher",
+            FilterField::Slug => "slug",
+            FilterField::UpdatedAt => "updated_at",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<FilterField> {
+        FilterField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+    }
+}
+
+/// How a field is compared with the values given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// `==`. Text matches ignoring case and diacritics, and `*` matches any
+    /// characters.
+    Eq,
+    /// `!=`, matching books without the field too
+    Ne,
+    /// `<` or `=lt=`
+    Lt,
+    /// `<=` or `=le=`
+    Le,
+    /// `>` or `=gt=`
+    Gt,
+    /// `>=` or `=ge=`
+    Ge,
+    /// `=in=`: `==` any of a list of values
+    In,
+    /// `=out=`: `!=` all of a list of values
+    Out,
+}
+
+/// A field that `?name=`-style searches look in for text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Name,
+    Author,
+}
+
+impl TextField {
+    fn value(self, book: &Book) -> &str {
+        match self {
+            TextField::Name => &book.name,
+            TextField::Author => &book.author,
+        }
+    }
+}
+
+/// The values a field is compared with, of the field's type. There is one,
+/// except for `=in=` and `=out=`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterValues {
+    Ids(Vec<i32>),
+    Texts(Vec<String>),
+    Times(Vec<DateTime<Utc>>),
+}
+
+/// A filter on books, parsed from e.g. `author=="Kazuo Ishiguro";id>100`.
+/// Only the fields in `FilterField` can be compared, so it can be turned
+/// into a query without any of it being spliced into SQL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Every filter matches, written `a;b`
+    And(Vec<Filter>),
+    /// Any filter matches, written `a,b`
+    Or(Vec<Filter>),
+    Compare {
+        field: FilterField,
+        comparison: Comparison,
+        values: FilterValues,
+    },
+    /// The field contains the text, ignoring case and diacritics, where `*`
+    /// is just a `*`.
+    /// Not written in filters, only made by `Filter::contains`.
+    Contains { field: TextField, text: String },
+}
+
+/// What is wrong with a filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError(String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl Filter {
+    /// Parse a filter in RSQL syntax: comparisons like `name=="Never Let Me
+    /// Go"` or `id=in=(1,2,3)`, joined by `;` (and) or `,` (or), with
+    /// parentheses for grouping. `;` binds tighter than `,`.
+    pub fn parse(input: &str) -> Result<Filter, FilterError> {
+        if input.chars().count() > MAX_LENGTH {
+            return Err(FilterError(format!(
+                "it must be at most {MAX_LENGTH} characters"
+            )));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+            comparisons: 0,
+        };
+        let filter = parser.or(0)?;
+        match parser.next() {
+            None => Ok(filter),
+            Some(token) => Err(FilterError(format!("unexpected {token}"))),
+        }
+    }
+
+    /// A filter for books whose field contains the text, ignoring case and
+    /// diacritics, as for `?name=` and `?author=`. The text has no wildcards.
+    pub fn contains(field: TextField, text: &str) -> Filter {
+        Filter::Contains {
+            field,
+            text: text.to_string(),
+        }
+    }
+
+    /// A filter that every one of the filters must match, or `None` if there
+    /// are none
+    pub fn all(mut filters: Vec<Filter>) -> Option<Filter> {
+        match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(Filter::And(filters)),
+        }
+    }
+
+    /// Whether the book matches, as it would in the DB
+    pub fn matches(&self, book: &Book) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(book)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(book)),
+            Filter::Compare {
+                field,
+                comparison,
+                values,
+            } => match (field, values) {
+                (FilterField::Id, FilterValues::Ids(ids)) => compare(&book.id, *comparison, ids),
+                (FilterField::UpdatedAt, FilterValues::Times(times)) => {
+                    compare(&book.updated_at, *comparison, times)
+                }
+                (field, FilterValues::Texts(texts)) => {
+                    let text = match field {
+                        FilterField::Name => Some(&book.name),
+                        FilterField::Author => Some(&book.author),
+                        FilterField::Slug => Some(&book.slug),
+                        FilterField::Isbn => book.isbn.as_ref(),
+                        _ => book.publisher.as_ref(),
+                    };
+                    compare_text(text.map(String::as_str), *comparison, texts)
+                }
+                _ => false,
+            },
+            Filter::Contains { field, text } => {
+                fold_text(field.value(book)).contains(&fold_text(text))
+            }
+        }
+    }
+}
+
+fn compare<T: Ord>(value: &T, comparison: Comparison, values: &[T]) -> bool {
+    let ordering = || value.cmp(&values[0]);
+    match comparison {
+        Comparison::Eq => ordering() == Ordering::Equal,
+        Comparison::Ne => ordering() != Ordering::Equal,
+        Comparison::Lt => ordering() == Ordering::Less,
+        Comparison::Le => ordering() != Ordering::Greater,
+        Comparison::Gt => ordering() == Ordering::Greater,
+        Comparison::Ge => ordering() != Ordering::Less,
+        Comparison::In => values.contains(value),
+        Comparison::Out => !values.contains(value),
+    }
+}
+
+/// As `compare`, but equality ignores case and diacritics and allows
+/// wildcards, and a missing value only matches `!=` and `=out=`, as `NULL`
+/// does in the DB. Ordering is by code point, which the DB's collation may
+/// not be.
+fn compare_text(text: Option<&str>, comparison: Comparison, patterns: &[String]) -> bool {
+    let Some(text) = text else {
+        return matches!(comparison, Comparison::Ne | Comparison::Out);
+    };
+    let folded = fold_text(text);
+    let like = |pattern: &String| wildcard_match(&folded, &fold_text(pattern));
+    match comparison {
+        Comparison::Eq | Comparison::In => patterns.iter().any(like),
+        Comparison::Ne | Comparison::Out => !patterns.iter().any(like),
+        _ => {
+            let ordering = text.cmp(patterns[0].as_str());
+            match comparison {
+                Comparison::Lt => ordering == Ordering::Less,
+                Comparison::Le => ordering != Ordering::Greater,
+                Comparison::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }
+        }
+    }
+}
+
+/// Whether the text matches the pattern, where `*` matches any characters
+fn wildcard_match(text: &str, pattern: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcards
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Comparison(Comparison),
+    /// A field name or unquoted value
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::And => write!(f, "';'"),
+            Token::Or => write!(f, "','"),
+            Token::Comparison(_) => write!(f, "comparison"),
+            Token::Word(word) => write!(f, "{word:?}"),
+            Token::Quoted(text) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+/// Characters that end an unquoted value
+const RESERVED: &str = "\"'();,=!<>";
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ';' => Token::And,
+            ',' => Token::Or,
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => text.extend(chars.next()),
+                        Some(end) if end == c => break,
+                        Some(other) => text.push(other),
+                        None => return Err(FilterError(format!("unclosed {c}"))),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            '=' | '!' | '<' | '>' => {
+                let mut operator = String::from(c);
+                if c == '=' {
+                    // `==` or `=name=`
+                    while let Some(&next) = chars.peek() {
+                        chars.next();
+                        operator.push(next);
+                        if next == '=' || !next.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                } else if chars.peek() == Some(&'=') {
+                    operator.extend(chars.next());
+                }
+                let comparison = match operator.as_str() {
+                    "==" => Comparison::Eq,
+                    "!=" => Comparison::Ne,
+                    "<" | "=lt=" => Comparison::Lt,
+                    "<=" | "=le=" => Comparison::Le,
+                    ">" | "=gt=" => Comparison::Gt,
+                    ">=" | "=ge=" => Comparison::Ge,
+                    "=in=" => Comparison::In,
+                    "=out=" => Comparison::Out,
+                    _ => {
+                        return Err(FilterError(format!(
+                        "unknown comparison {operator:?}: use ==, !=, <, <=, >, >=, =in= or =out="
+                    )))
+                    }
+                };
+                Token::Comparison(comparison)
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || RESERVED.contains(next) {
+                        break;
+                    }
+                    word.extend(chars.next());
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    comparisons: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.position) == Some(token);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), FilterError> {
+        match self.next() {
+            Some(next) if next == token => Ok(()),
+            Some(next) => Err(FilterError(format!("expected {token}, found {next}"))),
+            None => Err(FilterError(format!("expected {token} at the end"))),
+        }
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.and(depth)?];
+        while self.eat(&Token::Or) {
+            filters.push(self.and(depth)?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::Or(filters)
+        })
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.term(depth)?];
+        while self.eat(&Token::And) {
+            filters.push(self.term(depth)?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::And(filters)
+        })
+    }
+
+    fn term(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        if self.eat(&Token::Open) {
+            if depth == MAX_DEPTH {
+                return Err(FilterError(format!(
+                    "parentheses can be nested at most {MAX_DEPTH} deep"
+                )));
+            }
+            let filter = self.or(depth + 1)?;
+            self.expect(Token::Close)?;
+            return Ok(filter);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Filter, FilterError> {
+        self.comparisons += 1;
+        if self.comparisons > MAX_COMPARISONS {
+            return Err(FilterError(format!(
+                "it can make at most {MAX_COMPARISONS} comparisons"
+            )));
+        }
+
+        let field = match self.next() {
+            Some(Token::Word(name)) => FilterField::from_name(&name).ok_or_else(|| {
+                let fields: Vec<_> = FilterField::ALL.iter().map(|field| field.name()).collect();
+                FilterError(format!(
+                    "unknown field {name:?}: filter on {}",
+                    fields.join(", ")
+                ))
+            })?,
+            Some(token) => return Err(FilterError(format!("expected a field, found {token}"))),
+            None => return Err(FilterError("expected a field at the end".to_string())),
+        };
+        let comparison = match self.next() {
+            Some(Token::Comparison(comparison)) => comparison,
+            _ => {
+                return Err(FilterError(format!(
+                    "expected a comparison after {}",
+                    field.name()
+                )))
+            }
+        };
+
+        let mut values = Vec::new();
+        if matches!(comparison, Comparison::In | Comparison::Out) {
+            self.expect(Token::Open)?;
+            values.push(self.value()?);
+            while self.eat(&Token::Or) {
+                values.push(self.value()?);
+            }
+            self.expect(Token::Close)?;
+        } else {
+            values.push(self.value()?);
+        }
+
+        let invalid = |value: &String, expected| {
+            FilterError(format!(
+                "{} must be compared with {expected}, got {value:?}",
+                field.name()
+            ))
+        };
+        let values = match field {
+            FilterField::Id => FilterValues::Ids(
+                values
+                    .iter()
+                    .map(|value| value.parse().map_err(|_| invalid(value, "a whole number")))
+                    .collect::<Result<_, _>>()?,
+            ),
+            FilterField::UpdatedAt => FilterValues::Times(
+                values
+                    .iter()
+                    .map(|value| {
+                        DateTime::parse_from_rfc3339(value)
+                            .map(|time| time.with_timezone(&Utc))
+                            .map_err(|_| invalid(value, "a time like 2026-10-17T09:00:00Z"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => FilterValues::Texts(values),
+        };
+
+        Ok(Filter::Compare {
+            field,
+            comparison,
+            values,
+        })
+    }
+
+    fn value(&mut self) -> Result<String, FilterError> {
+        match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => Ok(value),
+            Some(token) => Err(FilterError(format!("expected a value, found {token}"))),
+            None => Err(FilterError("expected a value at the end".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(field: FilterField, comparison: Comparison, values: FilterValues) -> Filter {
+        Filter::Compare {
+            field,
+            comparison,
+            values,
+        }
+    }
+
+    fn texts(texts: &[&str]) -> FilterValues {
+        FilterValues::Texts(texts.iter().map(|text| text.to_string()).collect())
+    }
+
+    #[test]
+    fn filters_are_parsed_into_comparisons_of_known_fields() {
+        assert_eq!(
+            Filter::parse(r#"author=="Kazuo Ishiguro";id>100,(name==Emma*;isbn=out=(1,'2'))"#),
+            Ok(Filter::Or(vec![
+                Filter::And(vec![
+                    compare(
+                        FilterField::Author,
+                        Comparison::Eq,
+                        texts(&["Kazuo Ishiguro"])
+                    ),
+                    compare(
+                        FilterField::Id,
+                        Comparison::Gt,
+                        FilterValues::Ids(vec![100])
+                    ),
+                ]),
+                Filter::And(vec![
+                    compare(FilterField::Name, Comparison::Eq, texts(&["Emma*"])),
+                    compare(FilterField::Isbn, Comparison::Out, texts(&["1", "2"])),
+                ]),
+            ]))
+        );
+        assert_eq!(
+            Filter::parse("updated_at=ge=2026-10-17T09:00:00Z"),
+            Ok(compare(
+                FilterField::UpdatedAt,
+                Comparison::Ge,
+                FilterValues::Times(vec!["2026-10-17T09:00:00Z".parse().unwrap()])
+            ))
+        );
+
+        for invalid in [
+            "",
+            "author",
+            "author==",
+            "publication_year>2000",
+            "id==ten",
+            "id=like=1",
+            "name==\"Emma",
+            "(name==Emma",
+            "name==Emma)",
+            "name=in=Emma",
+            "name==Emma;",
+            &"(".repeat(MAX_DEPTH + 1),
+            &vec!["id==1"; MAX_COMPARISONS + 1].join(";"),
+        ] {
+            assert!(Filter::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn books_are_matched_as_in_the_db() {
+        let book = Book {
+            id: 7,
+            name: "Never Let Me Go".to_string(),
+            author: "Kazuo Ishiguro".to_string(),
+            updated_at: "2026-10-17T09:00:00Z".parse().unwrap(),
+            isbn: None,
+            publisher: Some("Faber".to_string()),
+            cover_url: None,
+            slug: "never-let-me-go".to_string(),
+        };
+        let matches = |filter: &str| Filter::parse(filter).unwrap().matches(&book);
+
+        assert!(matches("author==\"kazuo ishiguro\""));
+        assert!(matches("name==never*;name==*go"));
+        assert!(matches("name==*let*"));
+        assert!(!matches("name==never"));
+        assert!(matches("id=in=(1,7);updated_at<2026-10-18T00:00:00Z"));
+        assert!(matches("id<5,publisher=out=(Penguin,Vintage)"));
+        // A missing ISBN is never equal to anything
+        assert!(!matches("isbn==*"));
+        assert!(matches("isbn!=0141439513"));
+
+        let contains = |field, text| Filter::contains(field, text).matches(&book);
+        assert!(contains(TextField::Name, "LET ME"));
+        assert!(contains(TextField::Author, "ishiguro"));
+        assert!(!contains(TextField::Author, "let me"));
+        // A `*` is only ever a `*`
+        assert!(!contains(TextField::Name, "never*go"));
+        let starred = Book {
+            name: "M*A*S*H".to_string(),
+            ..book.clone()
+        };
+        assert!(Filter::contains(TextField::Name, "a*s").matches(&starred));
+
+        // Diacritics are ignored on both sides
+        let bronte = Book {
+            author: "Emily Brontë".to_string(),
+            ..book.clone()
+        };
+        assert!(Filter::contains(TextField::Author, "bronte").matches(&bronte));
+        assert!(Filter::parse("author==*BRONTE").unwrap().matches(&bronte));
+        assert!(!Filter::parse("author!=*bronte").unwrap().matches(&bronte));
+        let anne = Book {
+            author: "Anne Bronte".to_string(),
+            ..book.clone()
+        };
+        assert!(Filter::contains(TextField::Author, "Brontë").matches(&anne));
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::{
+    extract::{OriginalUri, Path, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, put},
+    Json, Router,
+};
+use tracing::{error, info};
+
+use crate::config::valid_flag_name;
+use crate::content_type::require_json;
+use crate::database::{DatabaseError, FlagStore};
+use crate::fallback::no_route;
+use crate::runtime_config::RuntimeConfigHandle;
+
+/// How often flags toggled through another replica are picked up
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Named switches for rolling features out without a redeploy. A flag
+/// toggled through the admin API takes precedence over the `[flags]`
+/// section of the config file, and a flag set in neither is off. Clones
+/// share their toggles.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    runtime_config: RuntimeConfigHandle,
+    /// The flags toggled through the admin API
+    overrides: Arc<RwLock<BTreeMap<String, bool>>>,
+    /// Where toggles are kept, so that they survive restarts and are shared
+    /// by every replica. Without one they only last as long as the process.
+    store: Option<FlagStore>,
+}
+
+/// A flag's current state, and where it comes from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FlagState {
+    pub enabled: bool,
+    /// `admin` if toggled through the admin API, otherwise `config`
+    pub source: &'static str,
+}
+
+impl FeatureFlags {
+    pub fn new(runtime_config: RuntimeConfigHandle) -> Self {
+        FeatureFlags {
+            runtime_config,
+            overrides: Arc::default(),
+            store: None,
+        }
+    }
+
+    /// Keep toggles in the DB
+    pub(crate) fn with_store(mut self, store: FlagStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        if let Some(enabled) = self.overrides().get(name) {
+            return *enabled;
+        }
+        self.runtime_config
+            .current()
+            .flags
+            .get(name)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Every flag that is set in the config file or toggled, by name
+    pub fn all(&self) -> BTreeMap<String, FlagState> {
+        let mut flags: BTreeMap<String, FlagState> = self
+            .runtime_config
+            .current()
+            .flags
+            .into_iter()
+            .map(|(name, enabled)| {
+                let state = FlagState {
+                    enabled,
+                    source: "config",
+                };
+                (name, state)
+            })
+            .collect();
+        for (name, enabled) in self.overrides().iter() {
+            let state = FlagState {
+                enabled: *enabled,
+                source: "admin",
+            };
+            flags.insert(name.clone(), state);
+        }
+        flags
+    }
+
+    /// Turn the flag on or off, whatever the config file says
+    pub async fn set(&self, name: &str, enabled: bool) -> Result<(), DatabaseError> {
+        if let Some(store) = &self.store {
+            store.set(name, enabled).await?;
+        }
+        self.overrides_mut().insert(name.to_string(), enabled);
+        Ok(())
+    }
+
+    /// Go back to the config file's setting for the flag, returning whether
+    /// it had been toggled
+    pub async fn clear(&self, name: &str) -> Result<bool, DatabaseError> {
+        let stored = match &self.store {
+            Some(store) => store.clear(name).await?,
+            None => false,
+        };
+        let overridden = self.overrides_mut().remove(name).is_some();
+        Ok(stored || overridden)
+    }
+
+    /// Replace the toggles with those in the store, if there is one
+    pub(crate) async fn refresh(&self) -> Result<(), DatabaseError> {
+        if let Some(store) = &self.store {
+            let overrides = store.load().await?;
+            *self.overrides_mut() = overrides.into_iter().collect();
+        }
+        Ok(())
+    }
+
+    /// Pick up toggles from the store, now and periodically, so that a flag
+    /// toggled through one replica takes effect on all of them
+    pub(crate) fn spawn_refresher(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.refresh().await {
+                    error!("Failed to load feature flags: {e}");
+                }
+            }
+        });
+    }
+
+    fn overrides(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<String, bool>> {
+        self.overrides
+            .read()
+            .expect("No thread panics holding the lock")
+    }
+
+    fn overrides_mut(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<String, bool>> {
+        self.overrides
+            .write()
+            .expect("No thread panics holding the lock")
+    }
+}
+
+/// Middleware hiding routes behind a flag, answering with a 404 while it
+/// is off, e.g.
+/// `route_layer(middleware::from_fn_with_state((flags, "reviews"), require_flag))`
+pub async fn require_flag(
+    State((flags, name)): State<(FeatureFlags, &'static str)>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Response {
+    if flags.is_enabled(name) {
+        next.run(request).await
+    } else {
+        no_route(&uri)
+    }
+}
+
+/// Routes for listing and toggling the flags, which are served behind the
+/// admin token
+pub(crate) fn flags_router(flags: FeatureFlags) -> Router {
+    Router::new()
+        .route("/admin/flags", get(list_flags))
+        .route(
+            "/admin/flags/{name}",
+            put(set_flag)
+                .route_layer(middleware::from_fn(require_json))
+                .delete(clear_flag),
+        )
+        .with_state(flags)
+}
+
+#[derive(serde::Deserialize)]
+struct SetFlag {
+    enabled: bool,
+}
+
+async fn list_flags(State(flags): State<FeatureFlags>) -> Json<BTreeMap<String, FlagState>> {
+    Json(flags.all())
+}
+
+async fn set_flag(
+    State(flags): State<FeatureFlags>,
+    Path(name): Path<String>,
+    Json(SetFlag { enabled }): Json<SetFlag>,
+) -> Result<Json<FlagState>, (StatusCode, String)> {
+    if !valid_flag_name(&name) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{name} must be named with lower-case letters, digits and underscores"),
+        ));
+    }
+    flags.set(&name, enabled).await.map_err(internal_error)?;
+    info!(name, enabled, "Toggled feature flag");
+
+    Ok(Json(FlagState {
+        enabled,
+        source: "admin",
+    }))
+}
+
+async fn clear_flag(
+    State(flags): State<FeatureFlags>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if flags.clear(&name).await.map_err(internal_error)? {
+        info!(name, "Cleared feature flag");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            format!("Feature flag {name} hasn't been toggled"),
+        ))
+    }
+}
+
+fn internal_error(e: DatabaseError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::RuntimeConfig;
+
+    #[tokio::test]
+    async fn toggles_take_precedence_over_the_config_file() {
+        let runtime_config = RuntimeConfigHandle::new(
+            RuntimeConfig {
+                flags: BTreeMap::from([
+                    ("reviews".to_string(), true),
+                    ("new_search".to_string(), false),
+                ]),
+                ..RuntimeConfig::default()
+            },
+            None,
+        );
+        let flags = FeatureFlags::new(runtime_config);
+        let app = flags_router(flags.clone()).route(
+            "/search",
+            get(|| async { "found" }).route_layer(middleware::from_fn_with_state(
+                (flags.clone(), "new_search"),
+                require_flag,
+            )),
+        );
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        assert!(flags.is_enabled("reviews"));
+        assert!(!flags.is_enabled("unknown"));
+        assert_eq!(send("GET", "/search", "").await.unwrap().status(), 404);
+
+        let response = send("PUT", "/admin/flags/new_search", r#"{"enabled": true}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(send("GET", "/search", "").await.unwrap().status(), 200);
+        assert_eq!(
+            flags.all()["new_search"],
+            FlagState {
+                enabled: true,
+                source: "admin"
+            }
+        );
+        let response = send("PUT", "/admin/flags/New-Search", r#"{"enabled": true}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+        let not_json = Request::put("/admin/flags/new_search")
+            .header("content-type", "text/plain")
+            .body(Body::from(r#"{"enabled": false}"#))
+            .unwrap();
+        let response = app.clone().oneshot(not_json).await.unwrap();
+        assert_eq!(response.status(), 415);
+        assert!(flags.is_enabled("new_search"));
+
+        let response = send("DELETE", "/admin/flags/new_search", "").await.unwrap();
+        assert_eq!(response.status(), 204);
+        assert!(!flags.is_enabled("new_search"));
+        let response = send("DELETE", "/admin/flags/new_search", "").await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::models::{Actor, Book, NewBook};
+
+type BeforeInsert = Arc<dyn Fn(&mut NewBook, &Actor) -> Result<(), String> + Send + Sync>;
+type AfterUpdate = Arc<dyn Fn(&Book, &Actor) + Send + Sync>;
+type OnDelete = Arc<dyn Fn(&Book, &Actor) -> Result<(), String> + Send + Sync>;
+
+/// Custom business rules, run by `BookService` around changes to the
+/// catalog, however they are made. Hooks of the same kind run in the order
+/// they were added, and a hook that rejects a change stops it, with a 422
+/// and the hook's message.
+#[derive(Clone, Default)]
+pub struct BookHooks {
+    before_insert: Vec<BeforeInsert>,
+    after_update: Vec<AfterUpdate>,
+    on_delete: Vec<OnDelete>,
+}
+
+impl fmt::Debug for BookHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BookHooks")
+            .field("before_insert", &self.before_insert.len())
+            .field("after_update", &self.after_update.len())
+            .field("on_delete", &self.on_delete.len())
+            .finish()
+    }
+}
+
+impl BookHooks {
+    pub fn new() -> Self {
+        BookHooks::default()
+    }
+
+    /// Check or change a new book after it has been validated, before it is
+    /// added
+    pub fn before_insert(
+        mut self,
+        hook: impl Fn(&mut NewBook, &Actor) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.before_insert.push(Arc::new(hook));
+        self
+    }
+
+    /// React to a book having been updated, including by a revert, a merge
+    /// or enrichment
+    pub fn after_update(mut self, hook: impl Fn(&Book, &Actor) + Send + Sync + 'static) -> Self {
+        self.after_update.push(Arc::new(hook));
+        self
+    }
+
+    /// Check a book before it is deleted, including as the duplicate in a
+    /// merge
+    pub fn on_delete(
+        mut self,
+        hook: impl Fn(&Book, &Actor) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_delete.push(Arc::new(hook));
+        self
+    }
+
+    pub(crate) fn run_before_insert(
+        &self,
+        new_book: &mut NewBook,
+        actor: &Actor,
+    ) -> Result<(), String> {
+        self.before_insert
+            .iter()
+            .try_for_each(|hook| hook(new_book, actor))
+    }
+
+    pub(crate) fn run_after_update(&self, book: &Book, actor: &Actor) {
+        for hook in &self.after_update {
+            hook(book, actor);
+        }
+    }
+
+    /// Whether there are any `on_delete` hooks, which need the book to be
+    /// looked up before it is deleted
+    pub(crate) fn checks_deletes(&self) -> bool {
+        !self.on_delete.is_empty()
+    }
+
+    pub(crate) fn run_on_delete(&self, book: &Book, actor: &Actor) -> Result<(), String> {
+        self.on_delete.iter().try_for_each(|hook| hook(book, actor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::memory::InMemoryBookRepo;
+    use crate::service::{BookService, ServiceError};
+
+    #[tokio::test]
+    async fn hooks_change_and_reject_changes_to_the_catalog() {
+        let updated = Arc::new(Mutex::new(Vec::new()));
+        let hooks = BookHooks::new()
+            .before_insert(|book, _| {
+                book.name = book.name.trim().to_string();
+                Ok(())
+            })
+            .before_insert(|book, _| match book.author.as_str() {
+                "Anonymous" => Err("Books need a known author".to_string()),
+                _ => Ok(()),
+            })
+            .after_update({
+                let updated = updated.clone();
+                move |book, actor| updated.lock().unwrap().push((book.id, actor.0.clone()))
+            })
+            .on_delete(|book, _| match book.publisher.as_deref() {
+                Some("Archive") => Err("Archived books can't be deleted".to_string()),
+                _ => Ok(()),
+            });
+        let mut books = BookService::new(InMemoryBookRepo::new()).with_hooks(hooks);
+        let actor = Actor(Some("editor".to_string()));
+        let new_book = |author: &str| NewBook {
+            name: " Beowulf ".to_string(),
+            author: author.to_string(),
+            ..NewBook::default()
+        };
+
+        let book = books
+            .insert_book(new_book("Unknown"), &actor)
+            .await
+            .unwrap();
+        assert_eq!(book.name, "Beowulf");
+        assert!(matches!(
+            books.insert_book(new_book("Anonymous"), &actor).await,
+            Err(ServiceError::Rejected(_))
+        ));
+
+        let archived = NewBook {
+            publisher: Some("Archive".to_string()),
+            ..new_book("Unknown")
+        };
+        books.update_book(book.id, archived, &actor).await.unwrap();
+        assert_eq!(
+            *updated.lock().unwrap(),
+            vec![(book.id, Some("editor".to_string()))]
+        );
+        let rejected = books.delete_book(book.id, &actor).await.unwrap_err();
+        assert_eq!(rejected.to_string(), "Archived books can't be deleted");
+        assert!(books.get_book(book.id).await.is_ok());
+    }
+}
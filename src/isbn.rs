@@ -0,0 +1,247 @@
+use std::fmt;
+
+use crate::models::Book;
+
+/// An ISBN in both of its forms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Isbn {
+    /// Only books numbered before ISBN-13 was introduced, whose ISBN-13s
+    /// start with 978, have one
+    pub isbn10: Option<String>,
+    pub isbn13: String,
+}
+
+/// What `POST /tools/isbn` found out about an ISBN
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IsbnCheck {
+    pub valid: bool,
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
+    /// Why the ISBN isn't valid
+    pub problem: Option<String>,
+    /// The books in the catalog with the ISBN, in either form
+    pub books: Vec<Book>,
+}
+
+/// Why the input isn't an ISBN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsbnProblem {
+    /// Anything but digits, with an `X` allowed as the ISBN-10 check digit
+    Character(char),
+    /// The number of digits, if not 10 or 13
+    Length(usize),
+    /// The last digit doesn't match the others, e.g. because one was misread
+    CheckDigit { expected: char, found: char },
+    /// A valid EAN-13, but of something other than a book
+    NotABook,
+}
+
+impl fmt::Display for IsbnProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsbnProblem::Character(c) => write!(f, "{c:?} can't be part of an ISBN"),
+            IsbnProblem::Length(length) => {
+                write!(f, "An ISBN has 10 or 13 digits, not {length}")
+            }
+            IsbnProblem::CheckDigit { expected, found } => {
+                write!(f, "The check digit should be {expected}, not {found}")
+            }
+            IsbnProblem::NotABook => write!(
+                f,
+                "This EAN-13 isn't of a book, as those start with 978 or 979"
+            ),
+        }
+    }
+}
+
+/// The ISBN without hyphens or spaces, if it is a valid ISBN-10 or ISBN-13
+pub fn normalize(input: &str) -> Option<String> {
+    let isbn = strip(input);
+    let valid = match isbn.len() {
+        10 => isbn10_checksum_ok(&isbn),
+        13 => isbn13_checksum_ok(&isbn),
+        _ => false,
+    };
+    valid.then_some(isbn)
+}
+
+/// Check an ISBN-10, or an ISBN-13 or the EAN-13 from a book's barcode,
+/// which are the same thing, and convert it to the other form
+pub fn parse(input: &str) -> Result<Isbn, IsbnProblem> {
+    let isbn = strip(input);
+    let length = isbn.chars().count();
+    // An X anywhere else is as wrong as any other letter
+    if let Some(c) = isbn
+        .chars()
+        .enumerate()
+        .find(|&(i, c)| !(c.is_ascii_digit() || (c == 'X' && i == 9 && length == 10)))
+        .map(|(_, c)| c)
+    {
+        return Err(IsbnProblem::Character(c));
+    }
+
+    match length {
+        10 => {
+            check(isbn10_check_digit(&isbn[..9]), &isbn)?;
+            let isbn13 = format!("978{}", &isbn[..9]);
+            Ok(Isbn {
+                isbn13: format!("{isbn13}{}", isbn13_check_digit(&isbn13)),
+                isbn10: Some(isbn),
+            })
+        }
+        13 => {
+            check(isbn13_check_digit(&isbn[..12]), &isbn)?;
+            let isbn10 = match &isbn[..3] {
+                "978" => Some(format!(
+                    "{}{}",
+                    &isbn[3..12],
+                    isbn10_check_digit(&isbn[3..12])
+                )),
+                "979" => None,
+                _ => return Err(IsbnProblem::NotABook),
+            };
+            Ok(Isbn {
+                isbn10,
+                isbn13: isbn,
+            })
+        }
+        _ => Err(IsbnProblem::Length(length)),
+    }
+}
+
+/// Upper-cased, without the hyphens or spaces ISBNs are often printed with
+fn strip(input: &str) -> String {
+    input
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn check(expected: char, isbn: &str) -> Result<(), IsbnProblem> {
+    let found = isbn.chars().last().unwrap_or_default();
+    if found == expected {
+        Ok(())
+    } else {
+        Err(IsbnProblem::CheckDigit { expected, found })
+    }
+}
+
+/// The weighted sum of the digits, 10 down to 1, is a multiple of 11. The
+/// check digit can be `X`, for 10.
+fn isbn10_checksum_ok(isbn: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in isbn.chars().enumerate() {
+        let digit = match (c, i) {
+            ('X', 9) => 10,
+            _ => match c.to_digit(10) {
+                Some(digit) => digit,
+                None => return false,
+            },
+        };
+        sum += digit * (10 - i as u32);
+    }
+    sum % 11 == 0
+}
+
+/// The sum of the digits, weighted alternately 1 and 3, is a multiple of 10
+fn isbn13_checksum_ok(isbn: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in isbn.chars().enumerate() {
+        match c.to_digit(10) {
+            Some(digit) => sum += digit * if i % 2 == 0 { 1 } else { 3 },
+            None => return false,
+        }
+    }
+    sum % 10 == 0
+}
+
+/// The check digit that makes `isbn10_checksum_ok` true, given the first
+/// nine digits
+fn isbn10_check_digit(digits: &str) -> char {
+    let sum: u32 = digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .zip((2..=10).rev())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        digit => char::from_digit(digit, 10).unwrap_or_default(),
+    }
+}
+
+/// The check digit that makes `isbn13_checksum_ok` true, given the first
+/// twelve digits
+fn isbn13_check_digit(digits: &str) -> char {
+    let sum: u32 = digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .zip([1, 3].into_iter().cycle())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    char::from_digit((10 - sum % 10) % 10, 10).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isbns_are_normalized_and_checked() {
+        assert_eq!(
+            normalize("978-0-14-143951-8").as_deref(),
+            Some("9780141439518")
+        );
+        assert_eq!(normalize("0 14 143951 3").as_deref(), Some("0141439513"));
+        assert_eq!(normalize("080442957x").as_deref(), Some("080442957X"));
+        // Wrong check digits
+        assert_eq!(normalize("9780141439519"), None);
+        assert_eq!(normalize("0141439514"), None);
+        assert_eq!(normalize("978014143951"), None);
+        assert_eq!(normalize("97801414395X8"), None);
+    }
+
+    #[test]
+    fn isbns_are_converted_between_forms() {
+        let persuasion = Isbn {
+            isbn10: Some("0141439513".to_string()),
+            isbn13: "9780141439518".to_string(),
+        };
+        assert_eq!(parse("0-14-143951-3"), Ok(persuasion.clone()));
+        assert_eq!(parse("9780141439518"), Ok(persuasion));
+        assert_eq!(
+            parse("080442957x").unwrap().isbn13,
+            "9780804429573".to_string()
+        );
+        assert_eq!(
+            parse("9780804429573").unwrap().isbn10.as_deref(),
+            Some("080442957X")
+        );
+        // 979 ISBNs have no ISBN-10
+        assert_eq!(parse("979-10-90636-07-1").unwrap().isbn10, None);
+    }
+
+    #[test]
+    fn problems_are_explained() {
+        assert_eq!(
+            parse("9780141439519"),
+            Err(IsbnProblem::CheckDigit {
+                expected: '8',
+                found: '9'
+            })
+        );
+        assert_eq!(
+            parse("0141439514"),
+            Err(IsbnProblem::CheckDigit {
+                expected: '3',
+                found: '4'
+            })
+        );
+        assert_eq!(parse("978014143951"), Err(IsbnProblem::Length(12)));
+        assert_eq!(parse("97801414395X8"), Err(IsbnProblem::Character('X')));
+        assert_eq!(parse("isbn 0141439513"), Err(IsbnProblem::Character('I')));
+        // A tin of beans
+        assert_eq!(parse("5000157024671"), Err(IsbnProblem::NotABook));
+    }
+}
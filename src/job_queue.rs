@@ -0,0 +1,466 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use diesel::sql_types::{BigInt, Double};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::database::{DBPool, DatabaseError};
+use crate::models::{Actor, QueuedJob};
+use crate::repo::BookRepo;
+use crate::schema::jobs;
+use crate::service::{BookService, ServiceError};
+use crate::webhooks::WebhookDispatcher;
+
+/// How many jobs a worker runs at once
+const BATCH_SIZE: i64 = 10;
+/// How often an idle worker looks for jobs that are due
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a worker has to finish a job before it is presumed to have died,
+/// and the job is run again
+const LEASE: Duration = Duration::from_secs(5 * 60);
+/// How long finished jobs are kept, for debugging
+pub(crate) const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+/// When finished jobs older than `RETENTION` are deleted: hourly
+pub(crate) const PRUNE_SCHEDULE: &str = "0 30 * * * *";
+/// How many dead jobs `GET /admin/jobs` shows
+const DEAD_LETTER_LENGTH: i64 = 100;
+
+/// Work done in the background by the job queue's workers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Job {
+    /// Send a delivery to a webhook
+    DeliverWebhook { delivery_id: i64 },
+    /// Fill in a new book's missing fields from its ISBN
+    EnrichBook { book_id: i32 },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::DeliverWebhook { .. } => "deliver_webhook",
+            Job::EnrichBook { .. } => "enrich_book",
+        }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        match self {
+            Job::DeliverWebhook { .. } => RetryPolicy::default(),
+            Job::EnrichBook { .. } => RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// How many times a job is attempted, and how long to wait in between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Six attempts over about five minutes
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 6,
+            initial_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after failed attempt number `attempt`, counting
+    /// from 1, doubling each time
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+/// Add jobs to the queue in the caller's transaction, so that they are only
+/// run if it commits
+pub(crate) async fn enqueue(
+    conn: &mut AsyncPgConnection,
+    new_jobs: &[Job],
+) -> Result<(), DatabaseError> {
+    if new_jobs.is_empty() {
+        return Ok(());
+    }
+    let rows: Vec<_> = new_jobs
+        .iter()
+        .map(|job| {
+            (
+                jobs::kind.eq(job.kind()),
+                jobs::payload
+                    .eq(serde_json::to_string(job).expect("Jobs can always be serialized")),
+                jobs::max_attempts.eq(job.retry_policy().max_attempts as i32),
+            )
+        })
+        .collect();
+    diesel::insert_into(jobs::table)
+        .values(rows)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The jobs in the `jobs` table. Any number of workers, on any number of
+/// replicas, can take jobs from it: each job is claimed by one worker at a
+/// time, with `FOR UPDATE SKIP LOCKED`.
+#[derive(Clone)]
+pub(crate) struct JobQueue {
+    pool: DBPool,
+}
+
+impl JobQueue {
+    pub fn new(pool: DBPool) -> Self {
+        JobQueue { pool }
+    }
+
+    /// Claim up to `limit` jobs that are due, or whose worker's lease has
+    /// run out, counting an attempt at each
+    async fn claim(&self, limit: i64) -> Result<Vec<QueuedJob>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let claimed = diesel::sql_query(
+            "UPDATE jobs
+             SET status = 'running',
+                 attempts = attempts + 1,
+                 locked_until = now() + make_interval(secs => $2)
+             WHERE id IN (
+                 SELECT id FROM jobs
+                 WHERE (status = 'queued' AND run_at <= now())
+                    OR (status = 'running' AND locked_until < now())
+                 ORDER BY run_at, id
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, kind, payload, status, attempts, max_attempts, run_at,
+                       last_error, created_at, finished_at",
+        )
+        .bind::<BigInt, _>(limit)
+        .bind::<Double, _>(LEASE.as_secs_f64())
+        .load(&mut conn)
+        .await?;
+
+        Ok(claimed)
+    }
+
+    async fn complete(&self, id: i64) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        diesel::update(jobs::table.find(id))
+            .set((
+                jobs::status.eq("done"),
+                jobs::locked_until.eq(None::<DateTime<Utc>>),
+                jobs::finished_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Queue a failed job to be run again after `backoff`
+    async fn retry(&self, id: i64, error: &str, backoff: Duration) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        diesel::update(jobs::table.find(id))
+            .set((
+                jobs::status.eq("queued"),
+                jobs::run_at.eq(Utc::now() + backoff),
+                jobs::locked_until.eq(None::<DateTime<Utc>>),
+                jobs::last_error.eq(error),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Give up on a job, leaving it in the dead letters
+    async fn bury(&self, id: i64, error: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        diesel::update(jobs::table.find(id))
+            .set((
+                jobs::status.eq("dead"),
+                jobs::locked_until.eq(None::<DateTime<Utc>>),
+                jobs::last_error.eq(error),
+                jobs::finished_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Queue a dead job to run again now, with a fresh set of attempts
+    async fn requeue(&self, id: i64) -> Result<Option<QueuedJob>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let job = diesel::update(jobs::table.find(id).filter(jobs::status.eq("dead")))
+            .set((
+                jobs::status.eq("queued"),
+                jobs::attempts.eq(0),
+                jobs::run_at.eq(diesel::dsl::now),
+                jobs::finished_at.eq(None::<DateTime<Utc>>),
+            ))
+            .returning(QueuedJob::as_returning())
+            .get_result(&mut conn)
+            .await
+            .optional()?;
+
+        Ok(job)
+    }
+
+    /// How many jobs of each kind have each status
+    async fn counts(&self) -> Result<Vec<(String, String, i64)>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let counts = jobs::table
+            .group_by((jobs::kind, jobs::status))
+            .select((jobs::kind, jobs::status, diesel::dsl::count_star()))
+            .load(&mut conn)
+            .await?;
+
+        Ok(counts)
+    }
+
+    /// The jobs that most recently ran out of attempts, newest first
+    async fn dead_letters(&self, limit: i64) -> Result<Vec<QueuedJob>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let dead = jobs::table
+            .filter(jobs::status.eq("dead"))
+            .select(QueuedJob::as_select())
+            .order(jobs::finished_at.desc())
+            .limit(limit)
+            .load(&mut conn)
+            .await?;
+
+        Ok(dead)
+    }
+}
+
+/// Delete the jobs that finished successfully more than `retention` ago.
+/// Dead jobs are kept until they are retried.
+pub(crate) async fn prune_jobs(pool: &DBPool, retention: Duration) -> Result<usize, DatabaseError> {
+    let mut conn = pool.get().await?;
+    let cutoff = Utc::now() - retention;
+
+    let deleted = diesel::delete(
+        jobs::table
+            .filter(jobs::status.eq("done"))
+            .filter(jobs::finished_at.lt(cutoff)),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    Ok(deleted)
+}
+
+/// Runs the jobs in the queue, retrying failed ones with exponential backoff
+/// until they run out of attempts
+pub(crate) struct JobWorker<R, E> {
+    queue: JobQueue,
+    webhooks: WebhookDispatcher,
+    books: BookService<R, E>,
+}
+
+impl<R, E> JobWorker<R, E>
+where
+    E: Error + Send + Sync + 'static,
+    R: BookRepo<E> + Send + Sync + Clone + 'static,
+{
+    pub fn new(queue: JobQueue, webhooks: WebhookDispatcher, books: BookService<R, E>) -> Self {
+        JobWorker {
+            queue,
+            webhooks,
+            books,
+        }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.queue.claim(BATCH_SIZE).await {
+                    Ok(claimed) => {
+                        let claimed_all = claimed.len() as i64 == BATCH_SIZE;
+                        join_all(claimed.into_iter().map(|job| self.run(job))).await;
+                        // There may be more due
+                        if claimed_all {
+                            continue;
+                        }
+                    }
+                    Err(e) => error!("Failed to claim jobs: {e}"),
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+    }
+
+    async fn run(&self, queued: QueuedJob) {
+        let recorded = match serde_json::from_str::<Job>(&queued.payload) {
+            Ok(job) => self.attempt(&queued, &job).await,
+            Err(e) => self.give_up(&queued, &format!("Unreadable job: {e}")).await,
+        };
+        if let Err(e) = recorded {
+            error!(
+                job = queued.id,
+                "Failed to record the outcome of a job: {e}"
+            );
+        }
+    }
+
+    /// Perform a job, and record whether it succeeded, is to be retried, or
+    /// has run out of attempts
+    async fn attempt(&self, queued: &QueuedJob, job: &Job) -> Result<(), DatabaseError> {
+        if queued.attempts > queued.max_attempts {
+            // Its worker stopped partway through the final attempt
+            let error = queued
+                .last_error
+                .as_deref()
+                .unwrap_or("Ran out of attempts");
+            return self.give_up(queued, error).await;
+        }
+
+        match self
+            .perform(job, queued.attempts == queued.max_attempts)
+            .await
+        {
+            Ok(()) => self.queue.complete(queued.id).await,
+            Err(e) if queued.attempts < queued.max_attempts => {
+                let backoff = job.retry_policy().backoff(queued.attempts as u32);
+                warn!(
+                    job = queued.id,
+                    kind = queued.kind,
+                    attempt = queued.attempts,
+                    "Job failed, will retry in {backoff:?}: {e}"
+                );
+                self.queue.retry(queued.id, &e.to_string(), backoff).await
+            }
+            Err(e) => self.give_up(queued, &e.to_string()).await,
+        }
+    }
+
+    async fn give_up(&self, queued: &QueuedJob, error: &str) -> Result<(), DatabaseError> {
+        error!(
+            job = queued.id,
+            kind = queued.kind,
+            attempt = queued.attempts,
+            "Job failed for the last time: {error}"
+        );
+        self.queue.bury(queued.id, error).await
+    }
+
+    async fn perform(
+        &self,
+        job: &Job,
+        final_attempt: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match job {
+            Job::DeliverWebhook { delivery_id } => {
+                self.webhooks.attempt(*delivery_id, final_attempt).await
+            }
+            Job::EnrichBook { book_id } => {
+                let actor = Actor(Some("enrichment".to_string()));
+                match self.books.clone().enrich_book(*book_id, &actor).await {
+                    // The book was deleted, or its ISBN removed, since
+                    Ok(_) | Err(ServiceError::NotFound(_) | ServiceError::NoIsbn(_)) => Ok(()),
+                    Err(e) => Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+/// Routes for inspecting the job queue and retrying dead jobs, which are
+/// served behind the admin token
+pub(crate) fn jobs_router(queue: JobQueue) -> Router {
+    Router::new()
+        .route("/admin/jobs", get(job_status))
+        .route("/admin/jobs/{id}/retry", post(retry_job))
+        .with_state(queue)
+}
+
+#[derive(Serialize)]
+struct JobStatus {
+    /// How many jobs of each kind have each status
+    counts: BTreeMap<String, BTreeMap<String, i64>>,
+    /// The jobs that most recently ran out of attempts
+    dead: Vec<QueuedJob>,
+}
+
+fn internal_error(e: DatabaseError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+async fn job_status(
+    State(queue): State<JobQueue>,
+) -> Result<Json<JobStatus>, (StatusCode, String)> {
+    let mut counts: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for (kind, status, count) in queue.counts().await.map_err(internal_error)? {
+        counts.entry(kind).or_default().insert(status, count);
+    }
+    let dead = queue
+        .dead_letters(DEAD_LETTER_LENGTH)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(JobStatus { counts, dead }))
+}
+
+async fn retry_job(
+    State(queue): State<JobQueue>,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, Json<QueuedJob>), (StatusCode, String)> {
+    match queue.requeue(id).await.map_err(internal_error)? {
+        Some(job) => {
+            info!(job = id, kind = job.kind, "Requeued dead job");
+            Ok((StatusCode::ACCEPTED, Json(job)))
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("No dead job found with ID: {id}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let retry = RetryPolicy::default();
+        let backoffs: Vec<u64> = (1..retry.max_attempts)
+            .map(|attempt| retry.backoff(attempt).as_secs())
+            .collect();
+        assert_eq!(backoffs, vec![10, 20, 40, 80, 160]);
+    }
+
+    #[test]
+    fn jobs_are_stored_as_json_tagged_with_their_kind() {
+        let job = Job::DeliverWebhook { delivery_id: 7 };
+        let json = serde_json::to_string(&job).unwrap();
+        assert_eq!(json, r#"{"kind":"deliver_webhook","delivery_id":7}"#);
+        assert_eq!(serde_json::from_str::<Job>(&json).unwrap(), job);
+        assert_eq!(job.kind(), "deliver_webhook");
+    }
+}
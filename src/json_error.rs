@@ -0,0 +1,95 @@
+use axum::{
+    body::to_bytes,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// The longest plain-text error message that is kept by `json_errors`
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// An error response with the API's error body, e.g. `{"error":"..."}`
+pub(crate) fn json_error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": message.into() }))).into_response()
+}
+
+/// Middleware that gives plain-text error responses the API's JSON error
+/// body instead, with the same status and headers. Handlers return their
+/// errors as `(StatusCode, String)`, and axum's own rejections, e.g. of a
+/// malformed body, are plain text too.
+pub(crate) async fn json_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/plain"));
+    if !is_text || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let message = match to_bytes(body, MAX_MESSAGE_SIZE).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let mut response = json_error(status, message);
+    response.headers_mut().extend(parts.headers);
+    *response.extensions_mut() = parts.extensions;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn plain_text_errors_are_given_the_json_error_body() {
+        let router = Router::new()
+            .route(
+                "/error",
+                get(|| async {
+                    (
+                        StatusCode::CONFLICT,
+                        [(header::RETRY_AFTER, "1")],
+                        "Book looks like a duplicate of: 7",
+                    )
+                }),
+            )
+            .route("/text", get(|| async { "Just text" }))
+            .layer(middleware::from_fn(json_errors));
+        let get = |path: &'static str| {
+            let router = router.clone();
+            async move {
+                router
+                    .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let response = get("/error").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"error": "Book looks like a duplicate of: 7"}));
+
+        // Only errors are changed
+        let response = get("/text").await;
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"Just text");
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::Display;
+use std::io;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use futures::{stream, Stream, StreamExt};
+use tracing::error;
+
+/// The most items serialized into one chunk of the body, out of those ready
+/// to be sent at once
+const CHUNK_ITEMS: usize = 64;
+
+/// A JSON array of the items, serialized and sent as they arrive instead of
+/// all at once, so they needn't all be in memory. An error ends the response
+/// early, so that the client sees a broken response, not a short array.
+pub(crate) fn json_array<T, E>(items: impl Stream<Item = Result<T, E>> + Send + 'static) -> Response
+where
+    T: serde::Serialize + Send + 'static,
+    E: Display + Send + 'static,
+{
+    let mut first = true;
+    let chunks = items.ready_chunks(CHUNK_ITEMS).map(move |items| {
+        let mut chunk = Vec::new();
+        for item in items {
+            let item = item.map_err(|e| {
+                error!("Failed to stream a response, ending it early: {e}");
+                io::Error::other(e.to_string())
+            })?;
+            if !first {
+                chunk.push(b',');
+            }
+            first = false;
+            serde_json::to_writer(&mut chunk, &item)?;
+        }
+        Ok::<_, io::Error>(Bytes::from(chunk))
+    });
+    let open = stream::once(async { Ok(Bytes::from_static(b"[")) });
+    let close = stream::once(async { Ok(Bytes::from_static(b"]")) });
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        Body::from_stream(open.chain(chunks).chain(close)),
+    )
+        .into_response()
+}
+
+/// Whether the response's body is streamed, rather than all there from the
+/// start, so that whatever produces it keeps running after the headers
+/// are sent
+pub(crate) fn is_streamed(response: &Response) -> bool {
+    response.body().size_hint().exact().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(items: Vec<Result<i32, String>>) -> Result<Bytes, axum::Error> {
+        let response = json_array(stream::iter(items));
+        axum::body::to_bytes(response.into_body(), usize::MAX).await
+    }
+
+    #[tokio::test]
+    async fn items_are_streamed_as_a_json_array() {
+        assert_eq!(body(vec![Ok(1), Ok(2), Ok(3)]).await.unwrap(), "[1,2,3]");
+        assert_eq!(body(Vec::new()).await.unwrap(), "[]");
+        assert!(body(vec![Ok(1), Err("connection lost".to_string())])
+            .await
+            .is_err());
+    }
+}
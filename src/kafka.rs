@@ -0,0 +1,204 @@
+/// The topic events are published to when `kafka_topic` is not set
+pub const DEFAULT_TOPIC: &str = "bookstore.book-events";
+
+/// Where to publish book events, when built with the `kafka` feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaConfig {
+    /// Comma-separated `host:port` bootstrap servers
+    pub brokers: String,
+    pub topic: String,
+}
+
+#[cfg(feature = "kafka")]
+pub(crate) use publisher::KafkaPublisher;
+
+#[cfg(feature = "kafka")]
+mod publisher {
+    use std::time::Duration;
+
+    use rdkafka::{
+        error::KafkaError,
+        message::{Header, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord},
+        util::Timeout,
+        ClientConfig,
+    };
+    use serde_json::{json, Value};
+    use tracing::debug;
+
+    use super::KafkaConfig;
+    use crate::events::BookEvent;
+
+    /// How long to wait for room in the producer's queue
+    const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Publishes book events relayed from the outbox to a Kafka topic, keyed
+    /// by book ID so that the events for each book stay in order
+    pub(crate) struct KafkaPublisher {
+        producer: FutureProducer,
+        topic: String,
+    }
+
+    impl KafkaPublisher {
+        /// Fails if the config is rejected. Brokers are only connected to
+        /// once there is something to publish.
+        pub fn new(config: &KafkaConfig) -> Result<Self, KafkaError> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", &config.brokers)
+                .set("enable.idempotence", "true")
+                .create()?;
+            Ok(KafkaPublisher {
+                producer,
+                topic: config.topic.clone(),
+            })
+        }
+
+        /// Publish an event, waiting for the brokers to acknowledge it
+        pub async fn publish(&self, event: &BookEvent) -> Result<(), KafkaError> {
+            let key = event.book_id().to_string();
+            let value = record(event).to_string();
+            let headers = OwnedHeaders::new().insert(Header {
+                key: "event_type",
+                value: Some(event.event_type()),
+            });
+            let record = FutureRecord::to(&self.topic)
+                .key(&key)
+                .payload(&value)
+                .headers(headers);
+
+            let (partition, offset) = self
+                .producer
+                .send(record, Timeout::After(QUEUE_TIMEOUT))
+                .await
+                .map_err(|(e, _)| e)?;
+            debug!(
+                topic = self.topic,
+                partition,
+                offset,
+                "Published {} to Kafka",
+                event.event_type()
+            );
+            Ok(())
+        }
+    }
+
+    /// The event in Kafka Connect's JSON format, a `schema` alongside the
+    /// `payload`, so that sink connectors using the `JsonConverter` can read
+    /// it without a schema registry. Every event type shares one flat
+    /// schema; deletions leave the book's fields null.
+    pub(super) fn record(event: &BookEvent) -> Value {
+        let book = event.book();
+        json!({
+            "schema": {
+                "type": "struct",
+                "name": "bookstore.BookEvent",
+                "optional": false,
+                "fields": [
+                    {"field": "type", "type": "string", "optional": false},
+                    {"field": "id", "type": "int32", "optional": false},
+                    {"field": "name", "type": "string", "optional": true},
+                    {"field": "author", "type": "string", "optional": true},
+                    {"field": "isbn", "type": "string", "optional": true},
+                    {"field": "publisher", "type": "string", "optional": true},
+                    {"field": "cover_url", "type": "string", "optional": true},
+                    {"field": "slug", "type": "string", "optional": true},
+                    {
+                        "field": "updated_at",
+                        "type": "int64",
+                        "name": "org.apache.kafka.connect.data.Timestamp",
+                        "version": 1,
+                        "optional": true
+                    },
+                ],
+            },
+            "payload": {
+                "type": event.event_type(),
+                "id": event.book_id(),
+                "name": book.map(|book| &book.name),
+                "author": book.map(|book| &book.author),
+                "isbn": book.and_then(|book| book.isbn.as_ref()),
+                "publisher": book.and_then(|book| book.publisher.as_ref()),
+                "cover_url": book.and_then(|book| book.cover_url.as_ref()),
+                "slug": book.map(|book| &book.slug),
+                "updated_at": book.map(|book| book.updated_at.timestamp_millis()),
+            },
+        })
+    }
+}
+
+#[cfg(all(test, feature = "kafka"))]
+mod tests {
+    use chrono::DateTime;
+    use serde_json::json;
+
+    use super::publisher::record;
+    use crate::events::{BookCreated, BookDeleted, BookEvent};
+    use crate::models::Book;
+
+    #[test]
+    fn events_are_published_with_their_schema() {
+        let created = BookEvent::BookCreated(BookCreated {
+            book: Book {
+                id: 7,
+                name: "Dune".to_string(),
+                author: "Frank Herbert".to_string(),
+                updated_at: DateTime::from_timestamp_millis(1_741_000_000_000).unwrap(),
+                isbn: Some("9780441172719".to_string()),
+                publisher: None,
+                cover_url: None,
+                slug: "dune".to_string(),
+            },
+        });
+        let created = record(&created);
+        assert_eq!(
+            created["payload"],
+            json!({
+                "type": "book_created",
+                "id": 7,
+                "name": "Dune",
+                "author": "Frank Herbert",
+                "isbn": "9780441172719",
+                "publisher": null,
+                "cover_url": null,
+                "slug": "dune",
+                "updated_at": 1_741_000_000_000_i64,
+            })
+        );
+        let fields: Vec<&str> = created["schema"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "type",
+                "id",
+                "name",
+                "author",
+                "isbn",
+                "publisher",
+                "cover_url",
+                "slug",
+                "updated_at"
+            ]
+        );
+
+        let deleted = record(&BookEvent::BookDeleted(BookDeleted { id: 7 }));
+        assert_eq!(
+            deleted["payload"],
+            json!({
+                "type": "book_deleted",
+                "id": 7,
+                "name": null,
+                "author": null,
+                "isbn": null,
+                "publisher": null,
+                "cover_url": null,
+                "slug": null,
+                "updated_at": null,
+            })
+        );
+    }
+}
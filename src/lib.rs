@@ -1,24 +1,787 @@
+mod access_log;
+mod admin;
+#[cfg(feature = "admin-ui")]
+mod admin_ui;
 mod api;
+mod api_version;
+mod backup;
+mod body_limit;
+mod cache_control;
+#[cfg(feature = "client")]
+pub mod client;
+mod client_ip;
+mod commands;
+mod compression;
+mod conditional;
+mod config;
+mod content_type;
+mod cors;
 mod database;
+mod deprecation;
+mod enrichment;
+mod events;
+mod export;
+mod fallback;
+mod filter;
+mod flags;
+mod hooks;
+mod isbn;
+mod job_queue;
+mod json_error;
+mod json_stream;
+mod kafka;
+mod load_shed;
+mod logging;
+mod memory;
+mod metrics;
 mod models;
+mod outbox;
+mod page;
+mod qr;
 mod repo;
+mod route_config;
+mod runtime_config;
+mod scheduler;
 mod schema;
+mod search;
+mod service;
+mod slow_log;
+mod slug;
+mod sort;
+mod stats;
+mod suggest;
+mod summary;
+pub mod test_support;
+mod timeout;
+mod tls;
+mod translations;
+mod usage;
+mod version;
+mod views;
+mod webhooks;
 
-use axum::{serve::Serve, Router};
-use tokio::net::TcpListener;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::future::{Future, IntoFuture};
+use std::io;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::Request;
+use axum::response::{IntoResponse, Response};
+use axum::routing::Route;
+use axum::Router;
+use listenfd::ListenFd;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tower::{Layer, Service};
 use tracing::info;
+use url::Url;
+
+use backup::run_backup;
+use database::{
+    create_db_pool, create_revision_partitions, warm_up_pool, DBPool, FlagStore, JobLeases,
+    WebhookStore,
+};
+use export::export_router;
+use job_queue::{jobs_router, prune_jobs, JobQueue, JobWorker};
+use outbox::{prune_outbox, OutboxRelay};
+use summary::summary_router;
+use usage::{prune_usage, usage_router, UsageStore};
+use views::{prune_views, spawn_view_flusher};
+use webhooks::{webhooks_router, WebhookDispatcher};
+
+pub use api::{build_api, ApiOptions, AuthHook, MiddlewareConfig, Pagination, ResponseHook};
+pub use api_version::ApiVersion;
+pub use backup::{
+    backup, restore, BackupConfig, BackupHeader, BackupSummary, RestoreSummary, RestoreTarget,
+};
+pub use body_limit::BodyLimits;
+pub use cache_control::CacheTtls;
+pub use client_ip::{ClientIp, TrustedProxies};
+pub use commands::{
+    export, migrate, migration_status, seed, CommandError, MigrateMode, MigrationStatus, MIGRATIONS,
+};
+pub use compression::CompressionConfig;
+pub use config::{Config, ConfigError, LogFormat, RuntimeConfig, Storage};
+pub use cors::CorsConfig;
+pub use database::{DatabaseBookRepo, DatabaseError, PoolConfig};
+pub use enrichment::{
+    BookMetadata, Enricher, EnrichmentConfig, EnrichmentError, LookupFuture, MetadataProvider,
+    ProviderKind,
+};
+pub use events::{BookChange, BookCreated, BookDeleted, BookEvent, BookUpdated, EventBus};
+pub use filter::{Comparison, Filter, FilterError, FilterField, FilterValues, TextField};
+pub use flags::{require_flag, FeatureFlags, FlagState};
+pub use hooks::BookHooks;
+pub use isbn::IsbnCheck;
+pub use kafka::KafkaConfig;
+pub use load_shed::ConcurrencyLimits;
+pub use logging::{init_tracing, LogFilterHandle};
+pub use memory::InMemoryBookRepo;
+pub use models::{Actor, Book, BookTranslation, NewBook, NewTranslation};
+pub use page::BookPage;
+pub use qr::PublicUrl;
+pub use repo::BookRepo;
+pub use route_config::{BookRoute, RouteConfig, RouteSettings};
+pub use runtime_config::RuntimeConfigHandle;
+pub use scheduler::{JobMetrics, JobStats, RunningScheduler, Scheduler};
+pub use search::SearchResult;
+pub use service::{BookService, DuplicatePolicy, ServiceError};
+pub use slow_log::SlowLogThresholds;
+pub use sort::{Collation, SortError, SortKey, SortSpec};
+pub use stats::{Aggregation, BookField, CatalogStats, FieldCount, Group, Metric};
+pub use suggest::{Suggestion, SuggestionKind};
+pub use summary::AdminSummary;
+pub use timeout::RequestTimeouts;
+pub use tls::TlsConfig;
+pub use translations::LocalizedBook;
+pub use usage::{ApiUsage, RouteUsage};
+pub use views::TrendingBook;
+pub use webhooks::{DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER};
+
+/// A running server, which completes when the server stops
+pub type ServerFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// Where the server listens for connections
+#[derive(Debug, Clone)]
+pub enum ListenerConfig {
+    Tcp(SocketAddr),
+    /// A Unix domain socket at the given path. Any existing file at that path
+    /// is removed before binding.
+    Unix(PathBuf),
+    /// A TCP or Unix socket already bound by systemd and passed to the process
+    /// via socket activation (`LISTEN_FDS`)
+    Systemd,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    pub listener: ListenerConfig,
+    pub db_pool: PoolConfig,
+    pub slow_log: SlowLogThresholds,
+    pub concurrency: ConcurrencyLimits,
+    pub timeouts: RequestTimeouts,
+    pub compression: CompressionConfig,
+    pub body_limits: BodyLimits,
+    /// Add CORS headers to responses, if set
+    pub cors: Option<CorsConfig>,
+    pub cache_ttls: CacheTtls,
+    /// Overrides of the middleware settings for individual `/books` routes
+    pub routes: RouteConfig,
+    /// Proxies whose forwarding headers are used to find the client's IP
+    pub trusted_proxies: TrustedProxies,
+    /// Serve HTTPS instead of plain HTTP. Only supported for TCP listeners.
+    pub tls: Option<TlsConfig>,
+    /// Where the API is mounted, e.g. `/api/bookstore`. Empty to serve it
+    /// from the root.
+    pub base_path: String,
+    /// Where the API is reached from outside, e.g. `https://books.example.com`,
+    /// for the links in books' QR codes. If not set, the `Host` of each
+    /// request is used.
+    pub public_url: Option<Url>,
+    /// Enables the `/admin` (including `/admin/jobs`) and `/webhooks`
+    /// endpoints, which require this bearer token
+    pub admin_token: Option<String>,
+    /// Takes scheduled backups if a schedule is set
+    pub backup: Option<BackupConfig>,
+    /// Publishes book events to Kafka if set. Requires the `kafka` feature.
+    pub kafka: Option<KafkaConfig>,
+    /// Where books' missing fields are looked up by ISBN
+    pub enrichment: EnrichmentConfig,
+    /// What happens when a new book looks like one already in the catalog
+    pub duplicate_policy: DuplicatePolicy,
+    pub runtime: RuntimeConfig,
+    /// Where changes to the catalog are published. Subscribe to a clone
+    /// before starting the server to receive them.
+    pub events: EventBus,
+    /// Lets a config reload change the log filter
+    pub log_filter_handle: Option<LogFilterHandle>,
+}
+
+#[derive(Debug)]
+pub enum StartupError {
+    DatabaseError(diesel_async::pooled_connection::PoolError),
+    WarmUpError(database::DatabaseError),
+    ListenerError(io::Error),
+    TlsError(io::Error),
+    #[cfg(feature = "kafka")]
+    KafkaError(rdkafka::error::KafkaError),
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupError::DatabaseError(e) => write!(f, "failed to create DB connection pool: {e}"),
+            StartupError::WarmUpError(e) => write!(f, "failed to warm up DB connections: {e}"),
+            StartupError::ListenerError(e) => write!(f, "failed to set up listener: {e}"),
+            StartupError::TlsError(e) => write!(f, "failed to load TLS certificate and key: {e}"),
+            #[cfg(feature = "kafka")]
+            StartupError::KafkaError(e) => write!(f, "failed to create Kafka producer: {e}"),
+        }
+    }
+}
+
+impl Error for StartupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StartupError::DatabaseError(e) => Some(e),
+            StartupError::WarmUpError(e) => Some(e),
+            StartupError::ListenerError(e) => Some(e),
+            StartupError::TlsError(e) => Some(e),
+            #[cfg(feature = "kafka")]
+            StartupError::KafkaError(e) => Some(e),
+        }
+    }
+}
+
+/// A server bound to its address. Awaiting it serves requests until it is
+/// shut down.
+pub struct Server {
+    local_addr: BoundAddress,
+    future: ServerFuture,
+}
+
+/// Where a server is listening
+#[derive(Debug, Clone)]
+pub enum BoundAddress {
+    Tcp(SocketAddr),
+    Unix(std::os::unix::net::SocketAddr),
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder<DatabaseBookRepo, DatabaseError> {
+        ServerBuilder {
+            database_url: Some(config::DEFAULT_DATABASE_URL.to_string()),
+            database_url_set: false,
+            options: ServerOptions::default(),
+            make_repo: Box::new(|pool, slow_query_threshold| {
+                let pool = pool.expect("in_memory is the only way to leave out the DB");
+                DatabaseBookRepo::new(pool, slow_query_threshold)
+            }),
+            layers: Vec::new(),
+            hooks: BookHooks::default(),
+            decorate_response: None,
+            shutdown: None,
+            error: PhantomData,
+        }
+    }
+
+    /// The address actually bound, e.g. to find the port picked for port 0
+    pub fn local_addr(&self) -> &BoundAddress {
+        &self.local_addr
+    }
+}
+
+impl IntoFuture for Server {
+    type Output = io::Result<()>;
+    type IntoFuture = ServerFuture;
+
+    fn into_future(self) -> ServerFuture {
+        self.future
+    }
+}
+
+/// Completes when the server should stop accepting connections
+pub type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Creates the book repo, given the DB pool, if there is a DB, and the slow
+/// query threshold
+type MakeRepo<R> = Box<dyn FnOnce(Option<DBPool>, Duration) -> R + Send>;
+
+/// Wraps the app in a middleware layer
+type AppLayer = Box<dyn FnOnce(Router) -> Router + Send>;
+
+/// Configures and starts a server. Every setting has a default, so
+/// `Server::builder().build()` serves the DB at `postgres://localhost/bookstore`
+/// on `127.0.0.1:3000`, and `Server::builder().in_memory().build()` serves
+/// books from memory instead.
+pub struct ServerBuilder<R, E> {
+    /// `None` to run without a DB
+    database_url: Option<String>,
+    /// Whether `database_url` was called, rather than left at the default
+    database_url_set: bool,
+    options: ServerOptions,
+    make_repo: MakeRepo<R>,
+    layers: Vec<AppLayer>,
+    hooks: BookHooks,
+    decorate_response: Option<ResponseHook>,
+    shutdown: Option<ShutdownSignal>,
+    // The repo's error type, which `BookRepo` is generic over
+    error: PhantomData<fn() -> E>,
+}
+
+impl<R, E> ServerBuilder<R, E> {
+    /// The Postgres DB, which holds the books unless `repo` is set, and
+    /// webhooks, jobs and history. Setting it after `in_memory` uses it for
+    /// everything but the books.
+    pub fn database_url(mut self, database_url: impl Into<String>) -> Self {
+        self.database_url = Some(database_url.into());
+        self.database_url_set = true;
+        self
+    }
+
+    /// Serve books from a new, empty `InMemoryBookRepo`, without a DB at
+    /// all, e.g. for demos or developing a frontend. Nothing is kept when the
+    /// server stops, and webhooks, jobs, API usage, the admin summary and
+    /// export, feature flags kept in the DB and scheduled jobs are disabled.
+    /// The API is otherwise the same.
+    pub fn in_memory(self) -> ServerBuilder<InMemoryBookRepo, Infallible> {
+        let mut builder = self.repo(InMemoryBookRepo::new());
+        builder.database_url = None;
+        builder
+    }
+
+    /// Every other setting, e.g. from `Config::server_options`. This replaces
+    /// the listener set by `bind`, so call it first.
+    pub fn options(mut self, options: ServerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Listen on a TCP address. With port 0, a free port is picked: see
+    /// `Server::local_addr`.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.options.listener = ListenerConfig::Tcp(addr);
+        self
+    }
+
+    /// Serve books from `repo` instead of the DB's `books` table. Unless
+    /// `database_url` is set too, there is then no DB, and webhooks, jobs
+    /// and the rest are disabled as with `in_memory`.
+    pub fn repo<R2, E2>(self, repo: R2) -> ServerBuilder<R2, E2>
+    where
+        R2: Send + 'static,
+    {
+        ServerBuilder {
+            database_url: self.database_url.filter(|_| self.database_url_set),
+            database_url_set: self.database_url_set,
+            options: self.options,
+            make_repo: Box::new(move |_, _| repo),
+            layers: self.layers,
+            hooks: self.hooks,
+            decorate_response: self.decorate_response,
+            shutdown: self.shutdown,
+            error: PhantomData,
+        }
+    }
 
-use api::build_api;
-use database::{create_db_pool, DatabaseBookRepo};
+    /// Wrap every route in a middleware layer, outside the built-in ones.
+    /// Layers added later wrap those added earlier.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers
+            .push(Box::new(move |router| router.layer(layer)));
+        self
+    }
 
-pub async fn start_server(db_url: String) -> Serve<TcpListener, Router, Router> {
-    let repo = DatabaseBookRepo::new(create_db_pool(db_url).await);
+    /// Run custom business rules around changes to the catalog
+    pub fn hooks(mut self, hooks: BookHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
 
-    let router = build_api(repo);
+    /// Change every response from the `/books` and `/changes` routes before
+    /// it is sent
+    pub fn decorate_response(
+        mut self,
+        hook: impl Fn(BookRoute, &mut Response) + Send + Sync + 'static,
+    ) -> Self {
+        self.decorate_response = Some(Arc::new(hook));
+        self
+    }
+
+    /// Shut down gracefully when `signal` completes, instead of on SIGTERM
+    /// or Ctrl-C
+    pub fn graceful_shutdown(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+}
+
+impl<R, E> ServerBuilder<R, E>
+where
+    E: Error + Send + Sync + 'static,
+    R: BookRepo<E> + Send + Sync + Clone + 'static,
+{
+    /// Connect to the DB, if there is one, start the background workers and
+    /// bind the listener
+    pub async fn build(self) -> Result<Server, StartupError> {
+        let ServerBuilder {
+            database_url,
+            options,
+            make_repo,
+            layers,
+            hooks,
+            decorate_response,
+            shutdown,
+            ..
+        } = self;
+        let shutdown = shutdown.unwrap_or_else(|| Box::pin(shutdown_signal()));
+
+        let pool = match database_url {
+            Some(database_url) => {
+                let pool = create_db_pool(database_url, options.db_pool)
+                    .await
+                    .map_err(StartupError::DatabaseError)?;
+                if options.db_pool.warm_up {
+                    let started = Instant::now();
+                    let connections = options.db_pool.min_idle.unwrap_or(1);
+                    warm_up_pool(&pool, connections)
+                        .await
+                        .map_err(StartupError::WarmUpError)?;
+                    info!(
+                        connections,
+                        elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+                        "Warmed up DB connections"
+                    );
+                }
+                Some(pool)
+            }
+            None => {
+                info!("Running without a DB: books are only kept in memory");
+                None
+            }
+        };
+        let repo = make_repo(pool.clone(), options.slow_log.query);
+
+        let runtime_config = RuntimeConfigHandle::new(options.runtime, options.log_filter_handle);
+        runtime_config.clone().spawn_sighup_listener();
+
+        let mut flags = FeatureFlags::new(runtime_config.clone());
+        if let Some(pool) = &pool {
+            flags = flags.with_store(FlagStore::new(pool.clone()));
+            flags.clone().spawn_refresher();
+        }
+
+        let mut enricher = Enricher::new(&options.enrichment);
+        if let Some(pool) = &pool {
+            enricher = enricher.with_cache(pool.clone());
+        }
+        let books = BookService::new(repo)
+            .with_events(options.events.clone())
+            .with_metadata(enricher)
+            .with_duplicate_policy(options.duplicate_policy)
+            .with_hooks(hooks);
+        spawn_view_flusher(books.clone());
+
+        let mut admin_routes = Router::new();
+        let mut usage = None;
+        if let Some(pool) = &pool {
+            let job_queue = JobQueue::new(pool.clone());
+            let webhooks = WebhookDispatcher::new(WebhookStore::new(pool.clone()));
+            JobWorker::new(job_queue.clone(), webhooks.clone(), books.clone()).spawn();
+            let relay = OutboxRelay::new(pool.clone(), webhooks.clone());
+            #[cfg(feature = "kafka")]
+            let relay = match &options.kafka {
+                Some(kafka) => relay.with_kafka(
+                    kafka::KafkaPublisher::new(kafka).map_err(StartupError::KafkaError)?,
+                ),
+                None => relay,
+            };
+            relay.spawn(&options.events);
+
+            let usage_store = UsageStore::new(pool.clone());
+            let api_usage = ApiUsage::default();
+            api_usage.clone().spawn_flusher(usage_store.clone());
+            usage = Some(api_usage);
+
+            admin_routes = webhooks_router(webhooks)
+                .merge(jobs_router(job_queue))
+                .merge(usage_router(usage_store))
+                .merge(summary_router(pool.clone()))
+                .merge(export_router(pool.clone()));
+        }
+
+        let job_metrics = JobMetrics::default();
+        let router = build_api(
+            books,
+            ApiOptions {
+                base_path: options.base_path,
+                public_url: PublicUrl(options.public_url),
+                decorate_response,
+                admin_token: options.admin_token,
+                admin_routes,
+                runtime_config,
+                flags: Some(flags),
+                usage,
+                jobs: job_metrics.clone(),
+                middleware: MiddlewareConfig {
+                    slow_log: options.slow_log,
+                    concurrency: options.concurrency,
+                    timeouts: options.timeouts,
+                    compression: options.compression,
+                    body_limits: options.body_limits,
+                    cors: options.cors,
+                    cache_ttls: options.cache_ttls,
+                    routes: options.routes,
+                    trusted_proxies: options.trusted_proxies,
+                },
+                ..ApiOptions::default()
+            },
+        );
+        let router = layers
+            .into_iter()
+            .fold(router, |router, layer| layer(router));
+
+        let (local_addr, server) = match options.listener {
+            ListenerConfig::Tcp(addr) => {
+                let listener =
+                    std::net::TcpListener::bind(addr).map_err(StartupError::ListenerError)?;
+                serve_tcp(listener, router, options.tls, shutdown).await
+            }
+            ListenerConfig::Unix(path) => {
+                remove_stale_socket(&path).map_err(StartupError::ListenerError)?;
+                let listener = std::os::unix::net::UnixListener::bind(&path)
+                    .map_err(StartupError::ListenerError)?;
+                serve_unix(listener, router, shutdown)
+            }
+            ListenerConfig::Systemd => {
+                let mut fds = ListenFd::from_env();
+                if let Ok(Some(listener)) = fds.take_tcp_listener(0) {
+                    serve_tcp(listener, router, options.tls, shutdown).await
+                } else if let Ok(Some(listener)) = fds.take_unix_listener(0) {
+                    serve_unix(listener, router, shutdown)
+                } else {
+                    Err(StartupError::ListenerError(io::Error::other(
+                        "no TCP or Unix listening socket was passed by systemd",
+                    )))
+                }
+            }
+        }?;
+
+        let scheduler = match pool {
+            Some(pool) => build_scheduler(pool, options.backup),
+            None => Scheduler::new(),
+        }
+        .start(job_metrics);
+
+        Ok(Server {
+            local_addr,
+            future: Box::pin(async move {
+                let result = server.await;
+                scheduler.shutdown().await;
+                result
+            }),
+        })
+    }
+}
+
+/// The background jobs enabled by the config
+fn build_scheduler(pool: DBPool, backup: Option<BackupConfig>) -> Scheduler {
+    let mut scheduler = Scheduler::new().with_leases(JobLeases::new(pool.clone(), lease_holder()));
+
+    let outbox_pool = pool.clone();
+    let prune_schedule = outbox::PRUNE_SCHEDULE
+        .parse()
+        .expect("PRUNE_SCHEDULE is a valid cron expression");
+    scheduler.add("prune_outbox", prune_schedule, move || {
+        let pool = outbox_pool.clone();
+        async move {
+            let deleted = prune_outbox(&pool, outbox::RETENTION).await?;
+            info!(deleted, "Pruned published events from the outbox");
+            Ok(())
+        }
+    });
+
+    let jobs_pool = pool.clone();
+    let prune_schedule = job_queue::PRUNE_SCHEDULE
+        .parse()
+        .expect("PRUNE_SCHEDULE is a valid cron expression");
+    scheduler.add("prune_jobs", prune_schedule, move || {
+        let pool = jobs_pool.clone();
+        async move {
+            let deleted = prune_jobs(&pool, job_queue::RETENTION).await?;
+            info!(deleted, "Pruned finished jobs from the job queue");
+            Ok(())
+        }
+    });
+
+    let views_pool = pool.clone();
+    let prune_schedule = views::PRUNE_SCHEDULE
+        .parse()
+        .expect("PRUNE_SCHEDULE is a valid cron expression");
+    scheduler.add("prune_book_views", prune_schedule, move || {
+        let pool = views_pool.clone();
+        async move {
+            let deleted = prune_views(&pool, views::RETENTION).await?;
+            info!(deleted, "Pruned old views of books");
+            Ok(())
+        }
+    });
+
+    let usage_pool = pool.clone();
+    let prune_schedule = usage::PRUNE_SCHEDULE
+        .parse()
+        .expect("PRUNE_SCHEDULE is a valid cron expression");
+    scheduler.add("prune_api_usage", prune_schedule, move || {
+        let pool = usage_pool.clone();
+        async move {
+            let deleted = prune_usage(&pool, usage::RETENTION).await?;
+            info!(deleted, "Pruned old API usage");
+            Ok(())
+        }
+    });
+
+    let partitions_pool = pool.clone();
+    let partition_schedule = database::PARTITION_SCHEDULE
+        .parse()
+        .expect("PARTITION_SCHEDULE is a valid cron expression");
+    scheduler.add("create_partitions", partition_schedule, move || {
+        let pool = partitions_pool.clone();
+        async move {
+            let created =
+                create_revision_partitions(&pool, database::PARTITION_MONTHS_AHEAD).await?;
+            info!(created, "Created partitions of the revision history");
+            Ok(())
+        }
+    });
+
+    if let Some(backup_config) = backup {
+        if let Some(schedule) = backup_config.schedule.clone() {
+            scheduler.add("backup", schedule, move || {
+                let pool = pool.clone();
+                let backup_config = backup_config.clone();
+                async move {
+                    let summary = run_backup(&pool, &backup_config).await?;
+                    info!(
+                        location = summary.location,
+                        books = summary.books,
+                        translations = summary.translations,
+                        deleted = summary.deleted.len(),
+                        "Backup complete"
+                    );
+                    Ok(())
+                }
+            });
+        }
+    }
+
+    scheduler
+}
+
+/// Identifies this replica in the `job_leases` table
+fn lease_holder() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .unwrap_or_default();
+    format!("{}:{}", host.trim(), std::process::id())
+}
+
+/// The default shutdown signal: completes when the process is asked to stop,
+/// so the server can stop accepting connections and let in-flight requests
+/// and jobs finish
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    info!("Shutting down");
+}
+
+async fn serve_tcp(
+    listener: std::net::TcpListener,
+    router: Router,
+    tls: Option<TlsConfig>,
+    shutdown: ShutdownSignal,
+) -> Result<(BoundAddress, ServerFuture), StartupError> {
+    let local_addr = listener.local_addr().map_err(StartupError::ListenerError)?;
+    let app = router.into_make_service_with_connect_info::<SocketAddr>();
+
+    match tls {
+        None => {
+            info!("Listening on http://{}", local_addr);
+            listener
+                .set_nonblocking(true)
+                .map_err(StartupError::ListenerError)?;
+            let listener = TcpListener::from_std(listener).map_err(StartupError::ListenerError)?;
+            Ok((
+                BoundAddress::Tcp(local_addr),
+                Box::pin(
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(shutdown)
+                        .into_future(),
+                ),
+            ))
+        }
+        Some(tls) => {
+            let rustls_config = tls.load().await.map_err(StartupError::TlsError)?;
+            tls.spawn_reloader(rustls_config.clone());
+
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            tokio::spawn(async move {
+                shutdown.await;
+                shutdown_handle.graceful_shutdown(None);
+            });
+
+            info!("Listening on https://{}", local_addr);
+            Ok((
+                BoundAddress::Tcp(local_addr),
+                Box::pin(
+                    axum_server::from_tcp_rustls(listener, rustls_config)
+                        .handle(handle)
+                        .serve(app),
+                ),
+            ))
+        }
+    }
+}
+
+/// Removes a socket file left behind by a previous run, which would make the
+/// bind fail. Anything else at `path` is left alone, in case the path is a
+/// mistake.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
 
-    let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    let local_addr = listener.local_addr().unwrap();
-    info!("Listening on {}", local_addr);
+/// TLS on a Unix socket is rejected when the config is validated
+fn serve_unix(
+    listener: std::os::unix::net::UnixListener,
+    router: Router,
+    shutdown: ShutdownSignal,
+) -> Result<(BoundAddress, ServerFuture), StartupError> {
+    let local_addr = listener.local_addr().map_err(StartupError::ListenerError)?;
+    info!("Listening on unix:{:?}", local_addr);
 
-    axum::serve(listener, router)
+    listener
+        .set_nonblocking(true)
+        .map_err(StartupError::ListenerError)?;
+    let listener = UnixListener::from_std(listener).map_err(StartupError::ListenerError)?;
+    Ok((
+        BoundAddress::Unix(local_addr),
+        Box::pin(
+            axum::serve(listener, router.into_make_service())
+                .with_graceful_shutdown(shutdown)
+                .into_future(),
+        ),
+    ))
 }
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+use crate::json_error::json_error;
+use crate::json_stream::is_streamed;
+
+/// How many `/books` requests may be handled at once. Requests over a limit
+/// are rejected straight away with a 503, rather than queueing up for a DB
+/// connection and making a stampede worse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConcurrencyLimits {
+    /// Limit across all routes
+    pub global: Option<usize>,
+    /// Limit applied to each route separately, so one busy route can't
+    /// starve the others
+    pub per_route: Option<usize>,
+}
+
+/// The in-flight requests counted against one limit. Clones share the count.
+#[derive(Debug, Clone)]
+pub(crate) struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(limit: usize) -> Self {
+        ConcurrencyLimit {
+            permits: Arc::new(Semaphore::new(limit)),
+        }
+    }
+}
+
+/// Middleware that runs the request if it fits within the limit, and
+/// otherwise sheds it with a 503. A streamed body counts towards the limit
+/// until it has been sent.
+pub(crate) async fn shed_load(
+    State(limit): State<ConcurrencyLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(permit) = limit.permits.clone().try_acquire_owned() else {
+        debug!(path = request.uri().path(), "Shedding load");
+        return (
+            [(header::RETRY_AFTER, "1")],
+            json_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The service is overloaded, try again later",
+            ),
+        )
+            .into_response();
+    };
+
+    let response = next.run(request).await;
+    if !is_streamed(&response) {
+        return response;
+    }
+    // Released when the body is dropped, once sent or if the client goes
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _permit = &permit;
+            chunk
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_shed() {
+        let (release, released) = oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(move || async move {
+                    if let Some(released) = released.lock().await.take() {
+                        let _ = released.await;
+                    }
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                ConcurrencyLimit::new(1),
+                shed_load,
+            ));
+
+        let request = || Request::get("/slow").body(Body::empty()).unwrap();
+        let in_flight = tokio::spawn(router.clone().oneshot(request()));
+        tokio::task::yield_now().await;
+
+        let shed = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(shed.headers()[header::RETRY_AFTER], "1");
+
+        release.send(()).unwrap();
+        assert_eq!(in_flight.await.unwrap().unwrap().status(), StatusCode::OK);
+        let after = router.oneshot(request()).await.unwrap();
+        assert_eq!(after.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn a_streamed_body_counts_towards_the_limit_until_it_is_dropped() {
+        let router = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    Body::from_stream(futures::stream::pending::<Result<String, io::Error>>())
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                ConcurrencyLimit::new(1),
+                shed_load,
+            ));
+
+        let request = || Request::get("/stream").body(Body::empty()).unwrap();
+        let streaming = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(streaming.status(), StatusCode::OK);
+
+        let shed = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(streaming);
+        let after = router.oneshot(request()).await.unwrap();
+        assert_eq!(after.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,44 @@
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+use crate::config::LogFormat;
+
+/// Handle for changing the log filter of a running process
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Install the global tracing subscriber.
+///
+/// `LogFormat::Json` emits one JSON object per log line, for consumption by
+/// log aggregation tools. The filter uses `RUST_LOG` syntax, and falls back to
+/// the `RUST_LOG` environment variable if not given.
+///
+/// When built with the `tokio-console` feature, task instrumentation is also
+/// published for `tokio-console` to connect to.
+pub fn init_tracing(log_format: LogFormat, log_filter: Option<&str>) -> LogFilterHandle {
+    let fmt_layer = match log_format {
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+        LogFormat::Text => fmt::layer().boxed(),
+    };
+
+    let (filter, handle) = reload::Layer::new(env_filter(log_filter));
+
+    let registry = tracing_subscriber::registry().with(fmt_layer.with_filter(filter));
+
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    registry.init();
+
+    handle
+}
+
+/// Build an `EnvFilter`. The filter must already have been validated.
+pub(crate) fn env_filter(log_filter: Option<&str>) -> EnvFilter {
+    match log_filter {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::from_default_env(),
+    }
+}
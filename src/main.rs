@@ -1,13 +1,177 @@
-use rust_bookstore_api::start_server;
-use std::env;
+use clap::{Args, Parser, Subcommand};
+use rust_bookstore_api::{
+    backup, export, init_tracing, migrate, migration_status, restore, seed, Config, MigrateMode,
+    RestoreTarget, Server, Storage,
+};
+use std::io;
+use std::process;
+
+#[derive(Parser)]
+#[command(version, about = "A bookstore REST API backed by Postgres")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Options for `serve`, when no command is given
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Keep books in memory instead of the DB, which isn't needed at all.
+    /// The same as setting `storage = "memory"`.
+    #[arg(long)]
+    in_memory: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the HTTP server. This is the default if no command is given.
+    Serve(ServeArgs),
+    /// Run any pending DB migrations. Exits with a non-zero status on failure.
+    Migrate {
+        /// List the pending migrations without applying them
+        #[arg(long, conflicts_with = "status")]
+        dry_run: bool,
+        /// List every migration and whether it has been applied
+        #[arg(long)]
+        status: bool,
+    },
+    /// Insert some sample books into the DB
+    Seed,
+    /// Write every book in the DB to stdout as JSON
+    Export,
+    /// Upload a compressed snapshot of the catalog to `backup_url`, deleting
+    /// any backups beyond `backup_retention`
+    Backup,
+    /// Validate a backup and load it into the DB
+    Restore {
+        /// URL of the backup to restore. Defaults to the newest backup under `backup_url`.
+        backup: Option<String>,
+        /// Restore into this schema instead of the live tables, to check the
+        /// data before swapping it in
+        #[arg(long, conflicts_with = "replace")]
+        schema: Option<String>,
+        /// Overwrite the live tables even if they already have rows
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Validate the configuration and exit
+    CheckConfig,
+}
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
 
-    let db_url = env::var("DATABASE_URL").unwrap_or("postgres://localhost/bookstore".to_string());
+    // In development, settings can be kept in a .env file. Variables that are
+    // already set in the environment take precedence.
+    dotenvy::dotenv().ok();
 
-    let server = start_server(db_url).await;
+    let config = Config::load().unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(1);
+    });
+
+    let log_filter_handle = init_tracing(config.log_format, config.runtime.log_filter.as_deref());
+
+    match cli.command.unwrap_or(Command::Serve(cli.serve)) {
+        Command::Serve(args) => {
+            let mut options = config.server_options();
+            options.log_filter_handle = Some(log_filter_handle);
+
+            let builder = Server::builder().options(options);
+            let server = if args.in_memory || config.storage == Storage::Memory {
+                builder.in_memory().build().await
+            } else {
+                builder
+                    .database_url(config.database_url.clone())
+                    .build()
+                    .await
+            };
+            let server = server.unwrap_or_else(|error| {
+                eprintln!("Failed to start server: {error}");
+                process::exit(1);
+            });
+
+            server.await.unwrap();
+        }
+        Command::Migrate { status: true, .. } => match migration_status(&config).await {
+            Ok(migrations) => {
+                for migration in migrations {
+                    let status = if migration.applied {
+                        "applied"
+                    } else {
+                        "pending"
+                    };
+                    println!("{status:<8} {}", migration.name);
+                }
+            }
+            Err(error) => exit_with_error("Failed to check migration status", error),
+        },
+        Command::Migrate { dry_run, .. } => {
+            let mode = if dry_run {
+                MigrateMode::DryRun
+            } else {
+                MigrateMode::Apply
+            };
+            match migrate(&config, mode).await {
+                Ok(migrations) => {
+                    let verb = if dry_run { "Would apply" } else { "Applied" };
+                    eprintln!("{verb} {} migrations", migrations.len());
+                    for migration in migrations {
+                        println!("{migration}");
+                    }
+                }
+                Err(error) => exit_with_error("Failed to run migrations", error),
+            }
+        }
+        Command::Seed => match seed(&config).await {
+            Ok(count) => eprintln!("Inserted {count} sample books"),
+            Err(error) => exit_with_error("Failed to seed the DB", error),
+        },
+        Command::Export => match export(&config, io::stdout().lock()).await {
+            Ok(count) => eprintln!("Exported {count} books"),
+            Err(error) => exit_with_error("Failed to export books", error),
+        },
+        Command::Backup => match backup(&config).await {
+            Ok(summary) => {
+                eprintln!(
+                    "Backed up {} books and {} translations to {}",
+                    summary.books, summary.translations, summary.location
+                );
+                for location in summary.deleted {
+                    eprintln!("Deleted old backup {location}");
+                }
+            }
+            Err(error) => exit_with_error("Failed to back up the catalog", error),
+        },
+        Command::Restore {
+            backup,
+            schema,
+            replace,
+        } => {
+            let target = match schema {
+                Some(schema) => RestoreTarget::Schema(schema),
+                None => RestoreTarget::Live { replace },
+            };
+            match restore(&config, backup.as_deref(), target).await {
+                Ok(summary) => eprintln!(
+                    "Restored {} books and {} translations from {} (taken at {})",
+                    summary.books,
+                    summary.translations,
+                    summary.location,
+                    summary.header.created_at
+                ),
+                Err(error) => exit_with_error("Failed to restore the backup", error),
+            }
+        }
+        Command::CheckConfig => eprintln!("Configuration OK"),
+    }
+}
 
-    server.await.unwrap();
+fn exit_with_error(context: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{context}: {error}");
+    process::exit(1);
 }
@@ -0,0 +1,749 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::SinkExt;
+
+use crate::events::{BookChange, BookCreated, BookDeleted, BookEvent, BookUpdated};
+use crate::filter::Filter;
+use crate::models::{normalize_text, Actor, Book, BookTranslation, NewBook, NewTranslation};
+use crate::repo::{id_at, BookRepo};
+use crate::search::{self, SearchResult};
+use crate::slug::{first_free, has_base, slugify};
+use crate::sort::SortSpec;
+use crate::stats::{BookField, Group, Metric};
+use crate::suggest::{rank, Suggestion, SuggestionKind};
+use crate::views::TrendingBook;
+
+/// The most probable duplicates of a book that are looked for, as in the DB
+const MAX_DUPLICATES: usize = 10;
+
+/// The most books an unpaged listing returns, as in the DB
+const MAX_LISTED_BOOKS: usize = 100;
+
+/// A `BookRepo` that keeps the catalog and its history in memory, for tests
+/// and for trying the API out without a DB. Clones share the same books.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryBookRepo {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    books: BTreeMap<i32, Book>,
+    /// Every revision of every book, in the order they were recorded. A
+    /// revision's position in the change feed is its index plus one.
+    revisions: Vec<BookChange>,
+    last_id: i32,
+    /// The slugs books had before being renamed, deleted or merged, with
+    /// the book each now leads to
+    old_slugs: BTreeMap<String, i32>,
+    /// How many times each book was viewed in each hour
+    views: BTreeMap<(i32, DateTime<Utc>), i64>,
+    /// Each book's translations, by book ID and language
+    translations: BTreeMap<(i32, String), BookTranslation>,
+}
+
+impl State {
+    fn save(&mut self, id: i32, new_book: NewBook) -> Book {
+        let slug = self.slug_for(id, &new_book.name);
+        let book = Book {
+            id,
+            name: new_book.name,
+            author: new_book.author,
+            updated_at: Utc::now(),
+            isbn: new_book.isbn,
+            publisher: new_book.publisher,
+            cover_url: new_book.cover_url,
+            slug,
+        };
+        self.books.insert(id, book.clone());
+        book
+    }
+
+    /// The slug for the book with the name, as in the DB: its current one
+    /// if the name still gives it, or else the first free one, retiring
+    /// its current one
+    fn slug_for(&mut self, id: i32, name: &str) -> String {
+        let base = slugify(name);
+        if let Some(current) = self.books.get(&id).map(|book| book.slug.clone()) {
+            if has_base(&current, &base) {
+                return current;
+            }
+            self.old_slugs.insert(current, id);
+        }
+        let slug = first_free(&base, |slug| {
+            self.books
+                .values()
+                .any(|book| book.id != id && book.slug == slug)
+                || self.old_slugs.get(slug).is_some_and(|owner| *owner != id)
+        });
+        self.old_slugs.remove(&slug);
+        slug
+    }
+
+    /// Remove the book and its translations, keeping its slug so that it is
+    /// never given to another book, and leading to `successor` if given
+    fn remove(&mut self, id: i32, successor: Option<i32>) -> bool {
+        let Some(book) = self.books.remove(&id) else {
+            return false;
+        };
+        self.translations.retain(|(book_id, _), _| *book_id != id);
+        let successor = successor.unwrap_or(id);
+        self.old_slugs.insert(book.slug, successor);
+        for owner in self.old_slugs.values_mut() {
+            if *owner == id {
+                *owner = successor;
+            }
+        }
+        true
+    }
+
+    /// Record an event as the book's next revision
+    fn record(&mut self, event: BookEvent, actor: &Actor, restores_revision: Option<i32>) {
+        let revision = self
+            .revisions
+            .iter()
+            .filter(|change| change.event.book_id() == event.book_id())
+            .count() as i32
+            + 1;
+        self.revisions.push(BookChange {
+            revision,
+            recorded_at: Utc::now(),
+            actor: actor.0.clone(),
+            restores_revision,
+            event,
+        });
+    }
+}
+
+impl InMemoryBookRepo {
+    pub fn new() -> Self {
+        InMemoryBookRepo::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("No thread panics holding the lock")
+    }
+}
+
+impl BookRepo<Infallible> for InMemoryBookRepo {
+    async fn list_books(&self) -> Result<Vec<Book>, Infallible> {
+        Ok(self
+            .state()
+            .books
+            .values()
+            .take(MAX_LISTED_BOOKS)
+            .cloned()
+            .collect())
+    }
+
+    async fn filter_books(&self, filter: &Filter) -> Result<Vec<Book>, Infallible> {
+        Ok(self
+            .state()
+            .books
+            .values()
+            .filter(|book| filter.matches(book))
+            .take(MAX_LISTED_BOOKS)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_books_after(
+        &self,
+        after_id: i32,
+        limit: i64,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<Book>, Infallible> {
+        Ok(self
+            .state()
+            .books
+            .range((Bound::Excluded(after_id), Bound::Unbounded))
+            .map(|(_, book)| book)
+            .filter(|book| filter.is_none_or(|filter| filter.matches(book)))
+            .take(usize::try_from(limit).unwrap_or_default())
+            .cloned()
+            .collect())
+    }
+
+    async fn stream_books(
+        &self,
+        filter: Option<&Filter>,
+        sort: &SortSpec,
+        mut sender: mpsc::Sender<Result<Book, Infallible>>,
+    ) {
+        // Copied first, so that the lock isn't held while the receiver catches up
+        let mut books: Vec<Book> = self
+            .state()
+            .books
+            .values()
+            .filter(|book| filter.is_none_or(|filter| filter.matches(book)))
+            .cloned()
+            .collect();
+        books.sort_by(|a, b| sort.compare(a, b));
+        books.truncate(MAX_LISTED_BOOKS);
+        for book in books {
+            if sender.send(Ok(book)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn get_book(&self, id: i32) -> Result<Option<Book>, Infallible> {
+        Ok(self.state().books.get(&id).cloned())
+    }
+
+    async fn get_book_by_slug(&self, slug: &str) -> Result<Option<Book>, Infallible> {
+        let state = self.state();
+        let book = state
+            .books
+            .values()
+            .find(|book| book.slug == slug)
+            .or_else(|| state.books.get(state.old_slugs.get(slug)?))
+            .cloned();
+        Ok(book)
+    }
+
+    async fn insert_book(&mut self, new_book: NewBook, actor: &Actor) -> Result<Book, Infallible> {
+        let mut state = self.state();
+        state.last_id += 1;
+        let id = state.last_id;
+        let book = state.save(id, new_book);
+        state.record(
+            BookEvent::BookCreated(BookCreated { book: book.clone() }),
+            actor,
+            None,
+        );
+        Ok(book)
+    }
+
+    async fn insert_books(
+        &mut self,
+        new_books: Vec<NewBook>,
+        actor: &Actor,
+    ) -> Result<Vec<Book>, Infallible> {
+        let mut state = self.state();
+        let mut books = Vec::with_capacity(new_books.len());
+        for new_book in new_books {
+            state.last_id += 1;
+            let id = state.last_id;
+            let book = state.save(id, new_book);
+            state.record(
+                BookEvent::BookCreated(BookCreated { book: book.clone() }),
+                actor,
+                None,
+            );
+            books.push(book);
+        }
+        Ok(books)
+    }
+
+    async fn update_book(
+        &mut self,
+        id: i32,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<Option<Book>, Infallible> {
+        let mut state = self.state();
+        if !state.books.contains_key(&id) {
+            return Ok(None);
+        }
+        let book = state.save(id, new_book);
+        state.record(
+            BookEvent::BookUpdated(BookUpdated { book: book.clone() }),
+            actor,
+            None,
+        );
+        Ok(Some(book))
+    }
+
+    async fn delete_book(&mut self, id: i32, actor: &Actor) -> Result<bool, Infallible> {
+        let mut state = self.state();
+        let deleted = state.remove(id, None);
+        if deleted {
+            state.record(BookEvent::BookDeleted(BookDeleted { id }), actor, None);
+        }
+        Ok(deleted)
+    }
+
+    async fn list_translations(&self, id: i32) -> Result<Vec<BookTranslation>, Infallible> {
+        Ok(self
+            .state()
+            .translations
+            .range((id, String::new())..)
+            .take_while(|((book_id, _), _)| *book_id == id)
+            .map(|(_, translation)| translation.clone())
+            .collect())
+    }
+
+    async fn put_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+        translation: &NewTranslation,
+    ) -> Result<Option<BookTranslation>, Infallible> {
+        let mut state = self.state();
+        if !state.books.contains_key(&id) {
+            return Ok(None);
+        }
+        let translation = BookTranslation {
+            book_id: id,
+            language: language.to_string(),
+            name: translation.name.clone(),
+            description: translation.description.clone(),
+            updated_at: Utc::now(),
+        };
+        state
+            .translations
+            .insert((id, language.to_string()), translation.clone());
+        Ok(Some(translation))
+    }
+
+    async fn delete_translation(&mut self, id: i32, language: &str) -> Result<bool, Infallible> {
+        Ok(self
+            .state()
+            .translations
+            .remove(&(id, language.to_string()))
+            .is_some())
+    }
+
+    async fn restore_book(
+        &mut self,
+        id: i32,
+        book: NewBook,
+        restores_revision: i32,
+        actor: &Actor,
+    ) -> Result<BookEvent, Infallible> {
+        let mut state = self.state();
+        let existed = state.books.contains_key(&id);
+        let book = state.save(id, book);
+        let event = if existed {
+            BookEvent::BookUpdated(BookUpdated { book })
+        } else {
+            BookEvent::BookCreated(BookCreated { book })
+        };
+        state.record(event.clone(), actor, Some(restores_revision));
+        Ok(event)
+    }
+
+    async fn find_duplicates(&self, book: &NewBook) -> Result<Vec<Book>, Infallible> {
+        let name = normalize_text(&book.name);
+        let author = normalize_text(&book.author);
+        let duplicates = self
+            .state()
+            .books
+            .values()
+            .filter(|existing| {
+                (book.isbn.is_some() && existing.isbn == book.isbn)
+                    || (!book.name.is_empty()
+                        && !book.author.is_empty()
+                        && normalize_text(&existing.name) == name
+                        && normalize_text(&existing.author) == author)
+            })
+            .take(MAX_DUPLICATES)
+            .cloned()
+            .collect();
+        Ok(duplicates)
+    }
+
+    async fn find_duplicates_of_any(&self, books: &[NewBook]) -> Result<Vec<Book>, Infallible> {
+        let duplicates = self
+            .state()
+            .books
+            .values()
+            .filter(|existing| {
+                let existing = NewBook::from(*existing);
+                books.iter().any(|book| book.looks_like(&existing))
+            })
+            .cloned()
+            .collect();
+        Ok(duplicates)
+    }
+
+    async fn find_books_by_isbn(&self, isbns: &[String]) -> Result<Vec<Book>, Infallible> {
+        let books = self
+            .state()
+            .books
+            .values()
+            .filter(|book| book.isbn.as_ref().is_some_and(|isbn| isbns.contains(isbn)))
+            .cloned()
+            .collect();
+        Ok(books)
+    }
+
+    async fn merge_books(
+        &mut self,
+        keep: i32,
+        duplicate: i32,
+        merged: Option<NewBook>,
+        actor: &Actor,
+    ) -> Result<Option<Vec<BookEvent>>, Infallible> {
+        let mut state = self.state();
+        if !state.books.contains_key(&keep) || !state.books.contains_key(&duplicate) {
+            return Ok(None);
+        }
+
+        let mut events = Vec::new();
+        if let Some(merged) = merged {
+            let book = state.save(keep, merged);
+            events.push(BookEvent::BookUpdated(BookUpdated { book }));
+        }
+        state.remove(duplicate, Some(keep));
+        events.push(BookEvent::BookDeleted(BookDeleted { id: duplicate }));
+        for event in &events {
+            state.record(event.clone(), actor, None);
+        }
+        Ok(Some(events))
+    }
+
+    async fn book_history(&self, id: i32) -> Result<Vec<BookChange>, Infallible> {
+        Ok(self
+            .state()
+            .revisions
+            .iter()
+            .filter(|change| change.event.book_id() == id)
+            .cloned()
+            .collect())
+    }
+
+    async fn changes(&self, after: i64, limit: i64) -> Result<Vec<(i64, BookChange)>, Infallible> {
+        Ok(self
+            .state()
+            .revisions
+            .iter()
+            .enumerate()
+            .map(|(index, change)| (index as i64 + 1, change.clone()))
+            .skip(after.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn random_book(&self, pick: f64) -> Result<Option<Book>, Infallible> {
+        let state = self.state();
+        let (Some(first), Some(last)) = (state.books.keys().next(), state.books.keys().last())
+        else {
+            return Ok(None);
+        };
+        Ok(state
+            .books
+            .range(id_at(*first, *last, pick)..)
+            .next()
+            .map(|(_, book)| book.clone()))
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Infallible> {
+        let mut counts: HashMap<(&str, SuggestionKind), i64> = HashMap::new();
+        let state = self.state();
+        for book in state.books.values() {
+            for (text, kind) in [
+                (&book.name, SuggestionKind::Title),
+                (&book.author, SuggestionKind::Author),
+            ] {
+                if text.to_lowercase().starts_with(prefix) {
+                    *counts.entry((text, kind)).or_default() += 1;
+                }
+            }
+        }
+        let mut suggestions: Vec<_> = counts
+            .into_iter()
+            .map(|((text, kind), books)| Suggestion {
+                text: text.to_string(),
+                kind,
+                books,
+            })
+            .collect();
+        rank(&mut suggestions, prefix);
+        suggestions.truncate(limit as usize);
+        Ok(suggestions)
+    }
+
+    async fn search_books(&self, query: &str, limit: i64) -> Result<Vec<SearchResult>, Infallible> {
+        let words = search::words(query);
+        let mut results: Vec<_> = self
+            .state()
+            .books
+            .values()
+            .filter_map(|book| {
+                let rank = search::rank(&words, book)?;
+                Some(SearchResult {
+                    book: book.clone(),
+                    rank,
+                })
+            })
+            .collect();
+        // Stable, so ties stay in order of ID
+        results.sort_by(|a, b| b.rank.total_cmp(&a.rank));
+        results.truncate(limit.max(0) as usize);
+        Ok(results)
+    }
+
+    async fn count_books(&self) -> Result<i64, Infallible> {
+        Ok(self.state().books.len() as i64)
+    }
+
+    async fn aggregate_books(
+        &self,
+        group_by: BookField,
+        metric: Metric,
+        limit: i64,
+    ) -> Result<Vec<Group>, Infallible> {
+        let state = self.state();
+        let mut groups: HashMap<Option<&str>, Vec<&Book>> = HashMap::new();
+        for book in state.books.values() {
+            let value = match group_by {
+                BookField::Author => Some(book.author.as_str()),
+                BookField::Publisher => book.publisher.as_deref(),
+            };
+            groups.entry(value).or_default().push(book);
+        }
+        let mut groups: Vec<Group> = groups
+            .into_iter()
+            .map(|(value, books)| {
+                let result = match metric {
+                    Metric::Count => books.len(),
+                    Metric::WithIsbn => books.iter().filter(|book| book.isbn.is_some()).count(),
+                    Metric::DistinctAuthors => books
+                        .iter()
+                        .map(|book| &book.author)
+                        .collect::<BTreeSet<_>>()
+                        .len(),
+                    Metric::DistinctPublishers => books
+                        .iter()
+                        .filter_map(|book| book.publisher.as_ref())
+                        .collect::<BTreeSet<_>>()
+                        .len(),
+                };
+                Group {
+                    value: value.map(str::to_string),
+                    result: result as i64,
+                }
+            })
+            .collect();
+        // Nulls last, as in Postgres
+        groups.sort_by(|a, b| {
+            b.result
+                .cmp(&a.result)
+                .then_with(|| a.value.is_none().cmp(&b.value.is_none()))
+                .then_with(|| a.value.cmp(&b.value))
+        });
+        groups.truncate(limit.max(0) as usize);
+        Ok(groups)
+    }
+
+    async fn record_views(
+        &mut self,
+        views: &[(i32, i32)],
+        hour: DateTime<Utc>,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        for (id, count) in views {
+            *state.views.entry((*id, hour)).or_default() += i64::from(*count);
+        }
+        Ok(())
+    }
+
+    async fn trending_books(
+        &self,
+        since: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<TrendingBook>, Infallible> {
+        let state = self.state();
+        let mut views: BTreeMap<i32, i64> = BTreeMap::new();
+        for ((id, _), count) in state.views.iter().filter(|((_, hour), _)| *hour >= since) {
+            *views.entry(*id).or_default() += count;
+        }
+        let mut trending: Vec<_> = views
+            .into_iter()
+            .filter_map(|(id, views)| {
+                let book = state.books.get(&id)?.clone();
+                Some(TrendingBook { book, views })
+            })
+            .collect();
+        // Stable, so ties stay in order of ID
+        trending.sort_by_key(|trending| std::cmp::Reverse(trending.views));
+        trending.truncate(limit.max(0) as usize);
+        Ok(trending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn changes_are_kept_as_revisions_in_the_order_they_were_made() {
+        let mut repo = InMemoryBookRepo::new();
+        let actor = Actor(Some("tester".to_string()));
+        let new_book = |name: &str| NewBook {
+            name: name.to_string(),
+            author: "Ursula K. Le Guin".to_string(),
+            ..NewBook::default()
+        };
+
+        let book = repo
+            .insert_book(new_book("Earthsea"), &actor)
+            .await
+            .unwrap();
+        repo.insert_book(new_book("The Dispossessed"), &actor)
+            .await
+            .unwrap();
+        repo.update_book(book.id, new_book("A Wizard of Earthsea"), &actor)
+            .await
+            .unwrap();
+        assert!(repo.delete_book(book.id, &actor).await.unwrap());
+        assert!(!repo.delete_book(book.id, &actor).await.unwrap());
+
+        let revisions: Vec<_> = repo
+            .book_history(book.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.revision, change.event.event_type()))
+            .collect();
+        assert_eq!(
+            revisions,
+            vec![
+                (1, "book_created"),
+                (2, "book_updated"),
+                (3, "book_deleted")
+            ]
+        );
+
+        let positions: Vec<_> = repo
+            .changes(1, 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(position, change)| (position, change.event.book_id()))
+            .collect();
+        assert_eq!(positions, vec![(2, 2), (3, book.id)]);
+
+        let duplicates = repo
+            .find_duplicates(&NewBook {
+                author: "ursula k le guin".to_string(),
+                ..new_book("the dispossessed!")
+            })
+            .await
+            .unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].name, "The Dispossessed");
+    }
+
+    #[tokio::test]
+    async fn listings_return_at_most_100_books_like_the_db() {
+        let mut repo = InMemoryBookRepo::new();
+        let actor = Actor::default();
+        for number in 1..=150 {
+            let new_book = NewBook {
+                name: format!("Volume {number:03}"),
+                author: "Anon".to_string(),
+                ..NewBook::default()
+            };
+            repo.insert_book(new_book, &actor).await.unwrap();
+        }
+
+        assert_eq!(repo.list_books().await.unwrap().len(), 100);
+        let filter = Filter::parse("author==Anon").unwrap();
+        assert_eq!(repo.filter_books(&filter).await.unwrap().len(), 100);
+
+        // The cap applies after sorting, so the last volumes come first
+        let (sender, receiver) = mpsc::channel(0);
+        let sort = SortSpec::parse("-name").unwrap();
+        let (_, books) = futures::join!(
+            repo.stream_books(None, &sort, sender),
+            receiver.collect::<Vec<_>>()
+        );
+        let names: Vec<_> = books.into_iter().map(|book| book.unwrap().name).collect();
+        assert_eq!(names.len(), 100);
+        assert_eq!(names[0], "Volume 150");
+        assert_eq!(names[99], "Volume 051");
+    }
+
+    #[tokio::test]
+    async fn old_slugs_lead_to_the_book_and_are_never_reused() {
+        let mut repo = InMemoryBookRepo::new();
+        let actor = Actor::default();
+        let new_book = |name: &str| NewBook {
+            name: name.to_string(),
+            author: "Frank Herbert".to_string(),
+            ..NewBook::default()
+        };
+        let slug_of = |book: Option<Book>| book.map(|book| (book.id, book.slug));
+
+        let dune = repo.insert_book(new_book("Dune"), &actor).await.unwrap();
+        let other = repo.insert_book(new_book("DUNE!"), &actor).await.unwrap();
+        assert_eq!(
+            (dune.slug.as_str(), other.slug.as_str()),
+            ("dune", "dune-2")
+        );
+
+        // Changes that don't change the slug keep it
+        let other = repo
+            .update_book(other.id, new_book("Dune"), &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(other.slug, "dune-2");
+
+        let messiah = repo
+            .update_book(other.id, new_book("Dune Messiah"), &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(messiah.slug, "dune-messiah");
+        assert_eq!(
+            slug_of(repo.get_book_by_slug("dune-2").await.unwrap()),
+            Some((other.id, "dune-messiah".to_string()))
+        );
+        let third = repo.insert_book(new_book("Dune"), &actor).await.unwrap();
+        assert_eq!(third.slug, "dune-3");
+
+        // Merged books' slugs lead to the book they were merged into
+        repo.merge_books(dune.id, third.id, None, &actor)
+            .await
+            .unwrap();
+        assert_eq!(
+            slug_of(repo.get_book_by_slug("dune-3").await.unwrap()),
+            Some((dune.id, "dune".to_string()))
+        );
+        repo.delete_book(dune.id, &actor).await.unwrap();
+        assert_eq!(repo.get_book_by_slug("dune").await.unwrap(), None);
+        let fourth = repo.insert_book(new_book("Dune"), &actor).await.unwrap();
+        assert_eq!(fourth.slug, "dune-4");
+    }
+
+    #[tokio::test]
+    async fn random_books_come_from_the_whole_range_of_ids() {
+        let mut repo = InMemoryBookRepo::new();
+        let actor = Actor::default();
+        assert_eq!(repo.random_book(0.5).await.unwrap(), None);
+
+        let mut ids = Vec::new();
+        for name in ["Emma", "Persuasion", "Mansfield Park", "Sanditon"] {
+            let new_book = NewBook {
+                name: name.to_string(),
+                author: "Jane Austen".to_string(),
+                ..NewBook::default()
+            };
+            ids.push(repo.insert_book(new_book, &actor).await.unwrap().id);
+        }
+        repo.delete_book(ids[1], &actor).await.unwrap();
+
+        let picked = |pick| {
+            let repo = repo.clone();
+            async move { repo.random_book(pick).await.unwrap().map(|book| book.id) }
+        };
+        assert_eq!(picked(0.0).await, Some(ids[0]));
+        // The deleted book's ID leads to the next book
+        assert_eq!(picked(0.3).await, Some(ids[2]));
+        assert_eq!(picked(0.6).await, Some(ids[2]));
+        assert_eq!(picked(0.99).await, Some(ids[3]));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::Write;
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use tokio::runtime::{Handle, RuntimeMetrics};
+
+use crate::scheduler::JobMetrics;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Handler for `GET /metrics`, exposing metrics in the Prometheus text format
+pub async fn metrics(State(jobs): State<JobMetrics>) -> impl IntoResponse {
+    let mut body = String::new();
+    write_runtime_metrics(&mut body, &Handle::current().metrics());
+    jobs.write_prometheus(&mut body);
+
+    ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], body)
+}
+
+/// Write the Tokio runtime metrics.
+///
+/// Per-worker poll and busy-time metrics are only available when built with
+/// `RUSTFLAGS="--cfg tokio_unstable"`.
+fn write_runtime_metrics(out: &mut String, metrics: &RuntimeMetrics) {
+    gauge(
+        out,
+        "tokio_workers",
+        "Number of worker threads used by the runtime",
+        metrics.num_workers(),
+    );
+    gauge(
+        out,
+        "tokio_alive_tasks",
+        "Number of tasks currently alive in the runtime",
+        metrics.num_alive_tasks(),
+    );
+    gauge(
+        out,
+        "tokio_global_queue_depth",
+        "Number of tasks currently scheduled in the global queue",
+        metrics.global_queue_depth(),
+    );
+
+    #[cfg(tokio_unstable)]
+    write_unstable_runtime_metrics(out, metrics);
+}
+
+#[cfg(tokio_unstable)]
+fn write_unstable_runtime_metrics(out: &mut String, metrics: &RuntimeMetrics) {
+    gauge(
+        out,
+        "tokio_blocking_threads",
+        "Number of additional threads spawned by the runtime for blocking operations",
+        metrics.num_blocking_threads(),
+    );
+    gauge(
+        out,
+        "tokio_blocking_queue_depth",
+        "Number of tasks waiting for a blocking thread",
+        metrics.blocking_queue_depth(),
+    );
+
+    per_worker(
+        out,
+        "tokio_worker_polls_total",
+        "counter",
+        "Number of tasks polled by each worker",
+        metrics,
+        |worker| metrics.worker_poll_count(worker) as f64,
+    );
+    per_worker(
+        out,
+        "tokio_worker_mean_poll_time_seconds",
+        "gauge",
+        "Mean time each worker spends polling a task",
+        metrics,
+        |worker| metrics.worker_mean_poll_time(worker).as_secs_f64(),
+    );
+    per_worker(
+        out,
+        "tokio_worker_busy_seconds_total",
+        "counter",
+        "Time each worker has spent busy",
+        metrics,
+        |worker| metrics.worker_total_busy_duration(worker).as_secs_f64(),
+    );
+}
+
+#[cfg(tokio_unstable)]
+fn per_worker(
+    out: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    metrics: &RuntimeMetrics,
+    value: impl Fn(usize) -> f64,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+    for worker in 0..metrics.num_workers() {
+        let _ = writeln!(out, "{name}{{worker=\"{worker}\"}} {}", value(worker));
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    // Writing to a String can't fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runtime_metrics_are_written_in_prometheus_text_format() {
+        let mut out = String::new();
+        write_runtime_metrics(&mut out, &Handle::current().metrics());
+
+        assert!(out.contains("# TYPE tokio_workers gauge\ntokio_workers 1\n"));
+        assert!(out.contains("tokio_alive_tasks "));
+        assert!(out.contains("tokio_global_queue_depth "));
+    }
+}
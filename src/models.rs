@@ -1,18 +1,218 @@
-use crate::schema::books;
+use chrono::{DateTime, Utc};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, diesel::Queryable, diesel::Selectable)]
+use crate::schema::{book_translations, books, jobs, webhook_deliveries, webhooks};
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    diesel::Queryable,
+    diesel::QueryableByName,
+    diesel::Selectable,
+)]
 #[diesel(table_name = books)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Book {
     pub id: i32,
     pub name: String,
     pub author: String,
+    /// When the book was added or last changed. Backups taken before this
+    /// was added don't have it, so it is restored as the time of the restore.
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    /// The ISBN-10 or ISBN-13, without hyphens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    /// An image of the book's cover
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_url: Option<String>,
+    /// The URL-friendly form of the name, unique among the books. Revisions
+    /// recorded and backups taken before this was added don't have it.
+    #[serde(default)]
+    pub slug: String,
 }
 
+/// Who made a change, recorded with each revision of a book. There are no
+/// user accounts, so for API requests this is the client's IP address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Actor(pub Option<String>);
+
 // TODO could build this using a macro, as it is just Book minus the ID field
-#[derive(Clone, serde::Deserialize, diesel::Insertable, diesel::AsChangeset)]
+/// A book as given when adding or replacing one. The name and author can be
+/// left out when there is an ISBN, to be filled in by enrichment.
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    diesel::Insertable,
+    diesel::AsChangeset,
+)]
 #[diesel(table_name = books)]
+#[diesel(treat_none_as_null = true)]
 pub struct NewBook {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub author: String,
+    pub isbn: Option<String>,
+    pub publisher: Option<String>,
+    pub cover_url: Option<String>,
+}
+
+impl From<&Book> for NewBook {
+    fn from(book: &Book) -> Self {
+        NewBook {
+            name: book.name.clone(),
+            author: book.author.clone(),
+            isbn: book.isbn.clone(),
+            publisher: book.publisher.clone(),
+            cover_url: book.cover_url.clone(),
+        }
+    }
+}
+
+impl NewBook {
+    /// Whether the book looks like the other, as `find_duplicates` would
+    /// find it: they have the same ISBN, or the same name and author
+    pub(crate) fn looks_like(&self, other: &NewBook) -> bool {
+        (self.isbn.is_some() && self.isbn == other.isbn)
+            || (!self.name.is_empty()
+                && !self.author.is_empty()
+                && normalize_text(&self.name) == normalize_text(&other.name)
+                && normalize_text(&self.author) == normalize_text(&other.author))
+    }
+}
+
+/// The text lower-cased and without diacritics, e.g. `Brontë` -> `bronte`,
+/// as the DB compares text with `unaccent_text` and `ILIKE`
+pub(crate) fn fold_text(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Lower-case the text and replace each run of anything but letters and
+/// digits with a single space, like the DB's `normalize_text`
+pub(crate) fn normalize_text(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A book's name and description in another language
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    diesel::Queryable,
+    diesel::Selectable,
+)]
+#[diesel(table_name = book_translations)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookTranslation {
+    pub book_id: i32,
+    /// A lower-cased language tag, e.g. `fr` or `de-at`
+    pub language: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A translation as given when adding or replacing one
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NewTranslation {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// An endpoint registered to be sent book events
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, diesel::Queryable, diesel::Selectable)]
+#[diesel(table_name = webhooks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// Signs each payload. Never sent back once registered.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// The event types to send, or every type if empty
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, serde::Deserialize, diesel::Insertable)]
+#[diesel(table_name = webhooks)]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// One event sent, or being sent, to a webhook
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, diesel::Queryable, diesel::Selectable)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub event_type: String,
+    /// The JSON body that is sent
+    pub payload: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: i32,
+    /// The response status of the last attempt, if there was a response
+    pub last_status_code: Option<i32>,
+    /// Why the last attempt failed, if it did
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+/// A unit of work in the background job queue
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    diesel::Queryable,
+    diesel::QueryableByName,
+    diesel::Selectable,
+)]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QueuedJob {
+    pub id: i64,
+    pub kind: String,
+    /// The job as JSON
+    pub payload: String,
+    /// `queued`, `running`, `done` or `dead`
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// When the job is next due to run, if it is queued
+    pub run_at: DateTime<Utc>,
+    /// Why the last attempt failed, if it did
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
 }
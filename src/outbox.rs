@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::sql_types::BigInt;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+use crate::database::{DBPool, DatabaseError};
+use crate::events::{BookEvent, EventBus};
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaPublisher;
+use crate::schema::outbox;
+use crate::webhooks::WebhookDispatcher;
+
+/// How many events are relayed in each transaction
+const BATCH_SIZE: i64 = 100;
+/// How often to look for events written by other replicas, or that failed
+/// to be relayed
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long published events are kept, for debugging
+pub(crate) const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+/// When published events older than `RETENTION` are deleted: hourly
+pub(crate) const PRUNE_SCHEDULE: &str = "0 0 * * * *";
+/// Identifies the advisory lock held by the replica that is relaying
+const RELAY_LOCK: i64 = 0x6f7574626f78;
+
+diesel::define_sql_function! {
+    fn pg_try_advisory_xact_lock(key: BigInt) -> Bool;
+}
+
+/// Relays the events in the outbox to webhooks and Kafka, in the order they
+/// were written, marking each one as published once every sink has it.
+///
+/// That makes delivery at-least-once: an event whose publishing fails
+/// partway, or whose replica stops before marking it, is published again.
+/// Only one replica relays at a time, so that events stay in order.
+pub(crate) struct OutboxRelay {
+    pool: DBPool,
+    webhooks: WebhookDispatcher,
+    #[cfg(feature = "kafka")]
+    kafka: Option<KafkaPublisher>,
+}
+
+impl OutboxRelay {
+    pub fn new(pool: DBPool, webhooks: WebhookDispatcher) -> Self {
+        OutboxRelay {
+            pool,
+            webhooks,
+            #[cfg(feature = "kafka")]
+            kafka: None,
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    pub fn with_kafka(mut self, kafka: KafkaPublisher) -> Self {
+        self.kafka = Some(kafka);
+        self
+    }
+
+    /// Relay events until the bus is dropped. Events published on the bus
+    /// wake the relay up, so those written by this replica are relayed
+    /// without waiting for the next poll.
+    pub fn spawn(self, events: &EventBus) -> JoinHandle<()> {
+        let mut wake = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match self.relay_batch().await {
+                    // There may be more waiting
+                    Ok(relayed) if relayed as i64 == BATCH_SIZE => continue,
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to relay events from the outbox: {e}"),
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    woken = wake.recv() => {
+                        if let Err(RecvError::Closed) = woken {
+                            break;
+                        }
+                        // The batch will include every event up to now
+                        wake = wake.resubscribe();
+                    }
+                }
+            }
+        })
+    }
+
+    /// Relay the oldest unpublished events, stopping at the first one that
+    /// fails. Returns how many were relayed.
+    async fn relay_batch(&self) -> Result<usize, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        conn.transaction::<_, DatabaseError, _>(|conn| {
+            async move {
+                let locked = diesel::select(pg_try_advisory_xact_lock(RELAY_LOCK))
+                    .get_result::<bool>(conn)
+                    .await?;
+                if !locked {
+                    // Another replica is relaying
+                    return Ok(0);
+                }
+
+                let events: Vec<(i64, String)> = outbox::table
+                    .filter(outbox::published_at.is_null())
+                    .order(outbox::id.asc())
+                    .limit(BATCH_SIZE)
+                    .select((outbox::id, outbox::payload))
+                    .load(conn)
+                    .await?;
+
+                let mut published = Vec::new();
+                for (id, payload) in events {
+                    if let Err(e) = self.publish(&payload).await {
+                        warn!(outbox_id = id, "Failed to relay event, will retry: {e}");
+                        break;
+                    }
+                    published.push(id);
+                }
+
+                diesel::update(outbox::table.filter(outbox::id.eq_any(&published)))
+                    .set(outbox::published_at.eq(diesel::dsl::now))
+                    .execute(conn)
+                    .await?;
+                Ok(published.len())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn publish(&self, payload: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let event: BookEvent = serde_json::from_str(payload)?;
+        self.webhooks.dispatch(&event, payload).await?;
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = &self.kafka {
+            kafka.publish(&event).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Delete the events that were published more than `retention` ago
+pub(crate) async fn prune_outbox(
+    pool: &DBPool,
+    retention: Duration,
+) -> Result<usize, DatabaseError> {
+    let mut conn = pool.get().await?;
+    let cutoff = Utc::now() - retention;
+
+    let deleted = diesel::delete(outbox::table.filter(outbox::published_at.lt(cutoff)))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(deleted)
+}
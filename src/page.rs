@@ -0,0 +1,51 @@
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+
+use crate::models::Book;
+
+/// What a cursor holds before it is encoded, so that it can later hold more
+/// without old cursors being misread
+const CURSOR_PREFIX: &str = "after_id:";
+
+/// A page of the books, in order of ID
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BookPage {
+    pub books: Vec<Book>,
+    /// Where to carry on from to get the books after these, or `None` if
+    /// there are no more
+    pub next_cursor: Option<String>,
+}
+
+/// A cursor for the books after the one with the ID. Clients should treat it
+/// as opaque, so that what it holds can change.
+pub(crate) fn encode_cursor(after_id: i32) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(format!("{CURSOR_PREFIX}{after_id}"))
+}
+
+/// The ID a cursor from `encode_cursor` carries on after, or `None` if it
+/// isn't one
+pub(crate) fn decode_cursor(cursor: &str) -> Option<i32> {
+    let decoded = BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    String::from_utf8(decoded)
+        .ok()?
+        .strip_prefix(CURSOR_PREFIX)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_carry_the_id_to_carry_on_after() {
+        for id in [1, 123, -5, i32::MAX] {
+            assert_eq!(decode_cursor(&encode_cursor(id)), Some(id));
+        }
+        assert_eq!(decode_cursor("123"), None);
+        assert_eq!(
+            decode_cursor(&BASE64_URL_SAFE_NO_PAD.encode("after_id:x")),
+            None
+        );
+        assert_eq!(decode_cursor("not base64!"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use qrcode::{Color, QrCode};
+use url::Url;
+
+/// The side of each module (the squares a QR code is made of) in pixels,
+/// enough for a phone to read a label printed at 300 DPI from arm's length
+const MODULE_PIXELS: usize = 8;
+
+/// The blank margin the QR code spec asks for on every side, in modules
+const QUIET_ZONE: usize = 4;
+
+/// Where the API is reached from outside, e.g. `https://books.example.com`,
+/// which the links in QR codes start with. If not set, links use the `Host`
+/// of the request for the QR code.
+#[derive(Debug, Clone, Default)]
+pub struct PublicUrl(pub Option<Url>);
+
+impl PublicUrl {
+    /// The start of a link to the API, or `None` if it isn't configured and
+    /// the request didn't say which host it was for
+    pub fn origin(&self, host: Option<&str>) -> Option<String> {
+        match (&self.0, host) {
+            (Some(url), _) => Some(url.as_str().trim_end_matches('/').to_string()),
+            (None, Some(host)) => Some(format!("http://{host}")),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A PNG of the QR code for `text`, black on white
+pub fn render_png(text: &str) -> Result<Vec<u8>, QrError> {
+    let code = QrCode::new(text).map_err(|e| QrError(e.to_string()))?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + 2 * QUIET_ZONE) * MODULE_PIXELS;
+
+    // One byte per pixel of 8-bit greyscale, 0 for black
+    let mut pixels = vec![u8::MAX; side * side];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let left = (i % modules + QUIET_ZONE) * MODULE_PIXELS;
+        let top = (i / modules + QUIET_ZONE) * MODULE_PIXELS;
+        for y in top..top + MODULE_PIXELS {
+            pixels[y * side + left..y * side + left + MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| QrError(e.to_string()))?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(|e| QrError(e.to_string()))?;
+    writer.finish().map_err(|e| QrError(e.to_string()))?;
+    Ok(png)
+}
+
+/// The text didn't fit in a QR code, or the PNG couldn't be encoded
+#[derive(Debug)]
+pub struct QrError(String);
+
+impl std::fmt::Display for QrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to render QR code: {}", self.0)
+    }
+}
+
+impl std::error::Error for QrError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_rendered_as_square_pngs() {
+        let png = render_png("https://books.example.com/v1/books/by-slug/emma").unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.width, info.height);
+        // Version 4 (33 modules) is the smallest that fits the link
+        assert_eq!(info.width as usize, (33 + 2 * QUIET_ZONE) * MODULE_PIXELS);
+    }
+
+    #[test]
+    fn links_start_with_the_public_url_or_the_host() {
+        let configured = PublicUrl(Some(Url::parse("https://books.example.com/").unwrap()));
+        assert_eq!(
+            configured.origin(Some("10.0.0.1:3000")).as_deref(),
+            Some("https://books.example.com")
+        );
+        let unset = PublicUrl::default();
+        assert_eq!(
+            unset.origin(Some("localhost:3000")).as_deref(),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(unset.origin(None), None);
+    }
+}
@@ -1,20 +1,211 @@
-use crate::models::{Book, NewBook};
+use crate::events::{BookChange, BookEvent};
+use crate::filter::Filter;
+use crate::models::{Actor, Book, BookTranslation, NewBook, NewTranslation};
+use crate::search::SearchResult;
+use crate::sort::SortSpec;
+use crate::stats::{BookField, Group, Metric};
+use crate::suggest::Suggestion;
+use crate::views::TrendingBook;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
 use std::error::Error;
 use std::future::Future;
 
 pub trait BookRepo<E: Error> {
     fn list_books(&self) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
 
+    /// The books matching the filter, ordered by ID, as many as `list_books`
+    /// returns
+    fn filter_books(&self, filter: &Filter) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
+
+    /// Up to `limit` books with IDs after `after_id`, in order of ID, and
+    /// only those matching the filter if there is one. Seeks to `after_id`
+    /// in the primary key's index, so later pages are as quick as the first.
+    fn list_books_after(
+        &self,
+        after_id: i32,
+        limit: i64,
+        filter: Option<&Filter>,
+    ) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
+
+    /// Send the books `list_books` would return, or `filter_books` if there
+    /// is a filter, sorted as given, to `sender` as they are read, so that
+    /// they needn't all be held in memory. Stops early if the receiver is
+    /// dropped. An error is sent last.
+    fn stream_books(
+        &self,
+        filter: Option<&Filter>,
+        sort: &SortSpec,
+        sender: mpsc::Sender<Result<Book, E>>,
+    ) -> impl Future<Output = ()> + Send;
+
     fn get_book(&self, id: i32) -> impl Future<Output = Result<Option<Book>, E>> + Send;
 
-    fn insert_book(&mut self, new_book: NewBook) -> impl Future<Output = Result<Book, E>> + Send;
+    /// The book with the slug, or that had it before it was renamed, or
+    /// before it was merged into the book returned
+    fn get_book_by_slug(&self, slug: &str) -> impl Future<Output = Result<Option<Book>, E>> + Send;
+
+    fn insert_book(
+        &mut self,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> impl Future<Output = Result<Book, E>> + Send;
+
+    /// Add the books all at once, or none of them. Returns them in the order
+    /// given.
+    fn insert_books(
+        &mut self,
+        new_books: Vec<NewBook>,
+        actor: &Actor,
+    ) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
 
     fn update_book(
         &mut self,
         id: i32,
         new_book: NewBook,
+        actor: &Actor,
     ) -> impl Future<Output = Result<Option<Book>, E>> + Send;
 
     /// Returns true if the book existed and was deleted, false otherwise
-    fn delete_book(&mut self, id: i32) -> impl Future<Output = Result<bool, E>> + Send;
+    fn delete_book(
+        &mut self,
+        id: i32,
+        actor: &Actor,
+    ) -> impl Future<Output = Result<bool, E>> + Send;
+
+    /// The book's translations, in order of language
+    fn list_translations(
+        &self,
+        id: i32,
+    ) -> impl Future<Output = Result<Vec<BookTranslation>, E>> + Send;
+
+    /// Add or replace the book's translation into `language`, which is
+    /// already normalized. `None` if there is no book with the ID.
+    fn put_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+        translation: &NewTranslation,
+    ) -> impl Future<Output = Result<Option<BookTranslation>, E>> + Send;
+
+    /// Returns true if the book had a translation into `language` and it was
+    /// deleted, false otherwise
+    fn delete_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+    ) -> impl Future<Output = Result<bool, E>> + Send;
+
+    /// Put a book back to an earlier revision's state, as a new revision,
+    /// creating it again with the same ID if it has since been deleted.
+    /// Returns the event for the change.
+    fn restore_book(
+        &mut self,
+        id: i32,
+        book: NewBook,
+        restores_revision: i32,
+        actor: &Actor,
+    ) -> impl Future<Output = Result<BookEvent, E>> + Send;
+
+    /// Books that are probably the same as `book`: those with its ISBN, or
+    /// with its name and author, ignoring case, punctuation and spacing
+    fn find_duplicates(&self, book: &NewBook) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
+
+    /// In one query, the books that may be duplicates of any of `books`, in
+    /// order of ID. These include those `find_duplicates` would find for
+    /// each, and may include others, so `NewBook::looks_like` tells which
+    /// are duplicates of which.
+    fn find_duplicates_of_any(
+        &self,
+        books: &[NewBook],
+    ) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
+
+    /// The books with any of the ISBNs, in order of ID
+    fn find_books_by_isbn(
+        &self,
+        isbns: &[String],
+    ) -> impl Future<Output = Result<Vec<Book>, E>> + Send;
+
+    /// Delete the book `duplicate` and, if `merged` is given, replace the
+    /// book `keep` with it, all at once. Returns the events for the changes,
+    /// or `None`, having changed nothing, if either book doesn't exist.
+    fn merge_books(
+        &mut self,
+        keep: i32,
+        duplicate: i32,
+        merged: Option<NewBook>,
+        actor: &Actor,
+    ) -> impl Future<Output = Result<Option<Vec<BookEvent>>, E>> + Send;
+
+    /// Every revision of the book, oldest first. Empty if there has never
+    /// been a book with the ID.
+    fn book_history(&self, id: i32) -> impl Future<Output = Result<Vec<BookChange>, E>> + Send;
+
+    /// Up to `limit` revisions of any book, in the order they were recorded,
+    /// starting after the one at position `after`. Each comes with its
+    /// position, which only ever increases.
+    fn changes(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<(i64, BookChange)>, E>> + Send;
+
+    /// The first book with an ID at least `pick` of the way from the lowest
+    /// ID to the highest, where `pick` is from 0 up to but not including 1.
+    /// `None` if there are no books.
+    fn random_book(&self, pick: f64) -> impl Future<Output = Result<Option<Book>, E>> + Send;
+
+    /// Up to `limit` names and authors starting with `prefix`, ignoring case,
+    /// most likely first, as ranked by `suggest::rank`. `prefix` is already
+    /// lower-cased.
+    fn suggest(
+        &self,
+        prefix: &str,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<Suggestion>, E>> + Send;
+
+    /// Up to `limit` books with every word of `query` in their name or
+    /// author, best matches first, and in order of ID when tied
+    fn search_books(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<SearchResult>, E>> + Send;
+
+    fn count_books(&self) -> impl Future<Output = Result<i64, E>> + Send;
+
+    /// The books grouped by their value of `group_by`, with `metric` for
+    /// each group. Returns the `limit` groups with the largest results,
+    /// largest first, and in order of value when tied.
+    fn aggregate_books(
+        &self,
+        group_by: BookField,
+        metric: Metric,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<Group>, E>> + Send;
+
+    /// Add views of books, by ID, to their views in the hour starting at
+    /// `hour`
+    fn record_views(
+        &mut self,
+        views: &[(i32, i32)],
+        hour: DateTime<Utc>,
+    ) -> impl Future<Output = Result<(), E>> + Send;
+
+    /// The `limit` books viewed most in the hours starting from `since`,
+    /// most first, and in order of ID when tied. Deleted books are left out.
+    fn trending_books(
+        &self,
+        since: DateTime<Utc>,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<TrendingBook>, E>> + Send;
+}
+
+/// The ID `pick` of the way from `first` to `last`, for `random_book`. Any ID
+/// is as likely as any other, but a book after a gap left by deleted books is
+/// also picked for the IDs in the gap.
+pub(crate) fn id_at(first: i32, last: i32, pick: f64) -> i32 {
+    let ids = i64::from(last) - i64::from(first) + 1;
+    let offset = (ids as f64 * pick.clamp(0.0, 1.0)) as i64;
+    (i64::from(first) + offset.min(ids - 1)) as i32
 }
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A `/books`, `/changes`, `/stats` or `/tools` route whose middleware can be configured on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BookRoute {
+    ListBooks,
+    GetBook,
+    GetBookBySlug,
+    InsertBook,
+    BulkInsertBooks,
+    UpdateBook,
+    DeleteBook,
+    ListTranslations,
+    PutTranslation,
+    DeleteTranslation,
+    BookHistory,
+    RevertBook,
+    DiffRevisions,
+    ListChanges,
+    EnrichBook,
+    CatalogStats,
+    AggregateBooks,
+    TrendingBooks,
+    RandomBook,
+    SuggestBooks,
+    SearchBooks,
+    BookQrCode,
+    CheckIsbn,
+}
+
+impl BookRoute {
+    pub const ALL: [BookRoute; 23] = [
+        BookRoute::ListBooks,
+        BookRoute::GetBook,
+        BookRoute::GetBookBySlug,
+        BookRoute::InsertBook,
+        BookRoute::BulkInsertBooks,
+        BookRoute::UpdateBook,
+        BookRoute::DeleteBook,
+        BookRoute::ListTranslations,
+        BookRoute::PutTranslation,
+        BookRoute::DeleteTranslation,
+        BookRoute::BookHistory,
+        BookRoute::RevertBook,
+        BookRoute::DiffRevisions,
+        BookRoute::ListChanges,
+        BookRoute::EnrichBook,
+        BookRoute::CatalogStats,
+        BookRoute::AggregateBooks,
+        BookRoute::TrendingBooks,
+        BookRoute::RandomBook,
+        BookRoute::SuggestBooks,
+        BookRoute::SearchBooks,
+        BookRoute::BookQrCode,
+        BookRoute::CheckIsbn,
+    ];
+
+    /// The name used for the route in the config, e.g. `routes.insert_book`
+    pub fn name(self) -> &'static str {
+        match self {
+            BookRoute::ListBooks => "list_books",
+            BookRoute::GetBook => "get_book",
+            BookRoute::GetBookBySlug => "get_book_by_slug",
+            BookRoute::InsertBook => "insert_book",
+            BookRoute::BulkInsertBooks => "bulk_insert_books",
+            BookRoute::UpdateBook => "update_book",
+            BookRoute::DeleteBook => "delete_book",
+            BookRoute::ListTranslations => "list_translations",
+            BookRoute::PutTranslation => "put_translation",
+            BookRoute::DeleteTranslation => "delete_translation",
+            BookRoute::BookHistory => "book_history",
+            BookRoute::RevertBook => "revert_book",
+            BookRoute::DiffRevisions => "diff_revisions",
+            BookRoute::ListChanges => "list_changes",
+            BookRoute::EnrichBook => "enrich_book",
+            BookRoute::CatalogStats => "catalog_stats",
+            BookRoute::AggregateBooks => "aggregate_books",
+            BookRoute::TrendingBooks => "trending_books",
+            BookRoute::RandomBook => "random_book",
+            BookRoute::SuggestBooks => "suggest_books",
+            BookRoute::SearchBooks => "search_books",
+            BookRoute::BookQrCode => "book_qr_code",
+            BookRoute::CheckIsbn => "check_isbn",
+        }
+    }
+}
+
+/// Overrides of the global middleware settings for one route. Anything left
+/// unset falls back to the global setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteSettings {
+    pub timeout: Option<Duration>,
+    /// Maximum size in bytes of a JSON request body
+    pub max_json_body_size: Option<usize>,
+    /// Limit on requests to this route handled at once, on top of any global
+    /// or per-path limit
+    pub max_concurrent_requests: Option<usize>,
+    /// Only serve requests carrying the admin token, as for `/admin`
+    pub require_admin_token: bool,
+}
+
+/// The settings of every route that has any overrides
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteConfig(BTreeMap<BookRoute, RouteSettings>);
+
+impl RouteConfig {
+    pub fn get(&self, route: BookRoute) -> RouteSettings {
+        self.0.get(&route).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, route: BookRoute, settings: RouteSettings) {
+        if settings == RouteSettings::default() {
+            self.0.remove(&route);
+        } else {
+            self.0.insert(route, settings);
+        }
+    }
+
+    /// Whether any route requires the admin token
+    pub fn requires_admin_token(&self) -> bool {
+        self.0.values().any(|settings| settings.require_admin_token)
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::config::{Config, ConfigError, RuntimeConfig};
+use crate::logging::{env_filter, LogFilterHandle};
+
+/// Shared access to the settings that can be changed without a restart
+#[derive(Clone)]
+pub struct RuntimeConfigHandle {
+    current: Arc<watch::Sender<RuntimeConfig>>,
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl RuntimeConfigHandle {
+    pub fn new(initial: RuntimeConfig, log_filter: Option<LogFilterHandle>) -> Self {
+        RuntimeConfigHandle {
+            current: Arc::new(watch::Sender::new(initial)),
+            log_filter,
+        }
+    }
+
+    pub fn current(&self) -> RuntimeConfig {
+        self.current.borrow().clone()
+    }
+
+    pub fn maintenance_mode(&self) -> bool {
+        self.current.borrow().maintenance_mode
+    }
+
+    /// Reload the config from its file and environment variables, and apply
+    /// the runtime settings. Other settings only take effect after a restart.
+    ///
+    /// If the reloaded config is invalid, nothing is changed.
+    pub fn reload(&self) -> Result<RuntimeConfig, ConfigError> {
+        let config = Config::load()?;
+        self.apply(config.runtime);
+        Ok(self.current())
+    }
+
+    pub(crate) fn apply(&self, runtime: RuntimeConfig) {
+        if let Some(handle) = &self.log_filter {
+            if let Err(e) = handle.reload(env_filter(runtime.log_filter.as_deref())) {
+                error!("Failed to change the log filter: {e}");
+            }
+        }
+        self.current.send_replace(runtime);
+    }
+
+    /// Reload the runtime settings whenever the process receives SIGHUP
+    pub(crate) fn spawn_sighup_listener(self) {
+        tokio::spawn(async move {
+            let mut sighup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+            while sighup.recv().await.is_some() {
+                match self.reload() {
+                    Ok(runtime) => info!("Reloaded runtime config: {:?}", runtime),
+                    Err(e) => error!("Failed to reload config, keeping the old one: {e}"),
+                }
+            }
+        });
+    }
+}
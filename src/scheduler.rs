@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::commands::CommandError;
+use crate::database::JobLeases;
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), CommandError>> + Send>>;
+
+type JobFn = Box<dyn Fn() -> JobFuture + Send + Sync>;
+
+struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+/// Runs background jobs in-process on cron schedules.
+///
+/// Each job runs on its own task and a run is never started while the
+/// previous one is still going: any ticks missed in the meantime are skipped
+/// (and counted in the metrics) rather than queued up.
+///
+/// With leases, each tick is claimed in the DB before running, so that when
+/// several replicas share a schedule only one of them runs each tick.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    leases: Option<JobLeases>,
+}
+
+/// Counters for one job, exposed on `/metrics`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobStats {
+    pub runs: u64,
+    pub failures: u64,
+    /// Ticks that were skipped because the previous run was still going
+    pub skipped: u64,
+    /// Ticks that another replica claimed and ran
+    pub claimed_elsewhere: u64,
+    pub running: bool,
+    pub last_duration: Duration,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// The stats of every scheduled job, keyed by job name
+#[derive(Debug, Clone, Default)]
+pub struct JobMetrics(Arc<Mutex<BTreeMap<&'static str, JobStats>>>);
+
+/// A started scheduler, which must be shut down to wait for running jobs
+pub struct RunningScheduler {
+    shutdown: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Only run each tick if it can be claimed in the `job_leases` table
+    pub fn with_leases(mut self, leases: JobLeases) -> Scheduler {
+        self.leases = Some(leases);
+        self
+    }
+
+    pub fn add<F, Fut>(&mut self, name: &'static str, schedule: Schedule, run: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), CommandError>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name,
+            schedule,
+            run: Box::new(move || Box::pin(run())),
+        });
+    }
+
+    pub fn start(self, metrics: JobMetrics) -> RunningScheduler {
+        let (shutdown, _) = watch::channel(false);
+        let mut tasks = JoinSet::new();
+
+        for job in self.jobs {
+            metrics.update(job.name, |_| {});
+            info!(job = job.name, schedule = %job.schedule, "Scheduled job");
+            tasks.spawn(run_job(
+                job,
+                self.leases.clone(),
+                metrics.clone(),
+                shutdown.subscribe(),
+            ));
+        }
+
+        RunningScheduler { shutdown, tasks }
+    }
+}
+
+impl RunningScheduler {
+    /// Stop starting new runs, and wait for any that are in progress to finish
+    pub async fn shutdown(mut self) {
+        let _ = self.shutdown.send(true);
+        if !self.tasks.is_empty() {
+            info!("Waiting for scheduled jobs to finish");
+        }
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+async fn run_job(
+    job: Job,
+    leases: Option<JobLeases>,
+    metrics: JobMetrics,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut after = Utc::now();
+
+    loop {
+        let Some(tick) = job.schedule.after(&after).next() else {
+            info!(job = job.name, "Job has no more scheduled runs");
+            return;
+        };
+        let wait = (tick - Utc::now()).to_std().unwrap_or_default();
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.changed() => return,
+        }
+
+        if let Some(leases) = &leases {
+            match leases.claim(job.name, tick).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!(job = job.name, %tick, "Another replica claimed this run");
+                    metrics.update(job.name, |stats| stats.claimed_elsewhere += 1);
+                    after = tick;
+                    continue;
+                }
+                Err(e) => {
+                    // Not knowing whether another replica has the tick, it's
+                    // safer not to run it
+                    error!(job = job.name, %tick, "Failed to claim job run, skipping it: {e}");
+                    metrics.update(job.name, |stats| stats.failures += 1);
+                    after = tick;
+                    continue;
+                }
+            }
+        }
+
+        metrics.update(job.name, |stats| stats.running = true);
+        let started = Instant::now();
+        let result = (job.run)().await;
+        let duration = started.elapsed();
+
+        let now = Utc::now();
+        let skipped = job
+            .schedule
+            .after(&tick)
+            .take_while(|missed| *missed <= now)
+            .count() as u64;
+        if skipped > 0 {
+            warn!(
+                job = job.name,
+                skipped, "Job overran its schedule, skipping missed runs"
+            );
+        }
+
+        metrics.update(job.name, |stats| {
+            stats.running = false;
+            stats.runs += 1;
+            stats.skipped += skipped;
+            stats.last_duration = duration;
+            match &result {
+                Ok(()) => stats.last_success = Some(now),
+                Err(_) => stats.failures += 1,
+            }
+        });
+        match result {
+            Ok(()) => info!(
+                job = job.name,
+                duration_ms = duration.as_millis() as u64,
+                "Job completed"
+            ),
+            Err(e) => error!(job = job.name, "Job failed: {e}"),
+        }
+
+        after = now;
+    }
+}
+
+impl JobMetrics {
+    fn update(&self, job: &'static str, f: impl FnOnce(&mut JobStats)) {
+        let mut jobs = self.0.lock().unwrap();
+        f(jobs.entry(job).or_default());
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, JobStats> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Write the job stats in the Prometheus text format
+    pub(crate) fn write_prometheus(&self, out: &mut String) {
+        let jobs = self.snapshot();
+        if jobs.is_empty() {
+            return;
+        }
+
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_runs_total",
+            "counter",
+            "Number of completed runs",
+            |s| s.runs as f64,
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_failures_total",
+            "counter",
+            "Number of failed runs",
+            |s| s.failures as f64,
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_skipped_total",
+            "counter",
+            "Number of runs skipped because the previous run overran",
+            |s| s.skipped as f64,
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_claimed_elsewhere_total",
+            "counter",
+            "Number of runs claimed by another replica",
+            |s| s.claimed_elsewhere as f64,
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_running",
+            "gauge",
+            "Whether the job is running now",
+            |s| u8::from(s.running) as f64,
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_last_duration_seconds",
+            "gauge",
+            "How long the last run took",
+            |s| s.last_duration.as_secs_f64(),
+        );
+        per_job(
+            out,
+            &jobs,
+            "scheduled_job_last_success_timestamp_seconds",
+            "gauge",
+            "When the job last succeeded, as a Unix timestamp",
+            |s| s.last_success.map_or(0.0, |t| t.timestamp() as f64),
+        );
+    }
+}
+
+fn per_job(
+    out: &mut String,
+    jobs: &BTreeMap<&'static str, JobStats>,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    value: impl Fn(&JobStats) -> f64,
+) {
+    // Writing to a String can't fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+    for (job, stats) in jobs {
+        let _ = writeln!(out, "{name}{{job=\"{job}\"}} {}", value(stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn jobs_run_on_schedule_and_record_metrics() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let metrics = JobMetrics::default();
+
+        let mut scheduler = Scheduler::new();
+        let job_calls = calls.clone();
+        scheduler.add(
+            "tick",
+            Schedule::from_str("* * * * * *").unwrap(),
+            move || {
+                let calls = job_calls.clone();
+                async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err("always fails".into())
+                }
+            },
+        );
+        let running = scheduler.start(metrics.clone());
+
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        running.shutdown().await;
+
+        let stats = &metrics.snapshot()["tick"];
+        assert!(calls.load(Ordering::SeqCst) >= 1);
+        assert_eq!(stats.runs, calls.load(Ordering::SeqCst) as u64);
+        assert_eq!(stats.failures, stats.runs);
+        assert!(stats.last_success.is_none());
+
+        let mut out = String::new();
+        metrics.write_prometheus(&mut out);
+        assert!(out.contains("# TYPE scheduled_job_runs_total counter\n"));
+        assert!(out.contains("scheduled_job_running{job=\"tick\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_a_running_job() {
+        let finished = Arc::new(AtomicU32::new(0));
+        let metrics = JobMetrics::default();
+
+        let mut scheduler = Scheduler::new();
+        let job_finished = finished.clone();
+        scheduler.add(
+            "slow",
+            Schedule::from_str("* * * * * *").unwrap(),
+            move || {
+                let finished = job_finished.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(1500)).await;
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            },
+        );
+        let running = scheduler.start(metrics.clone());
+
+        // Wait until the first run has started
+        while !metrics.snapshot()["slow"].running {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        running.shutdown().await;
+
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        assert_eq!(metrics.snapshot()["slow"].runs, 1);
+    }
+}
@@ -1,9 +1,171 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
+diesel::table! {
+    api_usage (day, route, client) {
+        day -> Date,
+        route -> Varchar,
+        client -> Varchar,
+        requests -> Int8,
+        client_errors -> Int8,
+        server_errors -> Int8,
+        total_latency_ms -> Float8,
+        max_latency_ms -> Float8,
+    }
+}
+
+diesel::table! {
+    book_revisions (id, recorded_at) {
+        id -> Int8,
+        book_id -> Int4,
+        event_type -> Varchar,
+        payload -> Text,
+        recorded_at -> Timestamptz,
+        revision -> Int4,
+        actor -> Nullable<Varchar>,
+        restores_revision -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    book_slugs (slug) {
+        slug -> Varchar,
+        book_id -> Int4,
+    }
+}
+
 diesel::table! {
+    book_translations (book_id, language) {
+        book_id -> Int4,
+        language -> Varchar,
+        name -> Varchar,
+        description -> Nullable<Text>,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    book_views (book_id, hour) {
+        book_id -> Int4,
+        hour -> Timestamptz,
+        views -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     books (id) {
         id -> Int4,
         name -> Varchar,
         author -> Varchar,
+        updated_at -> Timestamptz,
+        isbn -> Nullable<Varchar>,
+        publisher -> Nullable<Varchar>,
+        cover_url -> Nullable<Varchar>,
+        slug -> Varchar,
+        search_vector -> Tsvector,
+    }
+}
+
+diesel::table! {
+    feature_flags (name) {
+        name -> Varchar,
+        enabled -> Bool,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    job_leases (job) {
+        job -> Varchar,
+        tick -> Timestamptz,
+        holder -> Varchar,
+        claimed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Int8,
+        kind -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    metadata_lookups (provider, isbn) {
+        provider -> Varchar,
+        isbn -> Varchar,
+        metadata -> Nullable<Text>,
+        looked_up_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    outbox (id) {
+        id -> Int8,
+        event_type -> Varchar,
+        payload -> Text,
+        created_at -> Timestamptz,
+        published_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int4,
+        event_type -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Int4,
+        last_status_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_attempt_at -> Nullable<Timestamptz>,
     }
 }
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Varchar,
+        secret -> Varchar,
+        events -> Array<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::joinable!(book_translations -> books (book_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_usage,
+    book_revisions,
+    book_slugs,
+    book_translations,
+    book_views,
+    books,
+    feature_flags,
+    job_leases,
+    jobs,
+    metadata_lookups,
+    outbox,
+    webhook_deliveries,
+    webhooks,
+);
@@ -0,0 +1,89 @@
+use crate::models::{fold_text, Book};
+
+/// How many books `GET /books/search` returns when no `limit` is given, and
+/// the most it allows
+pub(crate) const DEFAULT_LIMIT: i64 = 20;
+pub(crate) const MAX_LIMIT: i64 = 100;
+/// The longest search accepted, in characters
+pub(crate) const MAX_QUERY_LENGTH: usize = 200;
+
+/// How much a word matched in the name, or in the author, adds to the rank,
+/// as Postgres weighs the `A` and `B` parts of a `tsvector` by default
+const NAME_WEIGHT: f32 = 1.0;
+const AUTHOR_WEIGHT: f32 = 0.4;
+
+/// A book matching a search, with how well it matches
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub book: Book,
+    /// Higher for better matches, such as those in the name rather than the
+    /// author. Only comparable with other results of the same search.
+    pub rank: f32,
+}
+
+/// The words of the text, lower-cased and without diacritics, as searched
+/// for, e.g. `Brontë` -> `bronte`
+pub(crate) fn words(text: &str) -> Vec<String> {
+    fold_text(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// How well the book matches the words, or `None` if any of them is in
+/// neither its name nor its author. Like the DB, but without stemming or
+/// leaving out common words, so `expectation` doesn't find `Expectations`.
+pub(crate) fn rank(query: &[String], book: &Book) -> Option<f32> {
+    if query.is_empty() {
+        return None;
+    }
+    let name = words(&book.name);
+    let author = words(&book.author);
+    let total = query.iter().try_fold(0.0, |total, word| {
+        if name.contains(word) {
+            Some(total + NAME_WEIGHT)
+        } else if author.contains(word) {
+            Some(total + AUTHOR_WEIGHT)
+        } else {
+            None
+        }
+    })?;
+    Some(total / query.len() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn books_with_every_word_match_best_in_the_name() {
+        let book = Book {
+            id: 1,
+            name: "Great Expectations".to_string(),
+            author: "Charles Dickens".to_string(),
+            updated_at: "2026-10-17T09:00:00Z".parse().unwrap(),
+            isbn: None,
+            publisher: None,
+            cover_url: None,
+            slug: "great-expectations".to_string(),
+        };
+        let rank = |query: &str| rank(&words(query), &book);
+
+        assert_eq!(rank("GREAT expectations!"), Some(1.0));
+        assert_eq!(rank("dickens"), Some(0.4));
+        assert!(rank("great dickens") > rank("charles dickens"));
+        assert_eq!(rank("great gatsby"), None);
+        assert_eq!(rank("  "), None);
+    }
+
+    #[test]
+    fn diacritics_are_ignored() {
+        assert_eq!(words("Emily BRONTË"), ["emily", "bronte"]);
+        assert_eq!(
+            words("Gabriel García Márquez"),
+            words("gabriel garcia marquez")
+        );
+    }
+}
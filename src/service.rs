@@ -0,0 +1,971 @@
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use futures::channel::mpsc;
+use futures::{stream, Stream, StreamExt};
+use tracing::{info, warn};
+
+use crate::enrichment::{Enricher, EnrichmentError};
+use crate::events::{
+    book_as_of, diff, BookChange, BookCreated, BookDeleted, BookEvent, BookUpdated, ChangeFeed,
+    EventBus, FeedEntry, RevisionDiff,
+};
+use crate::filter::Filter;
+use crate::hooks::BookHooks;
+use crate::isbn::{self, IsbnCheck};
+use crate::models::{Actor, Book, BookTranslation, NewBook, NewTranslation};
+use crate::page::{encode_cursor, BookPage};
+use crate::repo::BookRepo;
+use crate::search::SearchResult;
+use crate::sort::SortSpec;
+use crate::stats::{
+    Aggregation, BookField, CatalogStats, FieldCount, Group, Metric, StatsCache, TOP_N,
+};
+use crate::suggest::{normalize_prefix, Suggestion, SuggestionCache};
+use crate::translations::{best_match, normalize_language, LocalizedBook};
+use crate::views::{TrendingBook, ViewCounts};
+
+/// How many books read for `stream_books` can wait for the client before
+/// reading waits for it
+const BUFFERED_BOOKS: usize = 256;
+/// The most books `insert_books` adds at once, which a single insert of all
+/// of them stays well within Postgres' limit of bind parameters for
+pub(crate) const MAX_BULK_BOOKS: usize = 1000;
+
+/// The operations on the catalog, independent of how they are invoked.
+///
+/// Business rules and cross-cutting concerns belong here rather than in the
+/// HTTP handlers, which only map requests and responses, so that the same
+/// logic applies however a book is read or written.
+pub struct BookService<R, E> {
+    repo: R,
+    events: EventBus,
+    metadata: Enricher,
+    duplicates: DuplicatePolicy,
+    hooks: BookHooks,
+    stats: StatsCache,
+    suggestions: SuggestionCache,
+    views: ViewCounts,
+    // The repo's error type, which `BookRepo` is generic over
+    error: PhantomData<fn() -> E>,
+}
+
+/// What happens when a new book looks like one already in the catalog: one
+/// with the same ISBN, or the same name and author
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Add it without checking
+    Allow,
+    /// Add it, logging a warning
+    #[default]
+    Warn,
+    /// Reject it
+    Block,
+}
+
+/// Why a catalog operation failed
+#[derive(Debug)]
+pub enum ServiceError<E> {
+    /// There is no book with the ID
+    NotFound(i32),
+    /// No book has, or had, the slug
+    SlugNotFound(String),
+    /// The book given is not valid
+    Invalid(String),
+    /// The new book looks like the books with these IDs
+    Duplicate(Vec<i32>),
+    /// The book at the index in a bulk insert looks like the earlier one at
+    /// `of`
+    DuplicateInBatch { index: usize, of: usize },
+    /// The book has no ISBN to look up its metadata by
+    NoIsbn(i32),
+    /// Looking up the book's metadata failed
+    Enrichment(EnrichmentError),
+    /// The book has no revision with the number
+    RevisionNotFound { id: i32, revision: i32 },
+    /// The revision deleted the book, so there is no state to restore
+    RevisionIsDeletion { id: i32, revision: i32 },
+    /// The book has no translation into the language
+    TranslationNotFound { id: i32, language: String },
+    /// A hook refused the change, for this reason
+    Rejected(String),
+    /// The repo failed
+    Repo(E),
+}
+
+impl<E: fmt::Display> fmt::Display for ServiceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::NotFound(id) => write!(f, "No book found with ID: {id}"),
+            ServiceError::SlugNotFound(slug) => write!(f, "No book found with slug: {slug}"),
+            ServiceError::Invalid(reason) => write!(f, "{reason}"),
+            ServiceError::Duplicate(ids) => {
+                let ids: Vec<_> = ids.iter().map(i32::to_string).collect();
+                write!(f, "Book looks like a duplicate of: {}", ids.join(", "))
+            }
+            ServiceError::DuplicateInBatch { index, of } => {
+                write!(f, "books[{index}] looks like a duplicate of books[{of}]")
+            }
+            ServiceError::NoIsbn(id) => write!(f, "Book {id} has no ISBN to look up"),
+            ServiceError::Enrichment(e) => write!(f, "{e}"),
+            ServiceError::RevisionNotFound { id, revision } => {
+                write!(f, "Book {id} has no revision {revision}")
+            }
+            ServiceError::RevisionIsDeletion { id, revision } => {
+                write!(
+                    f,
+                    "Revision {revision} of book {id} deleted it, so cannot be restored"
+                )
+            }
+            ServiceError::TranslationNotFound { id, language } => {
+                write!(f, "Book {id} has no translation into {language}")
+            }
+            ServiceError::Rejected(reason) => write!(f, "{reason}"),
+            ServiceError::Repo(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ServiceError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServiceError::NotFound(_)
+            | ServiceError::SlugNotFound(_)
+            | ServiceError::Invalid(_)
+            | ServiceError::Duplicate(_)
+            | ServiceError::DuplicateInBatch { .. }
+            | ServiceError::NoIsbn(_)
+            | ServiceError::RevisionNotFound { .. }
+            | ServiceError::RevisionIsDeletion { .. }
+            | ServiceError::TranslationNotFound { .. }
+            | ServiceError::Rejected(_) => None,
+            ServiceError::Enrichment(e) => Some(e),
+            ServiceError::Repo(e) => Some(e),
+        }
+    }
+}
+
+impl<R: Clone, E> Clone for BookService<R, E> {
+    fn clone(&self) -> Self {
+        BookService {
+            repo: self.repo.clone(),
+            events: self.events.clone(),
+            metadata: self.metadata.clone(),
+            duplicates: self.duplicates,
+            hooks: self.hooks.clone(),
+            stats: self.stats.clone(),
+            suggestions: self.suggestions.clone(),
+            views: self.views.clone(),
+            error: PhantomData,
+        }
+    }
+}
+
+impl<R, E> BookService<R, E> {
+    pub fn new(repo: R) -> Self {
+        BookService {
+            repo,
+            events: EventBus::default(),
+            metadata: Enricher::default(),
+            duplicates: DuplicatePolicy::default(),
+            hooks: BookHooks::default(),
+            stats: StatsCache::default(),
+            suggestions: SuggestionCache::default(),
+            views: ViewCounts::default(),
+            error: PhantomData,
+        }
+    }
+
+    /// Publish an event to `events` after each change to the catalog
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Look up books' metadata with `metadata`'s providers
+    pub fn with_metadata(mut self, metadata: Enricher) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Check new books for duplicates according to `policy`
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Run `hooks` around changes to the catalog
+    pub fn with_hooks(mut self, hooks: BookHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+}
+
+impl<E: Error, R: BookRepo<E>> BookService<R, E> {
+    pub async fn list_books(&self) -> Result<Vec<Book>, ServiceError<E>> {
+        let books = self.repo.list_books().await.map_err(ServiceError::Repo)?;
+        info!("Retrieved {} books from the DB", books.len());
+        Ok(books)
+    }
+
+    pub async fn filter_books(&self, filter: &Filter) -> Result<Vec<Book>, ServiceError<E>> {
+        let books = self
+            .repo
+            .filter_books(filter)
+            .await
+            .map_err(ServiceError::Repo)?;
+        info!(
+            "Retrieved {} books matching {:?} from the DB",
+            books.len(),
+            filter
+        );
+        Ok(books)
+    }
+
+    /// The books `list_books` or, given a filter, `filter_books` would
+    /// return, sorted as given, as they are read from the repo. Failing to
+    /// read the first is an error; failing after that ends the stream with
+    /// the error.
+    pub async fn stream_books(
+        &self,
+        filter: Option<Filter>,
+        sort: SortSpec,
+    ) -> Result<impl Stream<Item = Result<Book, ServiceError<E>>> + Send + 'static, ServiceError<E>>
+    where
+        E: Send + 'static,
+        R: Clone + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(BUFFERED_BOOKS);
+        let repo = self.repo.clone();
+        tokio::spawn(async move { repo.stream_books(filter.as_ref(), &sort, sender).await });
+
+        let mut books = receiver.map(|book| book.map_err(ServiceError::Repo));
+        match books.next().await {
+            Some(Err(e)) => Err(e),
+            first => Ok(stream::iter(first).chain(books)),
+        }
+    }
+
+    pub async fn get_book(&self, id: i32) -> Result<Book, ServiceError<E>> {
+        match self.repo.get_book(id).await.map_err(ServiceError::Repo)? {
+            Some(book) => {
+                info!("Retrieved book from DB: {:?}", book);
+                Ok(book)
+            }
+            None => {
+                info!("No book found in DB with ID: {}", id);
+                Err(ServiceError::NotFound(id))
+            }
+        }
+    }
+
+    /// Up to `limit` books after the one with ID `after_id`, or from the
+    /// first, in order of ID, and only those matching the filter if given
+    pub async fn list_books_after(
+        &self,
+        after_id: Option<i32>,
+        limit: i64,
+        filter: Option<&Filter>,
+    ) -> Result<BookPage, ServiceError<E>> {
+        // One more than asked for, to tell whether there is another page
+        let mut books = self
+            .repo
+            .list_books_after(after_id.unwrap_or(i32::MIN), limit + 1, filter)
+            .await
+            .map_err(ServiceError::Repo)?;
+        let next_cursor = if books.len() as i64 > limit {
+            books.truncate(limit as usize);
+            books.last().map(|book| encode_cursor(book.id))
+        } else {
+            None
+        };
+        info!("Retrieved a page of {} books from the DB", books.len());
+        Ok(BookPage { books, next_cursor })
+    }
+
+    /// The book with the slug, or that had it before it was renamed or
+    /// merged into another. Callers can tell the two apart by its slug.
+    pub async fn get_book_by_slug(&self, slug: &str) -> Result<Book, ServiceError<E>> {
+        match self
+            .repo
+            .get_book_by_slug(slug)
+            .await
+            .map_err(ServiceError::Repo)?
+        {
+            Some(book) => {
+                info!("Retrieved book from DB by slug {}: {:?}", slug, book);
+                Ok(book)
+            }
+            None => {
+                info!("No book found in DB with slug: {}", slug);
+                Err(ServiceError::SlugNotFound(slug.to_string()))
+            }
+        }
+    }
+
+    /// The book in the first of the preferred languages it has a translation
+    /// into, as chosen by `translations::best_match`, or as it is if none
+    pub async fn localize(
+        &self,
+        book: Book,
+        preferred: &[String],
+    ) -> Result<LocalizedBook, ServiceError<E>> {
+        if preferred.is_empty() {
+            return Ok(LocalizedBook::new(book, None));
+        }
+        let translations = self
+            .repo
+            .list_translations(book.id)
+            .await
+            .map_err(ServiceError::Repo)?;
+        Ok(LocalizedBook::new(
+            book,
+            best_match(preferred, translations),
+        ))
+    }
+
+    /// The book's translations, in order of language
+    pub async fn list_translations(
+        &self,
+        id: i32,
+    ) -> Result<Vec<BookTranslation>, ServiceError<E>> {
+        let book = self.get_book(id).await?;
+        self.repo
+            .list_translations(book.id)
+            .await
+            .map_err(ServiceError::Repo)
+    }
+
+    /// Add or replace the book's translation into the language, given as a
+    /// language tag such as `fr` or `de-AT`
+    pub async fn put_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+        translation: NewTranslation,
+    ) -> Result<BookTranslation, ServiceError<E>> {
+        let language = normalize_language(language)
+            .ok_or_else(|| ServiceError::Invalid(format!("Not a language tag: {language}")))?;
+        if translation.name.trim().is_empty() {
+            return Err(ServiceError::Invalid(
+                "A translation needs a name".to_string(),
+            ));
+        }
+        match self
+            .repo
+            .put_translation(id, &language, &translation)
+            .await
+            .map_err(ServiceError::Repo)?
+        {
+            Some(translation) => {
+                info!("Saved translation of book {} into {}", id, language);
+                Ok(translation)
+            }
+            None => {
+                info!("Tried to translate non-existent book with ID: {}", id);
+                Err(ServiceError::NotFound(id))
+            }
+        }
+    }
+
+    pub async fn delete_translation(
+        &mut self,
+        id: i32,
+        language: &str,
+    ) -> Result<(), ServiceError<E>> {
+        let language = normalize_language(language).unwrap_or_else(|| language.to_string());
+        if self
+            .repo
+            .delete_translation(id, &language)
+            .await
+            .map_err(ServiceError::Repo)?
+        {
+            info!("Deleted translation of book {} into {}", id, language);
+            Ok(())
+        } else {
+            Err(ServiceError::TranslationNotFound { id, language })
+        }
+    }
+
+    /// The book as it was at a time in the past, derived from its history
+    pub async fn get_book_as_of(
+        &self,
+        id: i32,
+        at: DateTime<Utc>,
+    ) -> Result<Book, ServiceError<E>> {
+        let history = self.book_history(id).await?;
+        book_as_of(&history, at).ok_or(ServiceError::NotFound(id))
+    }
+
+    /// Every revision of the book, including its deletion, oldest first
+    pub async fn book_history(&self, id: i32) -> Result<Vec<BookChange>, ServiceError<E>> {
+        let history = self
+            .repo
+            .book_history(id)
+            .await
+            .map_err(ServiceError::Repo)?;
+        if history.is_empty() {
+            info!("No history found in DB for book with ID: {}", id);
+            return Err(ServiceError::NotFound(id));
+        }
+        Ok(history)
+    }
+
+    /// Up to `limit` changes to the catalog after the one at `since`, or
+    /// from the beginning if `since` is `None`
+    pub async fn changes(
+        &self,
+        since: Option<i64>,
+        limit: i64,
+    ) -> Result<ChangeFeed, ServiceError<E>> {
+        let since = since.unwrap_or(0);
+        let changes = self
+            .repo
+            .changes(since, limit)
+            .await
+            .map_err(ServiceError::Repo)?;
+        let next_cursor = changes.last().map_or(since, |(position, _)| *position);
+        Ok(ChangeFeed {
+            changes: changes
+                .into_iter()
+                .map(|(position, change)| FeedEntry {
+                    cursor: position.to_string(),
+                    change,
+                })
+                .collect(),
+            next_cursor: next_cursor.to_string(),
+        })
+    }
+
+    /// The fields that differ between two revisions of the book
+    pub async fn diff_revisions(
+        &self,
+        id: i32,
+        from: i32,
+        to: i32,
+    ) -> Result<RevisionDiff, ServiceError<E>> {
+        let history = self.book_history(id).await?;
+        let revision = |revision| {
+            history
+                .iter()
+                .find(|change| change.revision == revision)
+                .ok_or(ServiceError::RevisionNotFound { id, revision })
+        };
+        Ok(diff(revision(from)?, revision(to)?))
+    }
+
+    /// An overview of the catalog. The queries behind it read every book, so
+    /// it is reused, by clones too, until it is `CACHE_TTL` old.
+    pub async fn catalog_stats(&self) -> Result<CatalogStats, ServiceError<E>> {
+        if let Some(stats) = self.stats.get() {
+            return Ok(stats);
+        }
+        // In separate statements, so that no repo error, which needn't be
+        // `Send`, is held across the next query
+        let total_books = self.repo.count_books().await.map_err(ServiceError::Repo)?;
+        let top_authors = self
+            .repo
+            .aggregate_books(BookField::Author, Metric::Count, TOP_N)
+            .await
+            .map_err(ServiceError::Repo)?;
+        let top_publishers = self
+            .repo
+            .aggregate_books(BookField::Publisher, Metric::Count, TOP_N)
+            .await
+            .map_err(ServiceError::Repo)?;
+        let counts = |groups: Vec<Group>| {
+            groups
+                .into_iter()
+                .map(|Group { value, result }| FieldCount {
+                    value,
+                    books: result,
+                })
+                .collect()
+        };
+        let stats = CatalogStats {
+            total_books,
+            top_authors: counts(top_authors),
+            top_publishers: counts(top_publishers),
+            computed_at: Utc::now(),
+        };
+        self.stats.set(stats.clone());
+        Ok(stats)
+    }
+
+    /// The books grouped by `group_by`, with `metric` for each of the
+    /// `limit` largest groups
+    pub async fn aggregate_books(
+        &self,
+        group_by: BookField,
+        metric: Metric,
+        limit: i64,
+    ) -> Result<Aggregation, ServiceError<E>> {
+        let groups = self
+            .repo
+            .aggregate_books(group_by, metric, limit)
+            .await
+            .map_err(ServiceError::Repo)?;
+        Ok(Aggregation {
+            group_by,
+            metric,
+            groups,
+        })
+    }
+
+    /// Count a view of the book. Views are only recorded, and so only
+    /// count towards trending books, once they are flushed.
+    pub fn record_view(&self, id: i32) {
+        self.views.add(id, 1);
+    }
+
+    /// Record the views counted, by clones too, since the last flush, as
+    /// views in the current hour. Returns how many books were viewed. If
+    /// recording fails, the views are kept to be recorded by the next flush.
+    pub async fn flush_views(&mut self) -> Result<usize, ServiceError<E>> {
+        let views: Vec<_> = self.views.take().into_iter().collect();
+        if views.is_empty() {
+            return Ok(0);
+        }
+        let hour = Utc::now()
+            .duration_trunc(TimeDelta::hours(1))
+            .expect("An hour divides any time");
+        if let Err(e) = self.repo.record_views(&views, hour).await {
+            for (id, count) in views {
+                self.views.add(id, count);
+            }
+            return Err(ServiceError::Repo(e));
+        }
+        Ok(views.len())
+    }
+
+    /// The `limit` books viewed most in the `window` up to now, most first
+    /// Up to `limit` names and authors starting with what was typed, most
+    /// likely first. Empty if too little was typed. The suggestions for each
+    /// prefix are cached, as each key pressed in a search box asks for them.
+    pub async fn suggest(
+        &self,
+        typed: &str,
+        limit: i64,
+    ) -> Result<Vec<Suggestion>, ServiceError<E>> {
+        let Some(prefix) = normalize_prefix(typed) else {
+            return Ok(Vec::new());
+        };
+        if let Some(suggestions) = self.suggestions.get(&prefix, limit) {
+            return Ok(suggestions);
+        }
+        let suggestions = self
+            .repo
+            .suggest(&prefix, limit)
+            .await
+            .map_err(ServiceError::Repo)?;
+        self.suggestions.set(&prefix, limit, suggestions.clone());
+        Ok(suggestions)
+    }
+
+    /// Up to `limit` books with every word searched for in their name or
+    /// author, best matches first
+    pub async fn search_books(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, ServiceError<E>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let results = self
+            .repo
+            .search_books(query, limit)
+            .await
+            .map_err(ServiceError::Repo)?;
+        info!("Found {} books searching for {:?}", results.len(), query);
+        Ok(results)
+    }
+
+    /// A book picked at random, or `None` if there are none
+    pub async fn random_book(&self) -> Result<Option<Book>, ServiceError<E>> {
+        let pick = rand::random::<f64>();
+        self.repo
+            .random_book(pick)
+            .await
+            .map_err(ServiceError::Repo)
+    }
+
+    /// Check the ISBN, and look for books with it in either form. An invalid
+    /// ISBN isn't an error, but is explained in the result.
+    pub async fn check_isbn(&self, input: &str) -> Result<IsbnCheck, ServiceError<E>> {
+        let isbn = match isbn::parse(input) {
+            Ok(isbn) => isbn,
+            Err(problem) => {
+                return Ok(IsbnCheck {
+                    valid: false,
+                    isbn10: None,
+                    isbn13: None,
+                    problem: Some(problem.to_string()),
+                    books: Vec::new(),
+                })
+            }
+        };
+        let isbns: Vec<String> = isbn.isbn10.iter().chain([&isbn.isbn13]).cloned().collect();
+        let books = self
+            .repo
+            .find_books_by_isbn(&isbns)
+            .await
+            .map_err(ServiceError::Repo)?;
+        Ok(IsbnCheck {
+            valid: true,
+            isbn10: isbn.isbn10,
+            isbn13: Some(isbn.isbn13),
+            problem: None,
+            books,
+        })
+    }
+
+    pub async fn trending_books(
+        &self,
+        window: Duration,
+        limit: i64,
+    ) -> Result<Vec<TrendingBook>, ServiceError<E>> {
+        let window = TimeDelta::from_std(window).expect("The window is at most the retention");
+        // Including the whole of the hour the window starts in
+        let since = (Utc::now() - window)
+            .duration_trunc(TimeDelta::hours(1))
+            .expect("An hour divides any time");
+        self.repo
+            .trending_books(since, limit)
+            .await
+            .map_err(ServiceError::Repo)
+    }
+
+    pub async fn insert_book(
+        &mut self,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<Book, ServiceError<E>> {
+        let new_book = self.check_new_book(new_book, actor).await?;
+        let book = self
+            .repo
+            .insert_book(new_book, actor)
+            .await
+            .map_err(ServiceError::Repo)?;
+        info!("Inserted book into the DB: {:?}", book);
+        self.events
+            .publish(BookEvent::BookCreated(BookCreated { book: book.clone() }));
+        Ok(book)
+    }
+
+    /// Add the books as `insert_book` would one at a time, but all at once,
+    /// or none of them if any can't be added. Errors about a book say where
+    /// it is in the list, e.g. `books[3]`.
+    pub async fn insert_books(
+        &mut self,
+        new_books: Vec<NewBook>,
+        actor: &Actor,
+    ) -> Result<Vec<Book>, ServiceError<E>> {
+        if new_books.len() > MAX_BULK_BOOKS {
+            return Err(ServiceError::Invalid(format!(
+                "At most {MAX_BULK_BOOKS} books can be added at once"
+            )));
+        }
+        let mut checked = Vec::with_capacity(new_books.len());
+        for (index, new_book) in new_books.into_iter().enumerate() {
+            let new_book = self
+                .prepare_new_book(new_book, actor)
+                .map_err(|e| match e {
+                    ServiceError::Invalid(reason) => {
+                        ServiceError::Invalid(format!("books[{index}]: {reason}"))
+                    }
+                    ServiceError::Rejected(reason) => {
+                        ServiceError::Rejected(format!("books[{index}]: {reason}"))
+                    }
+                    e => e,
+                })?;
+            checked.push(new_book);
+        }
+        if self.duplicates != DuplicatePolicy::Allow {
+            // One query for the whole batch, rather than one per book
+            let existing = self
+                .repo
+                .find_duplicates_of_any(&checked)
+                .await
+                .map_err(ServiceError::Repo)?;
+            for (index, new_book) in checked.iter().enumerate() {
+                let ids = existing
+                    .iter()
+                    .filter(|book| new_book.looks_like(&NewBook::from(*book)))
+                    .map(|book| book.id)
+                    .collect();
+                self.check_duplicates(new_book, ids)?;
+                // Those earlier in the batch aren't in the repo yet, so
+                // aren't found by the query
+                if let Some(of) = checked[..index]
+                    .iter()
+                    .position(|earlier| new_book.looks_like(earlier))
+                {
+                    if self.duplicates == DuplicatePolicy::Block {
+                        info!("Rejected books[{index}] as a probable duplicate of books[{of}]");
+                        return Err(ServiceError::DuplicateInBatch { index, of });
+                    }
+                    warn!("Adding books[{index}], a probable duplicate of books[{of}]");
+                }
+            }
+        }
+        let books = self
+            .repo
+            .insert_books(checked, actor)
+            .await
+            .map_err(ServiceError::Repo)?;
+        info!("Inserted {} books into the DB", books.len());
+        for book in &books {
+            self.events
+                .publish(BookEvent::BookCreated(BookCreated { book: book.clone() }));
+        }
+        Ok(books)
+    }
+
+    /// The new book, validated and as changed by the hooks, if it can be
+    /// added under the duplicate policy
+    async fn check_new_book(
+        &self,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<NewBook, ServiceError<E>> {
+        let new_book = self.prepare_new_book(new_book, actor)?;
+        if self.duplicates != DuplicatePolicy::Allow {
+            let duplicates = self
+                .repo
+                .find_duplicates(&new_book)
+                .await
+                .map_err(ServiceError::Repo)?;
+            let ids = duplicates.iter().map(|book| book.id).collect();
+            self.check_duplicates(&new_book, ids)?;
+        }
+        Ok(new_book)
+    }
+
+    /// The new book, validated and as changed by the hooks
+    fn prepare_new_book(
+        &self,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<NewBook, ServiceError<E>> {
+        let mut new_book = validate(new_book)?;
+        self.hooks
+            .run_before_insert(&mut new_book, actor)
+            .map_err(ServiceError::Rejected)?;
+        Ok(new_book)
+    }
+
+    /// Apply the duplicate policy to a new book that looks like the books
+    /// with these IDs
+    fn check_duplicates(&self, new_book: &NewBook, ids: Vec<i32>) -> Result<(), ServiceError<E>> {
+        if !ids.is_empty() {
+            if self.duplicates == DuplicatePolicy::Block {
+                info!(
+                    "Rejected probable duplicate of books {:?}: {:?}",
+                    ids, new_book
+                );
+                return Err(ServiceError::Duplicate(ids));
+            }
+            warn!(
+                "Adding probable duplicate of books {:?}: {:?}",
+                ids, new_book
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn update_book(
+        &mut self,
+        id: i32,
+        new_book: NewBook,
+        actor: &Actor,
+    ) -> Result<Book, ServiceError<E>> {
+        let new_book = validate(new_book)?;
+        match self
+            .repo
+            .update_book(id, new_book, actor)
+            .await
+            .map_err(ServiceError::Repo)?
+        {
+            Some(book) => {
+                info!("Updated book in DB: {:?}", book);
+                self.hooks.run_after_update(&book, actor);
+                self.events
+                    .publish(BookEvent::BookUpdated(BookUpdated { book: book.clone() }));
+                Ok(book)
+            }
+            None => {
+                info!("Tried to update non-existent book with ID: {}", id);
+                Err(ServiceError::NotFound(id))
+            }
+        }
+    }
+
+    pub async fn delete_book(&mut self, id: i32, actor: &Actor) -> Result<(), ServiceError<E>> {
+        if self.hooks.checks_deletes() {
+            let book = self.get_book(id).await?;
+            self.hooks
+                .run_on_delete(&book, actor)
+                .map_err(ServiceError::Rejected)?;
+        }
+        if self
+            .repo
+            .delete_book(id, actor)
+            .await
+            .map_err(ServiceError::Repo)?
+        {
+            info!("Deleted book from DB with ID: {}", id);
+            self.events
+                .publish(BookEvent::BookDeleted(BookDeleted { id }));
+            Ok(())
+        } else {
+            info!("Tried to delete non-existent book with ID: {}", id);
+            Err(ServiceError::NotFound(id))
+        }
+    }
+
+    /// Put the book back to how it was at an earlier revision, recording
+    /// that as a new revision rather than discarding the ones since. A
+    /// book that has been deleted since is created again.
+    pub async fn revert_book(
+        &mut self,
+        id: i32,
+        revision: i32,
+        actor: &Actor,
+    ) -> Result<Book, ServiceError<E>> {
+        let history = self.book_history(id).await?;
+        let change = history
+            .iter()
+            .find(|change| change.revision == revision)
+            .ok_or(ServiceError::RevisionNotFound { id, revision })?;
+        let book = match change.event.book() {
+            Some(book) => NewBook::from(book),
+            None => return Err(ServiceError::RevisionIsDeletion { id, revision }),
+        };
+
+        let event = self
+            .repo
+            .restore_book(id, book, revision, actor)
+            .await
+            .map_err(ServiceError::Repo)?;
+        let restored = event
+            .book()
+            .cloned()
+            .expect("restoring a book never deletes it");
+        info!(
+            actor = ?actor.0,
+            "Reverted book with ID {} to revision {}: {:?}", id, revision, restored
+        );
+        if let BookEvent::BookUpdated(_) = event {
+            self.hooks.run_after_update(&restored, actor);
+        }
+        self.events.publish(event);
+        Ok(restored)
+    }
+
+    /// Delete the book `duplicate`, first filling in any of the book `keep`'s
+    /// missing fields from it. Returns the kept book.
+    pub async fn merge_books(
+        &mut self,
+        keep: i32,
+        duplicate: i32,
+        actor: &Actor,
+    ) -> Result<Book, ServiceError<E>> {
+        if keep == duplicate {
+            return Err(ServiceError::Invalid(
+                "A book can't be merged into itself".to_string(),
+            ));
+        }
+        let kept = self.get_book(keep).await?;
+        let duplicated = self.get_book(duplicate).await?;
+        self.hooks
+            .run_on_delete(&duplicated, actor)
+            .map_err(ServiceError::Rejected)?;
+        let merged = merge(&kept, &duplicated);
+
+        let Some(events) = self
+            .repo
+            .merge_books(keep, duplicate, merged, actor)
+            .await
+            .map_err(ServiceError::Repo)?
+        else {
+            // One of them was deleted in the meantime
+            self.get_book(keep).await?;
+            return Err(ServiceError::NotFound(duplicate));
+        };
+        let updated = events.iter().find_map(BookEvent::book).cloned();
+        if let Some(book) = &updated {
+            self.hooks.run_after_update(book, actor);
+        }
+        let kept = updated.unwrap_or(kept);
+        info!(
+            actor = ?actor.0,
+            "Merged book with ID {} into book with ID {}", duplicate, keep
+        );
+        for event in events {
+            self.events.publish(event);
+        }
+        Ok(kept)
+    }
+
+    /// Fill in the book's missing fields from what is known about its ISBN,
+    /// as a new revision. The book is returned unchanged if nothing is known
+    /// or there is nothing to fill in.
+    pub async fn enrich_book(&mut self, id: i32, actor: &Actor) -> Result<Book, ServiceError<E>> {
+        let book = self.get_book(id).await?;
+        let Some(isbn) = &book.isbn else {
+            return Err(ServiceError::NoIsbn(id));
+        };
+        let metadata = self
+            .metadata
+            .lookup(isbn)
+            .await
+            .map_err(ServiceError::Enrichment)?;
+
+        match metadata.and_then(|metadata| metadata.fill_in(&book)) {
+            Some(filled) => {
+                info!("Enriching book with ID {} from ISBN {}", id, isbn);
+                self.update_book(id, filled, actor).await
+            }
+            None => Ok(book),
+        }
+    }
+}
+
+/// The book `keep` with its missing fields filled in from `duplicate`, or
+/// `None` if it has none that `duplicate` has
+fn merge(keep: &Book, duplicate: &Book) -> Option<NewBook> {
+    let mut merged = NewBook::from(keep);
+    let duplicate = NewBook::from(duplicate);
+    if merged.name.is_empty() {
+        merged.name = duplicate.name;
+    }
+    if merged.author.is_empty() {
+        merged.author = duplicate.author;
+    }
+    merged.isbn = merged.isbn.or(duplicate.isbn);
+    merged.publisher = merged.publisher.or(duplicate.publisher);
+    merged.cover_url = merged.cover_url.or(duplicate.cover_url);
+    (merged != NewBook::from(keep)).then_some(merged)
+}
+
+/// Normalize the book's ISBN, and check it has a name and author, or an
+/// ISBN to look them up by
+fn validate<E>(mut new_book: NewBook) -> Result<NewBook, ServiceError<E>> {
+    if let Some(given) = new_book.isbn.take() {
+        let normalized = isbn::normalize(&given)
+            .ok_or_else(|| ServiceError::Invalid(format!("Not a valid ISBN: {given}")))?;
+        new_book.isbn = Some(normalized);
+    }
+    if (new_book.name.is_empty() || new_book.author.is_empty()) && new_book.isbn.is_none() {
+        return Err(ServiceError::Invalid(
+            "A book needs a name and an author, unless it has an ISBN to look them up by"
+                .to_string(),
+        ));
+    }
+    Ok(new_book)
+}
@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+/// Requests and DB queries that take longer than these thresholds are logged
+/// as warnings, along with enough context to investigate them
+#[derive(Debug, Clone, Copy)]
+pub struct SlowLogThresholds {
+    pub request: Duration,
+    pub query: Duration,
+}
+
+impl Default for SlowLogThresholds {
+    fn default() -> Self {
+        SlowLogThresholds {
+            request: Duration::from_millis(500),
+            query: Duration::from_millis(200),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+/// The most characters of a name that go into its slug, before any suffix
+const MAX_LENGTH: usize = 80;
+
+/// The slug of a book with no name yet, e.g. one waiting to be enriched
+const UNNAMED: &str = "book";
+
+/// The URL-friendly form of a book's name, e.g. `never-let-me-go` for "Never
+/// Let Me Go!": lower-cased, with common accented letters replaced by plain
+/// ones, and each run of anything but ASCII letters and digits replaced by a
+/// hyphen. Matches the `slugify` function the migration backfilled slugs with.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        match fold(c) {
+            Some(folded) => slug.push_str(folded),
+            None if c.is_ascii_alphanumeric() => slug.push(c),
+            None if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+            None => {}
+        }
+    }
+    // Only ASCII is left, so any index is a character boundary
+    slug.truncate(MAX_LENGTH);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        UNNAMED.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// The plain letters for an accented lower-case letter
+fn fold(c: char) -> Option<&'static str> {
+    let folded = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => "a",
+        'æ' => "ae",
+        'ç' => "c",
+        'è' | 'é' | 'ê' | 'ë' => "e",
+        'ì' | 'í' | 'î' | 'ï' => "i",
+        'ñ' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => "o",
+        'œ' => "oe",
+        'ß' => "ss",
+        'ù' | 'ú' | 'û' | 'ü' => "u",
+        'ý' | 'ÿ' => "y",
+        _ => return None,
+    };
+    Some(folded)
+}
+
+/// Whether `slug` is `base`, or `base` with a suffix added to tell it apart
+/// from another book's, e.g. `dune-2`
+pub fn has_base(slug: &str, base: &str) -> bool {
+    match slug.strip_prefix(base) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix('-')
+            .and_then(|n| n.parse::<u32>().ok())
+            .is_some_and(|n| n >= 2),
+        None => false,
+    }
+}
+
+/// The first of `base`, `base-2`, `base-3` and so on that isn't `taken`
+pub fn first_free(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{base}-{n}"))
+        .find(|slug| !taken(slug))
+        .expect("There are fewer books than suffixes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_made_url_friendly() {
+        assert_eq!(slugify("Never Let Me Go"), "never-let-me-go");
+        assert_eq!(slugify("  Dune: Messiah!! "), "dune-messiah");
+        assert_eq!(slugify("Cien años de soledad"), "cien-anos-de-soledad");
+        assert_eq!(slugify("Die Straße"), "die-strasse");
+        assert_eq!(slugify("1984"), "1984");
+        assert_eq!(slugify("???"), "book");
+        assert_eq!(slugify(""), "book");
+        assert_eq!(slugify(&"a ".repeat(100)).len(), MAX_LENGTH - 1);
+    }
+
+    #[test]
+    fn colliding_slugs_get_the_first_free_suffix() {
+        let taken = ["dune", "dune-2", "dune-4"];
+        assert_eq!(first_free("dune", |slug| taken.contains(&slug)), "dune-3");
+        assert_eq!(first_free("emma", |slug| taken.contains(&slug)), "emma");
+
+        assert!(has_base("dune", "dune"));
+        assert!(has_base("dune-3", "dune"));
+        assert!(!has_base("dune-messiah", "dune"));
+        assert!(!has_base("dune-1", "dune"));
+        assert!(!has_base("dun", "dune"));
+    }
+}
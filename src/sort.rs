@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::filter::FilterField;
+use crate::models::{fold_text, Book};
+
+/// The languages whose rules text can be sorted by. Each has an ICU
+/// collation in Postgres, e.g. `de-x-icu`.
+const COLLATIONS: [&str; 14] = [
+    "da", "de", "en", "es", "fi", "fr", "is", "it", "nb", "nl", "pl", "pt", "sv", "tr",
+];
+
+/// One of the fields books are sorted by, and which way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: FilterField,
+    pub descending: bool,
+}
+
+/// How to sort books, parsed from e.g. `name,-author,id`: by each field in
+/// turn, ascending, or descending if it starts with `-`. Books that tie on
+/// every field are in order of ID, so the order is always the same.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortSpec {
+    keys: Vec<SortKey>,
+    collation: Option<Collation>,
+}
+
+/// The rules of a language for sorting text, e.g. `de` sorts `Ä` with `A`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collation(&'static str);
+
+impl Collation {
+    /// One of the languages in `COLLATIONS`, ignoring case
+    pub fn parse(language: &str) -> Result<Collation, SortError> {
+        let language = language.trim().to_ascii_lowercase();
+        COLLATIONS
+            .into_iter()
+            .find(|known| *known == language)
+            .map(Collation)
+            .ok_or_else(|| {
+                SortError(format!(
+                    "unknown collation {language}, expected one of: {}",
+                    COLLATIONS.join(", ")
+                ))
+            })
+    }
+
+    /// The language, e.g. `de`
+    pub fn language(self) -> &'static str {
+        self.0
+    }
+
+    /// The name of the collation in Postgres, e.g. `de-x-icu`
+    pub(crate) fn pg_name(self) -> String {
+        format!("{}-x-icu", self.0)
+    }
+}
+
+/// What is wrong with a sort
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortError(String);
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid sort: {}", self.0)
+    }
+}
+
+impl std::error::Error for SortError {}
+
+impl SortSpec {
+    /// Parse a comma-separated list of the fields in `FilterField`, each
+    /// given at most once
+    pub fn parse(input: &str) -> Result<SortSpec, SortError> {
+        let mut keys: Vec<SortKey> = Vec::new();
+        for name in input.split(',').map(str::trim) {
+            let (name, descending) = match name.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            if name.is_empty() {
+                return Err(SortError("expected a field".to_string()));
+            }
+            let field = FilterField::from_name(name).ok_or_else(|| {
+                let fields: Vec<_> = FilterField::ALL.iter().map(|f| f.name()).collect();
+                SortError(format!(
+                    "unknown field {name}, expected one of: {}",
+                    fields.join(", ")
+                ))
+            })?;
+            if keys.iter().any(|key| key.field == field) {
+                return Err(SortError(format!("{name} is given more than once")));
+            }
+            keys.push(SortKey { field, descending });
+        }
+        Ok(SortSpec {
+            keys,
+            collation: None,
+        })
+    }
+
+    /// Sort the name, author and publisher by the rules of the language,
+    /// rather than the DB's default collation
+    pub fn with_collation(self, collation: Collation) -> SortSpec {
+        SortSpec {
+            collation: Some(collation),
+            ..self
+        }
+    }
+
+    /// The fields to sort by, most significant first, not including the ID
+    /// that ties are broken by unless it was given
+    pub fn keys(&self) -> &[SortKey] {
+        &self.keys
+    }
+
+    pub fn collation(&self) -> Option<Collation> {
+        self.collation
+    }
+
+    /// Whether the ID is one of the keys, so ties needn't be broken by it
+    pub(crate) fn has_id(&self) -> bool {
+        self.keys.iter().any(|key| key.field == FilterField::Id)
+    }
+
+    /// The order of two books, as in the DB: a book without an ISBN or
+    /// publisher comes after those with one, or before when descending. Text
+    /// is compared as it is, where the DB may use its collation. With a
+    /// collation, the name, author and publisher are compared ignoring case
+    /// and diacritics first, which is close to, but simpler than, the rules
+    /// of any language.
+    pub fn compare(&self, a: &Book, b: &Book) -> Ordering {
+        let text = |a: &str, b: &str| match self.collation {
+            Some(_) => fold_text(a).cmp(&fold_text(b)).then(a.cmp(b)),
+            None => a.cmp(b),
+        };
+        self.keys
+            .iter()
+            .map(|key| {
+                let ordering = match key.field {
+                    FilterField::Id => a.id.cmp(&b.id),
+                    FilterField::Name => text(&a.name, &b.name),
+                    FilterField::Author => text(&a.author, &b.author),
+                    FilterField::Slug => a.slug.cmp(&b.slug),
+                    FilterField::Isbn => nulls_last(&a.isbn, &b.isbn),
+                    FilterField::Publisher => match (&a.publisher, &b.publisher) {
+                        (Some(a), Some(b)) => text(a, b),
+                        (a, b) => nulls_last(a, b),
+                    },
+                    FilterField::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+                };
+                if key.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.id.cmp(&b.id))
+    }
+}
+
+fn nulls_last(a: &Option<String>, b: &Option<String>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_are_parsed_into_known_fields() {
+        assert_eq!(
+            SortSpec::parse("name, -author,id"),
+            Ok(SortSpec {
+                keys: vec![
+                    SortKey {
+                        field: FilterField::Name,
+                        descending: false
+                    },
+                    SortKey {
+                        field: FilterField::Author,
+                        descending: true
+                    },
+                    SortKey {
+                        field: FilterField::Id,
+                        descending: false
+                    },
+                ],
+                collation: None,
+            })
+        );
+
+        for invalid in ["", "name,", "-", "publication_year", "name,-name", "+name"] {
+            assert!(SortSpec::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn books_are_sorted_as_in_the_db() {
+        let book = |id: i32, author: &str, publisher: Option<&str>| Book {
+            id,
+            name: format!("Book {id}"),
+            author: author.to_string(),
+            updated_at: "2026-10-17T09:00:00Z".parse().unwrap(),
+            isbn: None,
+            publisher: publisher.map(str::to_string),
+            cover_url: None,
+            slug: format!("book-{id}"),
+        };
+        let mut books = vec![
+            book(1, "Austen", None),
+            book(2, "Ishiguro", Some("Faber")),
+            book(3, "Austen", Some("Penguin")),
+            book(4, "Dickens", Some("Faber")),
+        ];
+        let sorted = |books: &mut Vec<Book>, sort: &str| {
+            let sort = SortSpec::parse(sort).unwrap();
+            books.sort_by(|a, b| sort.compare(a, b));
+            books.iter().map(|book| book.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(&mut books, "author"), [1, 3, 4, 2]);
+        assert_eq!(sorted(&mut books, "author,-id"), [3, 1, 4, 2]);
+        assert_eq!(sorted(&mut books, "publisher"), [2, 4, 3, 1]);
+        assert_eq!(sorted(&mut books, "-publisher,-author"), [1, 3, 2, 4]);
+        assert_eq!(sorted(&mut books, "updated_at"), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn a_collation_sorts_text_ignoring_case_and_diacritics() {
+        let book = |id: i32, name: &str| Book {
+            id,
+            name: name.to_string(),
+            author: "Anon".to_string(),
+            updated_at: "2026-10-17T09:00:00Z".parse().unwrap(),
+            isbn: None,
+            publisher: None,
+            cover_url: None,
+            slug: format!("book-{id}"),
+        };
+        let mut books = vec![book(1, "Zorn"), book(2, "Ärger"), book(3, "apfel")];
+        let sorted = |books: &mut Vec<Book>, sort: SortSpec| {
+            books.sort_by(|a, b| sort.compare(a, b));
+            books.iter().map(|book| book.id).collect::<Vec<_>>()
+        };
+        let by_name = SortSpec::parse("name").unwrap();
+
+        assert_eq!(sorted(&mut books, by_name.clone()), [1, 3, 2]);
+        let german = by_name.with_collation(Collation::parse("DE").unwrap());
+        assert_eq!(german.collation().map(Collation::language), Some("de"));
+        assert_eq!(sorted(&mut books, german), [3, 2, 1]);
+
+        assert!(Collation::parse("klingon").is_err());
+        assert!(Collation::parse("de-x-icu").is_err());
+    }
+}
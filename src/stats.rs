@@ -0,0 +1,199 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// How many authors and publishers the stats list
+pub(crate) const TOP_N: i64 = 10;
+/// How long stats are reused for before they are computed again
+pub(crate) const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// A field books can be grouped by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookField {
+    Author,
+    Publisher,
+}
+
+/// What is computed for each group of books
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// How many books there are
+    #[default]
+    Count,
+    /// How many books have an ISBN
+    WithIsbn,
+    /// How many different authors there are
+    DistinctAuthors,
+    /// How many different publishers there are, not counting books without
+    /// one
+    DistinctPublishers,
+}
+
+/// A metric computed for the books with one value of a field, `null` for
+/// books without one
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, diesel::QueryableByName,
+)]
+pub struct Group {
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    pub value: Option<String>,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub result: i64,
+}
+
+/// The books grouped by a field, with a metric for each group, largest
+/// first
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Aggregation {
+    pub group_by: BookField,
+    pub metric: Metric,
+    pub groups: Vec<Group>,
+}
+
+/// How many books have a value of a field. The value is `null` for books
+/// without one.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FieldCount {
+    pub value: Option<String>,
+    pub books: i64,
+}
+
+/// An overview of the catalog, for the admin dashboard
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CatalogStats {
+    pub total_books: i64,
+    /// The authors with the most books, most first
+    pub top_authors: Vec<FieldCount>,
+    pub top_publishers: Vec<FieldCount>,
+    /// When the stats were computed, as they are cached for `CACHE_TTL`
+    pub computed_at: DateTime<Utc>,
+}
+
+/// The last stats computed, shared by clones
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StatsCache(Arc<Mutex<Option<CatalogStats>>>);
+
+impl StatsCache {
+    /// The cached stats, unless they are older than `CACHE_TTL`
+    pub fn get(&self) -> Option<CatalogStats> {
+        let cached = self.lock().clone()?;
+        let age = (Utc::now() - cached.computed_at)
+            .to_std()
+            .unwrap_or_default();
+        (age < CACHE_TTL).then_some(cached)
+    }
+
+    pub fn set(&self, stats: CatalogStats) {
+        *self.lock() = Some(stats);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<CatalogStats>> {
+        self.0.lock().expect("No thread panics holding the lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryBookRepo;
+    use crate::models::{Actor, NewBook};
+    use crate::service::BookService;
+    use crate::test_support::spawn_test_app;
+
+    #[tokio::test]
+    async fn stats_count_books_by_author_and_are_cached() {
+        let mut books = BookService::new(InMemoryBookRepo::new());
+        let actor = Actor(None);
+        for (name, author, publisher) in [
+            ("Emma", "Jane Austen", Some("Penguin")),
+            ("Persuasion", "Jane Austen", None),
+            ("Middlemarch", "George Eliot", Some("Penguin")),
+        ] {
+            let book = NewBook {
+                name: name.to_string(),
+                author: author.to_string(),
+                publisher: publisher.map(str::to_string),
+                ..NewBook::default()
+            };
+            books.insert_book(book, &actor).await.unwrap();
+        }
+        let count = |value: Option<&str>, books| FieldCount {
+            value: value.map(str::to_string),
+            books,
+        };
+
+        let stats = books.catalog_stats().await.unwrap();
+        assert_eq!(stats.total_books, 3);
+        assert_eq!(
+            stats.top_authors,
+            vec![
+                count(Some("Jane Austen"), 2),
+                count(Some("George Eliot"), 1)
+            ]
+        );
+        assert_eq!(
+            stats.top_publishers,
+            vec![count(Some("Penguin"), 2), count(None, 1)]
+        );
+
+        books.delete_book(1, &actor).await.unwrap();
+        assert_eq!(books.catalog_stats().await.unwrap(), stats);
+    }
+
+    #[tokio::test]
+    async fn books_are_aggregated_by_whitelisted_fields_and_metrics() {
+        let app = spawn_test_app().await;
+        let client = reqwest::Client::new();
+        for (name, author, publisher) in [
+            ("Emma", "Jane Austen", "Penguin"),
+            ("Middlemarch", "George Eliot", "Penguin"),
+            ("Persuasion", "Jane Austen", "Vintage"),
+        ] {
+            client
+                .post(app.url("/v1/books"))
+                .json(&serde_json::json!({"name": name, "author": author, "publisher": publisher}))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let aggregation: Aggregation = client
+            .get(app.url("/v1/books/aggregate?group_by=publisher&metric=distinct_authors"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(aggregation.metric, Metric::DistinctAuthors);
+        assert_eq!(
+            aggregation.groups,
+            vec![
+                Group {
+                    value: Some("Penguin".to_string()),
+                    result: 2
+                },
+                Group {
+                    value: Some("Vintage".to_string()),
+                    result: 1
+                },
+            ]
+        );
+
+        for query in [
+            "group_by=name",
+            "group_by=author&metric=sum",
+            "group_by=author&limit=0",
+        ] {
+            let response = client
+                .get(app.url(&format!("/v1/books/aggregate?{query}")))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 400, "{query}");
+        }
+    }
+}
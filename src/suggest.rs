@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many suggestions `GET /books/suggest` returns when no `limit` is
+/// given, and the most it allows
+pub(crate) const DEFAULT_LIMIT: i64 = 8;
+pub(crate) const MAX_LIMIT: i64 = 20;
+/// Shorter prefixes match too much of the catalog to be worth suggesting from
+pub(crate) const MIN_PREFIX_LENGTH: usize = 2;
+/// Longer ones are cut short, as no name or author is that long
+const MAX_PREFIX_LENGTH: usize = 100;
+/// How long suggestions are reused for. Books added since then are only
+/// suggested once it runs out.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+/// The most prefixes whose suggestions are cached. Once full, the cache is
+/// emptied.
+const CACHE_CAPACITY: usize = 10_000;
+
+/// What a suggestion would be searched for as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    Title,
+    Author,
+}
+
+/// A name or author starting with what was typed
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Suggestion {
+    pub text: String,
+    pub kind: SuggestionKind,
+    /// How many books have it
+    pub books: i64,
+}
+
+/// What to look up suggestions for: lower-cased, without leading space, and
+/// no longer than `MAX_PREFIX_LENGTH`. A trailing space is kept, as it ends a
+/// word. `None` if it is too short.
+pub(crate) fn normalize_prefix(typed: &str) -> Option<String> {
+    let prefix: String = typed
+        .trim_start()
+        .chars()
+        .take(MAX_PREFIX_LENGTH)
+        .flat_map(char::to_lowercase)
+        .collect();
+    (prefix.trim_end().chars().count() >= MIN_PREFIX_LENGTH).then_some(prefix)
+}
+
+/// Exact matches first, then the texts most books have, then the shortest,
+/// so that the most likely completions come first
+pub(crate) fn rank(suggestions: &mut [Suggestion], prefix: &str) {
+    suggestions.sort_by(|a, b| {
+        let exact = |s: &Suggestion| s.text.to_lowercase() != prefix;
+        exact(a)
+            .cmp(&exact(b))
+            .then(b.books.cmp(&a.books))
+            .then(a.text.chars().count().cmp(&b.text.chars().count()))
+            .then(a.text.cmp(&b.text))
+    });
+}
+
+/// Suggestions recently looked up for each prefix and limit, shared by clones
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SuggestionCache(Arc<Mutex<HashMap<(String, i64), CachedSuggestions>>>);
+
+#[derive(Debug)]
+struct CachedSuggestions {
+    looked_up_at: Instant,
+    suggestions: Vec<Suggestion>,
+}
+
+impl SuggestionCache {
+    /// The cached suggestions, unless they are older than `CACHE_TTL`
+    pub fn get(&self, prefix: &str, limit: i64) -> Option<Vec<Suggestion>> {
+        let cache = self.lock();
+        let cached = cache.get(&(prefix.to_string(), limit))?;
+        (cached.looked_up_at.elapsed() < CACHE_TTL).then(|| cached.suggestions.clone())
+    }
+
+    pub fn set(&self, prefix: &str, limit: i64, suggestions: Vec<Suggestion>) {
+        let mut cache = self.lock();
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(
+            (prefix.to_string(), limit),
+            CachedSuggestions {
+                looked_up_at: Instant::now(),
+                suggestions,
+            },
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, i64), CachedSuggestions>> {
+        self.0.lock().expect("No thread panics holding the lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryBookRepo;
+    use crate::models::{Actor, NewBook};
+    use crate::service::BookService;
+
+    #[tokio::test]
+    async fn names_and_authors_are_suggested_most_likely_first() {
+        let mut books = BookService::new(InMemoryBookRepo::new());
+        let actor = Actor(None);
+        for (name, author) in [
+            ("Great Expectations", "Charles Dickens"),
+            ("Greenmantle", "John Buchan"),
+            ("The Power and the Glory", "Graham Greene"),
+            ("Brighton Rock", "Graham Greene"),
+            ("Gre", "Anonymous"),
+            ("Emma", "Jane Austen"),
+        ] {
+            let book = NewBook {
+                name: name.to_string(),
+                author: author.to_string(),
+                ..NewBook::default()
+            };
+            books.insert_book(book, &actor).await.unwrap();
+        }
+
+        let suggested = |typed: &'static str, limit| {
+            let books = books.clone();
+            async move {
+                let suggestions = books.suggest(typed, limit).await.unwrap();
+                suggestions
+                    .into_iter()
+                    .map(|s| (s.text, s.kind))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let title = |text: &str| (text.to_string(), SuggestionKind::Title);
+        assert_eq!(
+            suggested("  GRE", 3).await,
+            [
+                title("Gre"),
+                title("Greenmantle"),
+                title("Great Expectations")
+            ]
+        );
+        assert_eq!(
+            suggested("gr", 2).await,
+            [
+                ("Graham Greene".to_string(), SuggestionKind::Author),
+                title("Gre")
+            ]
+        );
+        assert!(suggested("g", 2).await.is_empty());
+    }
+
+    #[test]
+    fn prefixes_are_normalized() {
+        assert_eq!(normalize_prefix(" Gre").as_deref(), Some("gre"));
+        assert_eq!(normalize_prefix("the ").as_deref(), Some("the "));
+        assert_eq!(normalize_prefix("g "), None);
+        assert_eq!(
+            normalize_prefix(&"a".repeat(200)).map(|prefix| prefix.len()),
+            Some(MAX_PREFIX_LENGTH)
+        );
+    }
+}
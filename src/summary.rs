@@ -0,0 +1,82 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use diesel::sql_types::BigInt;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{DBPool, DatabaseError};
+
+/// The numbers the admin dashboard shows, so that it needs one request
+/// rather than one for each
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, diesel::QueryableByName)]
+pub struct AdminSummary {
+    #[diesel(sql_type = BigInt)]
+    pub total_books: i64,
+    /// Revisions recorded since midnight UTC
+    #[diesel(sql_type = BigInt)]
+    pub changes_today: i64,
+    /// Requests to the API since midnight UTC, as last written to
+    /// `api_usage`
+    #[diesel(sql_type = BigInt)]
+    pub requests_today: i64,
+    /// Deliveries that ran out of attempts
+    #[diesel(sql_type = BigInt)]
+    pub failed_webhook_deliveries: i64,
+    /// Deliveries still to be attempted
+    #[diesel(sql_type = BigInt)]
+    pub pending_webhook_deliveries: i64,
+    /// Jobs waiting to run or running
+    #[diesel(sql_type = BigInt)]
+    pub queued_jobs: i64,
+    /// Jobs that ran out of attempts, until they are retried
+    #[diesel(sql_type = BigInt)]
+    pub dead_jobs: i64,
+    /// Events in the outbox not yet sent to webhooks or Kafka
+    #[diesel(sql_type = BigInt)]
+    pub unpublished_events: i64,
+    #[diesel(sql_type = diesel::sql_types::Timestamptz)]
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Compute the summary, in one query
+async fn admin_summary(pool: &DBPool) -> Result<AdminSummary, DatabaseError> {
+    let mut conn = pool.get().await?;
+
+    let summary = diesel::sql_query(
+        "SELECT
+             (SELECT count(*) FROM books) AS total_books,
+             (SELECT count(*) FROM book_revisions
+              WHERE recorded_at >= date_trunc('day', now(), 'UTC')) AS changes_today,
+             (SELECT coalesce(sum(requests), 0)::BIGINT FROM api_usage
+              WHERE day = (now() AT TIME ZONE 'UTC')::DATE) AS requests_today,
+             (SELECT count(*) FROM webhook_deliveries
+              WHERE status = 'failed') AS failed_webhook_deliveries,
+             (SELECT count(*) FROM webhook_deliveries
+              WHERE status = 'pending') AS pending_webhook_deliveries,
+             (SELECT count(*) FROM jobs
+              WHERE status IN ('queued', 'running')) AS queued_jobs,
+             (SELECT count(*) FROM jobs WHERE status = 'dead') AS dead_jobs,
+             (SELECT count(*) FROM outbox
+              WHERE published_at IS NULL) AS unpublished_events,
+             now() AS computed_at",
+    )
+    .get_result(&mut conn)
+    .await?;
+
+    Ok(summary)
+}
+
+pub(crate) fn summary_router(pool: DBPool) -> Router {
+    Router::new()
+        .route("/admin/summary", get(get_summary))
+        .with_state(pool)
+}
+
+async fn get_summary(
+    State(pool): State<DBPool>,
+) -> Result<Json<AdminSummary>, (StatusCode, String)> {
+    admin_summary(&pool)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
@@ -0,0 +1,121 @@
+//! Helpers for testing against a running instance of the API, for our own
+//! integration tests and for applications that use it
+
+use std::error::Error;
+use std::future::IntoFuture;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::api::{build_api, ApiOptions};
+use crate::memory::InMemoryBookRepo;
+use crate::repo::BookRepo;
+use crate::service::BookService;
+
+/// The API serving on an ephemeral port. Dropping it shuts the server down,
+/// without waiting for it to stop.
+pub struct TestApp {
+    /// Where the API is served, e.g. `http://127.0.0.1:54321`, including
+    /// any `base_path`
+    pub base_url: String,
+    pub local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<io::Result<()>>,
+}
+
+impl TestApp {
+    /// The URL of a path on the server, e.g. `app.url("/v1/books")`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Stop the server, waiting for requests in flight to finish
+    pub async fn shutdown(self) -> io::Result<()> {
+        let _ = self.shutdown.send(());
+        self.server.await.expect("The server task doesn't panic")
+    }
+}
+
+/// Start the API on `127.0.0.1`, on a port picked by the OS, serving books
+/// from a new, empty `InMemoryBookRepo`. There is no DB, so background work
+/// such as webhooks and enrichment doesn't happen.
+pub async fn spawn_test_app() -> TestApp {
+    spawn_test_app_with(
+        BookService::new(InMemoryBookRepo::new()),
+        ApiOptions::default(),
+    )
+    .await
+}
+
+/// Like `spawn_test_app`, serving the given books, e.g. from another repo or
+/// with another duplicate policy, with the given options. Panics if no port
+/// can be bound.
+pub async fn spawn_test_app_with<R, E>(books: BookService<R, E>, options: ApiOptions) -> TestApp
+where
+    E: Error + Send + Sync + 'static,
+    R: BookRepo<E> + Send + Sync + Clone + 'static,
+{
+    let base_path = options.base_path.trim_end_matches('/').to_string();
+    let router = build_api(books, options);
+
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .expect("Can bind an ephemeral port");
+    let local_addr = listener
+        .local_addr()
+        .expect("A bound socket has an address");
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+    let server = tokio::spawn(
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async {
+            // Also shut down if the sender is dropped
+            let _ = shutdown_signal.await;
+        })
+        .into_future(),
+    );
+
+    TestApp {
+        base_url: format!("http://{local_addr}{base_path}"),
+        local_addr,
+        shutdown,
+        server,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_the_api_until_shut_down() {
+        let app = spawn_test_app().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(app.url("/v1/books"))
+            .json(&serde_json::json!({"name": "Kindred", "author": "Octavia E. Butler"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let books: serde_json::Value = client
+            .get(app.url("/v1/books"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(books[0]["name"], "Kindred");
+
+        let url = app.url("/v1/books");
+        app.shutdown().await.unwrap();
+        assert!(client.get(url).send().await.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, BodyDataStream},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use futures::task::AtomicWaker;
+use futures::{stream, StreamExt};
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::json_error::json_error;
+use crate::json_stream::is_streamed;
+
+/// How long `/books` requests may take before they are abandoned, so a hung
+/// DB connection can't hold client connections open indefinitely
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub default: Duration,
+    /// For `GET /books`, which returns many rows and so may take longer
+    pub list: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        RequestTimeouts {
+            default: Duration::from_secs(5),
+            list: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Middleware that drops the handler future (cancelling any work it is
+/// waiting on) and returns a 504 if it runs for longer than the timeout. A
+/// streamed body must be sent within the same time, or it is cut off.
+pub(crate) async fn request_timeout(
+    State(timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let deadline = Instant::now() + timeout;
+
+    match tokio::time::timeout_at(deadline, next.run(request)).await {
+        Ok(response) if is_streamed(&response) => {
+            response.map(|body| with_deadline(body, deadline, path))
+        }
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                %method,
+                path,
+                timeout_ms = timeout.as_millis() as u64,
+                "request timed out"
+            );
+            json_error(StatusCode::GATEWAY_TIMEOUT, "The request took too long")
+        }
+    }
+}
+
+/// The body, ended with an error if it is still being sent at the deadline.
+/// It is dropped then even if the client has stopped reading it, so that
+/// whatever produces it, such as a DB query, doesn't run on.
+fn with_deadline(body: Body, deadline: Instant, path: String) -> Body {
+    let inner = Arc::new(Cutoff {
+        body: Mutex::new(Some(body.into_data_stream())),
+        waker: AtomicWaker::new(),
+    });
+    let cutoff = Arc::downgrade(&inner);
+    tokio::spawn(async move {
+        tokio::time::sleep_until(deadline).await;
+        if let Some(cutoff) = cutoff.upgrade() {
+            if cutoff.body.lock().unwrap().take().is_some() {
+                warn!(path, "streamed response timed out, cutting it off");
+            }
+            cutoff.waker.wake();
+        }
+    });
+
+    let mut ended = false;
+    Body::from_stream(stream::poll_fn(move |cx| {
+        inner.waker.register(cx.waker());
+        match inner.body.lock().unwrap().as_mut() {
+            Some(body) => body.poll_next_unpin(cx),
+            None if ended => Poll::Ready(None),
+            None => {
+                ended = true;
+                Poll::Ready(Some(Err(axum::Error::new(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the response took too long",
+                )))))
+            }
+        }
+    }))
+}
+
+/// A streamed body, taken away at its deadline
+struct Cutoff {
+    body: Mutex<Option<BodyDataStream>>,
+    /// Woken at the deadline, in case the body was waiting for more
+    waker: AtomicWaker,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_requests_are_cancelled_with_a_504() {
+        let router = Router::new()
+            .route("/slow", get(|| tokio::time::sleep(Duration::from_secs(60))))
+            .route("/fast", get(|| async {}))
+            .route_layer(middleware::from_fn_with_state(
+                Duration::from_millis(50),
+                request_timeout,
+            ));
+
+        let request = |path| Request::get(path).body(Body::empty()).unwrap();
+
+        let slow = router.clone().oneshot(request("/slow")).await.unwrap();
+        assert_eq!(slow.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let fast = router.oneshot(request("/fast")).await.unwrap();
+        assert_eq!(fast.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn streamed_bodies_are_dropped_at_the_deadline_even_if_not_read() {
+        let (guard, dropped) = tokio::sync::oneshot::channel::<()>();
+        let guard = Arc::new(Mutex::new(Some(guard)));
+        let router = Router::new()
+            .route(
+                "/stream",
+                get(move || async move {
+                    // Sends the start of a list, then never finishes, like a
+                    // hung DB query
+                    let guard = guard.lock().unwrap().take();
+                    let chunks = stream::once(async { Ok::<_, io::Error>("[") })
+                        .chain(stream::pending())
+                        .map(move |chunk| {
+                            let _guard = &guard;
+                            chunk
+                        });
+                    Body::from_stream(chunks)
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                Duration::from_millis(50),
+                request_timeout,
+            ));
+
+        let request = Request::get("/stream").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without the body being read, as with a stalled client
+        tokio::time::timeout(Duration::from_secs(5), dropped)
+            .await
+            .expect("the body is dropped at the deadline")
+            .unwrap_err();
+        assert!(axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .is_err());
+    }
+}
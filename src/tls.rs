@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::interval;
+use tracing::{error, info};
+
+/// How often to check whether the certificate or key file has changed on disk
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Paths to a PEM-encoded certificate chain and private key
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub(crate) async fn load(&self) -> std::io::Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert_path, &self.key_path).await
+    }
+
+    /// Reload the certificate and key whenever the process receives SIGHUP or
+    /// either file is modified, so certificates can be rotated without a
+    /// restart. If a reload fails, the previous certificate stays in use.
+    pub(crate) fn spawn_reloader(self, rustls_config: RustlsConfig) {
+        tokio::spawn(async move {
+            let mut sighup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+            let mut file_check = interval(FILE_CHECK_INTERVAL);
+            let mut last_modified = self.last_modified();
+
+            loop {
+                tokio::select! {
+                    _ = sighup.recv() => {
+                        info!("Received SIGHUP, reloading TLS certificate");
+                    }
+                    _ = file_check.tick() => {
+                        let modified = self.last_modified();
+                        if modified == last_modified {
+                            continue;
+                        }
+                        info!("TLS certificate or key changed on disk, reloading");
+                    }
+                }
+
+                last_modified = self.last_modified();
+                match rustls_config
+                    .reload_from_pem_file(&self.cert_path, &self.key_path)
+                    .await
+                {
+                    Ok(()) => info!("Reloaded TLS certificate"),
+                    Err(e) => error!("Failed to reload TLS certificate, keeping the old one: {e}"),
+                }
+            }
+        });
+    }
+
+    fn last_modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let cert = std::fs::metadata(&self.cert_path).and_then(|m| m.modified());
+        let key = std::fs::metadata(&self.key_path).and_then(|m| m.modified());
+        cert.ok().zip(key.ok())
+    }
+}
@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+
+use crate::models::{Book, BookTranslation};
+
+/// The longest language tag kept, as in the `book_translations` table
+const MAX_LANGUAGE_LENGTH: usize = 35;
+
+/// A book in the language asked for, if it has a translation into it: its
+/// name is translated, and its description and the language are added
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LocalizedBook {
+    #[serde(flatten)]
+    pub book: Book,
+    /// The language of the translation used, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// When the translation used was last changed
+    #[serde(skip)]
+    translated_at: Option<DateTime<Utc>>,
+}
+
+impl LocalizedBook {
+    pub(crate) fn new(mut book: Book, translation: Option<BookTranslation>) -> LocalizedBook {
+        match translation {
+            Some(translation) => {
+                book.name = translation.name;
+                LocalizedBook {
+                    book,
+                    language: Some(translation.language),
+                    description: translation.description,
+                    translated_at: Some(translation.updated_at),
+                }
+            }
+            None => LocalizedBook {
+                book,
+                language: None,
+                description: None,
+                translated_at: None,
+            },
+        }
+    }
+
+    /// When the book or the translation used was last changed, whichever was
+    /// later
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        self.translated_at
+            .map_or(self.book.updated_at, |at| at.max(self.book.updated_at))
+    }
+}
+
+/// The language tag lower-cased, e.g. `de-AT` -> `de-at`, or `None` if it
+/// isn't one: a primary language of two to eight letters, then any subtags
+/// of one to eight letters or digits, each after a hyphen
+pub(crate) fn normalize_language(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_ascii_lowercase();
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let valid = tag.len() <= MAX_LANGUAGE_LENGTH
+        && (2..=8).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    valid.then_some(tag)
+}
+
+/// The languages in an `Accept-Language` header, e.g. `fr-CH, fr;q=0.9,
+/// en;q=0.8`, most preferred first. The wildcard, languages with a quality
+/// of 0 and anything that isn't a language tag are left out.
+pub(crate) fn preferred_languages(accept_language: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let language = normalize_language(parts.next()?)?;
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (quality > 0.0).then_some((language, quality))
+        })
+        .collect();
+    // Stable, so that languages of the same quality stay in order
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages
+        .into_iter()
+        .map(|(language, _)| language)
+        .collect()
+}
+
+/// The translation best matching the preferred languages. Each language is
+/// looked for as it is, then with its last subtag dropped and so on, e.g.
+/// `de-at` then `de`, before the next language is tried.
+pub(crate) fn best_match(
+    preferred: &[String],
+    translations: Vec<BookTranslation>,
+) -> Option<BookTranslation> {
+    let mut candidates = preferred.iter().flat_map(|language| {
+        let mut language = language.as_str();
+        std::iter::from_fn(move || {
+            let candidate = (!language.is_empty()).then_some(language)?;
+            language = language.rsplit_once('-').map_or("", |(rest, _)| rest);
+            Some(candidate)
+        })
+    });
+    let position = candidates.find_map(|candidate| {
+        translations
+            .iter()
+            .position(|translation| translation.language == candidate)
+    })?;
+    translations.into_iter().nth(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn translation(language: &str) -> BookTranslation {
+        BookTranslation {
+            book_id: 1,
+            language: language.to_string(),
+            name: format!("Name in {language}"),
+            description: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn best(accept_language: &str, available: &[&str]) -> Option<String> {
+        let translations = available.iter().map(|l| translation(l)).collect();
+        best_match(&preferred_languages(accept_language), translations)
+            .map(|translation| translation.language)
+    }
+
+    #[test]
+    fn the_most_preferred_available_language_is_used() {
+        assert_eq!(best("fr", &["de", "fr"]).as_deref(), Some("fr"));
+        assert_eq!(
+            best("de;q=0.5, fr;q=0.9", &["de", "fr"]).as_deref(),
+            Some("fr")
+        );
+        assert_eq!(best("de-AT, fr", &["de", "fr"]).as_deref(), Some("de"));
+        assert_eq!(best("fr-ca", &["fr-ca", "fr"]).as_deref(), Some("fr-ca"));
+        // A more specific translation isn't a match for a general language
+        assert_eq!(best("fr", &["fr-ca"]), None);
+        assert_eq!(best("es, *;q=0.1", &["de", "fr"]), None);
+        assert_eq!(
+            best("fr;q=0, de;q=0.1", &["de", "fr"]).as_deref(),
+            Some("de")
+        );
+        assert_eq!(best("", &["de"]), None);
+    }
+
+    #[test]
+    fn language_tags_are_checked_and_lower_cased() {
+        assert_eq!(normalize_language("de-AT").as_deref(), Some("de-at"));
+        assert_eq!(
+            normalize_language("zh-Hant-TW").as_deref(),
+            Some("zh-hant-tw")
+        );
+        assert_eq!(normalize_language("f"), None);
+        assert_eq!(normalize_language("fr_FR"), None);
+        assert_eq!(normalize_language("fr-"), None);
+        assert_eq!(normalize_language("*"), None);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use chrono::{NaiveDate, Utc};
+use diesel::upsert::excluded;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::client_ip::ClientIp;
+use crate::database::{DBPool, DatabaseError};
+use crate::route_config::BookRoute;
+use crate::schema::api_usage;
+
+/// How often counted requests are written to the DB
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// How long usage is kept
+pub(crate) const RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+/// When usage older than `RETENTION` is deleted: daily
+pub(crate) const PRUNE_SCHEDULE: &str = "0 50 3 * * *";
+/// How many rows `GET /admin/usage` returns when no `limit` is given, and the
+/// most it allows
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+/// The client of a request whose IP address isn't known, e.g. over a Unix
+/// socket
+const UNKNOWN_CLIENT: &str = "unknown";
+
+/// The requests to each route by each client on each day, counted since they
+/// were last written to the DB. Clones share the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct ApiUsage(Arc<Mutex<HashMap<UsageKey, UsageCounts>>>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct UsageKey {
+    pub day: NaiveDate,
+    pub route: BookRoute,
+    pub client: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct UsageCounts {
+    pub requests: i64,
+    /// Responses with a 4xx status
+    pub client_errors: i64,
+    /// Responses with a 5xx status
+    pub server_errors: i64,
+    pub total_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+impl UsageCounts {
+    fn add(&mut self, other: UsageCounts) {
+        self.requests += other.requests;
+        self.client_errors += other.client_errors;
+        self.server_errors += other.server_errors;
+        self.total_latency_ms += other.total_latency_ms;
+        self.max_latency_ms = self.max_latency_ms.max(other.max_latency_ms);
+    }
+}
+
+impl ApiUsage {
+    pub(crate) fn record(&self, key: UsageKey, status: StatusCode, latency: Duration) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let request = UsageCounts {
+            requests: 1,
+            client_errors: status.is_client_error().into(),
+            server_errors: status.is_server_error().into(),
+            total_latency_ms: latency_ms,
+            max_latency_ms: latency_ms,
+        };
+        self.add(key, request);
+    }
+
+    /// The counts so far, starting again from zero
+    pub(crate) fn take(&self) -> HashMap<UsageKey, UsageCounts> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn add(&self, key: UsageKey, counts: UsageCounts) {
+        self.lock().entry(key).or_default().add(counts);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<UsageKey, UsageCounts>> {
+        self.0.lock().expect("No thread panics holding the lock")
+    }
+
+    /// Write the counted requests to the DB every `FLUSH_INTERVAL`. If a
+    /// write fails, the counts are kept for the next one.
+    pub(crate) fn spawn_flusher(self, store: UsageStore) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let usage: Vec<_> = self.take().into_iter().collect();
+                if usage.is_empty() {
+                    continue;
+                }
+                match store.record(&usage).await {
+                    Ok(()) => debug!(rows = usage.len(), "Recorded API usage"),
+                    Err(e) => {
+                        error!("Failed to record API usage, will retry: {e}");
+                        for (key, counts) in usage {
+                            self.add(key, counts);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Middleware counting the request to the route in the `ApiUsage`, with the
+/// client's IP address, its status and how long it took
+pub(crate) async fn track_usage(
+    State((usage, route)): State<(ApiUsage, BookRoute)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    // Set by the `resolve_client_ip` middleware
+    let client = match request.extensions().get::<ClientIp>() {
+        Some(ClientIp(Some(ip))) => ip.to_string(),
+        _ => UNKNOWN_CLIENT.to_string(),
+    };
+
+    let response = next.run(request).await;
+
+    let key = UsageKey {
+        day: Utc::now().date_naive(),
+        route,
+        client,
+    };
+    usage.record(key, response.status(), started.elapsed());
+    response
+}
+
+/// A route's usage by a client on a day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteUsage {
+    pub day: NaiveDate,
+    pub route: String,
+    /// The client's IP address, or `unknown`
+    pub client: String,
+    pub requests: i64,
+    pub client_errors: i64,
+    pub server_errors: i64,
+    /// The share of requests that got a 4xx or 5xx response
+    pub error_rate: f64,
+    pub mean_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+/// The API usage in the `api_usage` table, written by every replica
+#[derive(Clone)]
+pub(crate) struct UsageStore {
+    pool: DBPool,
+}
+
+diesel::define_sql_function! {
+    fn greatest(
+        a: diesel::sql_types::Double,
+        b: diesel::sql_types::Double,
+    ) -> diesel::sql_types::Double;
+}
+
+type UsageRow = (NaiveDate, String, String, i64, i64, i64, f64, f64);
+
+impl UsageStore {
+    pub fn new(pool: DBPool) -> Self {
+        UsageStore { pool }
+    }
+
+    /// Add the counts to the usage already recorded
+    async fn record(&self, usage: &[(UsageKey, UsageCounts)]) -> Result<(), DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let rows: Vec<_> = usage
+            .iter()
+            .map(|(key, counts)| {
+                (
+                    api_usage::day.eq(key.day),
+                    api_usage::route.eq(key.route.name()),
+                    api_usage::client.eq(&key.client),
+                    api_usage::requests.eq(counts.requests),
+                    api_usage::client_errors.eq(counts.client_errors),
+                    api_usage::server_errors.eq(counts.server_errors),
+                    api_usage::total_latency_ms.eq(counts.total_latency_ms),
+                    api_usage::max_latency_ms.eq(counts.max_latency_ms),
+                )
+            })
+            .collect();
+        diesel::insert_into(api_usage::table)
+            .values(rows)
+            .on_conflict((api_usage::day, api_usage::route, api_usage::client))
+            .do_update()
+            .set((
+                api_usage::requests.eq(api_usage::requests + excluded(api_usage::requests)),
+                api_usage::client_errors
+                    .eq(api_usage::client_errors + excluded(api_usage::client_errors)),
+                api_usage::server_errors
+                    .eq(api_usage::server_errors + excluded(api_usage::server_errors)),
+                api_usage::total_latency_ms
+                    .eq(api_usage::total_latency_ms + excluded(api_usage::total_latency_ms)),
+                api_usage::max_latency_ms.eq(greatest(
+                    api_usage::max_latency_ms,
+                    excluded(api_usage::max_latency_ms),
+                )),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The usage matching the filter, most requests first
+    async fn query(&self, filter: &UsageFilter) -> Result<Vec<RouteUsage>, DatabaseError> {
+        let mut conn = self.pool.get().await?;
+
+        let mut query = api_usage::table.into_boxed();
+        if let Some(from) = filter.from {
+            query = query.filter(api_usage::day.ge(from));
+        }
+        if let Some(to) = filter.to {
+            query = query.filter(api_usage::day.le(to));
+        }
+        if let Some(route) = &filter.route {
+            query = query.filter(api_usage::route.eq(route));
+        }
+        if let Some(client) = &filter.client {
+            query = query.filter(api_usage::client.eq(client));
+        }
+        let rows: Vec<UsageRow> = query
+            .select((
+                api_usage::day,
+                api_usage::route,
+                api_usage::client,
+                api_usage::requests,
+                api_usage::client_errors,
+                api_usage::server_errors,
+                api_usage::total_latency_ms,
+                api_usage::max_latency_ms,
+            ))
+            .order((
+                api_usage::requests.desc(),
+                api_usage::day.desc(),
+                api_usage::route.asc(),
+                api_usage::client.asc(),
+            ))
+            .limit(filter.limit.unwrap_or(DEFAULT_LIMIT))
+            .load(&mut conn)
+            .await?;
+
+        Ok(rows.into_iter().map(route_usage).collect())
+    }
+}
+
+fn route_usage(row: UsageRow) -> RouteUsage {
+    let (
+        day,
+        route,
+        client,
+        requests,
+        client_errors,
+        server_errors,
+        total_latency_ms,
+        max_latency_ms,
+    ) = row;
+    // Every row counts at least one request
+    let requests_f64 = requests.max(1) as f64;
+    RouteUsage {
+        day,
+        route,
+        client,
+        requests,
+        client_errors,
+        server_errors,
+        error_rate: (client_errors + server_errors) as f64 / requests_f64,
+        mean_latency_ms: total_latency_ms / requests_f64,
+        max_latency_ms,
+    }
+}
+
+/// Delete the usage recorded for days that ended more than `retention` ago
+pub(crate) async fn prune_usage(
+    pool: &DBPool,
+    retention: Duration,
+) -> Result<usize, DatabaseError> {
+    let mut conn = pool.get().await?;
+    let cutoff = (Utc::now() - retention).date_naive();
+
+    let deleted = diesel::delete(api_usage::table.filter(api_usage::day.lt(cutoff)))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(deleted)
+}
+
+pub(crate) fn usage_router(store: UsageStore) -> Router {
+    Router::new()
+        .route("/admin/usage", get(list_usage))
+        .with_state(store)
+}
+
+#[derive(Deserialize)]
+struct UsageFilter {
+    /// The first day to include, e.g. `2026-10-01`
+    from: Option<NaiveDate>,
+    /// The last day to include
+    to: Option<NaiveDate>,
+    /// A route's name, e.g. `list_books`
+    route: Option<String>,
+    /// A client's IP address
+    client: Option<String>,
+    limit: Option<i64>,
+}
+
+async fn list_usage(
+    State(store): State<UsageStore>,
+    Query(filter): Query<UsageFilter>,
+) -> Result<Json<Vec<RouteUsage>>, (StatusCode, String)> {
+    if let Some(limit) = filter.limit {
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("The limit must be between 1 and {MAX_LIMIT}"),
+            ));
+        }
+    }
+    if let Some(route) = &filter.route {
+        if !BookRoute::ALL.iter().any(|known| known.name() == route) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("There is no route named {route}"),
+            ));
+        }
+    }
+
+    let usage = store
+        .query(&filter)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiOptions;
+    use crate::memory::InMemoryBookRepo;
+    use crate::service::BookService;
+    use crate::test_support::spawn_test_app_with;
+
+    #[tokio::test]
+    async fn requests_are_counted_by_route_and_client() {
+        let usage = ApiUsage::default();
+        let app = spawn_test_app_with(
+            BookService::new(InMemoryBookRepo::new()),
+            ApiOptions {
+                usage: Some(usage.clone()),
+                ..ApiOptions::default()
+            },
+        )
+        .await;
+        let client = reqwest::Client::new();
+        for path in ["/v1/books", "/v1/books", "/v1/books/1", "/books"] {
+            client.get(app.url(path)).send().await.unwrap();
+        }
+
+        let counts = usage.take();
+        let key = |route| UsageKey {
+            day: Utc::now().date_naive(),
+            route,
+            client: "127.0.0.1".to_string(),
+        };
+        assert_eq!(counts.len(), 2);
+        let list_books = counts[&key(BookRoute::ListBooks)];
+        assert_eq!((list_books.requests, list_books.client_errors), (3, 0));
+        let get_book = counts[&key(BookRoute::GetBook)];
+        assert_eq!((get_book.requests, get_book.client_errors), (1, 1));
+        assert!(get_book.max_latency_ms <= get_book.total_latency_ms);
+        assert!(usage.take().is_empty());
+    }
+}
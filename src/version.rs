@@ -0,0 +1,43 @@
+use axum::Json;
+
+/// Information about the running build, captured at compile time by `build.rs`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_timestamp: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("BUILD_GIT_SHA"),
+            build_timestamp: env!("BUILD_TIMESTAMP"),
+            features: env!("BUILD_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// Handler for `GET /version`
+pub async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn version_returns_the_crate_version() {
+        let Json(info) = version().await;
+
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        assert!(!info.build_timestamp.is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use tracing::{debug, error};
+
+use crate::database::{DBPool, DatabaseError};
+use crate::models::Book;
+use crate::repo::BookRepo;
+use crate::schema::book_views;
+use crate::service::BookService;
+
+/// How often counted views are written to the repo
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// How long views are kept, and so the longest trending window
+pub(crate) const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// When views older than `RETENTION` are deleted: daily
+pub(crate) const PRUNE_SCHEDULE: &str = "0 45 3 * * *";
+/// How many books `GET /books/trending` returns when no `limit` is given,
+/// and the most it allows
+pub(crate) const DEFAULT_LIMIT: i64 = 10;
+pub(crate) const MAX_LIMIT: i64 = 100;
+
+/// A book with how many times it was viewed in a window
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TrendingBook {
+    #[serde(flatten)]
+    pub book: Book,
+    pub views: i64,
+}
+
+/// Views of each book counted since they were last written to the repo,
+/// shared by clones, so that a view costs no query
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ViewCounts(Arc<Mutex<HashMap<i32, i32>>>);
+
+impl ViewCounts {
+    pub fn add(&self, id: i32, views: i32) {
+        let mut counts = self.lock();
+        let count = counts.entry(id).or_default();
+        *count = count.saturating_add(views);
+    }
+
+    /// The counts so far, starting again from zero
+    pub fn take(&self) -> HashMap<i32, i32> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<i32, i32>> {
+        self.0.lock().expect("No thread panics holding the lock")
+    }
+}
+
+/// Parse a trending window such as `24h` or `7d`, in whole hours or days,
+/// of at most `RETENTION`
+pub(crate) fn parse_window(window: &str) -> Option<Duration> {
+    let (amount, hours) = if let Some(amount) = window.strip_suffix('h') {
+        (amount, 1)
+    } else {
+        (window.strip_suffix('d')?, 24)
+    };
+    let amount: u64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    let window = Duration::from_secs(amount.checked_mul(hours * 60 * 60)?);
+    (window <= RETENTION).then_some(window)
+}
+
+/// Write the counted views to the repo every `FLUSH_INTERVAL`
+pub(crate) fn spawn_view_flusher<R, E>(mut books: BookService<R, E>)
+where
+    E: Error + Send + Sync + 'static,
+    R: BookRepo<E> + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match books.flush_views().await {
+                Ok(0) => {}
+                Ok(books) => debug!(books, "Recorded views"),
+                Err(e) => error!("Failed to record views, will retry: {e}"),
+            }
+        }
+    });
+}
+
+/// Delete the views recorded for hours that started more than `retention`
+/// ago
+pub(crate) async fn prune_views(
+    pool: &DBPool,
+    retention: Duration,
+) -> Result<usize, DatabaseError> {
+    let mut conn = pool.get().await?;
+    let cutoff = Utc::now() - retention;
+
+    let deleted = diesel::delete(book_views::table.filter(book_views::hour.lt(cutoff)))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryBookRepo;
+    use crate::models::{Actor, NewBook};
+
+    #[tokio::test]
+    async fn trending_books_are_those_viewed_most() {
+        let mut books = BookService::new(InMemoryBookRepo::new());
+        let actor = Actor(None);
+        let mut ids = Vec::new();
+        for name in ["Emma", "Dune", "Beloved"] {
+            let book = NewBook {
+                name: name.to_string(),
+                author: "Someone".to_string(),
+                ..NewBook::default()
+            };
+            ids.push(books.insert_book(book, &actor).await.unwrap().id);
+        }
+        for id in [ids[1], ids[1], ids[0], ids[1], ids[0], ids[2]] {
+            books.record_view(id);
+        }
+        books.delete_book(ids[2], &actor).await.unwrap();
+
+        let day = parse_window("24h").unwrap();
+        assert!(books.trending_books(day, 10).await.unwrap().is_empty());
+        assert_eq!(books.flush_views().await.unwrap(), 3);
+        let trending: Vec<_> = books
+            .trending_books(day, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|trending| (trending.book.name, trending.views))
+            .collect();
+        assert_eq!(
+            trending,
+            vec![("Dune".to_string(), 3), ("Emma".to_string(), 2)]
+        );
+
+        assert_eq!(parse_window("7d"), Some(Duration::from_secs(7 * 24 * 3600)));
+        for invalid in ["", "h", "0h", "-1d", "24", "2w", "31d"] {
+            assert_eq!(parse_window(invalid), None, "{invalid}");
+        }
+    }
+}
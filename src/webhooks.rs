@@ -0,0 +1,311 @@
+use std::error::Error;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    middleware,
+    routing::{get, post},
+    Json, Router,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::info;
+use url::Url;
+
+use crate::content_type::require_json;
+use crate::database::{DatabaseError, WebhookStore};
+use crate::events::BookEvent;
+use crate::models::{NewWebhook, Webhook, WebhookDelivery};
+
+/// Header carrying the signature of the body, `sha256=<hex HMAC-SHA256>`
+/// keyed with the webhook's secret
+pub const SIGNATURE_HEADER: &str = "x-bookstore-signature";
+/// Header carrying the event's `type`
+pub const EVENT_HEADER: &str = "x-bookstore-event";
+/// Header carrying the delivery ID, which is the same when a delivery is
+/// retried or redelivered, so receivers can ignore duplicates
+pub const DELIVERY_HEADER: &str = "x-bookstore-delivery";
+
+/// How long an endpoint has to respond to each attempt
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many deliveries `GET /webhooks/{id}/deliveries` shows
+const DELIVERY_LOG_LENGTH: i64 = 100;
+
+/// The value of the signature header for a body
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends book events relayed from the outbox to the webhooks subscribed to
+/// them, logging each delivery. Deliveries are sent by the job queue, which
+/// retries failed ones with exponential backoff.
+#[derive(Clone)]
+pub(crate) struct WebhookDispatcher {
+    store: WebhookStore,
+    client: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(store: WebhookStore) -> Self {
+        WebhookDispatcher {
+            store,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Log a delivery of the event to every webhook subscribed to it, and
+    /// queue a job to send each
+    pub async fn dispatch(&self, event: &BookEvent, payload: &str) -> Result<(), DatabaseError> {
+        self.store
+            .create_deliveries(event.event_type(), payload)
+            .await?;
+        Ok(())
+    }
+
+    /// Make an attempt at sending a delivery, failing if it should be
+    /// retried. After the final attempt, a delivery that failed is marked
+    /// `failed`.
+    pub async fn attempt(
+        &self,
+        delivery_id: i64,
+        final_attempt: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Either is gone if the webhook has been deleted since
+        let Some(delivery) = self.store.get_delivery(delivery_id).await? else {
+            return Ok(());
+        };
+        let Some(webhook) = self.store.get(delivery.webhook_id).await? else {
+            return Ok(());
+        };
+
+        let (status_code, error) = match self.send(&webhook, &delivery).await {
+            Ok(status) if status.is_success() => (Some(status.as_u16()), None),
+            Ok(status) => (
+                Some(status.as_u16()),
+                Some(format!("endpoint responded with {status}")),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let status = match (&error, final_attempt) {
+            (None, _) => "delivered",
+            (Some(_), true) => "failed",
+            (Some(_), false) => "pending",
+        };
+        self.store
+            .record_attempt(
+                delivery.id,
+                status,
+                status_code.map(i32::from),
+                error.as_deref(),
+            )
+            .await?;
+
+        match error {
+            None => {
+                info!(
+                    delivery = delivery.id,
+                    url = webhook.url,
+                    "Webhook delivered"
+                );
+                Ok(())
+            }
+            Some(error) => Err(format!(
+                "Delivery {} to {} failed: {error}",
+                delivery.id, webhook.url
+            )
+            .into()),
+        }
+    }
+
+    async fn send(
+        &self,
+        webhook: &Webhook,
+        delivery: &WebhookDelivery,
+    ) -> Result<reqwest::StatusCode, reqwest::Error> {
+        let response = self
+            .client
+            .post(&webhook.url)
+            .timeout(ATTEMPT_TIMEOUT)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event_type)
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, &delivery.payload))
+            .body(delivery.payload.clone())
+            .send()
+            .await?;
+        Ok(response.status())
+    }
+}
+
+/// Routes for registering webhooks and inspecting their deliveries, which
+/// are served behind the admin token
+pub(crate) fn webhooks_router(dispatcher: WebhookDispatcher) -> Router {
+    Router::new()
+        .route(
+            "/webhooks",
+            post(register_webhook)
+                .route_layer(middleware::from_fn(require_json))
+                .get(list_webhooks),
+        )
+        .route("/webhooks/{id}", get(get_webhook).delete(delete_webhook))
+        .route("/webhooks/{id}/deliveries", get(list_deliveries))
+        .route(
+            "/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+            post(redeliver),
+        )
+        .with_state(dispatcher)
+}
+
+fn internal_error(e: DatabaseError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn webhook_not_found(id: i32) -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        format!("No webhook found with ID: {id}"),
+    )
+}
+
+/// Why a webhook can't be registered, if it can't
+fn validate(new_webhook: &NewWebhook) -> Result<(), String> {
+    match Url::parse(&new_webhook.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => return Err(format!("Not an HTTP(S) URL: {}", new_webhook.url)),
+    }
+    if new_webhook.secret.is_empty() {
+        return Err("The secret must not be empty".to_string());
+    }
+    if let Some(unknown) = new_webhook
+        .events
+        .iter()
+        .find(|event| !BookEvent::TYPES.contains(&event.as_str()))
+    {
+        return Err(format!(
+            "Unknown event type {unknown}, expected one of {}",
+            BookEvent::TYPES.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+async fn register_webhook(
+    State(dispatcher): State<WebhookDispatcher>,
+    Json(new_webhook): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), (StatusCode, String)> {
+    validate(&new_webhook).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let webhook = dispatcher
+        .store
+        .register(new_webhook)
+        .await
+        .map_err(internal_error)?;
+    info!(id = webhook.id, url = webhook.url, "Registered webhook");
+
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+async fn list_webhooks(
+    State(dispatcher): State<WebhookDispatcher>,
+) -> Result<Json<Vec<Webhook>>, (StatusCode, String)> {
+    let webhooks = dispatcher.store.list().await.map_err(internal_error)?;
+
+    Ok(Json(webhooks))
+}
+
+async fn get_webhook(
+    State(dispatcher): State<WebhookDispatcher>,
+    Path(id): Path<i32>,
+) -> Result<Json<Webhook>, (StatusCode, String)> {
+    match dispatcher.store.get(id).await.map_err(internal_error)? {
+        Some(webhook) => Ok(Json(webhook)),
+        None => Err(webhook_not_found(id)),
+    }
+}
+
+async fn delete_webhook(
+    State(dispatcher): State<WebhookDispatcher>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if dispatcher.store.delete(id).await.map_err(internal_error)? {
+        info!(id, "Deleted webhook");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(webhook_not_found(id))
+    }
+}
+
+async fn list_deliveries(
+    State(dispatcher): State<WebhookDispatcher>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<WebhookDelivery>>, (StatusCode, String)> {
+    if dispatcher
+        .store
+        .get(id)
+        .await
+        .map_err(internal_error)?
+        .is_none()
+    {
+        return Err(webhook_not_found(id));
+    }
+    let deliveries = dispatcher
+        .store
+        .deliveries(id, DELIVERY_LOG_LENGTH)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(deliveries))
+}
+
+/// Send a delivery again, with a fresh set of attempts, whatever happened
+/// to it before
+async fn redeliver(
+    State(dispatcher): State<WebhookDispatcher>,
+    Path((id, delivery_id)): Path<(i32, i64)>,
+) -> Result<(StatusCode, Json<WebhookDelivery>), (StatusCode, String)> {
+    match dispatcher
+        .store
+        .reset_delivery(id, delivery_id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(delivery) => Ok((StatusCode::ACCEPTED, Json(delivery))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("No delivery {delivery_id} found for webhook {id}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_are_signed_with_hmac_sha256() {
+        // Test case 2 from RFC 4231
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn webhooks_need_an_http_url_a_secret_and_known_events() {
+        let webhook = |url: &str, secret: &str, events: &[&str]| NewWebhook {
+            url: url.to_string(),
+            secret: secret.to_string(),
+            events: events.iter().map(|event| event.to_string()).collect(),
+        };
+
+        assert!(validate(&webhook("https://example.com/hook", "s3cret", &[])).is_ok());
+        assert!(validate(&webhook("http://example.com", "s3cret", &["book_deleted"])).is_ok());
+        assert!(validate(&webhook("ftp://example.com", "s3cret", &[])).is_err());
+        assert!(validate(&webhook("example.com", "s3cret", &[])).is_err());
+        assert!(validate(&webhook("https://example.com", "", &[])).is_err());
+        assert!(validate(&webhook("https://example.com", "s3cret", &["book_read"])).is_err());
+    }
+}
@@ -1,86 +1,21 @@
 use diesel::prelude::*;
-use diesel_migrations::*;
+use diesel_migrations::MigrationHarness;
 use testcontainers_modules::postgres::Postgres;
-use testcontainers_modules::testcontainers::{ContainerAsync, runners::AsyncRunner};
+use testcontainers_modules::testcontainers::{runners::AsyncRunner, ContainerAsync};
 use tokio::time::{sleep, Duration};
 
-use rust_bookstore_api::start_server;
-
-const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
-
-// Note: not reusing the application's models is a deliberate choice
-#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
-struct Book {
-    id: i32,
-    name: String,
-    author: String,
-}
-#[derive(Debug, serde::Serialize)]
-struct BookInput {
-    name: String,
-    author: String,
-}
-
-struct BookClient {
-    client: reqwest::Client
-}
-
-impl BookClient {
-    async fn list_books(&self) -> Result<Vec<Book>, reqwest::Error> {
-        self.client
-            .get("http://localhost:3000/books")
-            .send()
-            .await?
-            .json::<Vec<Book>>()
-            .await
-    }
-
-    async fn get_book_raw(&self, id: i32) -> Result<reqwest::Response, reqwest::Error> {
-        self.client
-            .get(format!("http://localhost:3000/books/{id}"))
-            .send()
-            .await
-    }
-
-    async fn get_book(&self, id: i32) -> Result<Book, reqwest::Error> {
-        self.get_book_raw(id)
-            .await?
-            .json::<Book>()
-            .await
-    }
-
-    async fn insert_book(&self, name: String, author: String) -> Result<Book, reqwest::Error> {
-        let input = BookInput { name, author };
-        self.client
-            .post("http://localhost:3000/books")
-            .json(&input)
-            .send()
-            .await?
-            .json::<Book>()
-            .await
-    }
-
-    async fn update_book_raw(&self, id: i32, name: String, author: String) -> Result<reqwest::Response, reqwest::Error> {
-        let input = BookInput { name, author };
-        self.client
-            .put(format!("http://localhost:3000/books/{id}"))
-            .json(&input)
-            .send()
-            .await
-    }
-
-    async fn update_book(&self, id: i32, name: String, author: String) -> Result<Book, reqwest::Error> {
-        self.update_book_raw(id, name, author)
-            .await?
-            .json::<Book>()
-            .await
-    }
-
-    async fn delete_book(&self, id: i32) -> Result<reqwest::Response, reqwest::Error> {
-        self.client
-            .delete(format!("http://localhost:3000/books/{id}"))
-            .send()
-            .await
+use rust_bookstore_api::client::{BookstoreClient, ClientError};
+use rust_bookstore_api::test_support::spawn_test_app;
+use rust_bookstore_api::{
+    BoundAddress, InMemoryBookRepo, ListenerConfig, NewBook, NewTranslation, PoolConfig, Server,
+    ServerOptions, MIGRATIONS,
+};
+
+fn new_book(name: &str, author: &str) -> NewBook {
+    NewBook {
+        name: name.to_string(),
+        author: author.to_string(),
+        ..NewBook::default()
     }
 }
 
@@ -104,17 +39,21 @@ async fn setup_database(container: &ContainerAsync<Postgres>) -> String {
     connection_string
 }
 
-async fn run_tests(client: BookClient) -> Result<(), reqwest::Error> {
+async fn run_tests(client: BookstoreClient) -> Result<(), ClientError> {
     // Start with an empty book database
     let books = client.list_books().await?;
     assert_eq!(0, books.len());
 
     // Add a couple of books
-    let book1 = client.insert_book("Great Expectations".to_string(), "Charles Dickens".to_string()).await?;
+    let book1 = client
+        .insert_book(&new_book("Great Expectations", "Charles Dickens"))
+        .await?;
     assert_eq!("Great Expectations".to_string(), book1.name);
     assert_eq!("Charles Dickens".to_string(), book1.author);
 
-    let book2 = client.insert_book("Never Let Me Go".to_string(), "Kazuo Ishiguro".to_string()).await?;
+    let book2 = client
+        .insert_book(&new_book("Never Let Me Go", "Kazuo Ishiguro"))
+        .await?;
     assert_eq!("Never Let Me Go".to_string(), book2.name);
     assert_eq!("Kazuo Ishiguro".to_string(), book2.author);
 
@@ -122,6 +61,50 @@ async fn run_tests(client: BookClient) -> Result<(), reqwest::Error> {
     let books = client.list_books().await?;
     assert_eq!(2, books.len());
 
+    // Sort them
+    let books_by_name = client.list_books_sorted("-name,id").await?;
+    assert_eq!(vec![book2.clone(), book1.clone()], books_by_name);
+    let books_by_name = client.list_books_collated("-name,id", "de").await?;
+    assert_eq!(vec![book2.clone(), book1.clone()], books_by_name);
+
+    // Page through them
+    let page = client.list_books_page(None, 1).await?;
+    assert_eq!(vec![book1.clone()], page.books);
+    let page = client
+        .list_books_page(page.next_cursor.as_deref(), 1)
+        .await?;
+    assert_eq!(vec![book2.clone()], page.books);
+    assert_eq!(None, page.next_cursor);
+
+    // Filter them
+    let books_by_ishiguro = client.filter_books("author==*ishiguro;id>0").await?;
+    assert_eq!(vec![book2.clone()], books_by_ishiguro);
+    let books_by_dickens = client.find_books(Some("expect"), Some("DICKENS")).await?;
+    assert_eq!(vec![book1.clone()], books_by_dickens);
+
+    // Suggest them as their names are typed
+    let suggestions = client.suggest_books("great").await?;
+    assert_eq!(
+        vec!["Great Expectations"],
+        suggestions
+            .iter()
+            .map(|s| s.text.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    // Search for them by any of the words in their names and authors
+    let results = client.search_books("Dickens expectations").await?;
+    assert_eq!(
+        vec![book1.clone()],
+        results.iter().map(|r| r.book.clone()).collect::<Vec<_>>()
+    );
+    assert!(results[0].rank > 0.0);
+    assert!(client.search_books("gatsby").await?.is_empty());
+
+    // Pick one at random
+    let random_book = client.random_book().await?;
+    assert!(books.contains(&random_book));
+
     // Retrieve the books we just inserted
     let retrieved_book = client.get_book(book1.id).await?;
     assert_eq!(retrieved_book, book1);
@@ -129,11 +112,50 @@ async fn run_tests(client: BookClient) -> Result<(), reqwest::Error> {
     assert_eq!(retrieved_book, book2);
 
     // Retrieve a non-existent book
-    let get_book_response = client.get_book_raw(99).await?;
-    assert_eq!(404, get_book_response.status().as_u16());
+    assert!(matches!(
+        client.get_book(99).await,
+        Err(ClientError::NotFound(_))
+    ));
+
+    // Translate one, and get it in the closest language it has
+    let translation = NewTranslation {
+        name: "De grote verwachtingen".to_string(),
+        description: Some("Een roman".to_string()),
+    };
+    client.put_translation(book1.id, "nl", &translation).await?;
+    let localized = client.get_book_in(book1.id, "nl-BE").await?;
+    assert_eq!(Some("nl"), localized.language.as_deref());
+    assert_eq!("De grote verwachtingen", localized.book.name);
+    assert_eq!(Some("Een roman"), localized.description.as_deref());
+    let localized = client.get_book_in(book1.id, "fr").await?;
+    assert_eq!(None, localized.language);
+    assert_eq!(book1, localized.book);
+    let translations = client.list_translations(book1.id).await?;
+    assert_eq!(
+        vec!["nl"],
+        translations
+            .iter()
+            .map(|t| t.language.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        client.put_translation(book1.id, "x", &translation).await,
+        Err(ClientError::Invalid(_))
+    ));
+    assert!(matches!(
+        client.put_translation(99, "nl", &translation).await,
+        Err(ClientError::NotFound(_))
+    ));
+    client.delete_translation(book1.id, "nl").await?;
+    assert!(matches!(
+        client.delete_translation(book1.id, "nl").await,
+        Err(ClientError::NotFound(_))
+    ));
 
     // Update one of the books
-    let updated_book = client.update_book(book2.id, "The Unconsoled".to_string(), "Kazuo Ishiguro".to_string()).await?;
+    let updated_book = client
+        .update_book(book2.id, &new_book("The Unconsoled", "Kazuo Ishiguro"))
+        .await?;
     assert_eq!(book2.id, updated_book.id);
     assert_eq!("The Unconsoled".to_string(), updated_book.name);
     assert_eq!("Kazuo Ishiguro".to_string(), updated_book.author);
@@ -142,28 +164,76 @@ async fn run_tests(client: BookClient) -> Result<(), reqwest::Error> {
     let retrieved_book = client.get_book(book2.id).await?;
     assert_eq!(updated_book, retrieved_book);
 
+    // Its old slug redirects to its new one
+    assert_eq!("the-unconsoled", updated_book.slug);
+    let retrieved_book = client.get_book_by_slug("never-let-me-go").await?;
+    assert_eq!(updated_book, retrieved_book);
+    assert!(matches!(
+        client.get_book_by_slug("nope").await,
+        Err(ClientError::NotFound(_))
+    ));
+
+    // Books can be found by either form of their ISBN
+    let persuasion = NewBook {
+        isbn: Some("0-14-143951-3".to_string()),
+        ..new_book("Persuasion", "Jane Austen")
+    };
+    let book3 = client.insert_book(&persuasion).await?;
+    let check = client.check_isbn("978-0-14-143951-8").await?;
+    assert!(check.valid);
+    assert_eq!(Some("0141439513".to_string()), check.isbn10);
+    assert_eq!(vec![book3.clone()], check.books);
+    let check = client.check_isbn("9780141439519").await?;
+    assert!(!check.valid);
+    assert!(check.books.is_empty());
+    client.delete_book(book3.id).await?;
+
+    // Add several books at once, or none of them if any is invalid
+    let bulk = client
+        .insert_books(&[
+            new_book("Emma", "Jane Austen"),
+            new_book("Mansfield Park", "Jane Austen"),
+        ])
+        .await?;
+    assert_eq!(
+        vec!["Emma", "Mansfield Park"],
+        bulk.iter().map(|b| b.name.as_str()).collect::<Vec<_>>()
+    );
+    assert_eq!(bulk[1], client.get_book(bulk[1].id).await?);
+    let invalid = client
+        .insert_books(&[new_book("Sanditon", "Jane Austen"), new_book("", "")])
+        .await;
+    assert!(matches!(invalid, Err(ClientError::Invalid(_))));
+    assert!(client.find_books(Some("Sanditon"), None).await?.is_empty());
+    for book in bulk {
+        client.delete_book(book.id).await?;
+    }
+
     // Update a non-existent book -> get a 404 response
-    let update_book_response = client.update_book_raw(99, "foo".to_string(), "bar".to_string()).await?;
-    assert_eq!(404, update_book_response.status().as_u16());
+    let update_result = client.update_book(99, &new_book("foo", "bar")).await;
+    assert!(matches!(update_result, Err(ClientError::NotFound(_))));
 
     // Delete a book
-    let delete_book_response = client.delete_book(book2.id).await?;
-    assert_eq!(204, delete_book_response.status().as_u16());
+    client.delete_book(book2.id).await?;
 
     // Check that the book has been deleted
     let books = client.list_books().await?;
     assert_eq!(1, books.len());
 
-    let get_book_response = client.get_book_raw(book2.id).await?;
-    assert_eq!(404, get_book_response.status().as_u16());
+    assert!(matches!(
+        client.get_book(book2.id).await,
+        Err(ClientError::NotFound(_))
+    ));
 
     // The other book we inserted should still exist
     let book1_again = client.get_book(book1.id).await?;
     assert_eq!(book1, book1_again);
 
     // Delete a non-existent book -> get a 404 response
-    let delete_book_response = client.delete_book(99).await?;
-    assert_eq!(404, delete_book_response.status().as_u16());
+    assert!(matches!(
+        client.delete_book(99).await,
+        Err(ClientError::NotFound(_))
+    ));
 
     Ok(())
 }
@@ -175,12 +245,115 @@ async fn bookstore_api_integration_test() {
     let db_url = setup_database(&postgres).await;
 
     // Run the HTTP server in a background thread, so we can run tests against it
-    let server = start_server(db_url).await;
+    let options = ServerOptions {
+        db_pool: PoolConfig {
+            min_idle: Some(2),
+            warm_up: true,
+            ..PoolConfig::default()
+        },
+        ..ServerOptions::default()
+    };
+    let server = Server::builder()
+        .database_url(db_url)
+        .options(options)
+        .bind(([127, 0, 0, 1], 0).into())
+        .build()
+        .await
+        .unwrap();
+    let BoundAddress::Tcp(addr) = server.local_addr() else {
+        panic!("Bound to a TCP address");
+    };
+    let base_url = format!("http://{addr}");
+    tokio::spawn(async move {
+        server.await.unwrap();
+    });
+
+    let client = BookstoreClient::new(base_url);
+
+    run_tests(client).await.unwrap();
+}
+
+#[tokio::test]
+async fn bookstore_api_in_memory_test() {
+    // The same tests, without Postgres
+    let app = spawn_test_app().await;
+
+    let client = BookstoreClient::new(&app.base_url);
+
+    run_tests(client).await.unwrap();
+    app.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn bookstore_server_in_memory_test() {
+    // The whole server, as run with --in-memory, with no DB to connect to
+    let server = Server::builder()
+        .in_memory()
+        .bind(([127, 0, 0, 1], 0).into())
+        .build()
+        .await
+        .unwrap();
+    let BoundAddress::Tcp(addr) = server.local_addr() else {
+        panic!("Bound to a TCP address");
+    };
+    let base_url = format!("http://{addr}");
+    tokio::spawn(async move {
+        server.await.unwrap();
+    });
+
+    let client = BookstoreClient::new(base_url);
+
+    run_tests(client).await.unwrap();
+}
+
+#[tokio::test]
+async fn bookstore_server_with_a_custom_repo_test() {
+    // A repo of the embedding app's own, without a DB URL, needs no DB
+    let server = Server::builder()
+        .repo::<_, std::convert::Infallible>(InMemoryBookRepo::new())
+        .bind(([127, 0, 0, 1], 0).into())
+        .build()
+        .await
+        .unwrap();
+    let BoundAddress::Tcp(addr) = server.local_addr() else {
+        panic!("Bound to a TCP address");
+    };
+    let base_url = format!("http://{addr}");
     tokio::spawn(async move {
         server.await.unwrap();
     });
 
-    let client = BookClient { client: reqwest::Client::new() };
+    let client = BookstoreClient::new(base_url);
 
     run_tests(client).await.unwrap();
 }
+
+#[tokio::test]
+async fn only_a_stale_socket_is_removed_before_binding() {
+    let build = |path: &std::path::Path| {
+        Server::builder()
+            .in_memory()
+            .options(ServerOptions {
+                listener: ListenerConfig::Unix(path.to_path_buf()),
+                ..ServerOptions::default()
+            })
+            .build()
+    };
+    let dir = std::env::temp_dir();
+    let id = std::process::id();
+
+    // A socket left behind by a previous run is replaced
+    let socket = dir.join(format!("bookstore-{id}.sock"));
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    let server = build(&socket).await.unwrap();
+    assert!(matches!(server.local_addr(), BoundAddress::Unix(_)));
+    drop(server);
+    std::fs::remove_file(&socket).unwrap();
+
+    // Any other file is left alone
+    let file = dir.join(format!("bookstore-{id}.toml"));
+    std::fs::write(&file, "port = 3000").unwrap();
+    assert!(build(&file).await.is_err());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "port = 3000");
+    std::fs::remove_file(&file).unwrap();
+}
@@ -0,0 +1,117 @@
+// A minimal admin interface over the /v1/books API
+
+const form = document.getElementById("book-form");
+const rows = document.getElementById("books");
+const status = document.getElementById("status");
+const summary = document.getElementById("summary");
+// Where the API is mounted, if behind a gateway: everything before /admin/ui
+const basePath = location.pathname.replace(/\/admin\/ui.*$/, "");
+
+function showStatus(message, isError = false) {
+  status.textContent = message;
+  status.className = isError ? "error" : "";
+}
+
+async function request(method, path, body) {
+  const response = await fetch(basePath + path, {
+    method,
+    headers: body ? { "Content-Type": "application/json" } : {},
+    body: body ? JSON.stringify(body) : undefined,
+  });
+  if (!response.ok) {
+    throw new Error(`${method} ${path} failed: ${response.status} ${await response.text()}`);
+  }
+  return response.status === 204 ? null : response.json();
+}
+
+function cell(text) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  return td;
+}
+
+function button(label, onClick) {
+  const b = document.createElement("button");
+  b.textContent = label;
+  b.addEventListener("click", onClick);
+  return b;
+}
+
+async function loadBooks() {
+  const books = await request("GET", "/v1/books");
+  rows.replaceChildren(
+    ...books.map((book) => {
+      const tr = document.createElement("tr");
+      const actions = document.createElement("td");
+      actions.append(
+        button("Edit", () => {
+          form.elements.id.value = book.id;
+          form.elements.name.value = book.name;
+          form.elements.author.value = book.author;
+        }),
+        button("Delete", () => deleteBook(book)),
+      );
+      tr.append(cell(book.id), cell(book.name), cell(book.author), cell(book.updated_at), actions);
+      return tr;
+    }),
+  );
+}
+
+const summaryLabels = {
+  total_books: "Books",
+  changes_today: "Changes today",
+  requests_today: "Requests today",
+  failed_webhook_deliveries: "Failed webhook deliveries",
+  queued_jobs: "Queued jobs",
+  dead_jobs: "Dead jobs",
+};
+
+async function loadSummary() {
+  const numbers = await request("GET", "/admin/summary");
+  summary.replaceChildren(
+    ...Object.entries(summaryLabels).flatMap(([key, label]) => {
+      const dt = document.createElement("dt");
+      dt.textContent = label;
+      const dd = document.createElement("dd");
+      dd.textContent = numbers[key];
+      return [dt, dd];
+    }),
+  );
+}
+
+async function deleteBook(book) {
+  if (!confirm(`Delete "${book.name}"?`)) {
+    return;
+  }
+  try {
+    await request("DELETE", `/v1/books/${book.id}`);
+    showStatus(`Deleted "${book.name}"`);
+    await loadBooks();
+  } catch (e) {
+    showStatus(e.message, true);
+  }
+}
+
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const id = form.elements.id.value;
+  const book = { name: form.elements.name.value, author: form.elements.author.value };
+  try {
+    const saved = id
+      ? await request("PUT", `/v1/books/${id}`, book)
+      : await request("POST", "/v1/books", book);
+    showStatus(`Saved "${saved.name}"`);
+    form.reset();
+    await loadBooks();
+  } catch (e) {
+    showStatus(e.message, true);
+  }
+});
+
+form.addEventListener("reset", () => {
+  form.elements.id.value = "";
+});
+
+loadBooks().catch((e) => showStatus(e.message, true));
+// Only served with a DB, so the UI works without it
+loadSummary().catch(() => summary.remove());